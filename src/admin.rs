@@ -1,47 +1,770 @@
-use std::env;
+use std::{
+    collections::BTreeMap,
+    env,
+    net::IpAddr,
+    sync::{atomic::Ordering, Arc},
+};
 
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    routing::{delete, get},
-    Json, Router,
+    extract::{Path, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+    Extension, Router,
 };
-use tower::ServiceBuilder;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::QueryBuilder;
 
-use crate::{db, error::ApiError, time::TimeService, ApiState};
+use crate::{
+    analytics, changes, db, debug,
+    error::ApiError,
+    groups,
+    json::Json,
+    misses,
+    params::Filtered,
+    payment,
+    query::Query,
+    rejections, replica, reservation, reserved, retry,
+    role::{self, Projection, Role, Roles},
+    snapshot, stages, supervisor,
+    time::TimeService,
+    transition, validate, ApiState,
+};
 
-pub fn routes<T: TimeService>() -> Router<ApiState<T>> {
-    match env::var("API_KEY") {
-        Err(_) => {
-            eprintln!("API_KEY not set, /admin endpoints will be disabled");
-            Router::new()
+#[derive(Clone, Default)]
+pub struct AdminKeys {
+    keys: Arc<[String]>,
+}
+
+impl AdminKeys {
+    pub fn new(keys: Vec<String>) -> Self {
+        Self { keys: keys.into() }
+    }
+
+    pub fn from_env() -> Self {
+        match env::var("API_KEY") {
+            Ok(key) if !key.is_empty() => Self::new(vec![key]),
+            _ => {
+                eprintln!("API_KEY not set, /admin endpoints will be disabled");
+                Self::default()
+            }
         }
-        Ok(key) => Router::new()
-            .route("/visitors", get(list_visitors))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn shortest(&self) -> Option<usize> {
+        self.keys.iter().map(String::len).min()
+    }
+
+    pub fn authorizes(&self, headers: &HeaderMap) -> bool {
+        headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|key| self.keys.iter().any(|x| x == key))
+    }
+}
+
+pub fn routes<T: TimeService>(
+    keys: AdminKeys,
+    readonly_keys: AdminKeys,
+    replica_keys: AdminKeys,
+    allow_delete: bool,
+) -> Router<ApiState<T>> {
+    let mut router = Router::new();
+    if allow_delete {
+        router = router
             .route("/visitors/:id", delete(delete_visitor))
-            .layer(
-                ServiceBuilder::new()
-                    .layer(tower_http::validate_request::ValidateRequestHeaderLayer::bearer(&key)),
-            ),
+            .route("/reservations/:nick", delete(delete_reservation))
+            .route("/reserved-nicks/:nick", delete(reserved::release));
+    }
+
+    let router = router
+        .route("/visitors", get(crate::list_visitors))
+        .route("/visitors/:id/note", put(set_note))
+        .route("/visitors/:id/nick", put(rename_visitor))
+        .route("/visitors/:id/promote", post(promote_visitor))
+        .route("/stats", get(stats))
+        .route("/groups", get(list_groups))
+        .route("/consistency-check", post(check_consistency))
+        .route("/debug-ip", post(enable_debug_ip))
+        .route("/debug-ip/:ip", delete(disable_debug_ip))
+        .route("/analytics/timeline", get(analytics_timeline))
+        .route("/payments/unmatched", get(list_unmatched_payments))
+        .route("/payments/import", post(import_payments))
+        .route("/reservations", get(list_reservations))
+        .route("/reservations/import", post(import_reservations))
+        .route(
+            "/reserved-nicks",
+            get(reserved::list).post(reserved::reserve),
+        )
+        .route("/rejections", get(list_rejections).delete(purge_rejections))
+        .route("/snapshot", get(snapshot::download))
+        .route("/dead-letters", get(retry::list))
+        .route("/dead-letters/:id/retry", post(retry::retry))
+        .route("/invites", post(stages::create_invites))
+        .layer(middleware::from_fn_with_state(keys.clone(), authorize))
+        .merge(
+            Router::new()
+                .route("/diff", get(diff))
+                .layer(middleware::from_fn_with_state(
+                    Roles {
+                        admin: keys,
+                        readonly: readonly_keys,
+                    },
+                    authorize_reader,
+                )),
+        );
+    if replica_keys.is_empty() {
+        return router;
+    }
+
+    router.merge(
+        Router::new()
+            .route("/replica/apply", post(replica::apply))
+            .layer(middleware::from_fn_with_state(
+                replica_keys,
+                replica::authorize,
+            )),
+    )
+}
+
+async fn authorize(State(keys): State<AdminKeys>, request: Request, next: Next) -> Response {
+    if keys.keys.is_empty() {
+        return ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "admin endpoints are disabled, no API key configured",
+        )
+        .with_code("admin_disabled")
+        .into_response();
+    }
+
+    match keys.authorizes(request.headers()) {
+        true => next.run(request).await,
+        false => ApiError::new(StatusCode::UNAUTHORIZED, "invalid API key")
+            .with_code("unauthorized")
+            .into_response(),
+    }
+}
+
+// Routes that only read, where a readonly key gets in too and sees what it sees on the list
+async fn authorize_reader(State(roles): State<Roles>, request: Request, next: Next) -> Response {
+    if roles.admin.is_empty() && roles.readonly.is_empty() {
+        return ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "admin endpoints are disabled, no API key configured",
+        )
+        .with_code("admin_disabled")
+        .into_response();
+    }
+
+    match roles.resolve(&request) {
+        Role::Public => ApiError::new(StatusCode::UNAUTHORIZED, "invalid API key")
+            .with_code("unauthorized")
+            .into_response(),
+        Role::Readonly | Role::Admin => next.run(request).await,
     }
 }
 
-async fn list_visitors<T: TimeService>(
+#[derive(Deserialize)]
+struct TimelineQuery {
+    #[serde(default)]
+    bucket: analytics::Bucket,
+    format: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DebugIpRequest {
+    ip: IpAddr,
+    ttl_seconds: u32,
+}
+
+#[derive(Deserialize)]
+struct NoteRequest {
+    note: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Noted {
+    id: i32,
+    note: Option<String>,
+    already: bool,
+}
+
+#[derive(Deserialize)]
+struct PromoteQuery {
+    #[serde(default)]
+    force: bool,
+}
+
+#[derive(Serialize)]
+struct Promoted {
+    id: i32,
+    status: &'static str,
+    already: bool,
+}
+
+#[derive(Deserialize)]
+struct RenameRequest {
+    nick: String,
+}
+
+#[derive(Serialize)]
+struct Renamed {
+    id: i32,
+    old_nick: String,
+    new_nick: String,
+    already: bool,
+}
+
+#[derive(Deserialize)]
+struct DiffQuery {
+    since: String,
+}
+
+#[derive(Serialize)]
+struct Diff {
+    since: DateTime<Utc>,
+    created: Vec<Projection>,
+    modified: Vec<Projection>,
+    deleted: Vec<i64>,
+    counts: DiffCounts,
+    journal_complete: bool,
+}
+
+#[derive(Serialize)]
+struct DiffCounts {
+    created: usize,
+    modified: usize,
+    deleted: usize,
+}
+
+#[derive(Serialize)]
+struct PaymentImport {
+    matched: Vec<String>,
+    already_paid: Vec<String>,
+    unmatched: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ReservationImport {
+    imported: u64,
+}
+
+#[derive(Serialize)]
+struct Stats {
+    visitors: u32,
+    referrals: Vec<ReferralCount>,
+    verify_lookups: u64,
+    retries: BTreeMap<&'static str, retry::Counts>,
+    negative_cache: misses::Counts,
+    stages: BTreeMap<String, i64>,
+    sources: BTreeMap<String, i64>,
+    tasks: BTreeMap<&'static str, supervisor::Health>,
+}
+
+#[derive(sqlx::FromRow, Serialize)]
+struct ReferralCount {
+    code: Option<String>,
+    count: i64,
+}
+
+async fn list_groups<T: TimeService>(
     State(state): State<ApiState<T>>,
-) -> Result<(StatusCode, Json<Vec<db::Visitor>>), ApiError> {
-    let visitors = sqlx::query_as::<_, db::Visitor>(r#"SELECT * FROM visitor ORDER BY id"#)
+) -> Result<(StatusCode, Json<Vec<groups::Group>>), ApiError> {
+    Ok((StatusCode::OK, Json(groups::list(&state.db).await?)))
+}
+
+async fn stats<T: TimeService>(
+    Filtered(filter): Filtered,
+    State(state): State<ApiState<T>>,
+) -> Result<(StatusCode, Json<Stats>), ApiError> {
+    let visitors = filter.count(&state.db).await?;
+    let mut select = QueryBuilder::new("SELECT referral AS code, COUNT(id) AS count FROM visitor");
+    filter.push_where(&mut select);
+    select.push(" GROUP BY referral ORDER BY count DESC, code");
+    let referrals = select
+        .build_query_as::<ReferralCount>()
         .fetch_all(&state.db)
         .await?;
+    let mut select = QueryBuilder::new("SELECT source, COUNT(id) FROM visitor");
+    filter.push_where(&mut select);
+    select.push(" GROUP BY source");
+    let sources = select
+        .build_query_as::<(String, i64)>()
+        .fetch_all(&state.db)
+        .await?
+        .into_iter()
+        .collect();
 
-    Ok((StatusCode::OK, Json(visitors)))
+    Ok((
+        StatusCode::OK,
+        Json(Stats {
+            visitors,
+            referrals,
+            verify_lookups: state.verify_lookups.load(Ordering::Relaxed),
+            retries: state.retries.snapshot(),
+            negative_cache: state.misses.counts(),
+            stages: stages::usage(&state.db).await?,
+            sources,
+            tasks: state.tasks.roster(),
+        }),
+    ))
 }
 
-async fn delete_visitor<T: TimeService>(
+async fn set_note<T: TimeService>(
     Path(id): Path<i32>,
     State(state): State<ApiState<T>>,
-) -> Result<StatusCode, ApiError> {
-    let rows = sqlx::query(r#"DELETE FROM visitor WHERE id = ?"#)
+    Json(request): Json<NoteRequest>,
+) -> Result<(StatusCode, Json<Noted>), ApiError> {
+    let note = validate::note(request.note)?;
+    let mut tx = transition::begin(&state.db, id).await?;
+    let current: Option<String> =
+        sqlx::query_scalar("SELECT admin_note FROM visitor WHERE id = $1")
+            .bind(id)
+            .fetch_one(&mut *tx)
+            .await?;
+    let outcome = transition::Outcome::of(&current, &note);
+    if outcome.is_already() {
+        eprintln!("[note] visitor {} already noted ({})", id, outcome.label());
+        return Ok((
+            StatusCode::OK,
+            Json(Noted {
+                id,
+                note,
+                already: true,
+            }),
+        ));
+    }
+
+    sqlx::query(r#"UPDATE visitor SET admin_note = $1 WHERE id = $2"#)
+        .bind(&note)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    changes::record(
+        &mut tx,
+        id.into(),
+        changes::Change::Updated,
+        state.config.change_journal_length,
+        state.time.clone().now(),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(Noted {
+            id,
+            note,
+            already: false,
+        }),
+    ))
+}
+
+async fn rename_visitor<T: TimeService>(
+    Path(id): Path<i32>,
+    State(state): State<ApiState<T>>,
+    Json(request): Json<RenameRequest>,
+) -> Result<(StatusCode, Json<Renamed>), ApiError> {
+    let nick = validate::nick(&request.nick)?;
+    let mut tx = transition::begin(&state.db, id).await?;
+    let old_nick: String = sqlx::query_scalar("SELECT nick FROM visitor WHERE id = $1")
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+    let outcome = transition::Outcome::of(&old_nick, &nick);
+    if outcome.is_already() {
+        eprintln!(
+            "[rename] visitor {} already {:?} ({})",
+            id,
+            nick,
+            outcome.label()
+        );
+        return Ok((
+            StatusCode::OK,
+            Json(Renamed {
+                id,
+                old_nick,
+                new_nick: nick,
+                already: true,
+            }),
+        ));
+    }
+
+    let taken: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM visitor WHERE nick = $1 COLLATE NOCASE AND id != $2)",
+    )
+    .bind(&nick)
+    .bind(id)
+    .fetch_one(&mut *tx)
+    .await?;
+    if taken {
+        return Err(ApiError::nick_taken());
+    }
+
+    sqlx::query("UPDATE visitor SET nick = $1 WHERE id = $2")
+        .bind(&nick)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    changes::record(
+        &mut tx,
+        id.into(),
+        changes::Change::Updated,
+        state.config.change_journal_length,
+        state.time.clone().now(),
+    )
+    .await?;
+    tx.commit().await?;
+    eprintln!(
+        "[rename] visitor {} from {:?} to {:?} ({})",
+        id,
+        old_nick,
+        nick,
+        outcome.label()
+    );
+
+    Ok((
+        StatusCode::OK,
+        Json(Renamed {
+            id,
+            old_nick,
+            new_nick: nick,
+            already: false,
+        }),
+    ))
+}
+
+// Organizers promote by hand, a freed place is never filled from the waitlist automatically. Going past VISITOR_LIMIT
+// takes `?force=true`, counted inside the transaction so two promotions cannot both take the last place.
+async fn promote_visitor<T: TimeService>(
+    Path(id): Path<i32>,
+    Query(query): Query<PromoteQuery>,
+    State(state): State<ApiState<T>>,
+) -> Result<(StatusCode, Json<Promoted>), ApiError> {
+    let mut tx = transition::begin(&state.db, id).await?;
+    let status: String = sqlx::query_scalar("SELECT status FROM visitor WHERE id = $1")
         .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+    let outcome = transition::Outcome::of(&status.as_str(), &role::CONFIRMED);
+
+    if !outcome.is_already() {
+        if let Some(limit) = state.config.visitor_limit.filter(|_| !query.force) {
+            let confirmed: i64 =
+                sqlx::query_scalar("SELECT COUNT(*) FROM visitor WHERE status = $1")
+                    .bind(role::CONFIRMED)
+                    .fetch_one(&mut *tx)
+                    .await?;
+            if confirmed >= i64::from(limit) {
+                return Err(ApiError::new(StatusCode::CONFLICT, "party is full")
+                    .with_code("party_full")
+                    .with_detail("visitor_limit", limit));
+            }
+        }
+        sqlx::query("UPDATE visitor SET status = $1 WHERE id = $2")
+            .bind(role::CONFIRMED)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        changes::record(
+            &mut tx,
+            id.into(),
+            changes::Change::Updated,
+            state.config.change_journal_length,
+            state.time.clone().now(),
+        )
+        .await?;
+        tx.commit().await?;
+    }
+    eprintln!("[promote] visitor {} ({})", id, outcome.label());
+
+    Ok((
+        StatusCode::OK,
+        Json(Promoted {
+            id,
+            status: role::CONFIRMED,
+            already: outcome.is_already(),
+        }),
+    ))
+}
+
+// Modified and deleted visitors come from the change journal, which only keeps the last CHANGE_JOURNAL_LENGTH changes
+async fn diff<T: TimeService>(
+    Query(query): Query<DiffQuery>,
+    Extension(role): Extension<Role>,
+    State(state): State<ApiState<T>>,
+) -> Result<(StatusCode, Json<Diff>), ApiError> {
+    let since = parse_since(&query.since, state.time.now()).ok_or_else(|| {
+        ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "since must be an RFC 3339 timestamp or a relative duration like 24h",
+        )
+    })?;
+
+    let created = sqlx::query_as::<_, db::Visitor>(
+        r#"SELECT * FROM visitor WHERE created_at > $1 ORDER BY created_at, id"#,
+    )
+    .bind(since)
+    .fetch_all(&state.db)
+    .await?;
+    let modified = sqlx::query_as::<_, db::Visitor>(
+        r#"SELECT * FROM visitor WHERE created_at <= $1 AND id IN
+(SELECT visitor_id FROM visitor_change WHERE kind = 'updated' AND changed_at > $1)
+ORDER BY id"#,
+    )
+    .bind(since)
+    .fetch_all(&state.db)
+    .await?;
+    let deleted: Vec<i64> = sqlx::query_scalar(
+        r#"SELECT DISTINCT visitor_id FROM visitor_change
+WHERE kind = 'deleted' AND changed_at > $1 AND visitor_id NOT IN (SELECT id FROM visitor)
+ORDER BY visitor_id"#,
+    )
+    .bind(since)
+    .fetch_all(&state.db)
+    .await?;
+    // Nothing was trimmed from the journal yet, or it still holds a change from before since
+    let journal_complete: bool = sqlx::query_scalar(
+        r#"SELECT COALESCE(MIN(generation), 1) = 1 OR EXISTS (SELECT 1 FROM visitor_change WHERE changed_at <= $1)
+FROM visitor_change"#,
+    )
+    .bind(since)
+    .fetch_one(&state.db)
+    .await?;
+
+    let project = |visitors: Vec<db::Visitor>| -> Vec<Projection> {
+        visitors
+            .into_iter()
+            .filter(|visitor| role.sees(visitor))
+            .map(|visitor| role.project(visitor))
+            .collect()
+    };
+    let (created, modified) = (project(created), project(modified));
+    Ok((
+        StatusCode::OK,
+        Json(Diff {
+            since,
+            counts: DiffCounts {
+                created: created.len(),
+                modified: modified.len(),
+                deleted: deleted.len(),
+            },
+            created,
+            modified,
+            deleted,
+            journal_complete,
+        }),
+    ))
+}
+
+fn parse_since(value: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Some(timestamp.to_utc());
+    }
+
+    let unit = value.chars().last()?;
+    let amount: i64 = value[..value.len() - unit.len_utf8()].parse().ok()?;
+    let duration = match unit {
+        's' => Duration::try_seconds(amount),
+        'm' => Duration::try_minutes(amount),
+        'h' => Duration::try_hours(amount),
+        'd' => Duration::try_days(amount),
+        _ => None,
+    }?;
+
+    now.checked_sub_signed(duration)
+}
+
+async fn analytics_timeline<T: TimeService>(
+    Query(query): Query<TimelineQuery>,
+    State(state): State<ApiState<T>>,
+) -> Result<Response, ApiError> {
+    let rows = analytics::timeline(&state.db, query.bucket).await?;
+
+    match query.format.as_deref() {
+        None | Some("json") => Ok((StatusCode::OK, Json(rows)).into_response()),
+        Some("csv") => Ok((
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "text/csv; charset=utf-8")],
+            analytics::csv(&rows),
+        )
+            .into_response()),
+        Some(_) => Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "format must be json or csv",
+        )),
+    }
+}
+
+async fn enable_debug_ip<T: TimeService>(
+    State(state): State<ApiState<T>>,
+    Json(request): Json<DebugIpRequest>,
+) -> Result<StatusCode, ApiError> {
+    if request.ttl_seconds > debug::MAX_TTL_SECONDS {
+        return Err(
+            ApiError::new(StatusCode::BAD_REQUEST, "ttl_seconds is too long")
+                .with_detail("field", "ttl_seconds")
+                .with_detail("max", debug::MAX_TTL_SECONDS),
+        );
+    }
+    let now = state.time.now();
+    let until = now + Duration::seconds(request.ttl_seconds.into());
+    if !state.debug_ips.enable(request.ip, until, now) {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            format!("at most {} IPs can be debugged at once", debug::MAX_IPS),
+        ));
+    }
+
+    eprintln!("debug logging enabled for {} until {}", request.ip, until);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn disable_debug_ip<T: TimeService>(
+    Path(ip): Path<IpAddr>,
+    State(state): State<ApiState<T>>,
+) -> StatusCode {
+    match state.debug_ips.disable(ip) {
+        true => {
+            eprintln!("debug logging disabled for {}", ip);
+            StatusCode::NO_CONTENT
+        }
+        false => StatusCode::NOT_FOUND,
+    }
+}
+
+async fn check_consistency<T: TimeService>(
+    State(state): State<ApiState<T>>,
+) -> Result<(StatusCode, Json<Vec<db::Finding>>), ApiError> {
+    Ok((
+        StatusCode::OK,
+        Json(db::check_consistency(&state.db).await?),
+    ))
+}
+
+async fn list_unmatched_payments<T: TimeService>(
+    State(state): State<ApiState<T>>,
+) -> Result<(StatusCode, Json<Vec<db::Visitor>>), ApiError> {
+    let visitors = sqlx::query_as::<_, db::Visitor>(
+        r#"SELECT * FROM visitor WHERE payment_status = 'unpaid' ORDER BY id"#,
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok((StatusCode::OK, Json(visitors)))
+}
+
+async fn import_payments<T: TimeService>(
+    State(state): State<ApiState<T>>,
+    body: String,
+) -> Result<(StatusCode, Json<PaymentImport>), ApiError> {
+    let mut import = PaymentImport {
+        matched: Vec::new(),
+        already_paid: Vec::new(),
+        unmatched: Vec::new(),
+    };
+
+    let mut tx = state.db.begin().await?;
+    for reference in payment_references(&body) {
+        if !payment::is_valid(&reference) {
+            import.unmatched.push(reference);
+            continue;
+        }
+
+        let id: Option<i64> = sqlx::query_scalar(
+            r#"UPDATE visitor SET payment_status = 'paid' WHERE payment_reference = $1 AND payment_status = 'unpaid' RETURNING id"#,
+        )
+        .bind(&reference)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let paid: bool = sqlx::query_scalar(
+            r#"SELECT EXISTS (SELECT 1 FROM visitor WHERE payment_reference = $1 AND payment_status = 'paid')"#,
+        )
+        .bind(&reference)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        match id {
+            None if paid => import.already_paid.push(reference),
+            Some(id) => {
+                changes::record(
+                    &mut tx,
+                    id,
+                    changes::Change::Updated,
+                    state.config.change_journal_length,
+                    state.time.clone().now(),
+                )
+                .await?;
+                import.matched.push(reference)
+            }
+            None => import.unmatched.push(reference),
+        }
+    }
+    tx.commit().await?;
+
+    Ok((StatusCode::OK, Json(import)))
+}
+
+// Bank exports differ, so use the "reference" column if the CSV has a header row and the first column otherwise
+fn payment_references(csv: &str) -> Vec<String> {
+    let mut lines = csv
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .peekable();
+
+    let header = lines.peek().and_then(|line| {
+        line.split([',', ';']).position(|field| {
+            field
+                .trim()
+                .trim_matches('"')
+                .eq_ignore_ascii_case("reference")
+        })
+    });
+    if header.is_some() {
+        lines.next();
+    }
+
+    lines
+        .filter_map(|line| line.split([',', ';']).nth(header.unwrap_or(0)))
+        .map(|field| payment::normalize(field.trim_matches('"')))
+        .filter(|reference| !reference.is_empty())
+        .collect()
+}
+
+async fn list_reservations<T: TimeService>(
+    State(state): State<ApiState<T>>,
+) -> Result<(StatusCode, Json<Vec<reservation::Reservation>>), ApiError> {
+    let reservations = sqlx::query_as::<_, reservation::Reservation>(
+        r#"SELECT nick, email FROM reservation ORDER BY nick_key"#,
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok((StatusCode::OK, Json(reservations)))
+}
+
+async fn import_reservations<T: TimeService>(
+    State(state): State<ApiState<T>>,
+    Json(entries): Json<Vec<reservation::ImportEntry>>,
+) -> Result<(StatusCode, Json<ReservationImport>), ApiError> {
+    let imported = reservation::import(&state.db, entries).await?;
+
+    Ok((StatusCode::OK, Json(ReservationImport { imported })))
+}
+
+async fn delete_reservation<T: TimeService>(
+    Path(nick): Path<String>,
+    State(state): State<ApiState<T>>,
+) -> Result<StatusCode, ApiError> {
+    let rows = sqlx::query(r#"DELETE FROM reservation WHERE nick_key = $1"#)
+        .bind(reservation::key(&nick))
         .execute(&state.db)
         .await?
         .rows_affected();
@@ -52,27 +775,125 @@ async fn delete_visitor<T: TimeService>(
     }
 }
 
+async fn list_rejections<T: TimeService>(
+    State(state): State<ApiState<T>>,
+) -> Result<(StatusCode, Json<Vec<rejections::Rejection>>), ApiError> {
+    let rejections = sqlx::query_as::<_, rejections::Row>(
+        r#"SELECT * FROM rejected_submission ORDER BY id DESC"#,
+    )
+    .fetch_all(&state.db)
+    .await?
+    .into_iter()
+    .map(rejections::Rejection::from)
+    .collect();
+
+    Ok((StatusCode::OK, Json(rejections)))
+}
+
+async fn purge_rejections<T: TimeService>(
+    State(state): State<ApiState<T>>,
+) -> Result<StatusCode, ApiError> {
+    sqlx::query(r#"DELETE FROM rejected_submission"#)
+        .execute(&state.db)
+        .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn delete_visitor<T: TimeService>(
+    Path(id): Path<i32>,
+    State(state): State<ApiState<T>>,
+) -> Result<StatusCode, ApiError> {
+    let mut tx = state.db.begin().await?;
+    let rows = sqlx::query(r#"DELETE FROM visitor WHERE id = ?"#)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    if rows == 0 {
+        return Ok(StatusCode::NOT_FOUND);
+    }
+
+    let now = state.time.now();
+    analytics::record(&mut tx, analytics::Event::RegistrationDeleted, now).await?;
+    changes::record(
+        &mut tx,
+        id.into(),
+        changes::Change::Deleted,
+        state.config.change_journal_length,
+        now,
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
 #[cfg(test)]
 mod test {
-    use std::env;
-
     use axum::body::Body;
     use http_body_util::BodyExt;
     use hyper::{Request, StatusCode};
     use tower::ServiceExt;
 
+    use chrono::{DateTime, Duration};
+
+    use super::AdminKeys;
     use crate::{
+        changes::{self, Change},
+        config::Config,
         testing,
         time::{ConstantTimeService, TimeService},
     };
 
+    fn config() -> Config {
+        Config {
+            admin_keys: AdminKeys::new(vec!["key".into()]),
+            ..Config::default()
+        }
+    }
+
     #[tokio::test]
-    async fn should_require_key_to_list_visitors() {
-        env::set_var("API_KEY", "key");
+    async fn should_disable_admin_without_keys() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let api = crate::api(time.clone(), db.clone(), Config::default());
+
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .header("Authorization", "Bearer key")
+                    .method("GET")
+                    .uri("/admin/visitors")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let body = String::from_utf8(
+            response
+                .into_body()
+                .collect()
+                .await
+                .unwrap()
+                .to_bytes()
+                .to_vec(),
+        )
+        .unwrap();
+        assert_eq!(
+            body,
+            r#"{"error":"admin endpoints are disabled, no API key configured","code":"admin_disabled"}"#
+        );
+    }
 
+    #[tokio::test]
+    async fn should_require_key_to_list_visitors() {
         let time = ConstantTimeService::new();
         let db = testing::database().await;
-        let api = crate::api(time.clone(), db.clone());
+        let api = crate::api(time.clone(), db.clone(), config());
 
         let response = api
             .oneshot(
@@ -91,15 +912,13 @@ mod test {
 
     #[tokio::test]
     async fn can_list_visitors() {
-        env::set_var("API_KEY", "key");
-
         let time = ConstantTimeService::new();
         let db = testing::database().await;
-        let api = crate::api(time.clone(), db.clone());
+        let api = crate::api(time.clone(), db.clone(), config());
 
         testing::insert_visitor(&db, "Groupless", None).await;
 
-        testing::insert_visitor(&db, "With Group", Some("Awesome".into())).await;
+        testing::insert_visitor(&db, "With Group", Some("Awesome")).await;
 
         let response = api
             .oneshot(
@@ -128,7 +947,7 @@ mod test {
         assert_eq!(
             body,
             format!(
-                r#"[{{"id":1,"created_at":"{0}","ip":"127.0.0.1:8080","nick":"Groupless","group":null,"email":null,"extra":null}},{{"id":2,"created_at":"{0}","ip":"127.0.0.1:8080","nick":"With Group","group":"Awesome","email":null,"extra":null}}]"#,
+                r#"[{{"id":1,"created_at":"{0}","ip":"127.0.0.1","user_agent":null,"nick":"Groupless","group":null,"email":null,"confirmed_at":null,"consent_at":null,"extra":null,"referral":null,"admin_note":null,"payment_reference":null,"payment_status":null,"status":"confirmed","source":"web"}},{{"id":2,"created_at":"{0}","ip":"127.0.0.1","user_agent":null,"nick":"With Group","group":"Awesome","email":null,"confirmed_at":null,"consent_at":null,"extra":null,"referral":null,"admin_note":null,"payment_reference":null,"payment_status":null,"status":"confirmed","source":"web"}}]"#,
                 time.now().format("%FT%TZ")
             )
         );
@@ -136,11 +955,9 @@ mod test {
 
     #[tokio::test]
     async fn should_require_key_to_delete_visitor() {
-        env::set_var("API_KEY", "key");
-
         let time = ConstantTimeService::new();
         let db = testing::database().await;
-        let api = crate::api(time.clone(), db.clone());
+        let api = crate::api(time.clone(), db.clone(), config());
 
         let response = api
             .oneshot(
@@ -159,11 +976,9 @@ mod test {
 
     #[tokio::test]
     async fn can_delete_visitor() {
-        env::set_var("API_KEY", "key");
-
         let time = ConstantTimeService::new();
         let db = testing::database().await;
-        let api = crate::api(time.clone(), db.clone());
+        let api = crate::api(time.clone(), db.clone(), config());
 
         testing::insert_visitor(&db, "Groupless", None).await;
 
@@ -188,4 +1003,675 @@ mod test {
 
         assert_eq!(remaining, 0);
     }
+
+    #[tokio::test]
+    async fn can_filter_visitors_by_referral() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let api = crate::api(time.clone(), db.clone(), config());
+
+        testing::insert_visitor(&db, "Flyer Reader", None).await;
+        testing::insert_visitor(&db, "Forum Lurker", None).await;
+        sqlx::query("UPDATE visitor SET referral = 'flyer' WHERE id = 1")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .header("Authorization", "Bearer key")
+                    .method("GET")
+                    .uri("/admin/visitors?referral=flyer")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body: serde_json::Value =
+            serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes())
+                .unwrap();
+        let nicks: Vec<&str> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|visitor| visitor["nick"].as_str().unwrap())
+            .collect();
+        assert_eq!(nicks, vec!["Flyer Reader"]);
+    }
+
+    #[tokio::test]
+    async fn can_show_referral_stats() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let api = crate::api(time.clone(), db.clone(), config());
+
+        for nick in ["One", "Two", "Three", "Four"] {
+            testing::insert_visitor(&db, nick, None).await;
+        }
+        sqlx::query("UPDATE visitor SET referral = 'flyer' WHERE id IN (1, 2)")
+            .execute(&db)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE visitor SET referral = 'forum' WHERE id = 3")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .header("Authorization", "Bearer key")
+                    .method("GET")
+                    .uri("/admin/stats")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = String::from_utf8(
+            response
+                .into_body()
+                .collect()
+                .await
+                .unwrap()
+                .to_bytes()
+                .to_vec(),
+        )
+        .unwrap();
+        let task = format!(
+            r#"{{"running":true,"restarts":0,"last_started_at":{}}}"#,
+            serde_json::to_string(&time.now()).unwrap()
+        );
+        assert_eq!(
+            body,
+            format!(
+                r#"{{"visitors":4,"referrals":[{{"code":"flyer","count":2}},{{"code":null,"count":1}},{{"code":"forum","count":1}}],"verify_lookups":0,"retries":{{}},"negative_cache":{{"hits":0,"misses":0,"dampened":0}},"stages":{{}},"sources":{{"web":4}},"tasks":{{"draft_sweep":{task},"idempotency_sweep":{task},"rejection_sweep":{task},"storage_probe":{task}}}}}"#
+            )
+        );
+    }
+
+    #[test]
+    fn should_parse_since() {
+        let now = DateTime::parse_from_rfc3339("2024-06-01T12:00:00Z")
+            .unwrap()
+            .to_utc();
+
+        assert_eq!(
+            super::parse_since("2024-05-31T10:00:00+02:00", now),
+            Some(now - Duration::hours(28))
+        );
+        assert_eq!(
+            super::parse_since("24h", now),
+            Some(now - Duration::hours(24))
+        );
+        assert_eq!(
+            super::parse_since("30m", now),
+            Some(now - Duration::minutes(30))
+        );
+        assert_eq!(super::parse_since("2d", now), Some(now - Duration::days(2)));
+        assert_eq!(super::parse_since("yesterday", now), None);
+        assert_eq!(super::parse_since("", now), None);
+    }
+
+    #[tokio::test]
+    async fn can_diff_visitors_since_timestamp() {
+        let now = DateTime::parse_from_rfc3339("2024-06-01T12:00:00Z")
+            .unwrap()
+            .to_utc();
+        let time = ConstantTimeService::at(now);
+        let db = testing::database().await;
+        let api = crate::api(time.clone(), db.clone(), config());
+
+        let boundary = now - Duration::hours(24);
+        testing::insert_visitor_at(&db, "Before", boundary - Duration::milliseconds(1)).await;
+        testing::insert_visitor_at(&db, "Exactly", boundary).await;
+        testing::insert_visitor_at(&db, "After", boundary + Duration::milliseconds(1)).await;
+        testing::insert_visitor_at(&db, "Latest", now).await;
+
+        for since in ["24h", "2024-05-31T12:00:00Z"] {
+            let response = api
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .header("Authorization", "Bearer key")
+                        .method("GET")
+                        .uri(format!("/admin/diff?since={}", since))
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let body: serde_json::Value =
+                serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes())
+                    .unwrap();
+            let nicks: Vec<&str> = body["created"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|visitor| visitor["nick"].as_str().unwrap())
+                .collect();
+            assert_eq!(nicks, vec!["After", "Latest"]);
+            assert_eq!(body["counts"]["created"], 2);
+            assert_eq!(body["since"], "2024-05-31T12:00:00Z");
+        }
+    }
+
+    #[tokio::test]
+    async fn can_diff_modified_and_deleted_visitors_with_readonly_key() {
+        let now = DateTime::parse_from_rfc3339("2024-06-01T12:00:00Z")
+            .unwrap()
+            .to_utc();
+        let time = ConstantTimeService::at(now);
+        let db = testing::database().await;
+        let api = crate::api(
+            time.clone(),
+            db.clone(),
+            Config {
+                readonly_keys: AdminKeys::new(vec!["readonly".into()]),
+                ..config()
+            },
+        );
+
+        let boundary = now - Duration::hours(24);
+        let before = boundary - Duration::hours(1);
+        for nick in ["Stale", "Renamed", "Exactly", "Gone"] {
+            testing::insert_visitor_at(&db, nick, before).await;
+        }
+        testing::insert_visitor_at(&db, "New", now).await;
+        let mut conn = db.acquire().await.unwrap();
+        for (id, change, at) in [
+            (1, Change::Updated, boundary - Duration::milliseconds(1)),
+            (2, Change::Updated, boundary + Duration::milliseconds(1)),
+            (2, Change::Updated, now),
+            (3, Change::Updated, boundary),
+            (4, Change::Deleted, now),
+            (5, Change::Updated, now),
+        ] {
+            changes::record(&mut conn, id, change, 100, at)
+                .await
+                .unwrap();
+        }
+        sqlx::query("DELETE FROM visitor WHERE id = 4")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        let diff = |key: Option<&'static str>| {
+            let api = api.clone();
+            async move {
+                let mut request = Request::builder()
+                    .method("GET")
+                    .uri("/admin/diff?since=24h");
+                if let Some(key) = key {
+                    request = request.header("Authorization", format!("Bearer {}", key));
+                }
+                api.oneshot(request.body(Body::empty()).unwrap())
+                    .await
+                    .unwrap()
+            }
+        };
+
+        assert_eq!(diff(None).await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(diff(Some("wrong")).await.status(), StatusCode::UNAUTHORIZED);
+
+        for key in ["key", "readonly"] {
+            let response = diff(Some(key)).await;
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let body: serde_json::Value =
+                serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes())
+                    .unwrap();
+            let nicks = |field: &str| -> Vec<String> {
+                body[field]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|visitor| visitor["nick"].as_str().unwrap().to_owned())
+                    .collect()
+            };
+            assert_eq!(nicks("created"), vec!["New"]);
+            assert_eq!(nicks("modified"), vec!["Renamed"]);
+            assert_eq!(body["deleted"], serde_json::json!([4]));
+            assert_eq!(
+                body["counts"],
+                serde_json::json!({"created": 1, "modified": 1, "deleted": 1})
+            );
+            assert_eq!(body["journal_complete"], true);
+            assert_eq!(body["modified"][0].get("ip").is_some(), key == "key");
+        }
+
+        changes::record(&mut conn, 5, Change::Updated, 1, now)
+            .await
+            .unwrap();
+        let response = diff(Some("key")).await;
+        let body: serde_json::Value =
+            serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes())
+                .unwrap();
+        assert_eq!(body["journal_complete"], false);
+    }
+
+    #[tokio::test]
+    async fn should_reject_invalid_diff_timestamp() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let api = crate::api(time.clone(), db.clone(), config());
+
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .header("Authorization", "Bearer key")
+                    .method("GET")
+                    .uri("/admin/diff?since=yesterday")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn can_set_admin_note() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let api = crate::api(time.clone(), db.clone(), config());
+
+        testing::insert_visitor(&db, "Door Crew Favourite", None).await;
+
+        for (note, expected, already) in [
+            (
+                r#"{"note":" Paid cash, owes 5€ "}"#,
+                Some("Paid cash, owes 5€"),
+                false,
+            ),
+            (
+                r#"{"note":"Paid cash, owes 5€"}"#,
+                Some("Paid cash, owes 5€"),
+                true,
+            ),
+            (r#"{"note":""}"#, None, false),
+            (r#"{"note":null}"#, None, true),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .header("Authorization", "Bearer key")
+                        .header("Content-Type", "application/json")
+                        .method("PUT")
+                        .uri("/admin/visitors/1/note")
+                        .body(Body::from(note))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::OK);
+            let body: serde_json::Value =
+                serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes())
+                    .unwrap();
+            assert_eq!(
+                body,
+                serde_json::json!({"id": 1, "note": expected, "already": already})
+            );
+
+            let stored: Option<String> =
+                sqlx::query_scalar("SELECT admin_note FROM visitor WHERE id = 1")
+                    .fetch_one(&db)
+                    .await
+                    .unwrap();
+            assert_eq!(stored.as_deref(), expected);
+        }
+        let updates: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM visitor_change WHERE kind = 'updated'")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(updates, 2);
+
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .header("Authorization", "Bearer key")
+                    .header("Content-Type", "application/json")
+                    .method("PUT")
+                    .uri("/admin/visitors/2/note")
+                    .body(Body::from(r#"{"note":"Nobody"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn can_rename_visitor() {
+        let db = testing::database().await;
+        let api = crate::api(ConstantTimeService::new(), db.clone(), config());
+        testing::insert_visitor(&db, "Slummy", None).await;
+        testing::insert_visitor(&db, "Truck", None).await;
+
+        let send = |method: &str, uri: &str, etag: Option<&str>, body: Option<&str>| {
+            let mut request = Request::builder()
+                .header("Authorization", "Bearer key")
+                .header("Content-Type", "application/json")
+                .method(method)
+                .uri(uri);
+            if let Some(etag) = etag {
+                request = request.header("If-None-Match", etag);
+            }
+            api.clone().oneshot(
+                request
+                    .body(Body::from(body.unwrap_or_default().to_owned()))
+                    .unwrap(),
+            )
+        };
+
+        let listing = send("GET", "/visitors", None, None).await.unwrap();
+        let etag = listing.headers()["ETag"].to_str().unwrap().to_owned();
+        let generation = listing.headers()["X-Generation"]
+            .to_str()
+            .unwrap()
+            .to_owned();
+
+        for (id, nick, status) in [
+            (2, "slummy", StatusCode::CONFLICT),
+            (2, "\u{200B}truck", StatusCode::BAD_REQUEST),
+            (3, "Nobody", StatusCode::NOT_FOUND),
+        ] {
+            let body = serde_json::json!({ "nick": nick }).to_string();
+            let uri = format!("/admin/visitors/{}/nick", id);
+            let response = send("PUT", &uri, None, Some(&body)).await.unwrap();
+            assert_eq!(response.status(), status, "{}", nick);
+        }
+
+        let response = send(
+            "PUT",
+            "/admin/visitors/2/nick",
+            None,
+            Some(r#"{"nick":" Truck  Driver "}"#),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            &body[..],
+            br#"{"id":2,"old_nick":"Truck","new_nick":"Truck Driver","already":false}"#
+        );
+
+        let listing = send("GET", "/visitors", Some(&etag), None).await.unwrap();
+        assert_eq!(listing.status(), StatusCode::OK, "cached list is stale");
+        let body = listing.into_body().collect().await.unwrap().to_bytes();
+        assert!(String::from_utf8_lossy(&body).contains("Truck Driver"));
+
+        let changes = send(
+            "GET",
+            &format!("/visitors/changes?since={}", generation),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        let changes: serde_json::Value =
+            serde_json::from_slice(&changes.into_body().collect().await.unwrap().to_bytes())
+                .unwrap();
+        assert_eq!(changes["updated"][0]["nick"], "Truck Driver");
+    }
+
+    #[tokio::test]
+    async fn should_never_expose_admin_note_publicly() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let api = crate::api(time.clone(), db.clone(), config());
+
+        testing::insert_visitor(&db, "Secretive", Some("Hidden")).await;
+        sqlx::query("UPDATE visitor SET admin_note = 'needs accessible seating' WHERE id = 1")
+            .execute(&db)
+            .await
+            .unwrap();
+
+        for uri in ["/visitors", "/visitors?limit=1", "/status"] {
+            let response = api
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("GET")
+                        .uri(uri)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let body = String::from_utf8(
+                response
+                    .into_body()
+                    .collect()
+                    .await
+                    .unwrap()
+                    .to_bytes()
+                    .to_vec(),
+            )
+            .unwrap();
+            assert!(!body.contains("accessible seating"), "{} leaked note", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn can_check_consistency() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let api = crate::api(time.clone(), db.clone(), config());
+
+        testing::insert_visitor(&db, "Fairlight", None).await;
+        testing::insert_visitor(&db, "fairlight ", None).await;
+
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .header("Authorization", "Bearer key")
+                    .method("POST")
+                    .uri("/admin/consistency-check")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = String::from_utf8(
+            response
+                .into_body()
+                .collect()
+                .await
+                .unwrap()
+                .to_bytes()
+                .to_vec(),
+        )
+        .unwrap();
+        assert_eq!(
+            body,
+            r#"[{"kind":"duplicate_nick","nick":"fairlight","visitor_ids":[1,2]}]"#
+        );
+    }
+
+    #[tokio::test]
+    async fn can_list_group_variants() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let api = crate::api(time.clone(), db.clone(), config());
+
+        testing::insert_visitor(&db, "One", Some("Fairlight ")).await;
+        testing::insert_visitor(&db, "Two", Some("fairlight")).await;
+
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .header("Authorization", "Bearer key")
+                    .method("GET")
+                    .uri("/admin/groups")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = String::from_utf8(
+            response
+                .into_body()
+                .collect()
+                .await
+                .unwrap()
+                .to_bytes()
+                .to_vec(),
+        )
+        .unwrap();
+        assert_eq!(
+            body,
+            r#"[{"name":"Fairlight","count":2,"variants":[{"name":"Fairlight ","count":1},{"name":"fairlight","count":1}]}]"#
+        );
+    }
+
+    #[tokio::test]
+    async fn can_import_payments() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let api = crate::api(time.clone(), db.clone(), config());
+
+        testing::insert_visitor(&db, "Paid", None).await;
+        testing::insert_visitor(&db, "Unpaid", None).await;
+        for (id, reference) in [(1, "10016"), (2, "10023")] {
+            sqlx::query(
+                "UPDATE visitor SET payment_reference = $1, payment_status = 'unpaid' WHERE id = $2",
+            )
+            .bind(reference)
+            .bind(id)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+
+        let response = api
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .header("Authorization", "Bearer key")
+                    .header("Content-Type", "text/csv")
+                    .method("POST")
+                    .uri("/admin/payments/import")
+                    .body(Body::from(
+                        "date;amount;reference\n2024-01-02;20.00;1001 6\n2024-01-02;20.00;1001 7\n",
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = String::from_utf8(
+            response
+                .into_body()
+                .collect()
+                .await
+                .unwrap()
+                .to_bytes()
+                .to_vec(),
+        )
+        .unwrap();
+        assert_eq!(
+            body,
+            r#"{"matched":["10016"],"already_paid":[],"unmatched":["10017"]}"#
+        );
+
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .header("Authorization", "Bearer key")
+                    .method("GET")
+                    .uri("/admin/payments/unmatched")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let body = String::from_utf8(
+            response
+                .into_body()
+                .collect()
+                .await
+                .unwrap()
+                .to_bytes()
+                .to_vec(),
+        )
+        .unwrap();
+        assert!(body.contains(r#""nick":"Unpaid""#));
+        assert!(!body.contains(r#""nick":"Paid""#));
+    }
+
+    #[test]
+    fn should_find_reference_column() {
+        assert_eq!(
+            super::payment_references("10016\n\"1002 3\",20.00\n"),
+            vec!["10016", "10023"]
+        );
+        assert_eq!(
+            super::payment_references("Amount,Reference\n20.00,rf18 5390 0754 7034\n"),
+            vec!["RF18539007547034"]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_record_deletions_in_timeline() {
+        let time = ConstantTimeService::at("2024-03-01T18:30:00Z".parse().unwrap());
+        let db = testing::database().await;
+        let api = crate::api(time.clone(), db.clone(), config());
+
+        testing::insert_visitor(&db, "Leaving", None).await;
+        for (method, uri) in [
+            ("DELETE", "/admin/visitors/1"),
+            ("GET", "/admin/analytics/timeline?bucket=day&format=csv"),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .header("Authorization", "Bearer key")
+                        .method(method)
+                        .uri(uri)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert!(response.status().is_success());
+
+            if method == "GET" {
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                assert_eq!(
+                    &body[..],
+                    b"bucket,created,deleted\n2024-03-01T00:00:00+00:00,0,1\n"
+                );
+            }
+        }
+    }
 }
@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use serde::{Deserialize, Serialize, Serializer};
+use sqlx::{SqliteConnection, SqlitePool};
+
+const COARSE_BELOW: u64 = 5;
+
+// Deliberately carries nothing about the visitor, only what happened
+#[derive(Clone, Copy)]
+pub enum Event {
+    RegistrationCreated,
+    RegistrationDeleted,
+}
+
+impl Event {
+    fn kind(self) -> &'static str {
+        match self {
+            Self::RegistrationCreated => "registration_created",
+            Self::RegistrationDeleted => "registration_deleted",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Bucket {
+    #[default]
+    Hour,
+    Day,
+}
+
+impl Bucket {
+    fn width(self) -> TimeDelta {
+        match self {
+            Bucket::Hour => TimeDelta::hours(1),
+            Bucket::Day => TimeDelta::days(1),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Row {
+    pub bucket: DateTime<Utc>,
+    pub created: u64,
+    pub deleted: u64,
+}
+
+pub async fn record(
+    conn: &mut SqliteConnection,
+    event: Event,
+    at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(r#"INSERT INTO analytics_event (kind, occurred_at) VALUES ($1, $2)"#)
+        .bind(event.kind())
+        .bind(at)
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+// A count as shown to the public, small ones only as "<5" in privacy mode
+#[derive(Debug, PartialEq)]
+pub enum Count {
+    Exact(u64),
+    Below(u64),
+}
+
+impl Serialize for Count {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Count::Exact(count) => serializer.serialize_u64(*count),
+            Count::Below(limit) => serializer.collect_str(&format_args!("<{}", limit)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PublicRow {
+    pub bucket: DateTime<Utc>,
+    pub created: Count,
+    pub deleted: Count,
+}
+
+// Every aggregate shown to the public goes through here. Small counts in narrow buckets would let anyone hold them
+// against the public list and tell when someone registered, so privacy mode coarsens both.
+#[derive(Clone, Copy, Default)]
+pub struct Privacy {
+    pub enabled: bool,
+}
+
+impl Privacy {
+    pub fn count(self, count: u64) -> Count {
+        match self.enabled && count < COARSE_BELOW {
+            true => Count::Below(COARSE_BELOW),
+            false => Count::Exact(count),
+        }
+    }
+
+    fn width(self, bucket: Bucket) -> TimeDelta {
+        match self.enabled {
+            true => bucket.width().max(TimeDelta::hours(6)),
+            false => bucket.width(),
+        }
+    }
+
+    pub async fn timeline(
+        self,
+        db: &SqlitePool,
+        bucket: Bucket,
+    ) -> Result<Vec<PublicRow>, sqlx::Error> {
+        Ok(tally(db, self.width(bucket))
+            .await?
+            .into_iter()
+            .map(|row| PublicRow {
+                bucket: row.bucket,
+                created: self.count(row.created),
+                deleted: self.count(row.deleted),
+            })
+            .collect())
+    }
+}
+
+pub async fn timeline(db: &SqlitePool, bucket: Bucket) -> Result<Vec<Row>, sqlx::Error> {
+    tally(db, bucket.width()).await
+}
+
+async fn tally(db: &SqlitePool, width: TimeDelta) -> Result<Vec<Row>, sqlx::Error> {
+    let events = sqlx::query_as::<_, (String, DateTime<Utc>)>(
+        r#"SELECT kind, occurred_at FROM analytics_event ORDER BY id"#,
+    )
+    .fetch_all(db)
+    .await?;
+
+    let mut rows = BTreeMap::<DateTime<Utc>, Row>::new();
+    for (kind, occurred_at) in events {
+        let start = occurred_at.duration_trunc(width).unwrap_or(occurred_at);
+        let row = rows.entry(start).or_insert(Row {
+            bucket: start,
+            created: 0,
+            deleted: 0,
+        });
+        match kind.as_str() {
+            "registration_created" => row.created += 1,
+            "registration_deleted" => row.deleted += 1,
+            _ => {}
+        }
+    }
+
+    Ok(rows.into_values().collect())
+}
+
+pub fn csv(rows: &[Row]) -> String {
+    let mut csv = String::from("bucket,created,deleted\n");
+    for row in rows {
+        csv.push_str(&format!(
+            "{},{},{}\n",
+            row.bucket.to_rfc3339(),
+            row.created,
+            row.deleted
+        ));
+    }
+    csv
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::testing;
+
+    #[tokio::test]
+    async fn should_have_no_personal_columns() {
+        let db = testing::database().await;
+        let columns: Vec<String> = sqlx::query_scalar(
+            "SELECT name FROM pragma_table_info('analytics_event') ORDER BY cid",
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap();
+
+        assert_eq!(columns, vec!["id", "kind", "occurred_at"]);
+    }
+
+    #[tokio::test]
+    async fn should_bucket_events() {
+        let db = testing::database().await;
+        let mut conn = db.acquire().await.unwrap();
+        for (event, minute) in [
+            (Event::RegistrationCreated, 5),
+            (Event::RegistrationCreated, 55),
+            (Event::RegistrationDeleted, 65),
+        ] {
+            let at =
+                Utc.with_ymd_and_hms(2024, 3, 1, 18, 0, 0).unwrap() + TimeDelta::minutes(minute);
+            record(&mut conn, event, at).await.unwrap();
+        }
+
+        let rows = timeline(&db, Bucket::Hour).await.unwrap();
+        assert_eq!(
+            csv(&rows),
+            "bucket,created,deleted\n2024-03-01T18:00:00+00:00,2,0\n2024-03-01T19:00:00+00:00,0,1\n"
+        );
+
+        let rows = timeline(&db, Bucket::Day).await.unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!((rows[0].created, rows[0].deleted), (2, 1));
+    }
+
+    #[tokio::test]
+    async fn should_coarsen_public_timeline() {
+        let db = testing::database().await;
+        let mut conn = db.acquire().await.unwrap();
+        let night = Utc.with_ymd_and_hms(2024, 3, 1, 1, 0, 0).unwrap();
+        for minutes in [5, 150] {
+            record(
+                &mut conn,
+                Event::RegistrationCreated,
+                night + TimeDelta::minutes(minutes),
+            )
+            .await
+            .unwrap();
+        }
+        for minutes in 0..6 {
+            let noon = night + TimeDelta::hours(11) + TimeDelta::minutes(minutes);
+            record(&mut conn, Event::RegistrationCreated, noon)
+                .await
+                .unwrap();
+        }
+
+        let private = Privacy { enabled: true };
+        assert_eq!(
+            serde_json::to_string(&private.timeline(&db, Bucket::Hour).await.unwrap()).unwrap(),
+            r#"[{"bucket":"2024-03-01T00:00:00Z","created":"<5","deleted":"<5"},{"bucket":"2024-03-01T12:00:00Z","created":6,"deleted":"<5"}]"#
+        );
+
+        let open = Privacy::default()
+            .timeline(&db, Bucket::Hour)
+            .await
+            .unwrap();
+        assert_eq!(open.len(), 3);
+        assert_eq!(open[0].created, Count::Exact(1));
+        assert_eq!(timeline(&db, Bucket::Hour).await.unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,270 @@
+use std::{collections::BTreeMap, net::SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, StatusCode},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::ApiError, fields, json::Json, misses, policy, stages, time::TimeService, validate,
+    ApiState, Registration,
+};
+
+pub const MAX_MEMBERS: usize = 20;
+
+#[derive(Deserialize)]
+pub struct BatchRequest {
+    group: Option<String>,
+    members: Vec<Member>,
+    captcha_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct Member {
+    nick: String,
+    email: Option<String>,
+    consent: Option<bool>,
+    extra: Option<serde_json::Value>,
+    fields: Option<BTreeMap<String, serde_json::Value>>,
+}
+
+#[derive(Serialize)]
+pub struct BatchRegistration {
+    members: Vec<Registration>,
+}
+
+// All members or none, and an error names the member it is about by position
+pub async fn register<T: TimeService>(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    State(state): State<ApiState<T>>,
+    Json(request): Json<BatchRequest>,
+) -> Result<(StatusCode, Json<BatchRegistration>), ApiError> {
+    if request.members.is_empty() || request.members.len() > MAX_MEMBERS {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("a batch has 1 to {} members", MAX_MEMBERS),
+        )
+        .with_detail("field", "members")
+        .with_detail("max", MAX_MEMBERS));
+    }
+
+    let now = state.time.clone().now();
+    crate::ensure_open(&state, now).await?;
+
+    let group = validate::group(request.group, state.config.group_max_length)?;
+    let group = validate::allowed_group(group, state.config.allowed_groups.as_deref())?;
+    let mut members = Vec::with_capacity(request.members.len());
+    for (index, member) in request.members.into_iter().enumerate() {
+        let validated = (|| {
+            let nick = validate::nick(&member.nick)?;
+            state.config.nick_blocklist.check(&nick)?;
+            validate::consent(member.email.as_deref(), member.consent)?;
+            let email = validate::email(member.email)?;
+            let extra = validate::extra(member.extra)?;
+            let answers = state.config.custom_fields.validate(member.fields)?;
+            Ok::<_, ApiError>((
+                policy::ValidatedRegistration {
+                    nick,
+                    group: group.clone(),
+                    email,
+                    extra,
+                    referral: None,
+                    payment_exempt: false,
+                },
+                answers,
+            ))
+        })();
+        members.push(validated.map_err(|error| error.with_detail("member", index))?);
+    }
+
+    let admission = match state.config.registration_opens_at.filter(|&at| now < at) {
+        Some(opens_at) => Some(
+            state
+                .config
+                .stages
+                .admit(
+                    &state.db,
+                    &stages::Candidate {
+                        referral: None,
+                        group: group.as_deref(),
+                        invite_token: None,
+                    },
+                    now,
+                    opens_at,
+                )
+                .await?,
+        ),
+        None => None,
+    };
+
+    let client = crate::Client::of(&state.config, &headers, addr);
+    if let Some(captcha) = &state.config.captcha {
+        crate::verify_captcha(captcha, request.captcha_token.as_deref(), &client.ip).await?;
+    }
+
+    let mut tx = state.db.begin().await?;
+    crate::ensure_ip_allowance(&mut tx, &state.config, &client.ip, members.len()).await?;
+    let mut registrations = Vec::with_capacity(members.len());
+    for (index, (member, answers)) in members.into_iter().enumerate() {
+        let context = policy::PolicyContext {
+            now,
+            ip: Some(client.ip.clone()),
+        };
+        let stored = async {
+            let member = state.config.policies.evaluate(member, &context)?;
+            let stored = crate::store(&mut tx, &state, member, &client, None, now).await?;
+            fields::store(&mut tx, stored.visitor.id.into(), &answers).await?;
+            Ok::<_, ApiError>(stored)
+        }
+        .await
+        .map_err(|error| error.with_detail("member", index))?;
+        if let Some(admission) = admission.clone() {
+            admission
+                .record(&mut tx, stored.visitor.id.into(), now)
+                .await?;
+        }
+        registrations.push(stored);
+    }
+    tx.commit().await?;
+
+    for registration in &registrations {
+        state
+            .misses
+            .forget(misses::Kind::Visitor, &registration.visitor.id.to_string());
+    }
+    eprintln!(
+        "[batch] registered {} visitors from {}",
+        registrations.len(),
+        client.ip
+    );
+
+    Ok((
+        StatusCode::CREATED,
+        Json(BatchRegistration {
+            members: registrations,
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::Body, Router};
+    use http_body_util::BodyExt;
+    use hyper::Request;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{config::Config, testing, time::ConstantTimeService};
+
+    async fn send(api: &Router, uri: &str, body: Value) -> (StatusCode, Value) {
+        let response = api
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .extension(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 4711))))
+                    .header("Content-Type", "application/json")
+                    .method("POST")
+                    .uri(uri)
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    async fn nicks(db: &sqlx::SqlitePool) -> Vec<String> {
+        sqlx::query_scalar(r#"SELECT nick FROM visitor ORDER BY id"#)
+            .fetch_all(db)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_register_whole_batch() {
+        let db = testing::database().await;
+        let api = crate::api(ConstantTimeService::new(), db.clone(), Config::default());
+
+        let (status, body) = send(
+            &api,
+            "/register/batch",
+            json!({
+                "group": "Fairlight",
+                "members": [{"nick": "Razor"}, {"nick": "Blitter", "email": "blitter@example.com", "consent": true}]
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let members = body["members"].as_array().unwrap();
+        assert_eq!(members.len(), 2);
+        assert_eq!(members[1]["id"], 2);
+        assert_eq!(members[1]["group"], "Fairlight");
+        assert!(members[1]["edit_token"].is_string());
+        assert_eq!(nicks(&db).await, ["Razor", "Blitter"]);
+        let sources: Vec<String> = sqlx::query_scalar("SELECT DISTINCT source FROM visitor")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+        assert_eq!(sources, [crate::db::WEB]);
+
+        // The batch was one request, two more single registrations fit in the default burst of 3
+        for nick in ["Copper", "Sprite"] {
+            let (status, _) = send(&api, "/register", json!({ "nick": nick })).await;
+            assert_eq!(status, StatusCode::CREATED, "{}", nick);
+        }
+        let (status, _) = send(&api, "/register", json!({"nick": "Raster"})).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn should_roll_back_batch_with_duplicate() {
+        let db = testing::database().await;
+        testing::insert_visitor(&db, "Blitter", None).await;
+        let api = crate::api(ConstantTimeService::new(), db.clone(), Config::default());
+
+        let (status, body) = send(
+            &api,
+            "/register/batch",
+            json!({"members": [{"nick": "Razor"}, {"nick": "blitter"}, {"nick": "Copper"}]}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(
+            body,
+            json!({"error": "nick is already registered", "code": "nick_taken", "member": 1})
+        );
+
+        let (status, body) = send(
+            &api,
+            "/register/batch",
+            json!({"members": [{"nick": "Razor"}, {"nick": "Copper", "email": "copper", "consent": true}]}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["field"], "email");
+        assert_eq!(body["member"], 1);
+        assert_eq!(nicks(&db).await, ["Blitter"]);
+    }
+
+    #[tokio::test]
+    async fn should_cap_batch_size() {
+        let db = testing::database().await;
+        let api = crate::api(ConstantTimeService::new(), db.clone(), Config::default());
+
+        let members: Vec<Value> = (0..=MAX_MEMBERS)
+            .map(|i| json!({ "nick": format!("Member {}", i) }))
+            .collect();
+        for members in [&members[..0], &members[..]] {
+            let (status, body) = send(&api, "/register/batch", json!({ "members": members })).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(body["field"], "members");
+            assert_eq!(body["max"], MAX_MEMBERS);
+        }
+        assert!(nicks(&db).await.is_empty());
+    }
+}
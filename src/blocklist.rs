@@ -0,0 +1,115 @@
+use std::{env, fs, io, path::Path};
+
+use axum::http::StatusCode;
+
+use crate::error::ApiError;
+
+// Nicks nobody may register, one pattern per line where "*" stands for any run of characters
+#[derive(Clone, Debug, Default)]
+pub struct NickBlocklist(Vec<String>);
+
+impl NickBlocklist {
+    pub fn from_env() -> Self {
+        let Ok(path) = env::var("NICK_BLOCKLIST") else {
+            return Self::default();
+        };
+        match Self::load(Path::new(&path)) {
+            Ok(blocklist) => blocklist,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                eprintln!(
+                    "NICK_BLOCKLIST {} does not exist, not filtering nicks",
+                    path
+                );
+                Self::default()
+            }
+            Err(error) => panic!("bad NICK_BLOCKLIST {}: {}", path, error),
+        }
+    }
+
+    // Blank lines and lines starting with # are skipped, a pattern of nothing but wildcards would block everyone
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut patterns = Vec::new();
+        for (index, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let pattern = line.trim();
+            if pattern.is_empty() || pattern.starts_with('#') {
+                continue;
+            }
+            if pattern.chars().all(|c| c == '*') {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {} matches every nick", index + 1),
+                ));
+            }
+            patterns.push(pattern.to_lowercase());
+        }
+        Ok(Self(patterns))
+    }
+
+    pub fn check(&self, nick: &str) -> Result<(), ApiError> {
+        let nick = nick.to_lowercase();
+        match self.0.iter().any(|pattern| matches(pattern, &nick)) {
+            true => Err(ApiError::new(StatusCode::BAD_REQUEST, "nick not allowed")
+                .with_code("nick_not_allowed")
+                .with_detail("field", "nick")),
+            false => Ok(()),
+        }
+    }
+}
+
+fn matches(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn blocklist(contents: &str) -> io::Result<NickBlocklist> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("blocklist.txt");
+        fs::write(&path, contents).unwrap();
+        NickBlocklist::load(&path)
+    }
+
+    #[test]
+    fn should_block_matching_nicks() {
+        let blocklist = blocklist("# organizers\nOrga\n\n  *admin*\nmod*\n*bot\n").unwrap();
+        for nick in [
+            "orga",
+            "ORGA",
+            "Admin",
+            "The Admin Team",
+            "moderator",
+            "Razorbot",
+        ] {
+            assert!(blocklist.check(nick).is_err(), "{}", nick);
+        }
+        for nick in ["Organizer", "Razor", "Demod", "Bottom", "adm in"] {
+            assert!(blocklist.check(nick).is_ok(), "{}", nick);
+        }
+        assert!(matches("a*b*c", "abbc"));
+        assert!(!matches("a*b*c", "acb"));
+    }
+
+    #[test]
+    fn should_refuse_malformed_files() {
+        assert!(blocklist("Orga\n**\n").is_err());
+        assert!(NickBlocklist::load(Path::new("/nonexistent/blocklist.txt")).is_err());
+        assert!(NickBlocklist::default().check("Anyone").is_ok());
+    }
+}
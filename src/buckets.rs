@@ -0,0 +1,84 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::validate;
+
+const OTHER: &str = "#";
+
+#[derive(Serialize)]
+pub struct Buckets<T> {
+    pub total: usize,
+    pub counts: BTreeMap<String, usize>,
+    pub buckets: BTreeMap<String, Vec<T>>,
+}
+
+pub fn bucket<T>(items: Vec<T>, nick: impl Fn(&T) -> &str) -> Buckets<T> {
+    let total = items.len();
+
+    let mut buckets = BTreeMap::<String, Vec<T>>::new();
+    for item in items {
+        buckets.entry(label(nick(&item))).or_default().push(item);
+    }
+    for items in buckets.values_mut() {
+        items.sort_by_cached_key(|item| {
+            let nick = nick(item);
+            (nick.to_lowercase(), nick.to_owned())
+        });
+    }
+
+    Buckets {
+        total,
+        counts: buckets
+            .iter()
+            .map(|(label, items)| (label.clone(), items.len()))
+            .collect(),
+        buckets,
+    }
+}
+
+fn label(nick: &str) -> String {
+    let normalized: String = validate::normalize(nick)
+        .unwrap_or_default()
+        .nfc()
+        .collect();
+
+    match normalized.graphemes(true).next() {
+        Some(grapheme) if grapheme.chars().next().is_some_and(char::is_alphabetic) => {
+            grapheme.to_uppercase()
+        }
+        _ => OTHER.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_label_by_first_grapheme() {
+        assert_eq!(label("slummy"), "S");
+        assert_eq!(label("Slummy"), "S");
+        assert_eq!(label("  ärsyttävä"), "Ä");
+        assert_eq!(label("a\u{308}rsyttävä"), "Ä");
+        assert_eq!(label("1337 h4x0r"), "#");
+        assert_eq!(label("🦀 Crab"), "#");
+        assert_eq!(label("_underscore"), "#");
+    }
+
+    #[test]
+    fn should_sort_within_buckets() {
+        let buckets = bucket(
+            vec!["beta", "Alpha", "42", "alpha", "Bravo", "🦀", "apple"],
+            |nick| nick,
+        );
+
+        assert_eq!(buckets.total, 7);
+        assert_eq!(buckets.buckets["#"], vec!["42", "🦀"]);
+        assert_eq!(buckets.buckets["A"], vec!["Alpha", "alpha", "apple"]);
+        assert_eq!(buckets.buckets["B"], vec!["beta", "Bravo"]);
+        assert_eq!(buckets.counts["A"], 3);
+    }
+}
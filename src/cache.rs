@@ -0,0 +1,304 @@
+use axum::{
+    body::{self, Body},
+    extract::{Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, NaiveDateTime, Utc};
+
+const NO_STORE: HeaderValue = HeaderValue::from_static("no-store");
+const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+#[derive(Clone)]
+pub struct Policies {
+    pub lists: HeaderValue,
+    pub status: HeaderValue,
+}
+
+impl Default for Policies {
+    fn default() -> Self {
+        Self {
+            lists: HeaderValue::from_static("no-cache"),
+            status: HeaderValue::from_static("max-age=5, stale-while-revalidate=30"),
+        }
+    }
+}
+
+pub async fn control(State(policies): State<Policies>, request: Request, next: Next) -> Response {
+    let (policy, etag) = match request.uri().path() {
+        "/visitors" | "/visitors/buckets" | "/groups" => (policies.lists, true),
+        "/status" => (policies.status, false),
+        _ => (NO_STORE, false),
+    };
+    let cacheable = request.method() == Method::GET;
+    let if_none_match = request.headers().get(header::IF_NONE_MATCH).cloned();
+    let if_modified_since = request
+        .headers()
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(parse_http_date);
+
+    let mut response = next.run(request).await;
+    if !cacheable || response.status() != StatusCode::OK {
+        response
+            .headers_mut()
+            .insert(header::CACHE_CONTROL, NO_STORE);
+        return response;
+    }
+
+    if etag {
+        let (mut parts, body) = response.into_parts();
+        let Ok(bytes) = body::to_bytes(body, usize::MAX).await else {
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        };
+
+        let tag = HeaderValue::try_from(format!("\"{:016x}\"", fnv1a(&bytes)))
+            .expect("hex digits are a valid header value");
+        // A tag wins over a date, which only counts to the second as the header carries it
+        let unmodified = match &if_none_match {
+            Some(value) => matches(value, &tag),
+            None => parts
+                .headers
+                .get(header::LAST_MODIFIED)
+                .and_then(parse_http_date)
+                .zip(if_modified_since)
+                .is_some_and(|(modified, since)| modified <= since),
+        };
+        response = match unmodified {
+            true => {
+                parts.status = StatusCode::NOT_MODIFIED;
+                parts.headers.remove(header::CONTENT_TYPE);
+                Response::from_parts(parts, Body::empty())
+            }
+            false => Response::from_parts(parts, Body::from(bytes)),
+        };
+        response.headers_mut().insert(header::ETAG, tag);
+    }
+
+    response.headers_mut().insert(header::CACHE_CONTROL, policy);
+    response
+}
+
+pub fn http_date(at: DateTime<Utc>) -> HeaderValue {
+    HeaderValue::try_from(at.format(HTTP_DATE).to_string())
+        .expect("a formatted date is a valid header value")
+}
+
+fn parse_http_date(value: &HeaderValue) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(value.to_str().ok()?, HTTP_DATE)
+        .ok()
+        .map(|at| at.and_utc())
+}
+
+// If-None-Match compares weakly, so a tag a compressing proxy marked W/ still matches, as does any of a list or *
+fn matches(if_none_match: &HeaderValue, tag: &HeaderValue) -> bool {
+    let (Ok(candidates), Ok(tag)) = (if_none_match.to_str(), tag.to_str()) else {
+        return false;
+    };
+    candidates
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == tag)
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use axum::{body::Body, extract::ConnectInfo};
+    use chrono::{Duration, TimeZone, Utc};
+    use hyper::{header, Request, StatusCode};
+    use tower::ServiceExt;
+
+    use crate::{admin::AdminKeys, config::Config, testing, time::ConstantTimeService};
+
+    async fn cache_control(uri: &str, config: Config) -> Option<String> {
+        let db = testing::database().await;
+        let api = crate::api(ConstantTimeService::new(), db, config);
+
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri(uri)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        response
+            .headers()
+            .get(header::CACHE_CONTROL)
+            .map(|value| value.to_str().unwrap().to_owned())
+    }
+
+    #[tokio::test]
+    async fn should_set_policy_per_route() {
+        for (uri, expected) in [
+            ("/visitors", "no-cache"),
+            ("/groups", "no-cache"),
+            ("/status", "max-age=5, stale-while-revalidate=30"),
+            ("/admin/visitors", "no-store"),
+            ("/visitors?limit=abc", "no-store"),
+            ("/nonexistent", "no-store"),
+        ] {
+            assert_eq!(
+                cache_control(uri, Config::default()).await.as_deref(),
+                Some(expected),
+                "{}",
+                uri
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn should_allow_overriding_policies() {
+        let mut config = Config::default();
+        config.cache_policies.lists = "public, max-age=10".parse().unwrap();
+
+        assert_eq!(
+            cache_control("/visitors", config).await.as_deref(),
+            Some("public, max-age=10")
+        );
+    }
+
+    #[tokio::test]
+    async fn should_revalidate_with_etag() {
+        let db = testing::database().await;
+        let api = crate::api(ConstantTimeService::new(), db.clone(), Config::default());
+
+        let request = |etag: Option<&str>| {
+            let mut builder = Request::builder().method("GET").uri("/visitors");
+            if let Some(etag) = etag {
+                builder = builder.header(header::IF_NONE_MATCH, etag);
+            }
+            builder.body(Body::empty()).unwrap()
+        };
+
+        let response = api.clone().oneshot(request(None)).await.unwrap();
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_owned();
+
+        let response = api.clone().oneshot(request(Some(&etag))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-cache");
+
+        testing::insert_visitor(&db, "Newcomer", None).await;
+        let response = api.oneshot(request(Some(&etag))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(response.headers()[header::ETAG], etag.as_str());
+    }
+
+    #[tokio::test]
+    async fn should_revalidate_after_admin_deletion() {
+        let db = testing::database().await;
+        testing::insert_visitor(&db, "Razor", None).await;
+        let api = crate::api(
+            ConstantTimeService::new(),
+            db,
+            Config {
+                admin_keys: AdminKeys::new(vec!["key".into()]),
+                ..Config::default()
+            },
+        );
+        let send = |method: &str, uri: &str, if_none_match: &str| {
+            api.clone().oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri(uri)
+                    .header("Authorization", "Bearer key")
+                    .header(header::IF_NONE_MATCH, if_none_match)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+
+        let response = send("GET", "/visitors", "").await.unwrap();
+        let etag = response.headers()[header::ETAG]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        for if_none_match in [
+            format!("W/{}", etag),
+            format!("\"0\", {}", etag),
+            "*".into(),
+        ] {
+            let response = send("GET", "/visitors", &if_none_match).await.unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::NOT_MODIFIED,
+                "{}",
+                if_none_match
+            );
+        }
+
+        let response = send("DELETE", "/admin/visitors/1", "").await.unwrap();
+        assert!(response.status().is_success());
+        let response = send("GET", "/visitors", &etag).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_ne!(response.headers()[header::ETAG], etag.as_str());
+    }
+
+    #[tokio::test]
+    async fn should_revalidate_with_last_modified() {
+        let db = testing::database().await;
+        let at = Utc.with_ymd_and_hms(2024, 3, 1, 18, 0, 0).unwrap();
+        let api = |time| crate::api(ConstantTimeService::at(time), db.clone(), Config::default());
+        let request = |if_modified_since: &str| {
+            Request::get("/visitors")
+                .extension(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 4711))))
+                .header(header::IF_MODIFIED_SINCE, if_modified_since)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let register = |time, nick: &str| {
+            api(time).oneshot(
+                Request::post("/register")
+                    .extension(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 4711))))
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(format!(r#"{{"nick":"{}"}}"#, nick)))
+                    .unwrap(),
+            )
+        };
+
+        let response = api(at).oneshot(request("")).await.unwrap();
+        assert!(response.headers().get(header::LAST_MODIFIED).is_none());
+
+        register(at, "Razor").await.unwrap();
+        let response = api(at).oneshot(request("")).await.unwrap();
+        assert_eq!(
+            response.headers()[header::LAST_MODIFIED],
+            "Fri, 01 Mar 2024 18:00:00 GMT"
+        );
+        for (since, expected) in [
+            ("Fri, 01 Mar 2024 18:00:00 GMT", StatusCode::NOT_MODIFIED),
+            ("Fri, 01 Mar 2024 19:00:00 GMT", StatusCode::NOT_MODIFIED),
+            ("Fri, 01 Mar 2024 17:59:59 GMT", StatusCode::OK),
+            ("yesterday", StatusCode::OK),
+        ] {
+            let response = api(at).oneshot(request(since)).await.unwrap();
+            assert_eq!(response.status(), expected, "{}", since);
+        }
+
+        let later = at + Duration::minutes(5);
+        register(later, "Blitter").await.unwrap();
+        let response = api(later)
+            .oneshot(request("Fri, 01 Mar 2024 18:00:00 GMT"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::LAST_MODIFIED],
+            "Fri, 01 Mar 2024 18:05:00 GMT"
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use std::{env, sync::Arc, time::Duration};
+
+use axum::async_trait;
+use serde::Deserialize;
+
+pub const TURNSTILE_URL: &str = "https://challenges.cloudflare.com/turnstile/v0/siteverify";
+pub const RECAPTCHA_URL: &str = "https://www.google.com/recaptcha/api/siteverify";
+
+#[derive(Clone)]
+pub struct CaptchaConfig {
+    pub verifier: Arc<dyn Verifier>,
+    pub fail_open: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Verification {
+    Passed,
+    Failed,
+    Unavailable,
+}
+
+#[async_trait]
+pub trait Verifier: Send + Sync + 'static {
+    async fn verify(&self, token: &str, ip: &str) -> Verification;
+}
+
+// The siteverify protocol shared by Turnstile, reCAPTCHA and hCaptcha
+pub struct SiteVerify {
+    pub secret: String,
+    pub verify_url: String,
+    pub timeout: Duration,
+    pub http: reqwest::Client,
+}
+
+#[derive(Deserialize)]
+struct SiteVerifyResponse {
+    success: bool,
+}
+
+impl CaptchaConfig {
+    pub fn from_env() -> Option<Self> {
+        let (secret, default_url) = match (
+            env::var("TURNSTILE_SECRET"),
+            env::var("RECAPTCHA_SECRET"),
+            env::var("CAPTCHA_SECRET"),
+        ) {
+            (Ok(secret), _, _) => (secret, Some(TURNSTILE_URL)),
+            (_, Ok(secret), _) => (secret, Some(RECAPTCHA_URL)),
+            (_, _, Ok(secret)) => (secret, None),
+            _ => return None,
+        };
+
+        let timeout = env::var("CAPTCHA_TIMEOUT_MS").map_or(1500, |value| {
+            value
+                .parse()
+                .unwrap_or_else(|_| panic!("bad CAPTCHA_TIMEOUT_MS: {}", value))
+        });
+        let verify_url = env::var("CAPTCHA_VERIFY_URL")
+            .ok()
+            .or(default_url.map(str::to_owned))
+            .unwrap_or_else(|| panic!("CAPTCHA_VERIFY_URL not set"));
+
+        Some(Self {
+            verifier: Arc::new(SiteVerify {
+                secret,
+                verify_url,
+                timeout: Duration::from_millis(timeout),
+                http: reqwest::Client::new(),
+            }),
+            fail_open: env::var("CAPTCHA_FAIL_OPEN").is_ok_and(|value| value == "true"),
+        })
+    }
+}
+
+#[async_trait]
+impl Verifier for SiteVerify {
+    async fn verify(&self, token: &str, ip: &str) -> Verification {
+        let response = self
+            .http
+            .post(&self.verify_url)
+            .timeout(self.timeout)
+            .form(&[
+                ("secret", self.secret.as_str()),
+                ("response", token),
+                ("remoteip", ip),
+            ])
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+
+        let body = match response {
+            Ok(response) => response.json::<SiteVerifyResponse>().await,
+            Err(error) => Err(error),
+        };
+
+        match body {
+            Ok(SiteVerifyResponse { success: true }) => Verification::Passed,
+            Ok(SiteVerifyResponse { success: false }) => Verification::Failed,
+            Err(error) => {
+                eprintln!("captcha verification unavailable: {}", error);
+                Verification::Unavailable
+            }
+        }
+    }
+}
+
+// Passes "good", cannot be reached for "slow" and fails everything else
+#[cfg(test)]
+pub struct FakeVerifier;
+
+#[cfg(test)]
+#[async_trait]
+impl Verifier for FakeVerifier {
+    async fn verify(&self, token: &str, _ip: &str) -> Verification {
+        match token {
+            "good" => Verification::Passed,
+            "slow" => Verification::Unavailable,
+            _ => Verification::Failed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use axum::{http::StatusCode, routing::post, Router};
+    use tokio::net::TcpListener;
+
+    use super::{SiteVerify, Verification, Verifier};
+    use crate::testing;
+
+    async fn verify(status: StatusCode, body: &'static str) -> Verification {
+        let url = testing::serve(
+            Router::new().route("/siteverify", post(move || async move { (status, body) })),
+        )
+        .await;
+
+        SiteVerify {
+            secret: "secret".into(),
+            verify_url: format!("{}/siteverify", url),
+            timeout: Duration::from_millis(100),
+            http: reqwest::Client::new(),
+        }
+        .verify("token", "127.0.0.1")
+        .await
+    }
+
+    #[tokio::test]
+    async fn should_pass_on_success() {
+        let result = verify(StatusCode::OK, r#"{"success":true}"#).await;
+        assert_eq!(result, Verification::Passed);
+    }
+
+    #[tokio::test]
+    async fn should_fail_on_rejection() {
+        let result = verify(StatusCode::OK, r#"{"success":false}"#).await;
+        assert_eq!(result, Verification::Failed);
+    }
+
+    #[tokio::test]
+    async fn should_be_unavailable_on_server_error() {
+        let result = verify(StatusCode::INTERNAL_SERVER_ERROR, "").await;
+        assert_eq!(result, Verification::Unavailable);
+    }
+
+    #[tokio::test]
+    async fn should_be_unavailable_on_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Accept connections but never answer them
+        tokio::spawn(async move {
+            let mut connections = Vec::new();
+            loop {
+                connections.push(listener.accept().await.unwrap());
+            }
+        });
+
+        let result = SiteVerify {
+            secret: "secret".into(),
+            verify_url: format!("http://{}/siteverify", addr),
+            timeout: Duration::from_millis(100),
+            http: reqwest::Client::new(),
+        }
+        .verify("token", "127.0.0.1")
+        .await;
+        assert_eq!(result, Verification::Unavailable);
+    }
+}
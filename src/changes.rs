@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use sqlx::{SqliteConnection, SqlitePool};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Change {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl Change {
+    fn kind(self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Updated => "updated",
+            Self::Deleted => "deleted",
+        }
+    }
+
+    fn from_kind(kind: &str) -> Option<Self> {
+        match kind {
+            "created" => Some(Self::Created),
+            "updated" => Some(Self::Updated),
+            "deleted" => Some(Self::Deleted),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Journal {
+    pub generation: i64,
+    pub full_refetch: bool,
+    pub added: Vec<i64>,
+    pub updated: Vec<i64>,
+    pub removed: Vec<i64>,
+}
+
+pub async fn record(
+    conn: &mut SqliteConnection,
+    visitor_id: i64,
+    change: Change,
+    keep: u32,
+    now: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    let generation = sqlx::query(
+        r#"INSERT INTO visitor_change (visitor_id, kind, changed_at) VALUES ($1, $2, $3)"#,
+    )
+    .bind(visitor_id)
+    .bind(change.kind())
+    .bind(now)
+    .execute(&mut *conn)
+    .await?
+    .last_insert_rowid();
+
+    sqlx::query(r#"DELETE FROM visitor_change WHERE generation <= $1"#)
+        .bind(generation - i64::from(keep.max(1)))
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn generation(db: &SqlitePool) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar(r#"SELECT COALESCE(MAX(generation), 0) FROM visitor_change"#)
+        .fetch_one(db)
+        .await
+}
+
+// Unknown for a journal still empty, or last written before changes were timed
+pub async fn last_changed(db: &SqlitePool) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    sqlx::query_scalar(r#"SELECT changed_at FROM visitor_change ORDER BY generation DESC LIMIT 1"#)
+        .fetch_optional(db)
+        .await
+        .map(Option::flatten)
+}
+
+pub async fn since(db: &SqlitePool, since: i64) -> Result<Journal, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    let (oldest, generation) = sqlx::query_as::<_, (Option<i64>, i64)>(
+        r#"SELECT MIN(generation), COALESCE(MAX(generation), 0) FROM visitor_change"#,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if since < 0 || since > generation || oldest.is_some_and(|oldest| oldest > since + 1) {
+        return Ok(Journal {
+            generation,
+            full_refetch: true,
+            ..Journal::default()
+        });
+    }
+
+    let rows = sqlx::query_as::<_, (i64, String)>(
+        r#"SELECT visitor_id, kind FROM visitor_change WHERE generation > $1 ORDER BY generation"#,
+    )
+    .bind(since)
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    let mut journal = collapse(
+        rows.iter()
+            .filter_map(|(id, kind)| Some((*id, Change::from_kind(kind)?))),
+    );
+    journal.generation = generation;
+    Ok(journal)
+}
+
+fn collapse(changes: impl Iterator<Item = (i64, Change)>) -> Journal {
+    let mut first_and_last = BTreeMap::<i64, (Change, Change)>::new();
+    for (id, change) in changes {
+        first_and_last
+            .entry(id)
+            .and_modify(|(_, last)| *last = change)
+            .or_insert((change, change));
+    }
+
+    let mut journal = Journal::default();
+    for (id, (first, last)) in first_and_last {
+        match (first, last) {
+            (Change::Created, Change::Deleted) => {}
+            (Change::Created, _) => journal.added.push(id),
+            (_, Change::Deleted) => journal.removed.push(id),
+            _ => journal.updated.push(id),
+        }
+    }
+    journal
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_collapse_changes_per_visitor() {
+        let journal = collapse(
+            [
+                (1, Change::Created),
+                (2, Change::Created),
+                (2, Change::Deleted),
+                (3, Change::Updated),
+                (4, Change::Deleted),
+                (5, Change::Deleted),
+                (5, Change::Created),
+                (1, Change::Updated),
+            ]
+            .into_iter(),
+        );
+
+        assert_eq!(journal.added, vec![1]);
+        assert_eq!(journal.updated, vec![3, 5]);
+        assert_eq!(journal.removed, vec![4]);
+    }
+}
@@ -0,0 +1,466 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use sqlx::SqlitePool;
+
+use crate::{config, db, fields, retry, snapshot, supervisor::Stop, time::TimeService};
+
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+const DOOR_LIST: &str = "door-list.csv";
+const FINAL_SNAPSHOT: &str = "final.sqlite3";
+const RETRY: retry::Policy = retry::Policy {
+    max_attempts: 5,
+    base: Duration::from_secs(10),
+    max: Duration::from_secs(600),
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    CloseRegistration,
+    Export,
+}
+
+impl Action {
+    fn name(self) -> &'static str {
+        match self {
+            Action::CloseRegistration => "close_registration",
+            Action::Export => "export",
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "close_registration" => Ok(Action::CloseRegistration),
+            "export" => Ok(Action::Export),
+            _ => Err(format!("unknown close action {}", value)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Schedule {
+    pub closes_at: DateTime<Utc>,
+    pub actions: Vec<Action>,
+    pub export_dir: Option<PathBuf>,
+}
+
+impl Schedule {
+    pub fn from_env() -> Option<Self> {
+        let closes_at = env::var("REGISTRATION_CLOSES_AT").ok()?;
+        let closes_at = DateTime::parse_from_rfc3339(&closes_at)
+            .unwrap_or_else(|_| panic!("bad REGISTRATION_CLOSES_AT: {}", closes_at))
+            .with_timezone(&Utc);
+
+        let actions: Vec<Action> = env::var("CLOSE_ACTIONS")
+            .unwrap_or_else(|_| Action::CloseRegistration.name().into())
+            .split(',')
+            .map(str::trim)
+            .filter(|action| !action.is_empty())
+            .map(|action| {
+                action
+                    .parse()
+                    .unwrap_or_else(|error| panic!("bad CLOSE_ACTIONS: {}", error))
+            })
+            .collect();
+
+        let export_dir = env::var("CLOSE_EXPORT_DIR").ok().map(PathBuf::from);
+        if actions.contains(&Action::Export) && export_dir.is_none() {
+            panic!("CLOSE_EXPORT_DIR not set");
+        }
+
+        Some(Self {
+            closes_at,
+            actions,
+            export_dir,
+        })
+    }
+
+    // Holds from the deadline on, even before the background task has recorded the close
+    pub fn has_closed_registration(&self, now: DateTime<Utc>) -> bool {
+        self.actions.contains(&Action::CloseRegistration) && now >= self.closes_at
+    }
+}
+
+pub async fn is_closed(db: &SqlitePool) -> Result<bool, sqlx::Error> {
+    has_run(db, Action::CloseRegistration).await
+}
+
+async fn has_run(db: &SqlitePool, action: Action) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM close_action WHERE name = $1)")
+        .bind(action.name())
+        .fetch_one(db)
+        .await
+}
+
+// Every action is recorded once it has succeeded, so a restart only runs what is still missing. A failing
+// action is retried with backoff and dead-lettered in the end, without holding up the others.
+pub async fn tick(
+    db: &SqlitePool,
+    schedule: &Schedule,
+    tracker: &mut retry::Tracker,
+    now: DateTime<Utc>,
+) -> Result<Vec<Action>, sqlx::Error> {
+    if now < schedule.closes_at {
+        return Ok(Vec::new());
+    }
+
+    let mut ran = Vec::new();
+    for &action in &schedule.actions {
+        if has_run(db, action).await? || !tracker.ready(db, action.name(), now).await? {
+            continue;
+        }
+
+        let result = match action {
+            Action::CloseRegistration => Ok(()),
+            Action::Export => {
+                let dir = schedule
+                    .export_dir
+                    .as_deref()
+                    .expect("export needs CLOSE_EXPORT_DIR");
+                export(db, dir)
+                    .await
+                    .map_err(|error| format!("export into {} failed: {}", dir.display(), error))
+            }
+        };
+        if let Err(error) = result {
+            tracker.failed(db, action.name(), &error, now).await?;
+            continue;
+        }
+
+        sqlx::query("INSERT INTO close_action (name, ran_at) VALUES ($1, $2)")
+            .bind(action.name())
+            .bind(now)
+            .execute(db)
+            .await?;
+        eprintln!("[close] ran {} at {}", action.name(), now.to_rfc3339());
+        tracker.succeeded(action.name());
+        ran.push(action);
+    }
+
+    Ok(ran)
+}
+
+pub async fn run<T: TimeService>(
+    db: SqlitePool,
+    time: T,
+    schedule: Schedule,
+    metrics: retry::Metrics,
+    mut stop: Stop,
+) {
+    let mut tracker = retry::Tracker::new("close", RETRY, metrics);
+    while stop.sleep(TICK_INTERVAL).await {
+        if let Err(error) = tick(&db, &schedule, &mut tracker, time.clone().now()).await {
+            eprintln!("[close] {}", error);
+        }
+    }
+}
+
+async fn export(db: &SqlitePool, dir: &Path) -> Result<(), snapshot::SnapshotError> {
+    fs::create_dir_all(dir)?;
+
+    let mut visitors = sqlx::query_as::<_, db::Visitor>(
+        "SELECT * FROM visitor WHERE status = 'confirmed' ORDER BY nick",
+    )
+    .fetch_all(db)
+    .await?;
+    fields::attach(db, &mut visitors).await?;
+    // A column for every custom field somebody answered
+    let custom: Vec<String> =
+        sqlx::query_scalar("SELECT DISTINCT name FROM visitor_field ORDER BY name")
+            .fetch_all(db)
+            .await?;
+
+    let mut csv = String::from("id,nick,group,payment_status,source");
+    for name in &custom {
+        csv.push(',');
+        csv.push_str(&field(name));
+    }
+    csv.push('\n');
+    for visitor in visitors {
+        csv.push_str(&format!(
+            "{},{},{},{},{}",
+            visitor.id,
+            field(&visitor.nick),
+            field(visitor.group.as_deref().unwrap_or_default()),
+            field(visitor.payment_status.as_deref().unwrap_or_default()),
+            field(&visitor.source),
+        ));
+        for name in &custom {
+            let answer = match visitor.fields.get(name) {
+                Some(serde_json::Value::String(text)) => text.clone(),
+                Some(value) => value.to_string(),
+                None => String::new(),
+            };
+            csv.push(',');
+            csv.push_str(&field(&answer));
+        }
+        csv.push('\n');
+    }
+    fs::write(dir.join(DOOR_LIST), csv)?;
+
+    // Left over from an export that did not get recorded
+    let snapshot = dir.join(FINAL_SNAPSHOT);
+    if snapshot.exists() {
+        fs::remove_file(&snapshot)?;
+    }
+    snapshot::create(db, &snapshot, config::effective(false)).await?;
+
+    Ok(())
+}
+
+// A leading ' keeps a spreadsheet from running a nick like =HYPERLINK(...) as a formula
+fn field(value: &str) -> String {
+    let value = match value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        true => format!("'{}", value),
+        false => value.to_owned(),
+    };
+    match value.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::Body, http::StatusCode};
+    use chrono::Duration;
+    use hyper::Request;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{config::Config, testing, time::ConstantTimeService};
+
+    fn schedule(closes_at: DateTime<Utc>, export_dir: &Path) -> Schedule {
+        Schedule {
+            closes_at,
+            actions: vec![Action::Export, Action::CloseRegistration],
+            export_dir: Some(export_dir.to_owned()),
+        }
+    }
+
+    fn tracker() -> retry::Tracker {
+        retry::Tracker::new("close", RETRY, retry::Metrics::default())
+    }
+
+    fn register() -> Request<Body> {
+        Request::builder()
+            .extension(axum::extract::ConnectInfo(std::net::SocketAddr::from((
+                [127, 0, 0, 1],
+                8080,
+            ))))
+            .header("Content-Type", "application/json")
+            .method("POST")
+            .uri("/register")
+            .body(Body::from(r#"{"nick":"Late"}"#))
+            .unwrap()
+    }
+
+    #[test]
+    fn should_quote_csv_fields() {
+        assert_eq!(field("Razor"), "Razor");
+        assert_eq!(field("Razor, 1911"), "\"Razor, 1911\"");
+        assert_eq!(field(r#"The "Best""#), r#""The ""Best""""#);
+    }
+
+    #[test]
+    fn should_defuse_spreadsheet_formulas() {
+        assert_eq!(field("=1+1"), "'=1+1");
+        assert_eq!(field("+49 123"), "'+49 123");
+        assert_eq!(field("-Razor-"), "'-Razor-");
+        assert_eq!(field("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(
+            field(r#"=HYPERLINK("http://example.com","x")"#),
+            r#""'=HYPERLINK(""http://example.com"",""x"")""#
+        );
+        assert_eq!(field("Razor=1911"), "Razor=1911");
+    }
+
+    #[tokio::test]
+    async fn should_run_each_action_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = testing::file_database(dir.path()).await;
+        testing::insert_visitor(&db, "Razor", Some("Razor, 1911")).await;
+        let closes_at = Utc::now();
+        let export_dir = dir.path().join("final");
+
+        // A file where the directory should be makes the export fail, registration still closes
+        fs::write(&export_dir, "").unwrap();
+        let schedule = schedule(closes_at, &export_dir);
+        let mut tracker = tracker();
+        assert_eq!(
+            tick(
+                &db,
+                &schedule,
+                &mut tracker,
+                closes_at - Duration::seconds(1)
+            )
+            .await
+            .unwrap(),
+            vec![]
+        );
+        assert!(!is_closed(&db).await.unwrap());
+        assert_eq!(
+            tick(&db, &schedule, &mut tracker, closes_at).await.unwrap(),
+            vec![Action::CloseRegistration]
+        );
+        assert!(is_closed(&db).await.unwrap());
+
+        // Restarted with the problem fixed only the export is left to do
+        fs::remove_file(&export_dir).unwrap();
+        let mut tracker = self::tracker();
+        for (at, expected) in [(1, vec![Action::Export]), (2, vec![])] {
+            assert_eq!(
+                tick(
+                    &db,
+                    &schedule,
+                    &mut tracker,
+                    closes_at + Duration::seconds(at)
+                )
+                .await
+                .unwrap(),
+                expected
+            );
+        }
+
+        let runs: Vec<(String, DateTime<Utc>)> =
+            sqlx::query_as("SELECT name, ran_at FROM close_action ORDER BY ran_at")
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert_eq!(
+            runs,
+            vec![
+                ("close_registration".to_owned(), closes_at),
+                ("export".to_owned(), closes_at + Duration::seconds(1)),
+            ]
+        );
+        assert_eq!(
+            fs::read_to_string(export_dir.join(DOOR_LIST)).unwrap(),
+            "id,nick,group,payment_status,source\n1,Razor,\"Razor, 1911\",,web\n"
+        );
+        assert!(export_dir.join(FINAL_SNAPSHOT).exists());
+    }
+
+    #[tokio::test]
+    async fn should_refuse_registration_once_closed() {
+        let db = testing::database().await;
+        let api = crate::api(ConstantTimeService::new(), db.clone(), Config::default());
+
+        let schedule = Schedule {
+            closes_at: Utc::now(),
+            actions: vec![Action::CloseRegistration],
+            export_dir: None,
+        };
+        tick(&db, &schedule, &mut tracker(), schedule.closes_at)
+            .await
+            .unwrap();
+
+        let response = api.oneshot(register()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn should_close_registration_at_deadline() {
+        let closes_at = Utc::now();
+        for (now, closing, expected) in [
+            (closes_at - Duration::seconds(1), true, StatusCode::CREATED),
+            (closes_at, true, StatusCode::FORBIDDEN),
+            (closes_at + Duration::days(1), false, StatusCode::CREATED),
+        ] {
+            let db = testing::database().await;
+            let config = Config {
+                closing: closing.then(|| Schedule {
+                    closes_at,
+                    actions: vec![Action::CloseRegistration],
+                    export_dir: None,
+                }),
+                ..Config::default()
+            };
+            let api = crate::api(ConstantTimeService::at(now), db.clone(), config);
+            if expected == StatusCode::FORBIDDEN {
+                // Past the deadline the database is not needed to answer
+                db.close().await;
+            }
+
+            let response = api.clone().oneshot(register()).await.unwrap();
+            assert_eq!(response.status(), expected, "{} {}", now, closing);
+
+            if expected == StatusCode::FORBIDDEN {
+                // Nor to turn away a retry that could otherwise be replayed
+                let mut retry = register();
+                retry
+                    .headers_mut()
+                    .insert("Idempotency-Key", "late-retry".parse().unwrap());
+                let response = api.oneshot(retry).await.unwrap();
+                assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_dead_letter_failing_action() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = testing::file_database(dir.path()).await;
+        let api = crate::api(
+            ConstantTimeService::new(),
+            db.clone(),
+            Config {
+                admin_keys: crate::admin::AdminKeys::new(vec!["key".into()]),
+                ..Config::default()
+            },
+        );
+        let closes_at = Utc::now();
+        let export_dir = dir.path().join("final");
+        fs::write(&export_dir, "").unwrap();
+        let schedule = schedule(closes_at, &export_dir);
+        let mut tracker = tracker();
+
+        let mut now = closes_at;
+        for _ in 0..RETRY.max_attempts {
+            assert!(!tick(&db, &schedule, &mut tracker, now)
+                .await
+                .unwrap()
+                .contains(&Action::Export));
+            now += Duration::hours(1);
+        }
+        let dead: Vec<(i64, String)> = sqlx::query_as("SELECT id, item FROM dead_letter")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+        assert_eq!(dead, vec![(1, "export".to_owned())]);
+        assert!(is_closed(&db).await.unwrap());
+
+        fs::remove_file(&export_dir).unwrap();
+        assert_eq!(
+            tick(&db, &schedule, &mut tracker, now).await.unwrap(),
+            vec![],
+            "dead-lettered actions wait for a manual retry"
+        );
+
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .header("Authorization", "Bearer key")
+                    .method("POST")
+                    .uri("/admin/dead-letters/1/retry")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(
+            tick(&db, &schedule, &mut tracker, now).await.unwrap(),
+            vec![Action::Export]
+        );
+    }
+}
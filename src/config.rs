@@ -0,0 +1,329 @@
+use std::{
+    env,
+    num::{NonZeroU32, NonZeroU64},
+    str::FromStr,
+};
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{
+    admin::AdminKeys, analytics, blocklist::NickBlocklist, cache, captcha::CaptchaConfig, closing,
+    fields::CustomFields, misses, params, payment::ReferenceScheme, policy::Policies,
+    proxy::TrustedProxies, replica, stages::Stages, throttle,
+};
+
+#[derive(Clone)]
+pub struct Config {
+    pub admin_keys: AdminKeys,
+    pub verify_keys: AdminKeys,
+    pub readonly_keys: AdminKeys,
+    pub group_max_length: usize,
+    pub normalize_existing_groups: bool,
+    pub referral_codes: Vec<String>,
+    pub allowed_groups: Option<Vec<String>>,
+    pub honeypot_field: Option<String>,
+    pub nick_blocklist: NickBlocklist,
+    pub captcha: Option<CaptchaConfig>,
+    pub payment_reference: Option<ReferenceScheme>,
+    pub reservations_expire_at: Option<DateTime<Utc>>,
+    pub cache_policies: cache::Policies,
+    pub register_rate_limit: RateLimit,
+    pub max_registrations_per_ip: Option<u32>,
+    pub enforce_unique_email: bool,
+    pub repeat_window: Duration,
+    pub draft_max_bytes: usize,
+    pub draft_ttl: Duration,
+    pub edit_window: Option<Duration>,
+    pub edit_cutoff: Option<DateTime<Utc>>,
+    pub routes: Routes,
+    pub rejected_capture: bool,
+    pub rejected_retention: Duration,
+    pub timing_header: bool,
+    pub public_stats: analytics::Privacy,
+    pub change_journal_length: u32,
+    pub strict_mode: bool,
+    pub behind_proxy: bool,
+    pub trusted_proxies: TrustedProxies,
+    pub replica_push: Option<replica::Target>,
+    pub replica_keys: AdminKeys,
+    pub policies: Policies,
+    pub registration_opens_at: Option<DateTime<Utc>>,
+    pub stages: Stages,
+    pub custom_fields: CustomFields,
+    pub visitor_limit: Option<u32>,
+    pub waitlist: bool,
+    pub misses: misses::Settings,
+    pub closing: Option<closing::Schedule>,
+    pub unknown_params: params::Unknown,
+}
+
+// One request per period refills the burst, per client
+#[derive(Clone)]
+pub struct RateLimit {
+    pub period_seconds: u64,
+    pub burst: u32,
+    pub mode: throttle::Mode,
+    pub max_wait_seconds: u64,
+}
+
+#[derive(Clone)]
+pub struct Routes {
+    pub public_list: bool,
+    pub groups: bool,
+    pub status: bool,
+    pub admin_delete: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            admin_keys: AdminKeys::default(),
+            verify_keys: AdminKeys::default(),
+            readonly_keys: AdminKeys::default(),
+            group_max_length: 48,
+            normalize_existing_groups: false,
+            referral_codes: Vec::new(),
+            allowed_groups: None,
+            honeypot_field: None,
+            nick_blocklist: NickBlocklist::default(),
+            captcha: None,
+            payment_reference: None,
+            reservations_expire_at: None,
+            cache_policies: cache::Policies::default(),
+            register_rate_limit: RateLimit {
+                period_seconds: 60,
+                burst: 3,
+                mode: throttle::Mode::default(),
+                max_wait_seconds: 10,
+            },
+            max_registrations_per_ip: None,
+            enforce_unique_email: false,
+            repeat_window: Duration::minutes(10),
+            draft_max_bytes: 16 * 1024,
+            draft_ttl: Duration::hours(24),
+            edit_window: None,
+            edit_cutoff: None,
+            routes: Routes {
+                public_list: true,
+                groups: true,
+                status: true,
+                admin_delete: true,
+            },
+            rejected_capture: false,
+            rejected_retention: Duration::days(14),
+            timing_header: false,
+            public_stats: analytics::Privacy::default(),
+            change_journal_length: 1000,
+            strict_mode: false,
+            behind_proxy: false,
+            trusted_proxies: TrustedProxies::default(),
+            replica_push: None,
+            replica_keys: AdminKeys::default(),
+            policies: Policies::default(),
+            registration_opens_at: None,
+            stages: Stages::default(),
+            custom_fields: CustomFields::default(),
+            visitor_limit: None,
+            waitlist: false,
+            misses: misses::Settings::default(),
+            closing: None,
+            unknown_params: params::Unknown::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+
+        Self {
+            admin_keys: AdminKeys::from_env(),
+            verify_keys: AdminKeys::new(list("VERIFY_KEYS").unwrap_or_default()),
+            readonly_keys: AdminKeys::new(list("READONLY_KEYS").unwrap_or_default()),
+            group_max_length: parse("GROUP_MAX_LENGTH").unwrap_or(defaults.group_max_length),
+            normalize_existing_groups: parse("NORMALIZE_EXISTING_GROUPS")
+                .unwrap_or(defaults.normalize_existing_groups),
+            referral_codes: list("REFERRAL_CODES").unwrap_or(defaults.referral_codes),
+            allowed_groups: list("ALLOWED_GROUPS").filter(|groups| !groups.is_empty()),
+            honeypot_field: env::var("HONEYPOT_FIELD").ok().filter(|x| !x.is_empty()),
+            nick_blocklist: NickBlocklist::from_env(),
+            captcha: CaptchaConfig::from_env(),
+            payment_reference: parse("PAYMENT_REFERENCE"),
+            reservations_expire_at: parse("RESERVATIONS_EXPIRE_AT"),
+            cache_policies: cache::Policies {
+                lists: parse("CACHE_CONTROL_LISTS").unwrap_or(defaults.cache_policies.lists),
+                status: parse("CACHE_CONTROL_STATUS").unwrap_or(defaults.cache_policies.status),
+            },
+            register_rate_limit: RateLimit {
+                period_seconds: parse("RATE_LIMIT_PERIOD_SECONDS")
+                    .map(NonZeroU64::get)
+                    .unwrap_or(defaults.register_rate_limit.period_seconds),
+                burst: parse("RATE_LIMIT_BURST")
+                    .map(NonZeroU32::get)
+                    .unwrap_or(defaults.register_rate_limit.burst),
+                mode: parse("RATE_LIMIT_MODE").unwrap_or(defaults.register_rate_limit.mode),
+                max_wait_seconds: parse("RATE_LIMIT_WAIT_SECONDS")
+                    .unwrap_or(defaults.register_rate_limit.max_wait_seconds),
+            },
+            max_registrations_per_ip: parse("MAX_REGISTRATIONS_PER_IP"),
+            enforce_unique_email: parse("ENFORCE_UNIQUE_EMAIL")
+                .unwrap_or(defaults.enforce_unique_email),
+            repeat_window: parse("REPEAT_WINDOW_MINUTES")
+                .map(Duration::minutes)
+                .unwrap_or(defaults.repeat_window),
+            draft_max_bytes: parse("DRAFT_MAX_BYTES").unwrap_or(defaults.draft_max_bytes),
+            draft_ttl: parse("DRAFT_TTL_HOURS")
+                .map(Duration::hours)
+                .unwrap_or(defaults.draft_ttl),
+            edit_window: parse("SELF_EDIT_WINDOW_HOURS").map(Duration::hours),
+            edit_cutoff: parse("SELF_EDIT_CUTOFF_AT"),
+            routes: Routes {
+                public_list: parse("ENABLE_PUBLIC_LIST").unwrap_or(defaults.routes.public_list),
+                groups: parse("ENABLE_GROUPS").unwrap_or(defaults.routes.groups),
+                status: parse("ENABLE_STATUS").unwrap_or(defaults.routes.status),
+                admin_delete: parse("ENABLE_ADMIN_DELETE").unwrap_or(defaults.routes.admin_delete),
+            },
+            rejected_capture: parse("REJECTED_CAPTURE").unwrap_or(defaults.rejected_capture),
+            rejected_retention: parse("REJECTED_RETENTION_DAYS")
+                .map(Duration::days)
+                .unwrap_or(defaults.rejected_retention),
+            timing_header: parse("TIMING_HEADER").unwrap_or(defaults.timing_header),
+            public_stats: analytics::Privacy {
+                enabled: parse("PUBLIC_STATS_PRIVACY").unwrap_or(defaults.public_stats.enabled),
+            },
+            change_journal_length: parse("CHANGE_JOURNAL_LENGTH")
+                .unwrap_or(defaults.change_journal_length),
+            strict_mode: parse("STRICT_MODE").unwrap_or(defaults.strict_mode),
+            behind_proxy: parse("BEHIND_PROXY").unwrap_or(defaults.behind_proxy),
+            trusted_proxies: parse("TRUSTED_PROXIES").unwrap_or(defaults.trusted_proxies),
+            replica_push: replica::Target::from_env(),
+            replica_keys: AdminKeys::new(list("REPLICA_KEYS").unwrap_or_default()),
+            policies: Policies::from_env(),
+            registration_opens_at: parse("REGISTRATION_OPENS_AT"),
+            stages: Stages::from_env(),
+            custom_fields: CustomFields::from_env(),
+            visitor_limit: parse("VISITOR_LIMIT"),
+            waitlist: parse("WAITLIST").unwrap_or(defaults.waitlist),
+            misses: misses::Settings {
+                cache_size: parse("NEGATIVE_CACHE_SIZE").unwrap_or(defaults.misses.cache_size),
+                cache_ttl: parse("NEGATIVE_CACHE_SECONDS")
+                    .map(Duration::seconds)
+                    .unwrap_or(defaults.misses.cache_ttl),
+                budget: parse("MISS_BUDGET").unwrap_or(defaults.misses.budget),
+            },
+            closing: closing::Schedule::from_env(),
+            unknown_params: parse("UNKNOWN_QUERY_PARAMS").unwrap_or(defaults.unknown_params),
+        }
+    }
+}
+
+const VARIABLES: &[&str] = &[
+    "API_KEY",
+    "VERIFY_KEYS",
+    "READONLY_KEYS",
+    "CORS_ORIGIN",
+    "SQLITE_DB",
+    "SQLITE_BASE_DIR",
+    "LISTEN_ADDR",
+    "GROUP_MAX_LENGTH",
+    "NORMALIZE_EXISTING_GROUPS",
+    "REFERRAL_CODES",
+    "ALLOWED_GROUPS",
+    "HONEYPOT_FIELD",
+    "NICK_BLOCKLIST",
+    "TURNSTILE_SECRET",
+    "RECAPTCHA_SECRET",
+    "CAPTCHA_SECRET",
+    "CAPTCHA_VERIFY_URL",
+    "CAPTCHA_TIMEOUT_MS",
+    "CAPTCHA_FAIL_OPEN",
+    "PAYMENT_REFERENCE",
+    "RESERVATIONS_EXPIRE_AT",
+    "CACHE_CONTROL_LISTS",
+    "CACHE_CONTROL_STATUS",
+    "RATE_LIMIT_PERIOD_SECONDS",
+    "RATE_LIMIT_BURST",
+    "RATE_LIMIT_MODE",
+    "RATE_LIMIT_WAIT_SECONDS",
+    "MAX_REGISTRATIONS_PER_IP",
+    "ENFORCE_UNIQUE_EMAIL",
+    "DRAFT_MAX_BYTES",
+    "REPEAT_WINDOW_MINUTES",
+    "DRAFT_TTL_HOURS",
+    "SELF_EDIT_WINDOW_HOURS",
+    "SELF_EDIT_CUTOFF_AT",
+    "ENABLE_PUBLIC_LIST",
+    "ENABLE_GROUPS",
+    "ENABLE_STATUS",
+    "ENABLE_ADMIN_DELETE",
+    "REJECTED_CAPTURE",
+    "REJECTED_RETENTION_DAYS",
+    "TIMING_HEADER",
+    "PUBLIC_STATS_PRIVACY",
+    "CHANGE_JOURNAL_LENGTH",
+    "STRICT_MODE",
+    "BEHIND_PROXY",
+    "TRUSTED_PROXIES",
+    "REPLICA_PUSH_URL",
+    "REPLICA_PUSH_KEY",
+    "REPLICA_KEYS",
+    "REGISTRATION_POLICIES",
+    "POLICY_BLOCKED_WORDS",
+    "POLICY_FREE_GROUP",
+    "POLICY_FAIL_OPEN",
+    "REGISTRATION_OPENS_AT",
+    "REGISTRATION_STAGES",
+    "CUSTOM_FIELDS",
+    "REGISTRATION_CLOSES_AT",
+    "VISITOR_LIMIT",
+    "WAITLIST",
+    "NEGATIVE_CACHE_SIZE",
+    "NEGATIVE_CACHE_SECONDS",
+    "MISS_BUDGET",
+    "CLOSE_ACTIONS",
+    "CLOSE_EXPORT_DIR",
+    "UNKNOWN_QUERY_PARAMS",
+];
+
+const SECRETS: &[&str] = &[
+    "API_KEY",
+    "VERIFY_KEYS",
+    "READONLY_KEYS",
+    "TURNSTILE_SECRET",
+    "RECAPTCHA_SECRET",
+    "CAPTCHA_SECRET",
+    "REPLICA_PUSH_KEY",
+    "REPLICA_KEYS",
+];
+
+pub fn effective(include_secrets: bool) -> Vec<(String, String)> {
+    VARIABLES
+        .iter()
+        .filter_map(|name| {
+            let value = env::var(name).ok()?;
+            match !include_secrets && SECRETS.contains(name) {
+                true => Some((name.to_string(), crate::snapshot::REDACTED.to_owned())),
+                false => Some((name.to_string(), value)),
+            }
+        })
+        .collect()
+}
+
+fn parse<T: FromStr>(name: &str) -> Option<T> {
+    env::var(name).ok().map(|value| {
+        value
+            .parse()
+            .unwrap_or_else(|_| panic!("bad {}: {}", name, value))
+    })
+}
+
+fn list(name: &str) -> Option<Vec<String>> {
+    env::var(name).ok().map(|value| {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(str::to_owned)
+            .collect()
+    })
+}
@@ -0,0 +1,192 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::{Sqlite, Transaction};
+
+use crate::{edits, error::ApiError, json::Json, time::TimeService, ApiState};
+
+#[derive(Serialize)]
+pub struct Confirmed {
+    confirmed: bool,
+    confirmed_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    edit_deadline: Option<DateTime<Utc>>,
+}
+
+// Like edit tokens only the hash is kept. Until mails go out the token is only logged, for the organizers to pass on.
+pub async fn issue(tx: &mut Transaction<'static, Sqlite>, id: i64) -> Result<String, sqlx::Error> {
+    let token: String = sqlx::query_scalar("SELECT lower(hex(randomblob(16)))")
+        .fetch_one(&mut **tx)
+        .await?;
+    sqlx::query(
+        "UPDATE visitor SET confirmation_token_hash = $1, confirmed_at = NULL WHERE id = $2",
+    )
+    .bind(hash(&token))
+    .bind(id)
+    .execute(&mut **tx)
+    .await?;
+    eprintln!(
+        "[confirm] visitor {} can confirm their email with {}",
+        id, token
+    );
+    Ok(token)
+}
+
+fn hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+fn not_found() -> ApiError {
+    ApiError::new(StatusCode::NOT_FOUND, "confirmation not found")
+}
+
+// Following the link twice, from a mail client that prefetches it for instance, keeps the first confirmation time
+pub async fn confirm<T: TimeService>(
+    Path(token): Path<String>,
+    State(state): State<ApiState<T>>,
+) -> Result<(StatusCode, Json<Confirmed>), ApiError> {
+    let token = token.to_lowercase();
+    if token.len() != 32 || !token.bytes().all(|x| x.is_ascii_hexdigit()) {
+        return Err(not_found());
+    }
+
+    let Some((id, confirmed_at, created_at)) =
+        sqlx::query_as::<_, (i64, DateTime<Utc>, DateTime<Utc>)>(
+            r#"UPDATE visitor SET confirmed_at = coalesce(confirmed_at, $1)
+WHERE confirmation_token_hash = $2
+RETURNING id, confirmed_at, created_at"#,
+        )
+        .bind(state.time.clone().now())
+        .bind(hash(&token))
+        .fetch_optional(&state.db)
+        .await?
+    else {
+        return Err(not_found());
+    };
+    eprintln!("[confirm] visitor {} confirmed their email", id);
+
+    Ok((
+        StatusCode::OK,
+        Json(Confirmed {
+            confirmed: true,
+            confirmed_at,
+            edit_deadline: edits::deadline(&state.config, created_at),
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use axum::{body::Body, extract::ConnectInfo, Router};
+    use chrono::Duration;
+    use http_body_util::BodyExt;
+    use hyper::Request;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{admin::AdminKeys, config::Config, testing, time::ConstantTimeService};
+
+    async fn visit(api: &Router, token: &str) -> (StatusCode, Value) {
+        send(api, Request::get(format!("/confirm/{}", token))).await
+    }
+
+    async fn send(api: &Router, request: axum::http::request::Builder) -> (StatusCode, Value) {
+        let response = api
+            .clone()
+            .oneshot(
+                request
+                    .extension(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 4711))))
+                    .header("Authorization", "Bearer key")
+                    .header("Content-Type", "application/json")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn should_confirm_once() {
+        let db = testing::database().await;
+        testing::insert_visitor(&db, "Razor", None).await;
+        let mut tx = db.begin().await.unwrap();
+        let token = issue(&mut tx, 1).await.unwrap();
+        tx.commit().await.unwrap();
+
+        let time = ConstantTimeService::new();
+        let api = crate::api(time.clone(), db.clone(), Config::default());
+        let (status, first) = visit(&api, &token.to_uppercase()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(first["confirmed"], true);
+
+        let later = crate::api(
+            ConstantTimeService::at(time.now() + Duration::hours(1)),
+            db,
+            Config::default(),
+        );
+        let (status, second) = visit(&later, &token).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(second["confirmed_at"], first["confirmed_at"]);
+
+        for token in ["0".repeat(32), "not-a-token".into()] {
+            let (status, _) = visit(&later, &token).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{}", token);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_list_unconfirmed_emails() {
+        let db = testing::database().await;
+        let api = crate::api(
+            ConstantTimeService::new(),
+            db.clone(),
+            Config {
+                admin_keys: AdminKeys::new(vec!["key".into()]),
+                ..Config::default()
+            },
+        );
+        for body in [
+            r#"{"nick":"Razor","email":"razor@example.com","consent":true}"#,
+            r#"{"nick":"Fairlight"}"#,
+        ] {
+            let response = api
+                .clone()
+                .oneshot(
+                    Request::post("/register")
+                        .extension(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 4711))))
+                        .header("Content-Type", "application/json")
+                        .body(Body::from(body))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+        }
+        let unconfirmed = || send(&api, Request::get("/admin/visitors?confirmed=false"));
+        let (_, listed) = unconfirmed().await;
+        assert_eq!(listed.as_array().unwrap().len(), 1);
+        assert_eq!(listed[0]["nick"], "Razor");
+        assert!(listed[0]["confirmed_at"].is_null());
+
+        // The token from the registration only went to the log, so hand out a fresh one
+        let mut tx = db.begin().await.unwrap();
+        let token = issue(&mut tx, 1).await.unwrap();
+        tx.commit().await.unwrap();
+        let (status, _) = visit(&api, &token).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, listed) = unconfirmed().await;
+        assert_eq!(listed, serde_json::json!([]));
+        let (_, listed) = send(&api, Request::get("/admin/visitors?confirmed=true")).await;
+        assert!(listed[0]["confirmed_at"].is_string());
+    }
+}
@@ -1,6 +1,6 @@
 use std::env;
 
-use axum::http::{HeaderValue, Method};
+use axum::http::{header, HeaderName, HeaderValue, Method};
 use tower::{
     layer::util::{Identity, Stack},
     ServiceBuilder,
@@ -16,8 +16,20 @@ pub fn layer() -> ServiceBuilder<Stack<CorsLayer, Identity>> {
 
     let cors = CorsLayer::new()
         .allow_headers(cors::Any)
-        .allow_methods(vec![Method::GET, Method::POST])
-        .allow_origin(origin);
+        .allow_methods(vec![
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::PATCH,
+            Method::DELETE,
+        ])
+        .allow_origin(origin)
+        // Only the safelisted response headers are readable cross-origin without this
+        .expose_headers([
+            header::LINK,
+            HeaderName::from_static("x-total-count"),
+            HeaderName::from_static("x-generation"),
+        ]);
 
     ServiceBuilder::new().layer(cors)
 }
@@ -30,13 +42,13 @@ mod test {
     use hyper::{Request, StatusCode};
     use tower::ServiceExt;
 
-    use crate::{testing, time::ConstantTimeService};
+    use crate::{config::Config, testing, time::ConstantTimeService};
 
     #[tokio::test]
     async fn should_allow_any_by_default() {
         let time = ConstantTimeService::new();
         let db = testing::database().await;
-        let api = crate::api(time.clone(), db.clone());
+        let api = crate::api(time.clone(), db.clone(), Config::default());
 
         let response = api
             .oneshot(
@@ -58,7 +70,7 @@ mod test {
                 .map(|x| x.to_str().unwrap())
         );
         assert_eq!(
-            Some("GET,POST"),
+            Some("GET,POST,PUT,PATCH,DELETE"),
             response
                 .headers()
                 .get("Access-Control-Allow-Methods")
@@ -73,13 +85,64 @@ mod test {
         );
     }
 
+    #[tokio::test]
+    async fn should_allow_preflight_for_draft_and_token_routes() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let api = crate::api(time.clone(), db.clone(), Config::default());
+
+        for (method, uri) in [
+            ("PUT", "/register/draft"),
+            ("PATCH", "/register/token"),
+            ("DELETE", "/register/token"),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .method("OPTIONS")
+                        .uri(uri)
+                        .header("Origin", "http://example.com")
+                        .header("Access-Control-Request-Method", method)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::OK, "{} {}", method, uri);
+            let allowed = response.headers()["Access-Control-Allow-Methods"]
+                .to_str()
+                .unwrap();
+            assert!(allowed.split(',').any(|x| x == method), "{}", allowed);
+        }
+
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .uri("/visitors")
+                    .header("Origin", "http://example.com")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            Some("link,x-total-count,x-generation"),
+            response
+                .headers()
+                .get("Access-Control-Expose-Headers")
+                .map(|x| x.to_str().unwrap())
+        );
+    }
+
     #[tokio::test]
     async fn should_allow_override_by_env() {
         env::set_var("CORS_ORIGIN", "http://example.com");
 
         let time = ConstantTimeService::new();
         let db = testing::database().await;
-        let api = crate::api(time.clone(), db.clone());
+        let api = crate::api(time.clone(), db.clone(), Config::default());
 
         let response = api
             .oneshot(
@@ -101,7 +164,7 @@ mod test {
                 .map(|x| x.to_str().unwrap())
         );
         assert_eq!(
-            Some("GET,POST"),
+            Some("GET,POST,PUT,PATCH,DELETE"),
             response
                 .headers()
                 .get("Access-Control-Allow-Methods")
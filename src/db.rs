@@ -1,17 +1,138 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+};
+
 use chrono::{DateTime, Utc};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
 
-#[derive(sqlx::FromRow, Serialize)]
+use crate::{
+    changes::{self, Change},
+    validate,
+};
+
+// Bump when init changes the tables in a way older binaries cannot read
+pub const SCHEMA_VERSION: u32 = 1;
+
+// Registrations take the next id above both the visitors and the ids the honeypot has made up, so a dropped
+// registration can show a plausible id that no real visitor will ever get
+pub const NEXT_VISITOR_ID: &str = "(SELECT max(coalesce((SELECT max(id) FROM visitor), 0), coalesce((SELECT max(id) FROM honeypot_id), 0)) + 1)";
+
+// How a visitor entered the system. Only public registration exists so far, other ways in bring their own.
+pub const WEB: &str = "web";
+
+#[derive(Debug, Deserialize, sqlx::FromRow, Serialize)]
 pub struct Visitor {
     pub id: i32,
     pub created_at: DateTime<Utc>,
     pub ip: String,
+    pub user_agent: Option<String>,
 
     pub nick: String,
     pub group: Option<String>,
     pub email: Option<String>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+    pub consent_at: Option<DateTime<Utc>>,
+    #[serde(with = "extra")]
     pub extra: Option<String>,
+
+    pub referral: Option<String>,
+    pub admin_note: Option<String>,
+
+    pub payment_reference: Option<String>,
+    pub payment_status: Option<String>,
+
+    pub status: String,
+    #[serde(default = "web")]
+    pub source: String,
+
+    // Answers to CUSTOM_FIELDS, only filled in where they are shown
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, serde_json::Value>,
+}
+
+// Pushed by a primary from before sources were recorded
+fn web() -> String {
+    WEB.to_owned()
+}
+
+// Stored as text, but an object given at registration goes back out as that object
+mod extra {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use serde_json::Value;
+
+    pub fn serialize<S: Serializer>(
+        extra: &Option<String>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match extra.as_deref().map(serde_json::from_str::<Value>) {
+            Some(Ok(object @ Value::Object(_))) => object.serialize(serializer),
+            _ => extra.serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<String>, D::Error> {
+        match Option::<Value>::deserialize(deserializer)? {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(extra)) => Ok(Some(extra)),
+            Some(object @ Value::Object(_)) => Ok(Some(object.to_string())),
+            Some(_) => Err(serde::de::Error::custom(
+                "extra must be a string or an object",
+            )),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Finding {
+    DuplicateNick { nick: String, visitor_ids: Vec<i32> },
+}
+
+pub fn resolve_path(path: &str, base_dir: &Path, home: Option<&Path>) -> io::Result<PathBuf> {
+    let expanded = match (path.strip_prefix('~'), home) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            home.join(rest.trim_start_matches('/'))
+        }
+        (Some(rest), None) if rest.is_empty() || rest.starts_with('/') => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "cannot expand ~ without a home directory",
+            ))
+        }
+        _ => PathBuf::from(path),
+    };
+
+    std::path::absolute(base_dir.join(expanded))
+}
+
+pub fn prepare_file(path: &Path) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+
+        // SQLite needs to create its -wal and -shm files next to the database
+        let probe = parent.join(".party-api-write-test");
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&probe)?;
+        fs::remove_file(probe)?;
+    }
+
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)?;
+
+    Ok(())
 }
 
 pub async fn init(db: &SqlitePool) -> Result<(), sqlx::Error> {
@@ -31,5 +152,522 @@ CREATE TABLE IF NOT EXISTS visitor (
     .execute(db)
     .await?;
 
+    add_column(db, "visitor", "referral", "referral TEXT").await?;
+    add_column(db, "visitor", "admin_note", "admin_note TEXT").await?;
+    add_column(db, "visitor", "payment_reference", "payment_reference TEXT").await?;
+    add_column(db, "visitor", "payment_status", "payment_status TEXT").await?;
+    add_column(
+        db,
+        "visitor",
+        "status",
+        "status TEXT NOT NULL DEFAULT 'confirmed'",
+    )
+    .await?;
+    add_column(db, "visitor", "edit_token_hash", "edit_token_hash TEXT").await?;
+    add_column(db, "visitor", "user_agent", "user_agent TEXT").await?;
+    add_column(db, "visitor", "confirmed_at", "confirmed_at TEXT").await?;
+    add_column(db, "visitor", "consent_at", "consent_at TEXT").await?;
+    add_column(
+        db,
+        "visitor",
+        "source",
+        "source TEXT NOT NULL DEFAULT 'web'",
+    )
+    .await?;
+    add_column(
+        db,
+        "visitor",
+        "confirmation_token_hash",
+        "confirmation_token_hash TEXT",
+    )
+    .await?;
+
+    sqlx::query(
+        r#"
+CREATE TABLE IF NOT EXISTS reservation (
+  nick_key TEXT PRIMARY KEY,
+  nick TEXT NOT NULL,
+  email TEXT NOT NULL
+) STRICT;"#,
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query(
+        r#"
+CREATE TABLE IF NOT EXISTS reserved_nick (
+  nick_key TEXT PRIMARY KEY,
+  nick TEXT NOT NULL,
+  token_hash TEXT NOT NULL,
+  created_at TEXT NOT NULL,
+  claimed_at TEXT
+) STRICT;"#,
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query(
+        r#"
+CREATE TABLE IF NOT EXISTS draft (
+  id TEXT PRIMARY KEY,
+  data TEXT NOT NULL,
+  updated_at TEXT NOT NULL
+) STRICT;"#,
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query(
+        r#"
+CREATE TABLE IF NOT EXISTS rejected_submission (
+  id INTEGER PRIMARY KEY,
+  created_at TEXT NOT NULL,
+  ip TEXT NOT NULL,
+  status INTEGER NOT NULL,
+  code TEXT,
+  body BLOB
+) STRICT;"#,
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query(
+        r#"
+CREATE TABLE IF NOT EXISTS analytics_event (
+  id INTEGER PRIMARY KEY,
+  kind TEXT NOT NULL,
+  occurred_at TEXT NOT NULL
+) STRICT;"#,
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query(
+        r#"
+CREATE TABLE IF NOT EXISTS visitor_change (
+  generation INTEGER PRIMARY KEY AUTOINCREMENT,
+  visitor_id INTEGER NOT NULL,
+  kind TEXT NOT NULL
+) STRICT;"#,
+    )
+    .execute(db)
+    .await?;
+    add_column(db, "visitor_change", "changed_at", "changed_at TEXT").await?;
+
+    sqlx::query(
+        r#"
+CREATE TABLE IF NOT EXISTS storage_probe (
+  id INTEGER PRIMARY KEY,
+  probed_at TEXT NOT NULL
+) STRICT;"#,
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query(
+        r#"
+CREATE TABLE IF NOT EXISTS replica_state (
+  id INTEGER PRIMARY KEY CHECK (id = 1),
+  sequence INTEGER NOT NULL,
+  applied_at TEXT NOT NULL
+) STRICT;"#,
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query(
+        r#"
+CREATE TABLE IF NOT EXISTS close_action (
+  name TEXT PRIMARY KEY,
+  ran_at TEXT NOT NULL
+) STRICT;"#,
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query(
+        r#"
+CREATE TABLE IF NOT EXISTS dead_letter (
+  id INTEGER PRIMARY KEY,
+  task TEXT NOT NULL,
+  item TEXT NOT NULL,
+  error TEXT NOT NULL,
+  attempts INTEGER NOT NULL,
+  first_failed_at TEXT NOT NULL,
+  dead_at TEXT NOT NULL
+) STRICT;"#,
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query(
+        r#"
+CREATE TABLE IF NOT EXISTS invite (
+  token TEXT PRIMARY KEY,
+  batch TEXT NOT NULL,
+  created_at TEXT NOT NULL,
+  used_at TEXT,
+  visitor_id INTEGER
+) STRICT;"#,
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query(
+        r#"
+CREATE TABLE IF NOT EXISTS registration_stage (
+  visitor_id INTEGER PRIMARY KEY,
+  label TEXT NOT NULL
+) STRICT;"#,
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query(
+        r#"
+CREATE TABLE IF NOT EXISTS idempotency_key (
+  key TEXT PRIMARY KEY,
+  created_at TEXT NOT NULL,
+  status INTEGER NOT NULL,
+  location TEXT,
+  body BLOB NOT NULL
+) STRICT;"#,
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query(
+        r#"
+CREATE TABLE IF NOT EXISTS honeypot_id (
+  id INTEGER PRIMARY KEY,
+  created_at TEXT NOT NULL
+) STRICT;"#,
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query(
+        r#"
+CREATE TABLE IF NOT EXISTS visitor_field (
+  visitor_id INTEGER NOT NULL,
+  name TEXT NOT NULL,
+  value TEXT NOT NULL,
+  PRIMARY KEY (visitor_id, name)
+) STRICT;"#,
+    )
+    .execute(db)
+    .await?;
+
+    // Visitor ids can be handed out again, so answers must not outlive their visitor
+    sqlx::query(
+        r#"
+CREATE TRIGGER IF NOT EXISTS visitor_field_cleanup AFTER DELETE ON visitor
+BEGIN
+  DELETE FROM visitor_field WHERE visitor_id = OLD.id;
+END;"#,
+    )
+    .execute(db)
+    .await?;
+
+    for event in ["INSERT", "UPDATE"] {
+        sqlx::query(&format!(
+            r#"
+CREATE TRIGGER IF NOT EXISTS visitor_field_lengths_{0} BEFORE {1} ON visitor
+WHEN length(NEW.nick) > {2} OR length(NEW.email) > {3} OR length(NEW.extra) > {4}
+BEGIN
+  SELECT RAISE(ABORT, 'visitor field too long');
+END;"#,
+            event.to_lowercase(),
+            event,
+            validate::NICK_MAX_LENGTH,
+            validate::EMAIL_MAX_LENGTH,
+            validate::EXTRA_MAX_LENGTH,
+        ))
+        .execute(db)
+        .await?;
+    }
+
+    // Databases from before this index may already hold nicks differing only in case
+    match sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS visitor_nick_nocase ON visitor (nick COLLATE NOCASE)",
+    )
+    .execute(db)
+    .await
+    {
+        Err(sqlx::Error::Database(error)) if error.code().as_deref() == Some("2067") => {
+            eprintln!(
+                "nicks are not unique ignoring case, see POST /admin/consistency-check: {}",
+                error
+            )
+        }
+        result => {
+            result?;
+        }
+    }
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS visitor_created_at ON visitor (created_at)")
+        .execute(db)
+        .await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS visitor_ip ON visitor (ip)")
+        .execute(db)
+        .await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS visitor_email ON visitor (lower(email))")
+        .execute(db)
+        .await?;
+    sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS visitor_payment_reference ON visitor (payment_reference)",
+    )
+    .execute(db)
+    .await?;
+    sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS visitor_edit_token_hash ON visitor (edit_token_hash)",
+    )
+    .execute(db)
+    .await?;
+    sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS visitor_confirmation_token_hash ON visitor (confirmation_token_hash)",
+    )
+    .execute(db)
+    .await?;
+
     Ok(())
 }
+
+async fn add_column(
+    db: &SqlitePool,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), sqlx::Error> {
+    let exists: bool =
+        sqlx::query_scalar("SELECT COUNT(*) > 0 FROM pragma_table_info($1) WHERE name = $2")
+            .bind(table)
+            .bind(column)
+            .fetch_one(db)
+            .await?;
+
+    if !exists {
+        sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {}", table, definition))
+            .execute(db)
+            .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn normalize_groups(
+    db: &SqlitePool,
+    journal_length: u32,
+    now: DateTime<Utc>,
+) -> Result<u64, sqlx::Error> {
+    let groups = sqlx::query_as::<_, (i32, String)>(
+        r#"SELECT id, "group" FROM visitor WHERE "group" IS NOT NULL"#,
+    )
+    .fetch_all(db)
+    .await?;
+
+    let mut tx = db.begin().await?;
+    let mut updated = 0;
+    for (id, group) in groups {
+        let normalized = validate::normalize(&group);
+        if normalized.as_deref() != Some(group.as_str()) {
+            sqlx::query(r#"UPDATE visitor SET "group" = $1 WHERE id = $2"#)
+                .bind(normalized)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            changes::record(&mut tx, id.into(), Change::Updated, journal_length, now).await?;
+            updated += 1;
+        }
+    }
+    tx.commit().await?;
+
+    Ok(updated)
+}
+
+pub async fn check_consistency(db: &SqlitePool) -> Result<Vec<Finding>, sqlx::Error> {
+    let nicks = sqlx::query_as::<_, (i32, String)>(r#"SELECT id, nick FROM visitor ORDER BY id"#)
+        .fetch_all(db)
+        .await?;
+
+    let mut by_normalized_nick = BTreeMap::<String, Vec<i32>>::new();
+    for (id, nick) in nicks {
+        let normalized = validate::normalize(&nick)
+            .unwrap_or_default()
+            .to_lowercase();
+        by_normalized_nick.entry(normalized).or_default().push(id);
+    }
+
+    Ok(by_normalized_nick
+        .into_iter()
+        .filter(|(_, visitor_ids)| visitor_ids.len() > 1)
+        .map(|(nick, visitor_ids)| Finding::DuplicateNick { nick, visitor_ids })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path};
+
+    use crate::testing;
+
+    #[tokio::test]
+    async fn should_reject_overlong_fields_in_database() {
+        let db = testing::database().await;
+        testing::insert_visitor(&db, &"n".repeat(crate::validate::NICK_MAX_LENGTH), None).await;
+
+        let result = sqlx::query(
+            r#"INSERT INTO visitor (created_at, ip, nick, extra) VALUES (CURRENT_TIMESTAMP, '', 'Long', $1)"#,
+        )
+        .bind("x".repeat(crate::validate::EXTRA_MAX_LENGTH + 1))
+        .execute(&db)
+        .await;
+        assert!(result.is_err());
+
+        let result = sqlx::query(r#"UPDATE visitor SET email = $1"#)
+            .bind("x".repeat(crate::validate::EMAIL_MAX_LENGTH + 1))
+            .execute(&db)
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn should_expand_tilde() {
+        let path = super::resolve_path(
+            "~/party/data.db",
+            Path::new("/srv"),
+            Some(Path::new("/home/orga")),
+        )
+        .unwrap();
+        assert_eq!(path, Path::new("/home/orga/party/data.db"));
+
+        let path = super::resolve_path("~party/data.db", Path::new("/srv"), None).unwrap();
+        assert_eq!(path, Path::new("/srv/~party/data.db"));
+    }
+
+    #[test]
+    fn should_anchor_relative_paths() {
+        let path = super::resolve_path("./data.db", Path::new("/srv/party"), None).unwrap();
+        assert_eq!(path, Path::new("/srv/party/data.db"));
+
+        let path = super::resolve_path("/var/lib/data.db", Path::new("/srv/party"), None).unwrap();
+        assert_eq!(path, Path::new("/var/lib/data.db"));
+    }
+
+    #[test]
+    fn should_create_missing_parents() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested/deeper/data.db");
+
+        super::prepare_file(&path).unwrap();
+
+        assert!(path.is_file());
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn should_fail_for_unusable_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("not-a-directory");
+        fs::write(&file, "").unwrap();
+
+        assert!(super::prepare_file(&file.join("data.db")).is_err());
+    }
+
+    #[tokio::test]
+    async fn can_normalize_existing_groups() {
+        let db = testing::database().await;
+
+        testing::insert_visitor(&db, "Spacey", Some("  Fairlight   Crew ")).await;
+        testing::insert_visitor(&db, "Blank", Some("   ")).await;
+        testing::insert_visitor(&db, "Clean", Some("Razor 1911")).await;
+
+        let updated = super::normalize_groups(&db, 1000, chrono::Utc::now())
+            .await
+            .unwrap();
+        assert_eq!(updated, 2);
+
+        let groups: Vec<Option<String>> =
+            sqlx::query_scalar(r#"SELECT "group" FROM visitor ORDER BY id"#)
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert_eq!(
+            groups,
+            vec![
+                Some("Fairlight Crew".to_owned()),
+                None,
+                Some("Razor 1911".to_owned())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_start_with_nicks_differing_in_case() {
+        let db = testing::database().await;
+        sqlx::query("DROP INDEX visitor_nick_nocase")
+            .execute(&db)
+            .await
+            .unwrap();
+        testing::insert_visitor(&db, "Foo", None).await;
+        testing::insert_visitor(&db, "FOO", None).await;
+
+        super::init(&db).await.unwrap();
+
+        testing::insert_visitor(&db, "Bar", None).await;
+        sqlx::query("DELETE FROM visitor WHERE nick = 'FOO'")
+            .execute(&db)
+            .await
+            .unwrap();
+        super::init(&db).await.unwrap();
+        assert!(
+            sqlx::query("INSERT INTO visitor (created_at, ip, nick) VALUES ('', '', 'bar')")
+                .execute(&db)
+                .await
+                .is_err(),
+            "index is created once the duplicates are gone"
+        );
+    }
+
+    #[tokio::test]
+    async fn should_backfill_registration_source() {
+        let db = testing::database().await;
+        sqlx::query("ALTER TABLE visitor DROP COLUMN source")
+            .execute(&db)
+            .await
+            .unwrap();
+        testing::insert_visitor(&db, "Old", None).await;
+
+        super::init(&db).await.unwrap();
+        let visitor: super::Visitor = sqlx::query_as("SELECT * FROM visitor")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(visitor.source, super::WEB);
+
+        // As pushed by a replica primary that does not know about sources yet
+        let mut pushed = serde_json::to_value(&visitor).unwrap();
+        pushed.as_object_mut().unwrap().remove("source");
+        let pushed: super::Visitor = serde_json::from_value(pushed).unwrap();
+        assert_eq!(pushed.source, super::WEB);
+    }
+
+    #[tokio::test]
+    async fn should_find_duplicate_normalized_nicks() {
+        let db = testing::database().await;
+
+        testing::insert_visitor(&db, "Slummy", None).await;
+        testing::insert_visitor(&db, "Unique", None).await;
+        testing::insert_visitor(&db, " slummy", None).await;
+        testing::insert_visitor(&db, "SLUMMY\t", None).await;
+
+        let findings = super::check_consistency(&db).await.unwrap();
+        assert_eq!(
+            findings,
+            vec![super::Finding::DuplicateNick {
+                nick: "slummy".into(),
+                visitor_ids: vec![1, 3, 4]
+            }]
+        );
+    }
+}
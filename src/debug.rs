@@ -0,0 +1,290 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::{self, Body},
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+
+use crate::{error::ApiError, proxy::TrustedProxies, time::TimeService, timing::Timings, ApiState};
+
+pub const MAX_IPS: usize = 5;
+pub const MAX_BODY_BYTES: usize = 4 * 1024;
+pub const MAX_TTL_SECONDS: u32 = 24 * 60 * 60;
+// axum's default body limit, which the handlers would apply anyway
+const MAX_READ_BYTES: usize = 2 * 1024 * 1024;
+
+// An IPv4 address seen through an IPv6 socket is the same client as the plain one
+#[derive(Clone, Default)]
+pub struct DebugIps {
+    until: Arc<Mutex<HashMap<IpAddr, DateTime<Utc>>>>,
+}
+
+impl DebugIps {
+    pub fn enable(&self, ip: IpAddr, until: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        let ip = ip.to_canonical();
+        let mut ips = self.until.lock().unwrap();
+        ips.retain(|_, until| *until > now);
+        if ips.len() >= MAX_IPS && !ips.contains_key(&ip) {
+            return false;
+        }
+        ips.insert(ip, until);
+        true
+    }
+
+    pub fn disable(&self, ip: IpAddr) -> bool {
+        self.until
+            .lock()
+            .unwrap()
+            .remove(&ip.to_canonical())
+            .is_some()
+    }
+
+    fn is_enabled(&self, ip: IpAddr, now: DateTime<Utc>) -> bool {
+        self.until
+            .lock()
+            .unwrap()
+            .get(&ip.to_canonical())
+            .is_some_and(|until| *until > now)
+    }
+}
+
+pub async fn trace<T: TimeService>(
+    State(state): State<ApiState<T>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(ip) = state
+        .config
+        .trusted_proxies
+        .client_ip(&request)
+        .filter(|ip| state.debug_ips.is_enabled(*ip, state.time.clone().now()))
+    else {
+        return next.run(request).await;
+    };
+
+    // The body is read here to be logged, before any handler limits it
+    let (parts, body) = request.into_parts();
+    let bytes = match body::to_bytes(body, MAX_READ_BYTES).await {
+        Ok(bytes) => bytes,
+        Err(error) => {
+            eprintln!("[debug {}] failed to read body: {}", ip, error);
+            return ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, "request body is too large")
+                .into_response();
+        }
+    };
+    for line in request_lines(
+        parts.method.as_str(),
+        &parts.uri.to_string(),
+        &parts.headers,
+        &bytes,
+    ) {
+        eprintln!("[debug {}] {}", ip, line);
+    }
+
+    let mut response = next
+        .run(Request::from_parts(parts, Body::from(bytes)))
+        .await;
+
+    eprintln!("[debug {}] < {}", ip, response.status());
+    if let Some(timings) = response.extensions().get::<Timings>() {
+        let header = timings.header();
+        eprintln!(
+            "[debug {}] < timing: {}",
+            ip,
+            header.to_str().unwrap_or_default()
+        );
+        response.headers_mut().insert(crate::timing::HEADER, header);
+    }
+
+    response
+}
+
+pub fn client_ip(request: &Request, proxies: &TrustedProxies) -> String {
+    proxies
+        .client_ip(request)
+        .map(|ip| ip.to_string())
+        .unwrap_or_default()
+}
+
+fn request_lines(method: &str, uri: &str, headers: &HeaderMap, body: &[u8]) -> Vec<String> {
+    let mut lines = vec![format!("> {} {}", method, uri)];
+    for (name, value) in headers {
+        let value = match name == header::AUTHORIZATION {
+            true => HeaderValue::from_static("[redacted]"),
+            false => value.clone(),
+        };
+        lines.push(format!(
+            "> {}: {}",
+            name,
+            String::from_utf8_lossy(value.as_bytes())
+        ));
+    }
+    if !body.is_empty() {
+        lines.push(format!(
+            "> {}",
+            String::from_utf8_lossy(&body[..body.len().min(MAX_BODY_BYTES)])
+        ));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use axum::{
+        body::Body,
+        extract::ConnectInfo,
+        http::{HeaderMap, HeaderValue},
+    };
+    use chrono::{Duration, Utc};
+    use http_body_util::BodyExt;
+    use hyper::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{admin::AdminKeys, config::Config, testing, time::ConstantTimeService};
+
+    #[test]
+    fn should_redact_authorization() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Authorization", HeaderValue::from_static("Bearer secret"));
+        headers.insert("User-Agent", HeaderValue::from_static("kiosk/1.0"));
+
+        let lines = request_lines("POST", "/register", &headers, b"{\"nick\":\"Kiosk\"}");
+        let output = lines.join("\n");
+        assert!(!output.contains("secret"));
+        assert!(output.contains("authorization: [redacted]"));
+        assert!(output.contains("user-agent: kiosk/1.0"));
+        assert!(output.contains(r#"{"nick":"Kiosk"}"#));
+    }
+
+    #[test]
+    fn should_cap_and_expire_debugged_ips() {
+        let ips = DebugIps::default();
+        let now = Utc::now();
+
+        let ip = |i: u8| IpAddr::from([10, 0, 0, i]);
+
+        for i in 0..MAX_IPS as u8 {
+            assert!(ips.enable(ip(i), now + Duration::minutes(5), now));
+        }
+        assert!(!ips.enable(ip(99), now + Duration::minutes(5), now));
+        assert!(ips.is_enabled("::ffff:10.0.0.1".parse().unwrap(), now));
+
+        let later = now + Duration::minutes(6);
+        assert!(!ips.is_enabled(ip(0), later));
+        assert!(ips.enable(ip(99), later + Duration::minutes(5), later));
+    }
+
+    #[tokio::test]
+    async fn should_only_trace_debugged_ip() {
+        let db = testing::database().await;
+        let api = crate::api(
+            ConstantTimeService::new(),
+            db,
+            Config {
+                admin_keys: AdminKeys::new(vec!["key".into()]),
+                ..Config::default()
+            },
+        );
+
+        let response = api
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .header("Authorization", "Bearer key")
+                    .header("Content-Type", "application/json")
+                    .method("POST")
+                    .uri("/admin/debug-ip")
+                    .body(Body::from(r#"{"ip":"10.0.0.1","ttl_seconds":300}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        for (ip, traced) in [("10.0.0.1", true), ("10.0.0.2", false)] {
+            let response = api
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .extension(ConnectInfo(SocketAddr::new(ip.parse().unwrap(), 4711)))
+                        .method("GET")
+                        .uri("/visitors")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(
+                response.headers().contains_key(crate::timing::HEADER),
+                traced,
+                "{}",
+                ip
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn should_refuse_bad_debug_requests() {
+        let db = testing::database().await;
+        let api = crate::api(
+            ConstantTimeService::new(),
+            db,
+            Config {
+                admin_keys: AdminKeys::new(vec!["key".into()]),
+                ..Config::default()
+            },
+        );
+        let send = |body: String| {
+            api.clone().oneshot(
+                Request::builder()
+                    .extension(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 4711))))
+                    .header("Authorization", "Bearer key")
+                    .header("Content-Type", "application/json")
+                    .method("POST")
+                    .uri("/admin/debug-ip")
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+        };
+
+        for (body, status) in [
+            (
+                r#"{"ip":"garbage","ttl_seconds":300}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                r#"{"ip":" 10.0.0.1","ttl_seconds":300}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                r#"{"ip":"10.0.0.1","ttl_seconds":86401}"#,
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                r#"{"ip":"::ffff:10.0.0.1","ttl_seconds":300}"#,
+                StatusCode::NO_CONTENT,
+            ),
+        ] {
+            let response = send(body.into()).await.unwrap();
+            assert_eq!(response.status(), status, "{}", body);
+        }
+
+        // The mapped address enabled 10.0.0.1 itself, whose oversized bodies are not read in full
+        let response = send("x".repeat(MAX_READ_BYTES + 1)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, r#"{"error":"request body is too large"}"#);
+    }
+}
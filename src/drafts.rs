@@ -0,0 +1,156 @@
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use sqlx::SqlitePool;
+
+use crate::{error::ApiError, json::Json, misses, supervisor::Stop, time::TimeService, ApiState};
+
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize)]
+pub struct DraftRequest {
+    id: String,
+    data: Value,
+}
+
+pub async fn save<T: TimeService>(
+    State(state): State<ApiState<T>>,
+    Json(request): Json<DraftRequest>,
+) -> Result<StatusCode, ApiError> {
+    if !is_uuid(&request.id) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "draft id must be a UUID",
+        ));
+    }
+
+    let data = request.data.to_string();
+    if data.len() > state.config.draft_max_bytes {
+        return Err(ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!(
+                "draft must be at most {} bytes",
+                state.config.draft_max_bytes
+            ),
+        ));
+    }
+
+    sqlx::query(
+        r#"INSERT INTO draft (id, data, updated_at) VALUES ($1, $2, $3)
+           ON CONFLICT (id) DO UPDATE SET data = excluded.data, updated_at = excluded.updated_at"#,
+    )
+    .bind(request.id.to_lowercase())
+    .bind(data)
+    .bind(state.time.now())
+    .execute(&state.db)
+    .await?;
+    state
+        .misses
+        .forget(misses::Kind::Draft, &request.id.to_lowercase());
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn load<T: TimeService>(
+    Path(id): Path<String>,
+    State(state): State<ApiState<T>>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let id = id.to_lowercase();
+    let now = state.time.clone().now();
+    let not_found = || ApiError::new(StatusCode::NOT_FOUND, "draft not found");
+    if state.misses.is_cached(misses::Kind::Draft, &id, now) {
+        return Err(not_found());
+    }
+
+    let Some(data) = sqlx::query_scalar::<_, String>(
+        r#"SELECT data FROM draft WHERE id = $1 AND updated_at > $2"#,
+    )
+    .bind(&id)
+    .bind(now - state.config.draft_ttl)
+    .fetch_optional(&state.db)
+    .await?
+    else {
+        state.misses.remember(misses::Kind::Draft, &id, now);
+        return Err(not_found());
+    };
+
+    Ok((StatusCode::OK, Json(serde_json::from_str(&data)?)))
+}
+
+pub async fn sweep(
+    db: &SqlitePool,
+    now: DateTime<Utc>,
+    ttl: chrono::Duration,
+) -> Result<u64, sqlx::Error> {
+    Ok(sqlx::query(r#"DELETE FROM draft WHERE updated_at <= $1"#)
+        .bind(now - ttl)
+        .execute(db)
+        .await?
+        .rows_affected())
+}
+
+pub async fn run_sweep(
+    time: impl TimeService,
+    db: SqlitePool,
+    ttl: chrono::Duration,
+    mut stop: Stop,
+) {
+    while stop.sleep(SWEEP_INTERVAL).await {
+        if let Err(error) = sweep(&db, time.clone().now(), ttl).await {
+            eprintln!("failed to sweep expired drafts: {}", error);
+        }
+    }
+}
+
+fn is_uuid(id: &str) -> bool {
+    id.len() == 36
+        && id.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+
+    use crate::testing;
+
+    #[test]
+    fn should_validate_uuid() {
+        assert!(super::is_uuid("0f8fad5b-d9cb-469f-a165-70867728950e"));
+        assert!(super::is_uuid("0F8FAD5B-D9CB-469F-A165-70867728950E"));
+        assert!(!super::is_uuid("0f8fad5bd9cb469fa16570867728950e"));
+        assert!(!super::is_uuid("../../../../../../../etc/passwd1234"));
+    }
+
+    #[tokio::test]
+    async fn should_sweep_expired_drafts() {
+        let db = testing::database().await;
+        let now = Utc::now();
+
+        for (id, updated_at) in [("old", now - Duration::hours(25)), ("new", now)] {
+            sqlx::query("INSERT INTO draft (id, data, updated_at) VALUES ($1, '{}', $2)")
+                .bind(id)
+                .bind(updated_at)
+                .execute(&db)
+                .await
+                .unwrap();
+        }
+
+        let swept = super::sweep(&db, now, Duration::hours(24)).await.unwrap();
+        assert_eq!(swept, 1);
+
+        let remaining: Vec<String> = sqlx::query_scalar("SELECT id FROM draft")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+        assert_eq!(remaining, vec!["new"]);
+    }
+}
@@ -0,0 +1,461 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+use chrono::{DateTime, Utc};
+use serde::{de::IgnoredAny, Deserialize, Deserializer};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::{Sqlite, Transaction};
+
+use crate::{
+    analytics, changes, config::Config, confirm, error::ApiError, json::Json, time::TimeService,
+    transition, validate, ApiState,
+};
+
+// Absent leaves a field alone, null clears it
+#[derive(Deserialize)]
+pub struct EditRequest {
+    nick: Option<IgnoredAny>,
+    #[serde(default, deserialize_with = "present")]
+    group: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    email: Option<Option<String>>,
+    consent: Option<bool>,
+    #[serde(default, deserialize_with = "present")]
+    extra: Option<Option<Value>>,
+}
+
+fn present<'de, D: Deserializer<'de>, T: Deserialize<'de>>(
+    deserializer: D,
+) -> Result<Option<Option<T>>, D::Error> {
+    Option::deserialize(deserializer).map(Some)
+}
+
+// Only the hash is stored, so a leaked database or snapshot cannot be used to edit registrations
+pub async fn issue(tx: &mut Transaction<'static, Sqlite>, id: i64) -> Result<String, sqlx::Error> {
+    let token: String = sqlx::query_scalar("SELECT lower(hex(randomblob(16)))")
+        .fetch_one(&mut **tx)
+        .await?;
+    sqlx::query("UPDATE visitor SET edit_token_hash = $1 WHERE id = $2")
+        .bind(hash(&token))
+        .bind(id)
+        .execute(&mut **tx)
+        .await?;
+    Ok(token)
+}
+
+fn hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+// The earlier of SELF_EDIT_WINDOW_HOURS after registering and SELF_EDIT_CUTOFF_AT, when either is set
+pub fn deadline(config: &Config, created_at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let window = config.edit_window.map(|window| created_at + window);
+    [window, config.edit_cutoff].into_iter().flatten().min()
+}
+
+fn ensure_editable(
+    config: &Config,
+    created_at: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> Result<(), ApiError> {
+    match deadline(config, created_at) {
+        Some(deadline) if now >= deadline => Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "registration can no longer be changed",
+        )
+        .with_code("edit_window_closed")
+        .with_detail("edit_deadline", deadline)),
+        _ => Ok(()),
+    }
+}
+
+fn not_found() -> ApiError {
+    ApiError::new(StatusCode::NOT_FOUND, "registration not found")
+}
+
+// Malformed tokens are answered like unknown ones, without a lookup
+fn parse(token: &str) -> Option<String> {
+    let token = token.to_lowercase();
+    match token.len() == 32 && token.bytes().all(|x| x.is_ascii_hexdigit()) {
+        true => Some(token),
+        false => None,
+    }
+}
+
+pub async fn edit<T: TimeService>(
+    Path(token): Path<String>,
+    State(state): State<ApiState<T>>,
+    Json(request): Json<EditRequest>,
+) -> Result<StatusCode, ApiError> {
+    let token = parse(&token).ok_or_else(not_found)?;
+    if request.nick.is_some() {
+        return Err(
+            ApiError::new(StatusCode::BAD_REQUEST, "nick cannot be changed")
+                .with_detail("field", "nick"),
+        );
+    }
+
+    let group = request
+        .group
+        .map(|group| {
+            let group = validate::group(group, state.config.group_max_length)?;
+            validate::allowed_group(group, state.config.allowed_groups.as_deref())
+        })
+        .transpose()?;
+    let email = request.email.map(validate::email).transpose()?;
+    let extra = request.extra.map(validate::extra).transpose()?;
+
+    let Some((id, created_at)) = sqlx::query_as::<_, (i32, DateTime<Utc>)>(
+        "SELECT id, created_at FROM visitor WHERE edit_token_hash = $1",
+    )
+    .bind(hash(&token))
+    .fetch_optional(&state.db)
+    .await?
+    else {
+        return Err(not_found());
+    };
+    ensure_editable(&state.config, created_at, state.time.clone().now())?;
+
+    let mut tx = transition::begin(&state.db, id).await?;
+    let current: (Option<String>, Option<String>, Option<String>) =
+        sqlx::query_as(r#"SELECT "group", email, extra FROM visitor WHERE id = $1"#)
+            .bind(id)
+            .fetch_one(&mut *tx)
+            .await?;
+    let target = (
+        group.unwrap_or_else(|| current.0.clone()),
+        email.unwrap_or_else(|| current.1.clone()),
+        extra.unwrap_or_else(|| current.2.clone()),
+    );
+    if transition::Outcome::of(&current, &target).is_already() {
+        return Ok(StatusCode::NO_CONTENT);
+    }
+    if target.1 != current.1 {
+        validate::consent(target.1.as_deref(), request.consent)?;
+    }
+    crate::ensure_unique_email(&mut tx, &state.config, target.1.as_deref(), Some(id.into()))
+        .await?;
+
+    sqlx::query(r#"UPDATE visitor SET "group" = $1, email = $2, extra = $3 WHERE id = $4"#)
+        .bind(&target.0)
+        .bind(&target.1)
+        .bind(&target.2)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    // A new address has to be confirmed again, and was consented to just now
+    if target.1 != current.1 {
+        match target.1 {
+            Some(_) => {
+                confirm::issue(&mut tx, id.into()).await?;
+            }
+            None => {
+                sqlx::query("UPDATE visitor SET confirmed_at = NULL, confirmation_token_hash = NULL WHERE id = $1")
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        sqlx::query("UPDATE visitor SET consent_at = $1 WHERE id = $2")
+            .bind(target.1.is_some().then(|| state.time.clone().now()))
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    changes::record(
+        &mut tx,
+        id.into(),
+        changes::Change::Updated,
+        state.config.change_journal_length,
+        state.time.clone().now(),
+    )
+    .await?;
+    tx.commit().await?;
+    eprintln!("[edit] visitor {} edited their registration", id);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Deleting the row is all it takes to free a place under VISITOR_LIMIT, which counts confirmed visitors as they are
+pub async fn cancel<T: TimeService>(
+    Path(token): Path<String>,
+    State(state): State<ApiState<T>>,
+) -> Result<StatusCode, ApiError> {
+    let token = parse(&token).ok_or_else(not_found)?;
+
+    let mut tx = state.db.begin().await?;
+    let Some((id, created_at)) = sqlx::query_as::<_, (i64, DateTime<Utc>)>(
+        "SELECT id, created_at FROM visitor WHERE edit_token_hash = $1",
+    )
+    .bind(hash(&token))
+    .fetch_optional(&mut *tx)
+    .await?
+    else {
+        return Err(not_found());
+    };
+    ensure_editable(&state.config, created_at, state.time.clone().now())?;
+    sqlx::query("DELETE FROM visitor WHERE id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    analytics::record(
+        &mut tx,
+        analytics::Event::RegistrationDeleted,
+        state.time.clone().now(),
+    )
+    .await?;
+    changes::record(
+        &mut tx,
+        id,
+        changes::Change::Deleted,
+        state.config.change_journal_length,
+        state.time.clone().now(),
+    )
+    .await?;
+    tx.commit().await?;
+    eprintln!("[cancel] visitor {} withdrew their registration", id);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use axum::{body::Body, extract::ConnectInfo, Router};
+    use http_body_util::BodyExt;
+    use hyper::Request;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{config::Config, testing, time::ConstantTimeService};
+
+    async fn send(api: &Router, method: &str, uri: &str, body: &str) -> (StatusCode, Value) {
+        let response = api
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 8080))))
+                    .header("Content-Type", "application/json")
+                    .method(method)
+                    .uri(uri)
+                    .body(Body::from(body.to_owned()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    async fn stored(
+        db: &sqlx::SqlitePool,
+    ) -> (String, Option<String>, Option<String>, Option<String>) {
+        sqlx::query_as(r#"SELECT nick, "group", email, extra FROM visitor"#)
+            .fetch_one(db)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_edit_with_token() {
+        let db = testing::database().await;
+        let api = crate::api(ConstantTimeService::new(), db.clone(), Config::default());
+
+        let (status, registration) = send(
+            &api,
+            "POST",
+            "/register",
+            r#"{"nick":"Razor","group":"Razor 1191","email":"razor@example.com","consent":true,"extra":"Vegan"}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let token = registration["edit_token"].as_str().unwrap();
+        let stored_hash: String = sqlx::query_scalar("SELECT edit_token_hash FROM visitor")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(stored_hash, hash(token));
+
+        let uri = format!("/register/{}", token.to_uppercase());
+        let (status, _) = send(
+            &api,
+            "PATCH",
+            &uri,
+            r#"{"group":" Razor  1911 ","extra":null}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(
+            stored(&db).await,
+            (
+                "Razor".into(),
+                Some("Razor 1911".into()),
+                Some("razor@example.com".into()),
+                None
+            )
+        );
+
+        let (status, body) = send(&api, "PATCH", &uri, r#"{"email":"razor","consent":true}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["field"], "email");
+        let (status, body) = send(&api, "PATCH", &uri, r#"{"email":"new@example.com"}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "consent_required");
+
+        let updates: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM visitor_change WHERE kind = 'updated'")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(updates, 1);
+    }
+
+    #[tokio::test]
+    async fn should_close_edits_after_window_or_cutoff() {
+        let db = testing::database().await;
+        let registered = DateTime::parse_from_rfc3339("2024-06-01T12:00:00Z")
+            .unwrap()
+            .to_utc();
+        let config = Config {
+            edit_window: Some(chrono::Duration::hours(48)),
+            edit_cutoff: Some(registered + chrono::Duration::hours(72)),
+            ..Config::default()
+        };
+        let at = |hours: i64, seconds: i64| {
+            let now =
+                registered + chrono::Duration::hours(hours) + chrono::Duration::seconds(seconds);
+            crate::api(ConstantTimeService::at(now), db.clone(), config.clone())
+        };
+
+        let (status, early) = send(&at(0, 0), "POST", "/register", r#"{"nick":"Razor"}"#).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(early["edit_deadline"], "2024-06-03T12:00:00Z");
+        let (_, late) = send(&at(48, 0), "POST", "/register", r#"{"nick":"Fairlight"}"#).await;
+        assert_eq!(late["edit_deadline"], "2024-06-04T12:00:00Z");
+
+        let mut tx = db.begin().await.unwrap();
+        let confirmation = confirm::issue(&mut tx, 1).await.unwrap();
+        tx.commit().await.unwrap();
+        let (status, confirmed) =
+            send(&at(50, 0), "GET", &format!("/confirm/{}", confirmation), "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(confirmed["edit_deadline"], "2024-06-03T12:00:00Z");
+
+        let early = format!("/register/{}", early["edit_token"].as_str().unwrap());
+        let late = format!("/register/{}", late["edit_token"].as_str().unwrap());
+        let edit = r#"{"group":"Razor 1911"}"#;
+        let (status, _) = send(&at(48, -1), "PATCH", &early, edit).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        for method in ["PATCH", "DELETE"] {
+            let (status, body) = send(&at(48, 0), method, &early, edit).await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{}", method);
+            assert_eq!(body["code"], "edit_window_closed");
+            assert_eq!(body["edit_deadline"], "2024-06-03T12:00:00Z");
+        }
+
+        let (status, _) = send(&at(72, -1), "PATCH", &late, edit).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, body) = send(&at(72, 0), "DELETE", &late, "").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["edit_deadline"], "2024-06-04T12:00:00Z");
+        let (status, _) = send(&at(72, -1), "DELETE", &late, "").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn should_hide_registrations_behind_wrong_tokens() {
+        let db = testing::database().await;
+        let api = crate::api(ConstantTimeService::new(), db, Config::default());
+        let (_, registration) = send(&api, "POST", "/register", r#"{"nick":"Razor"}"#).await;
+        let token = registration["edit_token"].as_str().unwrap();
+
+        let wrong = match token.starts_with('0') {
+            true => format!("1{}", &token[1..]),
+            false => format!("0{}", &token[1..]),
+        };
+        for token in [wrong.as_str(), &token[1..], "not-a-token"] {
+            let (status, body) = send(
+                &api,
+                "PATCH",
+                &format!("/register/{}", token),
+                r#"{"group":"Fairlight"}"#,
+            )
+            .await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{}", token);
+            assert_eq!(body["error"], "registration not found", "{}", token);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_not_change_nick() {
+        let db = testing::database().await;
+        let api = crate::api(ConstantTimeService::new(), db.clone(), Config::default());
+        let (_, registration) = send(&api, "POST", "/register", r#"{"nick":"Razor"}"#).await;
+        let uri = format!("/register/{}", registration["edit_token"].as_str().unwrap());
+
+        let (status, body) = send(
+            &api,
+            "PATCH",
+            &uri,
+            r#"{"nick":"Fairlight","group":"Fairlight"}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["field"], "nick");
+        assert_eq!(stored(&db).await, ("Razor".into(), None, None, None));
+    }
+
+    #[tokio::test]
+    async fn should_cancel_with_token() {
+        let db = testing::database().await;
+        let api = crate::api(
+            ConstantTimeService::new(),
+            db.clone(),
+            Config {
+                visitor_limit: Some(1),
+                ..Config::default()
+            },
+        );
+        let (_, registration) = send(&api, "POST", "/register", r#"{"nick":"Razor"}"#).await;
+        let uri = format!("/register/{}", registration["edit_token"].as_str().unwrap());
+        let (status, _) = send(&api, "POST", "/register", r#"{"nick":"Fairlight"}"#).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        for expected in [StatusCode::NO_CONTENT, StatusCode::NOT_FOUND] {
+            let (status, _) = send(&api, "DELETE", &uri, "").await;
+            assert_eq!(status, expected);
+        }
+        let (status, _) = send(&api, "POST", "/register", r#"{"nick":"Fairlight"}"#).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let deletions: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM visitor_change WHERE kind = 'deleted'")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(deletions, 1);
+    }
+
+    #[tokio::test]
+    async fn should_rate_limit_token_probing() {
+        let db = testing::database().await;
+        let api = crate::api(ConstantTimeService::new(), db, Config::default());
+
+        for (guess, expected) in [
+            (1, StatusCode::NOT_FOUND),
+            (2, StatusCode::NOT_FOUND),
+            (3, StatusCode::NOT_FOUND),
+            (4, StatusCode::TOO_MANY_REQUESTS),
+        ] {
+            let uri = format!("/register/{:032x}", guess);
+            let (status, _) = send(&api, "DELETE", &uri, "").await;
+            assert_eq!(status, expected, "{}", guess);
+        }
+    }
+}
@@ -1,39 +1,117 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, collections::BTreeMap};
 
 use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
-    Json,
 };
 use serde::Serialize;
+use serde_json::{Map, Value};
 use tower_governor::GovernorError;
 
-#[derive(Serialize)]
+use crate::{json::Json, storage};
+
+#[derive(Clone, Copy)]
+pub(crate) struct ErrorCode(pub &'static str);
+
+#[derive(Debug, Serialize)]
 pub(crate) struct ApiError {
     #[serde(skip_serializing)]
-    code: StatusCode,
+    status: StatusCode,
     error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+    #[serde(flatten)]
+    details: Map<String, Value>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, error: impl Into<String>) -> Self {
+        Self {
+            status,
+            error: error.into(),
+            code: None,
+            details: Map::new(),
+        }
+    }
+
+    // Every field that failed validation at once, so a form can mark them all
+    pub fn validation(fields: BTreeMap<String, String>) -> Self {
+        Self::new(StatusCode::UNPROCESSABLE_ENTITY, "validation failed")
+            .with_detail("fields", fields)
+    }
+
+    // The field a validation error is about, with the message no longer naming it
+    pub fn field_failure(&self) -> Option<(String, String)> {
+        let field = self.details.get("field")?.as_str()?;
+        let message = self
+            .error
+            .strip_prefix(field)
+            .map(str::trim_start)
+            .unwrap_or(&self.error);
+        Some((field.to_owned(), message.to_owned()))
+    }
+
+    pub fn code(&self) -> Option<&'static str> {
+        self.code
+    }
+
+    pub fn nick_taken() -> Self {
+        Self::new(StatusCode::CONFLICT, "nick is already registered").with_code("nick_taken")
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_detail(mut self, key: &str, value: impl Serialize) -> Self {
+        self.details.insert(
+            key.to_owned(),
+            serde_json::to_value(value).unwrap_or_default(),
+        );
+        self
+    }
 }
 
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
-        (self.code, Json(self)).into_response()
+        let code = self.code.map(ErrorCode);
+        let mut response = (self.status, Json(self)).into_response();
+        if let Some(code) = code {
+            response.extensions_mut().insert(code);
+        }
+        response
     }
 }
 
 impl From<sqlx::Error> for ApiError {
     fn from(error: sqlx::Error) -> Self {
+        if storage::is_unavailable(&error) {
+            eprintln!("database write failed: {}", error);
+            return storage::error();
+        }
+
         match error {
             sqlx::Error::Database(db_error) if db_error.code() == Some(Cow::Borrowed("2067")) => {
-                Self {
-                    code: StatusCode::BAD_REQUEST,
-                    error: db_error.to_string(),
+                match db_error.message().ends_with("visitor.nick") {
+                    true => Self::nick_taken(),
+                    false => {
+                        Self::new(StatusCode::CONFLICT, db_error.to_string()).with_code("conflict")
+                    }
                 }
             }
-            _ => Self {
-                code: StatusCode::INTERNAL_SERVER_ERROR,
-                error: error.to_string(),
-            },
+            _ => Self::new(StatusCode::INTERNAL_SERVER_ERROR, error.to_string()),
+        }
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(error: serde_json::Error) -> Self {
+        match error.classify() {
+            serde_json::error::Category::Data => {
+                Self::new(StatusCode::UNPROCESSABLE_ENTITY, error.to_string())
+            }
+            _ => Self::new(StatusCode::BAD_REQUEST, error.to_string()),
         }
     }
 }
@@ -41,14 +119,43 @@ impl From<sqlx::Error> for ApiError {
 impl From<GovernorError> for ApiError {
     fn from(error: GovernorError) -> Self {
         match error {
-            GovernorError::TooManyRequests { .. } => Self {
-                code: StatusCode::TOO_MANY_REQUESTS,
-                error: "too many requests".to_owned(),
-            },
-            _ => Self {
-                code: StatusCode::INTERNAL_SERVER_ERROR,
-                error: error.to_string(),
-            },
+            GovernorError::TooManyRequests { .. } => {
+                Self::new(StatusCode::TOO_MANY_REQUESTS, "too many requests")
+            }
+            _ => Self::new(StatusCode::INTERNAL_SERVER_ERROR, error.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ApiError;
+    use crate::testing;
+
+    #[tokio::test]
+    async fn should_only_report_nick_violations_as_taken() {
+        let db = testing::database().await;
+        let insert = |nick: &'static str, reference: &'static str| {
+            sqlx::query(
+                "INSERT INTO visitor (created_at, ip, nick, payment_reference) VALUES ('', '', $1, $2)",
+            )
+            .bind(nick)
+            .bind(reference)
+            .execute(&db)
+        };
+        insert("Slummy", "10016").await.unwrap();
+
+        for (nick, reference, code) in [
+            ("SLUMMY", "10029", "nick_taken"),
+            ("Truck", "10016", "conflict"),
+        ] {
+            let error = ApiError::from(insert(nick, reference).await.unwrap_err());
+            assert_eq!(
+                serde_json::to_value(error).unwrap()["code"],
+                code,
+                "{}",
+                nick
+            );
         }
     }
 }
@@ -0,0 +1,206 @@
+use std::{collections::BTreeMap, env};
+
+use axum::http::StatusCode;
+use serde::Deserialize;
+use serde_json::Value;
+use sqlx::{Sqlite, SqlitePool, Transaction};
+
+use crate::{db, error::ApiError, json, validate};
+
+const TEXT_MAX_LENGTH: usize = 256;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Field {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: Kind,
+    #[serde(default)]
+    pub required: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Kind {
+    Text,
+    Bool,
+    Choice { choices: Vec<String> },
+}
+
+// The party's own questions, answered under "fields" when registering
+#[derive(Clone, Default)]
+pub struct CustomFields(Vec<Field>);
+
+impl CustomFields {
+    pub fn new(fields: Vec<Field>) -> Self {
+        Self(fields)
+    }
+
+    pub fn from_env() -> Self {
+        let Ok(fields) = env::var("CUSTOM_FIELDS") else {
+            return Self::default();
+        };
+        Self::new(
+            serde_json::from_str(&fields)
+                .unwrap_or_else(|error| panic!("bad CUSTOM_FIELDS: {}", error)),
+        )
+    }
+
+    // Null and empty text count as not answered
+    pub fn validate(
+        &self,
+        submitted: Option<BTreeMap<String, Value>>,
+    ) -> Result<Vec<(String, Value)>, ApiError> {
+        let mut submitted = submitted.unwrap_or_default();
+        if let Some(name) = submitted
+            .keys()
+            .find(|name| !self.0.iter().any(|field| &field.name == *name))
+        {
+            return Err(invalid(name, format!("{} is not a known field", name)));
+        }
+
+        let mut answers = Vec::new();
+        for field in &self.0 {
+            let answer = match (submitted.remove(&field.name), &field.kind) {
+                (None | Some(Value::Null), _) => None,
+                (Some(Value::String(text)), Kind::Text) => {
+                    let text = text.trim();
+                    validate::length(&field.name, text, TEXT_MAX_LENGTH)?;
+                    (!text.is_empty()).then(|| Value::from(text))
+                }
+                (Some(value), Kind::Bool) if json::as_flag(&value).is_some() => {
+                    json::as_flag(&value).map(Value::Bool)
+                }
+                (Some(Value::String(choice)), Kind::Choice { choices })
+                    if choices.contains(&choice) =>
+                {
+                    Some(Value::String(choice))
+                }
+                (Some(_), kind) => {
+                    let expected = match kind {
+                        Kind::Text => "text".to_owned(),
+                        Kind::Bool => "true or false".to_owned(),
+                        Kind::Choice { choices } => format!("one of {}", choices.join(", ")),
+                    };
+                    return Err(invalid(
+                        &field.name,
+                        format!("{} must be {}", field.name, expected),
+                    ));
+                }
+            };
+            match answer {
+                Some(answer) => answers.push((field.name.clone(), answer)),
+                None if field.required => {
+                    return Err(invalid(&field.name, format!("{} is required", field.name)))
+                }
+                None => {}
+            }
+        }
+        Ok(answers)
+    }
+}
+
+fn invalid(name: &str, message: String) -> ApiError {
+    ApiError::new(StatusCode::BAD_REQUEST, message).with_detail("field", name)
+}
+
+// Values are kept as JSON text, so a bool comes back as a bool
+pub async fn store(
+    tx: &mut Transaction<'static, Sqlite>,
+    visitor_id: i64,
+    answers: &[(String, Value)],
+) -> Result<(), sqlx::Error> {
+    for (name, value) in answers {
+        sqlx::query("INSERT INTO visitor_field (visitor_id, name, value) VALUES ($1, $2, $3)")
+            .bind(visitor_id)
+            .bind(name)
+            .bind(value.to_string())
+            .execute(&mut **tx)
+            .await?;
+    }
+    Ok(())
+}
+
+pub async fn replace(
+    tx: &mut Transaction<'static, Sqlite>,
+    visitor_id: i64,
+    answers: &BTreeMap<String, Value>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM visitor_field WHERE visitor_id = $1")
+        .bind(visitor_id)
+        .execute(&mut **tx)
+        .await?;
+    store(
+        tx,
+        visitor_id,
+        &answers.clone().into_iter().collect::<Vec<_>>(),
+    )
+    .await
+}
+
+pub async fn attach(db: &SqlitePool, visitors: &mut [db::Visitor]) -> Result<(), sqlx::Error> {
+    if visitors.is_empty() {
+        return Ok(());
+    }
+    let ids: Vec<i32> = visitors.iter().map(|visitor| visitor.id).collect();
+    let rows: Vec<(i32, String, String)> = sqlx::query_as(
+        "SELECT visitor_id, name, value FROM visitor_field WHERE visitor_id IN (SELECT value FROM json_each($1))",
+    )
+    .bind(serde_json::to_string(&ids).unwrap_or_default())
+    .fetch_all(db)
+    .await?;
+
+    for (id, name, value) in rows {
+        if let Some(visitor) = visitors.iter_mut().find(|visitor| visitor.id == id) {
+            let value = serde_json::from_str(&value).unwrap_or(Value::String(value));
+            visitor.fields.insert(name, value);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    fn fields() -> CustomFields {
+        CustomFields::new(
+            serde_json::from_value(json!([
+                {"name": "shirt", "type": "choice", "choices": ["S", "M", "L"], "required": true},
+                {"name": "vegan", "type": "bool"},
+                {"name": "sleeping", "type": "text"}
+            ]))
+            .unwrap(),
+        )
+    }
+
+    fn submit(value: Value) -> Result<Vec<(String, Value)>, Value> {
+        fields()
+            .validate(serde_json::from_value(value).unwrap())
+            .map_err(|error| serde_json::to_value(error).unwrap())
+    }
+
+    #[test]
+    fn should_validate_against_configured_fields() {
+        assert_eq!(
+            submit(json!({"shirt": "L", "vegan": false, "sleeping": "  "})),
+            Ok(vec![
+                ("shirt".into(), json!("L")),
+                ("vegan".into(), json!(false))
+            ])
+        );
+        for (submitted, field) in [
+            (json!({"vegan": true}), "shirt"),
+            (json!({"shirt": null}), "shirt"),
+            (json!({"shirt": "XXL"}), "shirt"),
+            (json!({"shirt": "L", "vegan": "yes"}), "vegan"),
+            (json!({"shirt": "L", "sleeping": 3}), "sleeping"),
+            (json!({"shirt": "L", "pet": "cat"}), "pet"),
+        ] {
+            let error = submit(submitted.clone()).unwrap_err();
+            assert_eq!(error["field"], field, "{}", submitted);
+        }
+        assert!(CustomFields::default().validate(None).unwrap().is_empty());
+    }
+}
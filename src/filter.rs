@@ -0,0 +1,387 @@
+use chrono::{DateTime, Utc};
+use sqlx::{QueryBuilder, Sqlite, SqlitePool};
+
+use crate::{params::Problem, role, validate};
+
+// q is short for search, for a search box that submits the usual name
+const PUBLIC_KEYS: &[&str] = &["group", "grouped", "search", "q"];
+const ADMIN_KEYS: &[&str] = &[
+    "group",
+    "grouped",
+    "search",
+    "q",
+    "referral",
+    "created_after",
+    "created_before",
+    "confirmed",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Audience {
+    Public,
+    Admin,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct VisitorFilter {
+    group: Option<String>,
+    grouped: Option<bool>,
+    search: Option<String>,
+    referral: Option<String>,
+    created_after: Option<DateTime<Utc>>,
+    created_before: Option<DateTime<Utc>>,
+    email_confirmed: Option<bool>,
+    confirmed_only: bool,
+}
+
+impl VisitorFilter {
+    pub fn new(audience: Audience) -> Self {
+        Self {
+            confirmed_only: audience == Audience::Public,
+            ..Self::default()
+        }
+    }
+
+    pub fn accepts(key: &str) -> bool {
+        ADMIN_KEYS.contains(&key)
+    }
+
+    pub fn allows(key: &str, audience: Audience) -> bool {
+        match audience {
+            Audience::Public => PUBLIC_KEYS.contains(&key),
+            Audience::Admin => ADMIN_KEYS.contains(&key),
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), Problem> {
+        match key {
+            "group" => self.group = validate::normalize(value),
+            "grouped" => self.grouped = Some(boolean(key, value)?),
+            "search" | "q" => self.search = validate::normalize(value),
+            "referral" => self.referral = validate::normalize(value),
+            "created_after" => self.created_after = Some(timestamp(key, value)?),
+            "created_before" => self.created_before = Some(timestamp(key, value)?),
+            "confirmed" => self.email_confirmed = Some(boolean(key, value)?),
+            _ => unreachable!("only accepted keys are set"),
+        }
+        Ok(())
+    }
+
+    pub fn check(&self) -> Result<(), Problem> {
+        match (self.created_after, self.created_before) {
+            (Some(after), Some(before)) if after >= before => {
+                Err(Problem::new("created_after", "invalid_range").with("before", "created_before"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn push_where(&self, builder: &mut QueryBuilder<'_, Sqlite>) {
+        builder.push(" WHERE 1 = 1");
+        if let Some(group) = &self.group {
+            builder
+                .push(r#" AND "group" = "#)
+                .push_bind(group.clone())
+                .push(" COLLATE NOCASE");
+        }
+        match self.grouped {
+            Some(true) => {
+                builder.push(r#" AND "group" IS NOT NULL"#);
+            }
+            Some(false) => {
+                builder.push(r#" AND "group" IS NULL"#);
+            }
+            None => {}
+        }
+        if let Some(search) = &self.search {
+            builder
+                .push(" AND instr(lower(nick), lower(")
+                .push_bind(search.clone())
+                .push(")) > 0");
+        }
+        if let Some(referral) = &self.referral {
+            builder.push(" AND referral = ").push_bind(referral.clone());
+        }
+        if let Some(after) = self.created_after {
+            builder.push(" AND created_at >= ").push_bind(after);
+        }
+        if let Some(before) = self.created_before {
+            builder.push(" AND created_at < ").push_bind(before);
+        }
+        // Only visitors who gave an email can still confirm it
+        match self.email_confirmed {
+            Some(true) => {
+                builder.push(" AND confirmed_at IS NOT NULL");
+            }
+            Some(false) => {
+                builder.push(" AND email IS NOT NULL AND confirmed_at IS NULL");
+            }
+            None => {}
+        }
+        if self.confirmed_only {
+            builder.push(" AND status = ").push_bind(role::CONFIRMED);
+        }
+    }
+
+    pub async fn count(&self, db: &SqlitePool) -> Result<u32, sqlx::Error> {
+        let mut query = QueryBuilder::new("SELECT COUNT(id) FROM visitor");
+        self.push_where(&mut query);
+        query.build_query_scalar().fetch_one(db).await
+    }
+}
+
+fn boolean(key: &str, value: &str) -> Result<bool, Problem> {
+    value
+        .parse()
+        .map_err(|_| Problem::new(key, "invalid_boolean").with_value(value))
+}
+
+fn timestamp(key: &str, value: &str) -> Result<DateTime<Utc>, Problem> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .map_err(|_| Problem::new(key, "invalid_timestamp").with_value(value))
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::Body, http::StatusCode};
+    use chrono::TimeZone;
+    use http_body_util::BodyExt;
+    use hyper::Request;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{admin::AdminKeys, config::Config, testing, time::ConstantTimeService};
+
+    fn sql(filter: &VisitorFilter) -> String {
+        let mut query = QueryBuilder::new("SELECT id FROM visitor");
+        filter.push_where(&mut query);
+        query.sql().to_owned()
+    }
+
+    #[test]
+    fn should_compile_each_filter() {
+        let at = Utc.with_ymd_and_hms(2024, 3, 1, 18, 0, 0).unwrap();
+        for (filter, expected) in [
+            (VisitorFilter::default(), " WHERE 1 = 1"),
+            (
+                VisitorFilter {
+                    group: Some("Fairlight".into()),
+                    ..Default::default()
+                },
+                r#" WHERE 1 = 1 AND "group" = ? COLLATE NOCASE"#,
+            ),
+            (
+                VisitorFilter {
+                    grouped: Some(false),
+                    ..Default::default()
+                },
+                r#" WHERE 1 = 1 AND "group" IS NULL"#,
+            ),
+            (
+                VisitorFilter {
+                    search: Some("razor".into()),
+                    ..Default::default()
+                },
+                " WHERE 1 = 1 AND instr(lower(nick), lower(?)) > 0",
+            ),
+            (
+                VisitorFilter {
+                    referral: Some("flyer".into()),
+                    ..Default::default()
+                },
+                " WHERE 1 = 1 AND referral = ?",
+            ),
+            (
+                VisitorFilter {
+                    created_after: Some(at),
+                    created_before: Some(at),
+                    ..Default::default()
+                },
+                " WHERE 1 = 1 AND created_at >= ? AND created_at < ?",
+            ),
+            (
+                VisitorFilter {
+                    email_confirmed: Some(false),
+                    ..Default::default()
+                },
+                " WHERE 1 = 1 AND email IS NOT NULL AND confirmed_at IS NULL",
+            ),
+            (
+                VisitorFilter::new(Audience::Public),
+                " WHERE 1 = 1 AND status = ?",
+            ),
+        ] {
+            assert_eq!(sql(&filter), format!("SELECT id FROM visitor{}", expected));
+        }
+    }
+
+    #[tokio::test]
+    async fn should_only_bind_values() {
+        let db = testing::database().await;
+        testing::insert_visitor(&db, "Fairlight", Some("Fairlight")).await;
+
+        for value in [
+            "'; DROP TABLE visitor; --",
+            r#"" OR 1 = 1 --"#,
+            "%",
+            "') > 0 OR (1",
+        ] {
+            let mut filter = VisitorFilter::default();
+            for key in ["group", "search", "referral"] {
+                filter.set(key, value).unwrap();
+            }
+
+            assert!(!sql(&filter).contains(value.trim()), "{}", value);
+            assert_eq!(filter.count(&db).await.unwrap(), 0, "{}", value);
+        }
+
+        assert_eq!(
+            VisitorFilter::default().count(&db).await.unwrap(),
+            1,
+            "visitor table is intact"
+        );
+    }
+
+    #[tokio::test]
+    async fn should_agree_between_public_and_admin() {
+        let db = testing::database().await;
+        for (nick, group) in [
+            ("Razor", Some("Razor 1911")),
+            ("Raze", None),
+            ("Fairlight", Some("Fairlight")),
+            ("Quartex", Some("Razor 1911")),
+        ] {
+            testing::insert_visitor(&db, nick, group).await;
+        }
+        let api = crate::api(
+            ConstantTimeService::new(),
+            db,
+            Config {
+                admin_keys: AdminKeys::new(vec!["key".into()]),
+                ..Config::default()
+            },
+        );
+
+        for (query, expected) in [
+            ("", 4),
+            ("group=Razor+1911", 2),
+            ("search=RAZ", 2),
+            ("search=raz&group=Razor+1911", 1),
+        ] {
+            let mut counts = Vec::new();
+            for uri in [
+                format!("/visitors?{}", query),
+                format!("/visitors/buckets?{}", query),
+                format!("/admin/visitors?{}", query),
+                format!("/admin/stats?{}", query),
+            ] {
+                let response = api
+                    .clone()
+                    .oneshot(
+                        Request::builder()
+                            .header("Authorization", "Bearer key")
+                            .method("GET")
+                            .uri(&uri)
+                            .body(Body::empty())
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+
+                let body: serde_json::Value = serde_json::from_slice(
+                    &response.into_body().collect().await.unwrap().to_bytes(),
+                )
+                .unwrap();
+                counts.push(match &body {
+                    serde_json::Value::Array(visitors) => visitors.len() as u64,
+                    _ => body
+                        .get("visitors")
+                        .or(body.get("total"))
+                        .unwrap()
+                        .as_u64()
+                        .unwrap(),
+                });
+            }
+
+            assert_eq!(counts, vec![expected; 4], "{}", query);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_filter_by_group() {
+        let db = testing::database().await;
+        for (nick, group) in [
+            ("Razor", Some("Razor 1911")),
+            ("Fairlight", Some("Fairlight")),
+            ("Quartex", Some("Razor 1911")),
+            ("Raze", None),
+        ] {
+            testing::insert_visitor(&db, nick, group).await;
+        }
+        let api = crate::api(ConstantTimeService::new(), db, Config::default());
+
+        for (query, expected) in [
+            ("group=Razor+1911&sort=nick", vec!["Quartex", "Razor"]),
+            ("group=razor+1911&sort=nick", vec!["Quartex", "Razor"]),
+            ("group=Orange", vec![]),
+            ("grouped=false", vec!["Raze"]),
+            ("grouped=true&sort=-nick&limit=1", vec!["Razor"]),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(
+                    Request::get(format!("/visitors?{}", query))
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{}", query);
+            let body: Vec<serde_json::Value> =
+                serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes())
+                    .unwrap();
+            let nicks: Vec<&str> = body.iter().map(|x| x["nick"].as_str().unwrap()).collect();
+            assert_eq!(nicks, expected, "{}", query);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_search_nicks_literally() {
+        let db = testing::database().await;
+        for nick in ["Razor", "100% Amiga", "Fairlight", "100 Amigas", "Äpfel"] {
+            testing::insert_visitor(&db, nick, None).await;
+        }
+        let api = crate::api(ConstantTimeService::new(), db, Config::default());
+
+        for (search, expected) in [
+            ("search=AZO", vec!["Razor"]),
+            ("q=AZO", vec!["Razor"]),
+            ("search=amiga", vec!["100% Amiga", "100 Amigas"]),
+            ("q=Orange", vec![]),
+            ("search=%25", vec!["100% Amiga"]),
+            ("q=0%25+A", vec!["100% Amiga"]),
+            ("search=_", vec![]),
+            ("q=%C3%84PF", vec!["Äpfel"]),
+            // SQLite folds ASCII only
+            ("q=%C3%A4pf", vec![]),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(
+                    Request::get(format!("/visitors?{}&limit=2", search))
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{}", search);
+            let body: Vec<serde_json::Value> =
+                serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes())
+                    .unwrap();
+            let nicks: Vec<&str> = body.iter().map(|x| x["nick"].as_str().unwrap()).collect();
+            assert_eq!(nicks, expected, "{}", search);
+        }
+    }
+}
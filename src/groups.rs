@@ -0,0 +1,123 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use serde::Serialize;
+use sqlx::SqlitePool;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::validate;
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Group {
+    pub name: String,
+    pub count: i64,
+    pub variants: Vec<Variant>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Variant {
+    pub name: String,
+    pub count: i64,
+}
+
+pub async fn list(db: &SqlitePool) -> Result<Vec<Group>, sqlx::Error> {
+    let rows = sqlx::query_as::<_, (String, i64)>(
+        r#"SELECT "group", COUNT(id) FROM visitor WHERE "group" IS NOT NULL GROUP BY "group" ORDER BY "group""#,
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(merge(rows))
+}
+
+fn merge(rows: Vec<(String, i64)>) -> Vec<Group> {
+    let mut merged = HashMap::<String, Vec<Variant>>::new();
+    for (name, count) in rows {
+        let Some(key) = key(&name) else {
+            continue;
+        };
+        merged.entry(key).or_default().push(Variant { name, count });
+    }
+
+    let mut groups: Vec<Group> = merged
+        .into_values()
+        .map(|variants| {
+            let mut labels = HashMap::<String, i64>::new();
+            for variant in &variants {
+                let label = validate::normalize(&variant.name).unwrap_or_default();
+                *labels.entry(label).or_default() += variant.count;
+            }
+
+            let name = labels
+                .into_iter()
+                .max_by(|(a, a_count), (b, b_count)| a_count.cmp(b_count).then_with(|| b.cmp(a)))
+                .map(|(label, _)| label)
+                .unwrap_or_default();
+
+            Group {
+                name,
+                count: variants.iter().map(|variant| variant.count).sum(),
+                variants,
+            }
+        })
+        .collect();
+
+    groups.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then_with(|| collate(&a.name, &b.name))
+    });
+    groups
+}
+
+// Spellings of a group that only differ in case, spacing or Unicode composition are the same group
+pub fn key(name: &str) -> Option<String> {
+    validate::normalize(name).map(|normalized| normalized.nfc().collect::<String>().to_lowercase())
+}
+
+fn collate(a: &str, b: &str) -> Ordering {
+    a.to_lowercase()
+        .cmp(&b.to_lowercase())
+        .then_with(|| a.cmp(b))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_merge_variants_under_majority_casing() {
+        let groups = merge(vec![
+            ("Fairlight".into(), 1),
+            ("Fairlight ".into(), 1),
+            ("fairlight".into(), 1),
+            ("Razor 1911".into(), 1),
+        ]);
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].name, "Fairlight");
+        assert_eq!(groups[0].count, 3);
+        assert_eq!(groups[0].variants.len(), 3);
+        assert_eq!(groups[1].name, "Razor 1911");
+    }
+
+    #[test]
+    fn should_merge_unicode_normalization_forms() {
+        let groups = merge(vec![("Cre\u{300}me".into(), 1), ("Crème".into(), 2)]);
+
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].name, "Crème");
+        assert_eq!(groups[0].count, 3);
+    }
+
+    #[test]
+    fn should_order_by_count_then_name() {
+        let groups = merge(vec![
+            ("beta".into(), 1),
+            ("Alpha".into(), 1),
+            ("Gamma".into(), 2),
+        ]);
+
+        let names: Vec<&str> = groups.iter().map(|group| group.name.as_str()).collect();
+        assert_eq!(names, vec!["Gamma", "Alpha", "beta"]);
+    }
+}
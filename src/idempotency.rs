@@ -0,0 +1,170 @@
+use std::time::Duration;
+
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use sqlx::{Sqlite, SqlitePool, Transaction};
+
+use crate::{error::ApiError, json, supervisor::Stop, time::TimeService};
+
+pub const HEADER: &str = "Idempotency-Key";
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const MAX_KEY_LENGTH: usize = 255;
+
+fn ttl() -> chrono::Duration {
+    chrono::Duration::hours(24)
+}
+
+pub fn key(headers: &HeaderMap) -> Result<Option<String>, ApiError> {
+    let Some(value) = headers.get(HEADER) else {
+        return Ok(None);
+    };
+    match value.to_str().map(str::trim) {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => Ok(Some(key.to_owned())),
+        _ => Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid Idempotency-Key header",
+        )),
+    }
+}
+
+pub async fn replay(
+    db: &SqlitePool,
+    key: &str,
+    now: DateTime<Utc>,
+) -> Result<Option<Response>, ApiError> {
+    let stored: Option<(u16, Option<String>, Vec<u8>)> = sqlx::query_as(
+        "SELECT status, location, body FROM idempotency_key WHERE key = $1 AND created_at > $2",
+    )
+    .bind(key)
+    .bind(now - ttl())
+    .fetch_optional(db)
+    .await?;
+    let Some((status, location, body)) = stored else {
+        return Ok(None);
+    };
+
+    let mut response = (
+        StatusCode::from_u16(status).unwrap_or(StatusCode::OK),
+        [(header::CONTENT_TYPE, json::CONTENT_TYPE)],
+        body,
+    )
+        .into_response();
+    if let Some(location) = location.and_then(|x| HeaderValue::from_str(&x).ok()) {
+        response.headers_mut().insert(header::LOCATION, location);
+    }
+    Ok(Some(response))
+}
+
+// Stored in the registration's own transaction, so a retry either finds the response or finds nothing was registered.
+// An expired key the sweep has not got to yet is free to use again.
+pub async fn remember(
+    tx: &mut Transaction<'static, Sqlite>,
+    key: &str,
+    now: DateTime<Utc>,
+    status: StatusCode,
+    location: Option<&str>,
+    body: &[u8],
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM idempotency_key WHERE key = $1 AND created_at <= $2")
+        .bind(key)
+        .bind(now - ttl())
+        .execute(&mut **tx)
+        .await?;
+    sqlx::query(
+        "INSERT INTO idempotency_key (key, created_at, status, location, body) VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(key)
+    .bind(now)
+    .bind(status.as_u16())
+    .bind(location)
+    .bind(body)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+pub async fn sweep(db: &SqlitePool, now: DateTime<Utc>) -> Result<u64, sqlx::Error> {
+    Ok(
+        sqlx::query("DELETE FROM idempotency_key WHERE created_at <= $1")
+            .bind(now - ttl())
+            .execute(db)
+            .await?
+            .rows_affected(),
+    )
+}
+
+pub async fn run_sweep(time: impl TimeService, db: SqlitePool, mut stop: Stop) {
+    while stop.sleep(SWEEP_INTERVAL).await {
+        if let Err(error) = sweep(&db, time.clone().now()).await {
+            eprintln!("failed to sweep expired idempotency keys: {}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+
+    use crate::testing;
+
+    #[tokio::test]
+    async fn should_forget_keys_after_a_day() {
+        let db = testing::database().await;
+        let now = Utc::now();
+
+        let mut tx = db.begin().await.unwrap();
+        for (key, created_at) in [("old", now - Duration::hours(25)), ("new", now)] {
+            super::remember(
+                &mut tx,
+                key,
+                created_at,
+                super::StatusCode::CREATED,
+                None,
+                b"{}",
+            )
+            .await
+            .unwrap();
+        }
+        tx.commit().await.unwrap();
+
+        assert!(super::replay(&db, "old", now).await.unwrap().is_none());
+        assert!(super::replay(&db, "new", now).await.unwrap().is_some());
+        assert_eq!(super::sweep(&db, now).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn should_reuse_expired_key_before_sweep() {
+        let db = testing::database().await;
+        let now = Utc::now();
+        let later = now + Duration::hours(24) + Duration::minutes(1);
+
+        for (created_at, body) in [(now, b"first"), (later, b"again")] {
+            assert!(super::replay(&db, "key", created_at)
+                .await
+                .unwrap()
+                .is_none());
+            let mut tx = db.begin().await.unwrap();
+            super::remember(
+                &mut tx,
+                "key",
+                created_at,
+                super::StatusCode::CREATED,
+                None,
+                body,
+            )
+            .await
+            .unwrap();
+            tx.commit().await.unwrap();
+        }
+
+        let response = super::replay(&db, "key", later).await.unwrap().unwrap();
+        let body = http_body_util::BodyExt::collect(response.into_body())
+            .await
+            .unwrap()
+            .to_bytes();
+        assert_eq!(body, "again");
+    }
+}
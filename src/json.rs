@@ -0,0 +1,255 @@
+use std::{
+    str::FromStr,
+    sync::{Arc, OnceLock},
+};
+
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{FromRequest, Request},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Value};
+
+use crate::error::ApiError;
+
+pub const CONTENT_TYPE: &str = "application/json; charset=utf-8";
+
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+pub(crate) struct Json<T>(pub T);
+
+// Filled with the body as read by the extractor, for anyone who needs the raw bytes afterwards
+#[derive(Clone, Default)]
+pub(crate) struct RawBody(Arc<OnceLock<Bytes>>);
+
+impl RawBody {
+    pub fn get(&self) -> Option<&Bytes> {
+        self.0.get()
+    }
+}
+
+#[async_trait]
+impl<T, S> FromRequest<S> for Json<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if !has_json_content_type(req.headers()) {
+            return Err(unsupported_media_type("`Content-Type: application/json`"));
+        }
+
+        let raw_body = req.extensions().get::<RawBody>().cloned();
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(|rejection| ApiError::new(rejection.status(), rejection.body_text()))?;
+        if let Some(raw_body) = raw_body {
+            let _ = raw_body.0.set(bytes.clone());
+        }
+        let body = bytes.strip_prefix(BOM).unwrap_or(&bytes);
+
+        Ok(Json(serde_json::from_slice(body)?))
+    }
+}
+
+// A JSON body, or the same fields posted by a plain HTML form. Empty form fields are left out and `outer[inner]` keys
+// fill a nested object, so `fields[shirt]=L` answers a custom field.
+pub(crate) struct JsonOrForm<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for JsonOrForm<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        if !has_form_content_type(req.headers()) {
+            if !has_json_content_type(req.headers()) {
+                return Err(unsupported_media_type(
+                    "`Content-Type: application/json` or `application/x-www-form-urlencoded`",
+                ));
+            }
+            let Json(value) = Json::from_request(req, state).await?;
+            return Ok(JsonOrForm(value));
+        }
+
+        let raw_body = req.extensions().get::<RawBody>().cloned();
+        let bytes = Bytes::from_request(req, state)
+            .await
+            .map_err(|rejection| ApiError::new(rejection.status(), rejection.body_text()))?;
+        if let Some(raw_body) = raw_body {
+            let _ = raw_body.0.set(bytes.clone());
+        }
+
+        Ok(JsonOrForm(serde_json::from_value(form_object(&bytes))?))
+    }
+}
+
+pub(crate) fn flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<bool>, D::Error> {
+    match Option::<Value>::deserialize(deserializer)? {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => as_flag(&value).map(Some).ok_or_else(|| {
+            serde::de::Error::custom(format!("expected true or false, got {}", value))
+        }),
+    }
+}
+
+// A checkbox in a form sends its value, "on" unless it sets one
+pub(crate) fn as_flag(value: &Value) -> Option<bool> {
+    match value {
+        Value::Bool(value) => Some(*value),
+        Value::String(value) if ["true", "on"].contains(&value.as_str()) => Some(true),
+        Value::String(value) if value == "false" => Some(false),
+        _ => None,
+    }
+}
+
+// A form sends numbers as text like everything else
+pub(crate) fn number<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr + DeserializeOwned,
+{
+    match Option::<Value>::deserialize(deserializer)? {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(value)) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| serde::de::Error::custom(format!("expected a number, got {:?}", value))),
+        Some(value) => T::deserialize(value)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+    }
+}
+
+fn unsupported_media_type(expected: &str) -> ApiError {
+    ApiError::new(
+        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        format!("expected request with {}", expected),
+    )
+}
+
+fn form_object(body: &[u8]) -> Value {
+    let mut object = Map::new();
+    for (key, value) in form_urlencoded::parse(body) {
+        if value.is_empty() {
+            continue;
+        }
+        let value = Value::String(value.into_owned());
+        match key.strip_suffix(']').and_then(|key| key.split_once('[')) {
+            Some((outer, inner)) => {
+                let nested = object
+                    .entry(outer)
+                    .or_insert_with(|| Value::Object(Map::new()));
+                if let Value::Object(nested) = nested {
+                    nested.insert(inner.to_owned(), value);
+                }
+            }
+            None => {
+                object.insert(key.into_owned(), value);
+            }
+        }
+    }
+    Value::Object(object)
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        match serde_json::to_vec(&self.0) {
+            Ok(body) => (
+                [(header::CONTENT_TYPE, HeaderValue::from_static(CONTENT_TYPE))],
+                body,
+            )
+                .into_response(),
+            Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response(),
+        }
+    }
+}
+
+fn has_form_content_type(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|content_type| content_type.split(';').next())
+        .is_some_and(|essence| {
+            essence
+                .trim()
+                .eq_ignore_ascii_case("application/x-www-form-urlencoded")
+        })
+}
+
+fn has_json_content_type(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+
+    let mut parts = content_type.split(';').map(str::trim);
+    if !parts
+        .next()
+        .is_some_and(|essence| essence.eq_ignore_ascii_case("application/json"))
+    {
+        return false;
+    }
+
+    parts.all(|parameter| match parameter.split_once('=') {
+        Some((name, value)) if name.trim().eq_ignore_ascii_case("charset") => {
+            value.trim().trim_matches('"').eq_ignore_ascii_case("utf-8")
+        }
+        _ => true,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::{HeaderMap, HeaderValue};
+
+    use super::{form_object, has_form_content_type, has_json_content_type};
+
+    fn headers(content_type: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Type", HeaderValue::from_str(content_type).unwrap());
+        headers
+    }
+
+    #[test]
+    fn should_accept_json_content_types() {
+        assert!(has_json_content_type(&headers("application/json")));
+        assert!(has_json_content_type(&headers(
+            "application/json; charset=utf-8"
+        )));
+        assert!(has_json_content_type(&headers(
+            "Application/JSON;charset=\"UTF-8\""
+        )));
+    }
+
+    #[test]
+    fn should_read_form_fields() {
+        assert_eq!(
+            form_object(b"nick=Razor+1911&email=&fields%5Bshirt%5D=L&group=Fair%26light"),
+            serde_json::json!({"nick": "Razor 1911", "group": "Fair&light", "fields": {"shirt": "L"}})
+        );
+        assert!(!has_form_content_type(&headers("application/json")));
+        assert!(has_form_content_type(&headers(
+            "Application/x-www-form-urlencoded; charset=UTF-8"
+        )));
+    }
+
+    #[test]
+    fn should_reject_other_content_types() {
+        assert!(!has_json_content_type(&HeaderMap::new()));
+        assert!(!has_json_content_type(&headers("text/plain")));
+        assert!(!has_json_content_type(&headers(
+            "application/json; charset=latin1"
+        )));
+    }
+}
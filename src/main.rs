@@ -1,39 +1,128 @@
-use std::{env, net::SocketAddr, str::FromStr, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    env, fs,
+    net::SocketAddr,
+    path::PathBuf,
+    process,
+    str::FromStr,
+    sync::{atomic::AtomicU64, Arc},
+};
 
 use axum::{
-    extract::{ConnectInfo, State},
+    extract::{ConnectInfo, OriginalUri, Path, State},
     handler::Handler,
-    http::{HeaderMap, StatusCode},
-    response::IntoResponse,
-    routing::{get, post}, Json, Router,
+    http::{header, HeaderMap, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, patch, post, put},
+    Extension, Router,
 };
+use captcha::Verification;
+use chrono::{DateTime, Utc};
+use config::Config;
 use error::ApiError;
+use json::{Json, JsonOrForm};
+use params::{Filtered, Listed, Params};
+use query::Query;
+use role::{Role, Roles};
 use serde::{Deserialize, Serialize};
 use sqlx::{
     sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous},
-    SqlitePool,
+    QueryBuilder, Sqlite, SqlitePool, Transaction,
 };
 use time::{SystemTimeService, TimeService};
+use timing::Timings;
 use tokio::{net::TcpListener, signal};
 use tower::ServiceBuilder;
-use tower_governor::{
-    governor::GovernorConfigBuilder, key_extractor::SmartIpKeyExtractor, GovernorLayer,
-};
+use tower_governor::{governor::GovernorConfigBuilder, GovernorLayer};
 
 mod admin;
+mod analytics;
+mod batch;
+mod blocklist;
+mod buckets;
+mod cache;
+mod captcha;
+mod changes;
+mod closing;
+mod config;
+mod confirm;
 mod cors;
 mod db;
+mod debug;
+mod dev;
+mod drafts;
+mod edits;
 mod error;
+mod fields;
+mod filter;
+mod groups;
+mod idempotency;
+mod json;
+mod misses;
+mod pagination;
+mod params;
+mod payment;
+mod policy;
+mod proxy;
+mod query;
+mod rejections;
+mod replica;
+mod reservation;
+mod reserved;
+mod retry;
+mod role;
+mod schema;
+mod snapshot;
+mod stages;
+mod storage;
+mod strict;
+mod supervisor;
 #[cfg(test)]
 mod testing;
+mod throttle;
 mod time;
+mod timing;
+mod transition;
+mod validate;
+mod verify;
+
+const SCHEMA_VERSION: u32 = 1;
+const SHUTDOWN_DEADLINE: std::time::Duration = std::time::Duration::from_secs(10);
+const USER_AGENT_MAX_LENGTH: usize = 512;
 
 #[derive(Deserialize)]
 struct RegisterRequest {
     nick: String,
     group: Option<String>,
     email: Option<String>,
-    extra: Option<String>,
+    #[serde(default, deserialize_with = "json::flag")]
+    consent: Option<bool>,
+    extra: Option<serde_json::Value>,
+    fields: Option<BTreeMap<String, serde_json::Value>>,
+    #[serde(rename = "ref")]
+    referral: Option<String>,
+    #[serde(default, deserialize_with = "json::number")]
+    schema_version: Option<u32>,
+    captcha_token: Option<String>,
+    draft_id: Option<String>,
+    invite_token: Option<String>,
+    claim_token: Option<String>,
+    // Everything else, where the honeypot field ends up
+    #[serde(flatten)]
+    other: Option<HashMap<String, serde_json::Value>>,
+}
+
+#[derive(Deserialize)]
+struct PublicTimelineQuery {
+    #[serde(default)]
+    bucket: analytics::Bucket,
+}
+
+#[derive(Deserialize)]
+struct RegisterQuery {
+    #[serde(rename = "ref")]
+    referral: Option<String>,
 }
 
 #[derive(sqlx::FromRow, Serialize)]
@@ -43,80 +132,1028 @@ struct Visitor {
     group: Option<String>,
 }
 
+#[derive(Serialize)]
+struct Group {
+    name: String,
+    count: i64,
+}
+
+#[derive(Deserialize)]
+struct ChangesQuery {
+    since: i64,
+}
+
+#[derive(Serialize)]
+struct VisitorChanges {
+    generation: i64,
+    full_refetch: bool,
+    added: Vec<role::Projection>,
+    updated: Vec<role::Projection>,
+    removed: Vec<i64>,
+}
+
+#[derive(Serialize)]
+struct Registration {
+    #[serde(flatten)]
+    visitor: Visitor,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payment_reference: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    waitlist_position: Option<i64>,
+    edit_token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    edit_deadline: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+struct VisitorCount {
+    count: u32,
+    groups: usize,
+}
+
+#[derive(Serialize)]
+struct Status {
+    schema_version: u32,
+    capabilities: Vec<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    replica: Option<replica::Status>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    stopped_tasks: Vec<&'static str>,
+}
+
 #[derive(Clone)]
 pub struct ApiState<T: TimeService> {
     time: T,
     db: SqlitePool,
+    config: Arc<Config>,
+    http: reqwest::Client,
+    verify_lookups: Arc<AtomicU64>,
+    debug_ips: debug::DebugIps,
+    replica: replica::Progress,
+    retries: retry::Metrics,
+    misses: misses::Misses,
+    tasks: supervisor::Supervisor,
 }
 
-fn api(time: impl TimeService, db: SqlitePool) -> Router {
-    let add_visitor_rate_config = Arc::new(
-        GovernorConfigBuilder::default()
-            .per_second(60)
-            .burst_size(3)
-            .key_extractor(SmartIpKeyExtractor)
-            .error_handler(|error| ApiError::from(error).into_response())
-            .finish()
-            .unwrap(),
-    );
+fn api(time: impl TimeService, db: SqlitePool, config: Config) -> Router {
+    supervised_api(time, db, config).0
+}
+
+// The supervisor is handed back so that main can stop the background tasks before closing the pool
+fn supervised_api(
+    time: impl TimeService,
+    db: SqlitePool,
+    config: Config,
+) -> (Router, supervisor::Supervisor) {
+    let storage = storage::Storage::default();
+    let tasks = supervisor::Supervisor::new(time.clone(), supervisor::RESTART);
+    tasks.spawn("storage_probe", {
+        let (storage, db) = (storage.clone(), db.clone());
+        move |stop| storage.clone().run_probe(db.clone(), stop)
+    });
+    tasks.spawn("draft_sweep", {
+        let (time, db, ttl) = (time.clone(), db.clone(), config.draft_ttl);
+        move |stop| drafts::run_sweep(time.clone(), db.clone(), ttl, stop)
+    });
+    tasks.spawn("rejection_sweep", {
+        let (time, db, retention) = (time.clone(), db.clone(), config.rejected_retention);
+        move |stop| rejections::run_sweep(time.clone(), db.clone(), retention, stop)
+    });
+    tasks.spawn("idempotency_sweep", {
+        let (time, db) = (time.clone(), db.clone());
+        move |stop| idempotency::run_sweep(time.clone(), db.clone(), stop)
+    });
+
+    (
+        api_with_storage(time, db, config, storage, tasks.clone()),
+        tasks,
+    )
+}
+
+fn api_with_storage(
+    time: impl TimeService,
+    db: SqlitePool,
+    config: Config,
+    storage: storage::Storage,
+    tasks: supervisor::Supervisor,
+) -> Router {
+    let proxies = config.trusted_proxies.clone();
+    let rate_limit = move |seconds, burst| {
+        ServiceBuilder::new().layer(GovernorLayer {
+            config: Arc::new(
+                GovernorConfigBuilder::default()
+                    .per_second(seconds)
+                    .burst_size(burst)
+                    .key_extractor(proxy::ClientIpKeyExtractor(proxies.clone()))
+                    .error_handler(|error| ApiError::from(error).into_response())
+                    .finish()
+                    .unwrap(),
+            ),
+        })
+    };
 
-    let add_visitor_rate_limit = ServiceBuilder::new()
-        .layer(GovernorLayer {
-            config: add_visitor_rate_config,
+    let misses = misses::Misses::new(config.misses);
+    let state = ApiState {
+        time,
+        db,
+        config: Arc::new(config),
+        http: reqwest::Client::new(),
+        verify_lookups: Arc::default(),
+        debug_ips: debug::DebugIps::default(),
+        replica: replica::Progress::default(),
+        retries: retry::Metrics::default(),
+        misses,
+        tasks,
+    };
+    let config = state.config.clone();
+    if let Some(target) = config.replica_push.clone() {
+        let state = state.clone();
+        state.tasks.clone().spawn("replica_push", move |stop| {
+            replica::run_push(
+                state.db.clone(),
+                state.http.clone(),
+                target.clone(),
+                state.replica.clone(),
+                state.retries.clone(),
+                stop,
+            )
+        });
+    }
+    if let Some(schedule) = config.closing.clone() {
+        let state = state.clone();
+        state.tasks.clone().spawn("closing", move |stop| {
+            closing::run(
+                state.db.clone(),
+                state.time.clone(),
+                schedule.clone(),
+                state.retries.clone(),
+                stop,
+            )
         });
+    }
+
+    let capture_rejections = middleware::from_fn_with_state(state.clone(), rejections::capture);
+    let dampen_misses = middleware::from_fn_with_state(state.clone(), misses::dampen);
+    // A batch counts as one request against the same limit as single registrations
+    let register_limit = middleware::from_fn_with_state(
+        throttle::Throttle::new(
+            &config.register_rate_limit,
+            proxy::ClientIpKeyExtractor(config.trusted_proxies.clone()),
+        ),
+        throttle::limit,
+    );
+    let mut router = Router::new()
+        .route(
+            "/register",
+            post(
+                add_visitor
+                    .layer(capture_rejections)
+                    .layer(register_limit.clone()),
+            ),
+        )
+        .route(
+            "/register/batch",
+            post(batch::register.layer(register_limit)),
+        )
+        .route(
+            "/register/:token",
+            patch(
+                edits::edit
+                    .layer(dampen_misses.clone())
+                    .layer(rate_limit(5, 10)),
+            )
+            .delete(
+                edits::cancel
+                    .layer(dampen_misses.clone())
+                    .layer(rate_limit(30, 3)),
+            ),
+        )
+        .route(
+            "/confirm/:token",
+            get(confirm::confirm
+                .layer(dampen_misses.clone())
+                .layer(rate_limit(5, 10))),
+        )
+        .route(
+            "/register/draft",
+            put(drafts::save.layer(rate_limit(5, 10))),
+        )
+        .route(
+            "/register/draft/:id",
+            get(drafts::load
+                .layer(dampen_misses.clone())
+                .layer(rate_limit(5, 10))),
+        )
+        .route("/schemas", get(schema::list))
+        .route("/schemas/:file", get(schema::get))
+        .route(
+            "/verify",
+            post(verify::verify.layer(rate_limit(1, 30))).layer(middleware::from_fn_with_state(
+                config.verify_keys.clone(),
+                verify::authorize,
+            )),
+        );
+    if config.routes.public_list {
+        router = router
+            .route("/visitors", get(list_visitors))
+            .route("/visitors/buckets", get(list_visitor_buckets))
+            .route("/visitors/count", get(count_visitors))
+            .route("/visitors/timeline", get(visitor_timeline))
+            .route("/visitors/changes", get(list_visitor_changes))
+            .route("/visitors/:id", get(get_visitor.layer(dampen_misses)));
+    }
+    if config.routes.groups {
+        router = router.route("/groups", get(list_groups));
+    }
+    if config.routes.status {
+        router = router.route("/status", get(status));
+    }
 
-    Router::new()
-        .route("/register", post(add_visitor.layer(add_visitor_rate_limit)))
-        .route("/visitors", get(list_visitors))
-        .nest("/admin", admin::routes())
+    router
+        .nest(
+            "/admin",
+            admin::routes(
+                config.admin_keys.clone(),
+                config.readonly_keys.clone(),
+                config.replica_keys.clone(),
+                config.routes.admin_delete,
+            ),
+        )
+        .fallback(not_found)
+        .layer(middleware::from_fn_with_state(
+            Roles {
+                admin: config.admin_keys.clone(),
+                readonly: config.readonly_keys.clone(),
+            },
+            role::attach,
+        ))
+        .layer(middleware::from_fn_with_state(
+            !config.replica_keys.is_empty(),
+            replica::read_only,
+        ))
+        .layer(middleware::from_fn_with_state(storage, storage::guard))
+        .layer(middleware::from_fn_with_state(state.clone(), debug::trace))
+        .layer(middleware::from_fn_with_state(
+            config.timing_header,
+            timing::expose,
+        ))
+        .layer(middleware::from_fn_with_state(
+            config.cache_policies.clone(),
+            cache::control,
+        ))
         .layer(cors::layer())
-        .with_state(ApiState { time, db })
+        .with_state(state)
 }
 
 async fn add_visitor<T: TimeService>(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     headers: HeaderMap,
+    Query(query): Query<RegisterQuery>,
     State(state): State<ApiState<T>>,
-    Json(request): Json<RegisterRequest>,
-) -> Result<StatusCode, ApiError> {
-    sqlx::query(
-        r#"INSERT INTO visitor (created_at, ip, nick, "group", email, extra) VALUES ($1, $2, $3, $4, $5, $6)"#,
+    JsonOrForm(request): JsonOrForm<RegisterRequest>,
+) -> Result<Response, ApiError> {
+    let mut timings = Timings::start();
+    let schema_version = match headers.get("X-Schema-Version") {
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .and_then(|x| x.trim().parse::<u32>().ok())
+                .ok_or_else(|| {
+                    ApiError::new(StatusCode::BAD_REQUEST, "invalid X-Schema-Version header")
+                })?,
+        ),
+        None => request.schema_version,
+    };
+    if schema_version.is_some_and(|version| version < SCHEMA_VERSION) {
+        return Err(
+            ApiError::new(StatusCode::CONFLICT, "registration form is outdated")
+                .with_code("form_outdated")
+                .with_detail("schema_version", SCHEMA_VERSION),
+        );
+    }
+
+    let now = state.time.clone().now();
+    ensure_before_deadline(&state, now)?;
+    let idempotency_key = idempotency::key(&headers)?;
+    if let Some(key) = &idempotency_key {
+        if let Some(response) = idempotency::replay(&state.db, key, now).await? {
+            return Ok(response);
+        }
+    }
+
+    ensure_open(&state, now).await?;
+
+    let mut failures = validate::Failures::default();
+    let nick = failures.check(validate::nick(&request.nick).and_then(|nick| {
+        state.config.nick_blocklist.check(&nick)?;
+        Ok(nick)
+    }))?;
+    let group = failures.check(
+        validate::group(request.group, state.config.group_max_length).and_then(|group| {
+            validate::allowed_group(group, state.config.allowed_groups.as_deref())
+        }),
+    )?;
+    let consent = failures.check(validate::consent(request.email.as_deref(), request.consent))?;
+    let email = failures.check(validate::email(request.email))?;
+    let extra = failures.check(validate::extra(request.extra))?;
+    let answers = failures.check(state.config.custom_fields.validate(request.fields))?;
+    let (Some(nick), Some(group), Some(()), Some(email), Some(extra), Some(answers)) =
+        (nick, group, consent, email, extra, answers)
+    else {
+        return Err(failures.into_error());
+    };
+
+    let referral = request.referral.or(query.referral);
+    let known_referral = validate::referral(referral.as_deref(), &state.config.referral_codes);
+    if let (Some(referral), None) = (&referral, known_referral) {
+        eprintln!("ignoring unknown referral code: {}", referral);
+    }
+    let admission = match state.config.registration_opens_at.filter(|&at| now < at) {
+        Some(opens_at) => Some(
+            state
+                .config
+                .stages
+                .admit(
+                    &state.db,
+                    &stages::Candidate {
+                        referral: referral.as_deref(),
+                        group: group.as_deref(),
+                        invite_token: request.invite_token.as_deref(),
+                    },
+                    now,
+                    opens_at,
+                )
+                .await?,
+        ),
+        None => None,
+    };
+    timings.phase("validation");
+
+    let caught = state.config.honeypot_field.as_ref().is_some_and(|field| {
+        request
+            .other
+            .as_ref()
+            .and_then(|other| other.get(field))
+            .is_some_and(|value| !value.is_null() && value != "")
+    });
+    if caught {
+        return honeypot(&state, nick, group).await;
+    }
+
+    let client = Client::of(&state.config, &headers, addr);
+    if let Some(captcha) = &state.config.captcha {
+        verify_captcha(captcha, request.captcha_token.as_deref(), &client.ip).await?;
+        timings.phase("captcha");
+    }
+
+    let mut tx = state.db.begin().await?;
+    let registration = state.config.policies.evaluate(
+        policy::ValidatedRegistration {
+            nick,
+            group,
+            email,
+            extra,
+            referral: known_referral.cloned(),
+            payment_exempt: false,
+        },
+        &policy::PolicyContext {
+            now,
+            ip: Some(client.ip.clone()),
+        },
+    )?;
+
+    ensure_ip_allowance(&mut tx, &state.config, &client.ip, 1).await?;
+    let nick = registration.nick.clone();
+    let body = match store(
+        &mut tx,
+        &state,
+        registration,
+        &client,
+        request.claim_token.as_deref(),
+        now,
+    )
+    .await
+    {
+        // With an Idempotency-Key the client says itself whether it is retrying
+        Err(error) if error.code() == Some("nick_taken") && idempotency_key.is_none() => {
+            drop(tx);
+            return match repeated(&state, &nick, &client, now).await? {
+                Some(visitor) => Ok((
+                    StatusCode::OK,
+                    [(header::LOCATION, format!("/visitors/{}", visitor.id))],
+                    Json(visitor),
+                )
+                    .into_response()),
+                None => Err(error),
+            };
+        }
+        result => result?,
+    };
+    let id = i64::from(body.visitor.id);
+    fields::store(&mut tx, id, &answers).await?;
+    if let Some(admission) = admission {
+        admission.record(&mut tx, id, now).await?;
+    }
+    if let Some(draft_id) = request.draft_id {
+        sqlx::query(r#"DELETE FROM draft WHERE id = $1"#)
+            .bind(draft_id.to_lowercase())
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    // The public view does not show waitlisted visitors, so there is nothing to point at yet
+    let (status, location) = match body.waitlist_position {
+        Some(_) => (StatusCode::ACCEPTED, None),
+        None => (StatusCode::CREATED, Some(format!("/visitors/{}", id))),
+    };
+    if let Some(key) = &idempotency_key {
+        let serialized = serde_json::to_vec(&body)?;
+        idempotency::remember(&mut tx, key, now, status, location.as_deref(), &serialized).await?;
+    }
+    tx.commit().await?;
+    timings.phase("db");
+
+    state.misses.forget(misses::Kind::Visitor, &id.to_string());
+    let mut response = match location {
+        Some(location) => (status, [(header::LOCATION, location)], Json(body)).into_response(),
+        None => (status, Json(body)).into_response(),
+    };
+    response.extensions_mut().insert(timings);
+    Ok(response)
+}
+
+// Where a registration came from, as far as the request tells
+struct Client {
+    ip: String,
+    user_agent: Option<String>,
+}
+
+impl Client {
+    fn of(config: &Config, headers: &HeaderMap, addr: SocketAddr) -> Self {
+        Self {
+            ip: config
+                .trusted_proxies
+                .resolve(headers, addr.ip())
+                .to_string(),
+            user_agent: headers
+                .get(header::USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.chars().take(USER_AGENT_MAX_LENGTH).collect()),
+        }
+    }
+}
+
+// Only needs the config, so registration can turn away requests after the deadline before touching the database
+fn ensure_before_deadline<T: TimeService>(
+    state: &ApiState<T>,
+    now: DateTime<Utc>,
+) -> Result<(), ApiError> {
+    let past_deadline = state
+        .config
+        .closing
+        .as_ref()
+        .is_some_and(|schedule| schedule.has_closed_registration(now));
+    match past_deadline {
+        true => Err(registration_closed()),
+        false => Ok(()),
+    }
+}
+
+async fn ensure_open<T: TimeService>(
+    state: &ApiState<T>,
+    now: DateTime<Utc>,
+) -> Result<(), ApiError> {
+    ensure_before_deadline(state, now)?;
+    match closing::is_closed(&state.db).await? {
+        true => Err(registration_closed()),
+        false => Ok(()),
+    }
+}
+
+fn registration_closed() -> ApiError {
+    ApiError::new(StatusCode::FORBIDDEN, "registration is closed").with_code("registration_closed")
+}
+
+async fn verify_captcha(
+    captcha: &captcha::CaptchaConfig,
+    token: Option<&str>,
+    ip: &str,
+) -> Result<(), ApiError> {
+    let Some(token) = token.filter(|x| !x.is_empty()) else {
+        return Err(
+            ApiError::new(StatusCode::BAD_REQUEST, "captcha token is required")
+                .with_code("captcha_failed"),
+        );
+    };
+
+    match captcha.verifier.verify(token, ip).await {
+        Verification::Passed => Ok(()),
+        Verification::Unavailable if captcha.fail_open => Ok(()),
+        Verification::Failed => Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "captcha verification failed",
+        )
+        .with_code("captcha_failed")),
+        Verification::Unavailable => Err(ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "captcha verification is unavailable",
+        )
+        .with_code("captcha_unavailable")),
+    }
+}
+
+async fn ensure_ip_allowance(
+    tx: &mut Transaction<'static, Sqlite>,
+    config: &Config,
+    ip: &str,
+    adding: usize,
+) -> Result<(), ApiError> {
+    let Some(cap) = config.max_registrations_per_ip else {
+        return Ok(());
+    };
+    let registered: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM visitor WHERE ip = $1")
+        .bind(ip)
+        .fetch_one(&mut **tx)
+        .await?;
+    match registered + adding as i64 > cap.into() {
+        true => Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "too many registrations from this address",
+        )
+        .with_code("ip_limit_reached")),
+        false => Ok(()),
+    }
+}
+
+// Checked in the same transaction as the write, so two registrations racing with one email cannot both get in
+async fn ensure_unique_email(
+    tx: &mut Transaction<'static, Sqlite>,
+    config: &Config,
+    email: Option<&str>,
+    except: Option<i64>,
+) -> Result<(), ApiError> {
+    let Some(email) = email.filter(|_| config.enforce_unique_email) else {
+        return Ok(());
+    };
+    let taken: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM visitor WHERE lower(email) = lower($1) AND id IS NOT $2)",
+    )
+    .bind(email.trim())
+    .bind(except)
+    .fetch_one(&mut **tx)
+    .await?;
+    match taken {
+        true => Err(
+            ApiError::new(StatusCode::CONFLICT, "email is already registered")
+                .with_code("email_taken")
+                .with_detail("field", "email"),
+        ),
+        false => Ok(()),
+    }
+}
+
+// Everything a registration writes once it has been validated, for single and batch registration alike
+async fn store<T: TimeService>(
+    tx: &mut Transaction<'static, Sqlite>,
+    state: &ApiState<T>,
+    registration: policy::ValidatedRegistration,
+    client: &Client,
+    claim_token: Option<&str>,
+    now: DateTime<Utc>,
+) -> Result<Registration, ApiError> {
+    reserved::claim(tx, &registration.nick, claim_token, now).await?;
+    ensure_unique_email(tx, &state.config, registration.email.as_deref(), None).await?;
+    let has_email = registration.email.is_some();
+    reservation::claim(
+        tx,
+        &registration.nick,
+        registration.email.as_deref(),
+        now,
+        state.config.reservations_expire_at,
+    )
+    .await?;
+
+    // Counting in the INSERT itself keeps two registrations racing for the last place from both getting it
+    let inserted: Option<(i64, String)> = sqlx::query_as(&format!(
+        r#"WITH place AS (
+  SELECT $8 IS NULL OR (SELECT COUNT(*) FROM visitor WHERE status = 'confirmed') < $8 AS free
+)
+INSERT INTO visitor (id, created_at, ip, nick, "group", email, extra, referral, status, user_agent, source, consent_at)
+SELECT {}, $1, $2, $3, $4, $5, $6, $7, CASE WHEN free THEN 'confirmed' ELSE 'waitlisted' END, $10, $11, $12 FROM place
+WHERE free OR $9
+RETURNING id, status"#,
+        db::NEXT_VISITOR_ID,
+    ))
+    .bind(now)
+    .bind(&client.ip)
+    .bind(&registration.nick)
+    .bind(&registration.group)
+    .bind(registration.email)
+    .bind(registration.extra)
+    .bind(registration.referral)
+    .bind(state.config.visitor_limit)
+    .bind(state.config.waitlist)
+    .bind(&client.user_agent)
+    .bind(db::WEB)
+    // Validation let no email through without consent
+    .bind(has_email.then_some(now))
+    .fetch_optional(&mut **tx)
+    .await?;
+    let Some((id, status)) = inserted else {
+        return Err(ApiError::new(StatusCode::CONFLICT, "party is full").with_code("party_full"));
+    };
+    let waitlist_position: Option<i64> = match status == role::WAITLISTED {
+        true => Some(
+            sqlx::query_scalar("SELECT COUNT(*) FROM visitor WHERE status = $1 AND id <= $2")
+                .bind(role::WAITLISTED)
+                .bind(id)
+                .fetch_one(&mut **tx)
+                .await?,
+        ),
+        false => None,
+    };
+
+    let edit_token = edits::issue(tx, id).await?;
+    if has_email {
+        confirm::issue(tx, id).await?;
+    }
+
+    analytics::record(tx, analytics::Event::RegistrationCreated, now).await?;
+    changes::record(
+        tx,
+        id,
+        changes::Change::Created,
+        state.config.change_journal_length,
+        now,
     )
-    .bind(state.time.now())
-    .bind(
-        headers
-            .get("X-Forwarded-For")
-            .map(|x| x.to_str().ok())
-            .unwrap_or(Some(addr.to_string().as_str())),
+    .await?;
+
+    let payment_reference = match state
+        .config
+        .payment_reference
+        .filter(|_| !registration.payment_exempt)
+    {
+        Some(scheme) => {
+            let payment_reference = scheme.generate(id);
+            sqlx::query(
+                r#"UPDATE visitor SET payment_reference = $1, payment_status = 'unpaid' WHERE id = $2"#,
+            )
+            .bind(&payment_reference)
+            .bind(id)
+            .execute(&mut **tx)
+            .await?;
+            Some(payment_reference)
+        }
+        None => None,
+    };
+
+    Ok(Registration {
+        visitor: Visitor {
+            id: id as i32,
+            nick: registration.nick,
+            group: registration.group,
+        },
+        payment_reference,
+        status: waitlist_position.map(|_| role::WAITLISTED),
+        waitlist_position,
+        edit_token,
+        edit_deadline: edits::deadline(&state.config, now),
+    })
+}
+
+// A client resending a registration whose answer it never got, same nick from the same address shortly after. The edit
+// token is not handed out again, anyone else behind the same NAT would get it too.
+async fn repeated<T: TimeService>(
+    state: &ApiState<T>,
+    nick: &str,
+    client: &Client,
+    now: DateTime<Utc>,
+) -> Result<Option<Visitor>, sqlx::Error> {
+    if state.config.repeat_window <= chrono::Duration::zero() {
+        return Ok(None);
+    }
+    sqlx::query_as(
+        r#"SELECT id, nick, "group" FROM visitor
+WHERE nick = $1 COLLATE NOCASE AND ip = $2 AND created_at >= $3"#,
     )
-    .bind(request.nick)
-    .bind(request.group)
-    .bind(request.email)
-    .bind(request.extra)
-    .execute(&state.db)
+    .bind(nick)
+    .bind(&client.ip)
+    .bind(now - state.config.repeat_window)
+    .fetch_optional(&state.db)
+    .await
+}
+
+// Looks like any other registration, so the bot carries on as if it had worked. The id is taken the way a real one
+// would be and kept, so no visitor registering later gets it.
+async fn honeypot<T: TimeService>(
+    state: &ApiState<T>,
+    nick: String,
+    group: Option<String>,
+) -> Result<Response, ApiError> {
+    let now = state.time.clone().now();
+    let id: i32 = sqlx::query_scalar(&format!(
+        "INSERT INTO honeypot_id (id, created_at) VALUES ({}, $1) RETURNING id",
+        db::NEXT_VISITOR_ID
+    ))
+    .bind(now)
+    .fetch_one(&state.db)
     .await?;
+    let edit_token: String = sqlx::query_scalar("SELECT lower(hex(randomblob(16)))")
+        .fetch_one(&state.db)
+        .await?;
+    eprintln!("[honeypot] dropped registration of {}", nick);
 
-    Ok(StatusCode::CREATED)
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, format!("/visitors/{}", id))],
+        Json(Registration {
+            visitor: Visitor { id, nick, group },
+            payment_reference: state
+                .config
+                .payment_reference
+                .map(|scheme| scheme.generate(id.into())),
+            status: None,
+            waitlist_position: None,
+            edit_token,
+            edit_deadline: edits::deadline(&state.config, now),
+        }),
+    )
+        .into_response())
 }
 
 async fn list_visitors<T: TimeService>(
+    OriginalUri(uri): OriginalUri,
+    request_headers: HeaderMap,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    Extension(role): Extension<Role>,
+    Listed(Params { filter, page, sort }): Listed,
+    State(state): State<ApiState<T>>,
+) -> Result<
+    (
+        StatusCode,
+        HeaderMap,
+        Extension<Timings>,
+        Json<Vec<role::Projection>>,
+    ),
+    ApiError,
+> {
+    let mut timings = Timings::start();
+    let (limit, offset) = page.map_or((-1, 0), |page| (page.limit.into(), page.offset.into()));
+
+    // In registration order, the public list would tell who registered when
+    let order = match sort {
+        Some(sort) => sort.order_by(),
+        None if role.audience() == filter::Audience::Public
+            && state.config.public_stats.enabled =>
+        {
+            " ORDER BY nick COLLATE NOCASE, id"
+        }
+        None => " ORDER BY id",
+    };
+    // Read before the visitors, so a change landing in between is at worst listed early and never left out of a diff
+    // from this generation
+    let generation = changes::generation(&state.db).await?;
+    let last_changed = changes::last_changed(&state.db).await?;
+    let mut select = QueryBuilder::new("SELECT * FROM visitor");
+    filter.push_where(&mut select);
+    select
+        .push(order)
+        .push(" LIMIT ")
+        .push_bind::<i64>(limit)
+        .push(" OFFSET ")
+        .push_bind::<i64>(offset);
+    let mut visitors = select
+        .build_query_as::<db::Visitor>()
+        .fetch_all(&state.db)
+        .await?;
+    if role == Role::Admin {
+        fields::attach(&state.db, &mut visitors).await?;
+    }
+    let visitors = visitors
+        .into_iter()
+        .map(|visitor| role.project(visitor))
+        .collect();
+
+    let mut headers = match page {
+        Some(page) => {
+            let origin = peer.and_then(|ConnectInfo(peer)| {
+                state
+                    .config
+                    .trusted_proxies
+                    .origin(&request_headers, peer.ip())
+            });
+            page.headers(&uri, origin.as_deref(), filter.count(&state.db).await?)
+        }
+        None => HeaderMap::new(),
+    };
+    headers.insert("X-Generation", generation.into());
+    if let Some(changed_at) = last_changed {
+        headers.insert(header::LAST_MODIFIED, cache::http_date(changed_at));
+    }
+    timings.phase("db");
+
+    Ok((StatusCode::OK, headers, Extension(timings), Json(visitors)))
+}
+
+async fn get_visitor<T: TimeService>(
+    Path(id): Path<i64>,
+    Extension(role): Extension<Role>,
+    State(state): State<ApiState<T>>,
+) -> Result<(StatusCode, Json<role::Projection>), ApiError> {
+    let now = state.time.clone().now();
+    let not_found = || ApiError::new(StatusCode::NOT_FOUND, "visitor not found");
+    if state
+        .misses
+        .is_cached(misses::Kind::Visitor, &id.to_string(), now)
+    {
+        return Err(not_found());
+    }
+
+    let Some(mut visitor) = sqlx::query_as::<_, db::Visitor>("SELECT * FROM visitor WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&state.db)
+        .await?
+    else {
+        state
+            .misses
+            .remember(misses::Kind::Visitor, &id.to_string(), now);
+        return Err(not_found());
+    };
+    if !role.sees(&visitor) {
+        return Err(not_found());
+    }
+    if role == Role::Admin {
+        fields::attach(&state.db, std::slice::from_mut(&mut visitor)).await?;
+    }
+
+    Ok((StatusCode::OK, Json(role.project(visitor))))
+}
+
+async fn list_visitor_changes<T: TimeService>(
+    Extension(role): Extension<Role>,
+    Query(query): Query<ChangesQuery>,
+    State(state): State<ApiState<T>>,
+) -> Result<(StatusCode, Json<VisitorChanges>), ApiError> {
+    let journal = changes::since(&state.db, query.since).await?;
+
+    let ids = serde_json::to_string(&[&journal.added[..], &journal.updated[..]].concat())?;
+    let visitors = sqlx::query_as::<_, db::Visitor>(
+        "SELECT * FROM visitor WHERE id IN (SELECT value FROM json_each($1)) ORDER BY id",
+    )
+    .bind(ids)
+    .fetch_all(&state.db)
+    .await?;
+    let (added, updated): (Vec<_>, Vec<_>) = visitors
+        .into_iter()
+        .filter(|visitor| role.sees(visitor))
+        .partition(|visitor| journal.added.contains(&visitor.id.into()));
+    let project = |visitors: Vec<db::Visitor>| {
+        visitors
+            .into_iter()
+            .map(|visitor| role.project(visitor))
+            .collect()
+    };
+
+    Ok((
+        StatusCode::OK,
+        Json(VisitorChanges {
+            generation: journal.generation,
+            full_refetch: journal.full_refetch,
+            added: project(added),
+            updated: project(updated),
+            removed: journal.removed,
+        }),
+    ))
+}
+
+async fn list_visitor_buckets<T: TimeService>(
+    Filtered(filter): Filtered,
     State(state): State<ApiState<T>>,
-) -> Result<(StatusCode, Json<Vec<Visitor>>), ApiError> {
-    let visitors = sqlx::query_as::<_, Visitor>(r#"SELECT id, nick, "group" FROM visitor"#)
+) -> Result<(StatusCode, Json<buckets::Buckets<Visitor>>), ApiError> {
+    let mut select = QueryBuilder::new(r#"SELECT id, nick, "group" FROM visitor"#);
+    filter.push_where(&mut select);
+    let visitors = select
+        .build_query_as::<Visitor>()
         .fetch_all(&state.db)
         .await?;
 
-    Ok((StatusCode::OK, Json(visitors)))
+    Ok((
+        StatusCode::OK,
+        Json(buckets::bucket(visitors, |visitor| &visitor.nick)),
+    ))
+}
+
+// The same visitors the list would show, so the public count leaves out the waitlist
+async fn count_visitors<T: TimeService>(
+    Filtered(filter): Filtered,
+    State(state): State<ApiState<T>>,
+) -> Result<(StatusCode, Json<VisitorCount>), ApiError> {
+    let mut select = QueryBuilder::new(r#"SELECT DISTINCT "group" FROM visitor"#);
+    filter.push_where(&mut select);
+    select.push(r#" AND "group" IS NOT NULL"#);
+    let names: Vec<String> = select.build_query_scalar().fetch_all(&state.db).await?;
+    let groups: HashSet<String> = names.iter().filter_map(|name| groups::key(name)).collect();
+
+    Ok((
+        StatusCode::OK,
+        Json(VisitorCount {
+            count: filter.count(&state.db).await?,
+            groups: groups.len(),
+        }),
+    ))
+}
+
+async fn list_groups<T: TimeService>(
+    State(state): State<ApiState<T>>,
+) -> Result<(StatusCode, Json<Vec<Group>>), ApiError> {
+    let groups = groups::list(&state.db)
+        .await?
+        .into_iter()
+        .map(|group| Group {
+            name: group.name,
+            count: group.count,
+        })
+        .collect();
+
+    Ok((StatusCode::OK, Json(groups)))
+}
+
+async fn visitor_timeline<T: TimeService>(
+    Query(query): Query<PublicTimelineQuery>,
+    State(state): State<ApiState<T>>,
+) -> Result<(StatusCode, Json<Vec<analytics::PublicRow>>), ApiError> {
+    let rows = state
+        .config
+        .public_stats
+        .timeline(&state.db, query.bucket)
+        .await?;
+    Ok((StatusCode::OK, Json(rows)))
+}
+
+async fn status<T: TimeService>(
+    State(state): State<ApiState<T>>,
+) -> Result<Json<Status>, ApiError> {
+    let mut capabilities = Vec::new();
+    if state.config.public_stats.enabled {
+        capabilities.push("public_stats_privacy");
+    }
+
+    Ok(Json(Status {
+        schema_version: SCHEMA_VERSION,
+        capabilities,
+        replica: replica::status(&state).await?,
+        stopped_tasks: state.tasks.stopped(),
+    }))
+}
+
+async fn not_found() -> ApiError {
+    ApiError::new(StatusCode::NOT_FOUND, "not found")
 }
 
 #[tokio::main]
 async fn main() {
-    let db_connection_string = format!(
-        "sqlite://{}",
-        env::var("SQLITE_DB").unwrap_or("data.db".into())
+    let config = Config::from_env();
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().is_some_and(|command| command == "dev") {
+        if let Err(error) = dev::run(config).await {
+            eprintln!("dev mode failed: {}", error);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let base_dir = env::var("SQLITE_BASE_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| env::current_dir().expect("failed to read working directory"));
+    eprintln!(
+        "resolving relative database paths against {}",
+        base_dir.display()
+    );
+
+    let db_path = env::var("SQLITE_DB").unwrap_or("data.db".into());
+    let db_path = db::resolve_path(&db_path, &base_dir, env::home_dir().as_deref())
+        .unwrap_or_else(|error| panic!("bad SQLITE_DB {}: {}", db_path, error));
+
+    if args.first().is_some_and(|command| command == "snapshot") {
+        if let Err(error) = snapshot::run(&args[1..], &db_path).await {
+            eprintln!("snapshot failed: {}", error);
+            process::exit(1);
+        }
+        return;
+    }
+    db::prepare_file(&db_path)
+        .unwrap_or_else(|error| panic!("cannot write database {}: {}", db_path.display(), error));
+    eprintln!(
+        "using database {} ({} bytes)",
+        db_path.display(),
+        fs::metadata(&db_path).map(|x| x.len()).unwrap_or_default()
     );
-    let db_options = SqliteConnectOptions::from_str(&db_connection_string)
-        .expect(&format!("bad connection string: {}", db_connection_string))
+
+    let db_options = SqliteConnectOptions::new()
+        .filename(&db_path)
         .create_if_missing(true)
         .journal_mode(SqliteJournalMode::Wal)
         .synchronous(SqliteSynchronous::Normal);
@@ -128,19 +1165,61 @@ async fn main() {
 
     db::init(&db).await.expect("failed to initialize database");
 
+    for finding in db::check_consistency(&db)
+        .await
+        .expect("failed to check database consistency")
+    {
+        eprintln!("database inconsistency: {:?}", finding);
+    }
+
+    if config.normalize_existing_groups {
+        let updated = db::normalize_groups(
+            &db,
+            config.change_journal_length,
+            SystemTimeService {}.now(),
+        )
+        .await
+        .expect("failed to normalize existing groups");
+        eprintln!("normalized group of {} existing visitors", updated);
+    }
+
     let addr = env::var("LISTEN_ADDR").unwrap_or("127.0.0.1:3000".into());
-    let socket_address = SocketAddr::from_str(&addr).expect(&format!("bad LISTEN_ADDR: {}", addr));
+    let socket_address =
+        SocketAddr::from_str(&addr).unwrap_or_else(|_| panic!("bad LISTEN_ADDR: {}", addr));
+    if let Err(error) = strict::enforce(&strict::check(&config, socket_address), config.strict_mode)
+    {
+        panic!("{}", error);
+    }
     let listener = TcpListener::bind(socket_address)
         .await
         .expect("failed to bind listener");
 
+    let (api, tasks) = supervised_api(SystemTimeService {}, db.clone(), config);
     axum::serve(
         listener,
-        api(SystemTimeService {}, db).into_make_service_with_connect_info::<SocketAddr>(),
+        api.into_make_service_with_connect_info::<SocketAddr>(),
     )
     .with_graceful_shutdown(shutdown_signal())
     .await
     .unwrap();
+
+    if !tasks.shutdown(SHUTDOWN_DEADLINE).await {
+        eprintln!(
+            "background tasks still busy after {:?}, aborted them",
+            SHUTDOWN_DEADLINE
+        );
+    }
+
+    // Background sweeps share the pool, so closing it waits for their in-flight statements
+    if tokio::time::timeout(SHUTDOWN_DEADLINE, db.close())
+        .await
+        .is_err()
+    {
+        eprintln!(
+            "database still busy after {:?}, abandoning in-flight background work",
+            SHUTDOWN_DEADLINE
+        );
+    }
 }
 
 async fn shutdown_signal() {
@@ -169,7 +1248,11 @@ async fn shutdown_signal() {
 
 #[cfg(test)]
 mod test {
-    use std::net::{IpAddr, Ipv4Addr};
+    use std::{
+        collections::BTreeMap,
+        net::{IpAddr, Ipv4Addr},
+        time::{Duration, Instant},
+    };
 
     use axum::{body::Body, http::Request, response::IntoResponse};
     use http_body_util::BodyExt;
@@ -183,7 +1266,7 @@ mod test {
     async fn can_register_using_only_nick() {
         let time = ConstantTimeService::new();
         let db = testing::database().await;
-        let api = api(time.clone(), db.clone());
+        let api = api(time.clone(), db.clone(), Config::default());
 
         let response = api
             .oneshot(
@@ -211,7 +1294,7 @@ mod test {
 
         assert_eq!(visitor.id, 1);
         assert_eq!(visitor.created_at, time.now());
-        assert_eq!(visitor.ip, "127.0.0.1:8080");
+        assert_eq!(visitor.ip, "127.0.0.1");
         assert_eq!(visitor.nick, "Test");
         assert_eq!(visitor.group, None);
         assert_eq!(visitor.email, None);
@@ -219,35 +1302,446 @@ mod test {
     }
 
     #[tokio::test]
-    async fn can_only_register_single_nick() {
-        let time = ConstantTimeService::new();
+    async fn should_only_honor_forwarded_for_from_trusted_proxies() {
+        let proxy = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let stranger = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 4));
+
+        for (nick, trusted, peer, forwarded, expected) in [
+            (
+                "Hops",
+                "10.0.0.0/8",
+                proxy,
+                Some("203.0.113.7, 10.0.0.2"),
+                "203.0.113.7",
+            ),
+            (
+                "Port",
+                "10.0.0.0/8",
+                proxy,
+                Some("[2001:db8::7]:4711"),
+                "2001:db8::7",
+            ),
+            (
+                "Garbage",
+                "10.0.0.0/8",
+                proxy,
+                Some("<script>, 10.0.0.2"),
+                "10.0.0.1",
+            ),
+            ("Nothing", "10.0.0.0/8", proxy, None, "10.0.0.1"),
+            (
+                "Spoofed",
+                "10.0.0.0/8",
+                stranger,
+                Some("203.0.113.7"),
+                "198.51.100.4",
+            ),
+            ("Unconfigured", "", proxy, Some("203.0.113.7"), "10.0.0.1"),
+        ] {
+            let db = testing::database().await;
+            let api = api(
+                ConstantTimeService::new(),
+                db.clone(),
+                Config {
+                    trusted_proxies: trusted.parse().unwrap(),
+                    ..Config::default()
+                },
+            );
+            let mut request = Request::builder()
+                .extension(ConnectInfo(SocketAddr::new(peer, 8080)))
+                .method("POST")
+                .uri("/register")
+                .header("Content-Type", "application/json");
+            if let Some(forwarded) = forwarded {
+                request = request.header("X-Forwarded-For", forwarded);
+            }
+            let body = format!(r#"{{"nick":"{}"}}"#, nick);
+            let response = api
+                .oneshot(request.body(Body::from(body)).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED, "{}", nick);
+
+            let ip: String = sqlx::query_scalar("SELECT ip FROM visitor")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+            assert_eq!(ip, expected, "{}", nick);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_record_user_agent() {
         let db = testing::database().await;
-        let api = api(time.clone(), db.clone());
+        let api = api(ConstantTimeService::new(), db.clone(), Config::default());
 
-        testing::insert_visitor(&db, "Only One Nick", None).await;
+        let long = format!("Mozilla/5.0 {}", "x".repeat(USER_AGENT_MAX_LENGTH));
+        for (client, nick, user_agent) in [
+            (1, "Browser", Some("Mozilla/5.0 (X11; Linux x86_64)")),
+            (2, "Padded", Some(long.as_str())),
+            (3, "Script", None),
+        ] {
+            let mut request = timed_request(
+                "POST",
+                "/register",
+                client,
+                Some(format!(r#"{{"nick":"{}"}}"#, nick)),
+            );
+            if let Some(user_agent) = user_agent {
+                request
+                    .headers_mut()
+                    .insert(header::USER_AGENT, user_agent.parse().unwrap());
+            }
+            let response = api.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED, "{}", nick);
+        }
+
+        let stored: Vec<Option<String>> =
+            sqlx::query_scalar("SELECT user_agent FROM visitor ORDER BY id")
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert_eq!(
+            stored,
+            [
+                Some("Mozilla/5.0 (X11; Linux x86_64)".into()),
+                Some(long[..USER_AGENT_MAX_LENGTH].into()),
+                None
+            ]
+        );
 
         let response = api
-            .oneshot(
-                Request::builder()
-                    .extension(ConnectInfo(SocketAddr::new(
-                        IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
-                        8080,
-                    )))
-                    .method("POST")
-                    .uri("/register")
-                    .header("Content-Type", "application/json")
-                    .body(Body::from(r#"{"nick":"Only One Nick"}"#))
-                    .unwrap(),
-            )
+            .oneshot(timed_request("GET", "/visitors", 4, None))
             .await
             .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(!String::from_utf8_lossy(&body).contains("user_agent"));
+    }
 
-        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    #[tokio::test]
+    async fn should_record_registration_source() {
+        let db = testing::database().await;
+        let api = api(
+            ConstantTimeService::new(),
+            db.clone(),
+            Config {
+                admin_keys: admin::AdminKeys::new(vec!["key".into()]),
+                ..Config::default()
+            },
+        );
 
-        let body = String::from_utf8(
-            response
-                .into_body()
-                .collect()
+        let response = api
+            .clone()
+            .oneshot(timed_request(
+                "POST",
+                "/register",
+                1,
+                Some(r#"{"nick":"Razor"}"#.into()),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let mut request = timed_request("GET", "/admin/visitors", 1, None);
+        request
+            .headers_mut()
+            .insert(header::AUTHORIZATION, "Bearer key".parse().unwrap());
+        let body = api.clone().oneshot(request).await.unwrap().into_body();
+        let visitors: serde_json::Value =
+            serde_json::from_slice(&body.collect().await.unwrap().to_bytes()).unwrap();
+        assert_eq!(visitors[0]["source"], "web");
+
+        let response = api
+            .oneshot(timed_request("GET", "/visitors", 2, None))
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(!String::from_utf8_lossy(&body).contains("source"));
+    }
+
+    #[tokio::test]
+    async fn should_return_extra_objects_as_objects() {
+        let db = testing::database().await;
+        let api = api(
+            ConstantTimeService::new(),
+            db.clone(),
+            Config {
+                admin_keys: admin::AdminKeys::new(vec!["key".into()]),
+                ..Config::default()
+            },
+        );
+
+        for (client, body, expected) in [
+            (
+                1,
+                r#"{"nick":"Object","extra":{"diet":"vegan","shirt":"L"}}"#,
+                StatusCode::CREATED,
+            ),
+            (
+                2,
+                r#"{"nick":"Text","extra":"Snacks"}"#,
+                StatusCode::CREATED,
+            ),
+            (
+                3,
+                r#"{"nick":"List","extra":["vegan"]}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                4,
+                r#"{"nick":"Number","extra":42}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(timed_request(
+                    "POST",
+                    "/register",
+                    client,
+                    Some(body.into()),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected, "{}", body);
+        }
+
+        let mut request = timed_request("GET", "/admin/visitors", 5, None);
+        request
+            .headers_mut()
+            .insert(header::AUTHORIZATION, "Bearer key".parse().unwrap());
+        let response = api.oneshot(request).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let visitors: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            visitors[0]["extra"],
+            serde_json::json!({"diet": "vegan", "shirt": "L"})
+        );
+        assert_eq!(visitors[1]["extra"], "Snacks");
+    }
+
+    #[tokio::test]
+    async fn should_store_custom_fields() {
+        let db = testing::database().await;
+        let api = api(
+            ConstantTimeService::new(),
+            db.clone(),
+            Config {
+                admin_keys: admin::AdminKeys::new(vec!["key".into()]),
+                custom_fields: fields::CustomFields::new(
+                    serde_json::from_str(
+                        r#"[{"name":"shirt","type":"choice","choices":["S","M","L"],"required":true},
+                            {"name":"vegan","type":"bool"}]"#,
+                    )
+                    .unwrap(),
+                ),
+                ..Config::default()
+            },
+        );
+
+        for (client, body, expected, field) in [
+            (
+                1,
+                r#"{"nick":"Bare"}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "shirt",
+            ),
+            (
+                2,
+                r#"{"nick":"Pet","fields":{"shirt":"L","pet":"cat"}}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "pet",
+            ),
+            (
+                3,
+                r#"{"nick":"Vegan","fields":{"shirt":"L","vegan":"yes"}}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "vegan",
+            ),
+            (
+                4,
+                r#"{"nick":"Razor","fields":{"shirt":"L","vegan":true}}"#,
+                StatusCode::CREATED,
+                "",
+            ),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(timed_request(
+                    "POST",
+                    "/register",
+                    client,
+                    Some(body.into()),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected, "{}", body);
+            if expected == StatusCode::UNPROCESSABLE_ENTITY {
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                assert!(error["fields"][field].is_string(), "{}", error);
+            }
+        }
+
+        let mut request = timed_request("GET", "/admin/visitors", 5, None);
+        request
+            .headers_mut()
+            .insert(header::AUTHORIZATION, "Bearer key".parse().unwrap());
+        let response = api.clone().oneshot(request).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let visitors: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(visitors.as_array().unwrap().len(), 1);
+        assert_eq!(
+            visitors[0]["fields"],
+            serde_json::json!({"shirt": "L", "vegan": true})
+        );
+
+        let response = api
+            .oneshot(timed_request("GET", "/visitors/1", 6, None))
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(!String::from_utf8_lossy(&body).contains("shirt"));
+    }
+
+    #[tokio::test]
+    async fn should_accept_bool_custom_fields_from_forms() {
+        let db = testing::database().await;
+        let api = api(
+            ConstantTimeService::new(),
+            db.clone(),
+            Config {
+                custom_fields: fields::CustomFields::new(
+                    serde_json::from_str(r#"[{"name":"vegan","type":"bool","required":true}]"#)
+                        .unwrap(),
+                ),
+                ..Config::default()
+            },
+        );
+
+        for (client, body, status) in [
+            (1, "nick=Razor&fields%5Bvegan%5D=on", StatusCode::CREATED),
+            (2, "nick=Fairlight&fields[vegan]=true", StatusCode::CREATED),
+            (3, "nick=Orange&fields[vegan]=false", StatusCode::CREATED),
+            (
+                4,
+                "nick=Typo&fields[vegan]=yes",
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (5, "nick=Unchecked", StatusCode::UNPROCESSABLE_ENTITY),
+        ] {
+            let mut request = timed_request("POST", "/register", client, Some(body.into()));
+            request.headers_mut().insert(
+                header::CONTENT_TYPE,
+                header::HeaderValue::from_static("application/x-www-form-urlencoded"),
+            );
+            let response = api.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), status, "{}", body);
+        }
+
+        let stored: Vec<String> =
+            sqlx::query_scalar("SELECT value FROM visitor_field ORDER BY visitor_id")
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert_eq!(stored, ["true", "true", "false"]);
+    }
+
+    #[tokio::test]
+    async fn should_rate_limit_spoofed_clients_together() {
+        let db = testing::database().await;
+        let api = api(ConstantTimeService::new(), db, Config::default());
+
+        let mut statuses = vec![];
+        for i in 1..=4 {
+            let response = api
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .extension(ConnectInfo(SocketAddr::new(
+                            IpAddr::V4(Ipv4Addr::new(198, 51, 100, 4)),
+                            8080,
+                        )))
+                        .header("X-Forwarded-For", format!("203.0.113.{}", i))
+                        .header("Content-Type", "application/json")
+                        .method("POST")
+                        .uri("/register")
+                        .body(Body::from(format!(r#"{{"nick":"Spoof{}"}}"#, i)))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            statuses.push(response.status());
+        }
+        assert_eq!(statuses[..3], [StatusCode::CREATED; 3]);
+        assert_eq!(statuses[3], StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn should_rate_limit_proxied_clients_apart() {
+        let db = testing::database().await;
+        let api = api(
+            ConstantTimeService::new(),
+            db,
+            Config {
+                trusted_proxies: "10.0.0.0/8".parse().unwrap(),
+                ..Config::default()
+            },
+        );
+
+        // Five requests through one proxy stay within the burst of 3, as they come from four visitors
+        let mut statuses = vec![];
+        for (i, client) in [1, 2, 3, 4, 1].into_iter().enumerate() {
+            let mut request = timed_request(
+                "POST",
+                "/register",
+                1,
+                Some(format!(r#"{{"nick":"Proxied{}"}}"#, i)),
+            );
+            request.headers_mut().insert(
+                "X-Forwarded-For",
+                format!("203.0.113.{}", client).parse().unwrap(),
+            );
+            let response = api.clone().oneshot(request).await.unwrap();
+            statuses.push(response.status());
+        }
+        assert_eq!(statuses, [StatusCode::CREATED; 5]);
+    }
+
+    #[tokio::test]
+    async fn can_only_register_single_nick() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let api = api(time.clone(), db.clone(), Config::default());
+
+        testing::insert_visitor(&db, "Only One Nick", None).await;
+
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .extension(ConnectInfo(SocketAddr::new(
+                        IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                        8080,
+                    )))
+                    .method("POST")
+                    .uri("/register")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(r#"{"nick":"Only One Nick"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(
+            response.headers().get("Content-Type").unwrap(),
+            json::CONTENT_TYPE
+        );
+
+        let body = String::from_utf8(
+            response
+                .into_body()
+                .collect()
                 .await
                 .unwrap()
                 .to_bytes()
@@ -256,15 +1750,175 @@ mod test {
         .unwrap();
         assert_eq!(
             body,
-            r#"{"error":"(code: 2067) UNIQUE constraint failed: visitor.nick"}"#
+            r#"{"error":"nick is already registered","code":"nick_taken"}"#
         );
     }
 
+    #[tokio::test]
+    async fn should_reject_empty_nick_and_store_trimmed() {
+        let db = testing::database().await;
+        let api = api(ConstantTimeService::new(), db.clone(), Config::default());
+
+        for (client, nick, status) in [
+            (1, "", StatusCode::UNPROCESSABLE_ENTITY),
+            (2, "   ", StatusCode::UNPROCESSABLE_ENTITY),
+            (3, " Truck ", StatusCode::CREATED),
+            (4, "Truck", StatusCode::CONFLICT),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(timed_request(
+                    "POST",
+                    "/register",
+                    client,
+                    Some(format!(r#"{{"nick":"{}"}}"#, nick)),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status, "{:?}", nick);
+
+            if status == StatusCode::UNPROCESSABLE_ENTITY {
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                assert_eq!(
+                    &body[..],
+                    br#"{"error":"validation failed","fields":{"nick":"must not be empty"}}"#
+                );
+            }
+        }
+
+        let nicks: Vec<String> = sqlx::query_scalar("SELECT nick FROM visitor")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+        assert_eq!(nicks, vec!["Truck"]);
+    }
+
+    #[tokio::test]
+    async fn should_store_normalized_nick() {
+        let db = testing::database().await;
+        let api = api(ConstantTimeService::new(), db.clone(), Config::default());
+
+        for (client, nick, status) in [
+            (1, "Slummy", StatusCode::CREATED),
+            (2, "Slu\u{200B}mmy", StatusCode::UNPROCESSABLE_ENTITY),
+            (3, "Truck\n\n Driver", StatusCode::CREATED),
+            (4, "truck driver", StatusCode::CONFLICT),
+        ] {
+            let body = serde_json::json!({ "nick": nick });
+            let response = api
+                .clone()
+                .oneshot(timed_request(
+                    "POST",
+                    "/register",
+                    client,
+                    Some(body.to_string()),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status, "{:?}", nick);
+
+            if status == StatusCode::UNPROCESSABLE_ENTITY {
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                assert_eq!(
+                    &body[..],
+                    br#"{"error":"validation failed","fields":{"nick":"must not contain invisible or control characters"}}"#
+                );
+            }
+        }
+
+        let nicks: Vec<String> = sqlx::query_scalar("SELECT nick FROM visitor ORDER BY id")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+        assert_eq!(nicks, vec!["Slummy", "Truck Driver"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn should_resolve_concurrent_duplicate_nicks() {
+        const ATTEMPTS: u8 = 10;
+
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let api = api(time.clone(), db.clone(), Config::default());
+
+        let mut requests = tokio::task::JoinSet::new();
+        for i in 0..ATTEMPTS {
+            let api = api.clone();
+            requests.spawn(async move {
+                api.oneshot(
+                    Request::builder()
+                        .extension(ConnectInfo(SocketAddr::new(
+                            IpAddr::V4(Ipv4Addr::new(10, 0, 0, i)),
+                            8080,
+                        )))
+                        .method("POST")
+                        .uri("/register")
+                        .header("Content-Type", "application/json")
+                        .body(Body::from(r#"{"nick":"Racer"}"#))
+                        .unwrap(),
+                )
+                .await
+                .unwrap()
+                .status()
+            });
+        }
+
+        let mut created = 0;
+        let mut conflicts = 0;
+        while let Some(status) = requests.join_next().await {
+            match status.unwrap() {
+                StatusCode::CREATED => created += 1,
+                StatusCode::CONFLICT => conflicts += 1,
+                status => panic!("unexpected status {}", status),
+            }
+        }
+        assert_eq!(created, 1);
+        assert_eq!(conflicts, ATTEMPTS - 1);
+
+        let count: i32 = sqlx::query_scalar("SELECT COUNT(id) FROM visitor")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[tokio::test]
+    async fn should_reject_nick_differing_only_in_case() {
+        let db = testing::database().await;
+        let api = api(ConstantTimeService::new(), db, Config::default());
+
+        for (client, nick, status) in [
+            (1, "Foo", StatusCode::CREATED),
+            (2, "FOO", StatusCode::CONFLICT),
+            (3, " foo ", StatusCode::CONFLICT),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(timed_request(
+                    "POST",
+                    "/register",
+                    client,
+                    Some(format!(r#"{{"nick":"{}"}}"#, nick)),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status, "{}", nick);
+
+            if status == StatusCode::CONFLICT {
+                let body: serde_json::Value = serde_json::from_slice(
+                    &response.into_body().collect().await.unwrap().to_bytes(),
+                )
+                .unwrap();
+                assert_eq!(body["code"], "nick_taken");
+            }
+        }
+    }
+
     #[tokio::test]
     async fn can_register_with_all_fields() {
         let time = ConstantTimeService::new();
         let db = testing::database().await;
-        let api = api(time.clone(), db.clone());
+        let api = api(time.clone(), db.clone(), Config::default());
 
         let response = api
             .oneshot(
@@ -276,13 +1930,18 @@ mod test {
                     .method("POST")
                     .uri("/register")
                     .header("Content-Type", "application/json")
-                    .body(Body::from(r#"{"nick":"Test","group":"Testerz","email":"test@example.com","extra":"Snacks"}"#))
+                    .body(Body::from(r#"{"nick":"Test","group":"Testerz","email":"test@example.com","consent":true,"extra":"Snacks"}"#))
                     .unwrap(),
             )
             .await
             .unwrap();
 
         assert_eq!(response.status(), StatusCode::CREATED);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            testing::without_edit_token(&body),
+            r#"{"id":1,"nick":"Test","group":"Testerz"}"#
+        );
 
         // Check created DB entry
         let visitor = sqlx::query_as::<_, db::Visitor>(r#"SELECT * FROM visitor"#)
@@ -292,7 +1951,7 @@ mod test {
 
         assert_eq!(visitor.id, 1);
         assert_eq!(visitor.created_at, time.now());
-        assert_eq!(visitor.ip, "127.0.0.1:8080");
+        assert_eq!(visitor.ip, "127.0.0.1");
         assert_eq!(visitor.nick, "Test");
         assert_eq!(visitor.group.as_deref(), Some("Testerz"));
         assert_eq!(visitor.email.as_deref(), Some("test@example.com"));
@@ -300,13 +1959,62 @@ mod test {
     }
 
     #[tokio::test]
-    async fn should_rate_limit_register() {
+    async fn should_point_location_at_new_visitor() {
+        let db = testing::database().await;
+        testing::insert_visitor(&db, "Earlier", None).await;
+        let api = api(ConstantTimeService::new(), db.clone(), Config::default());
+
+        let response = api
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .extension(ConnectInfo(SocketAddr::new(
+                        IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                        8080,
+                    )))
+                    .method("POST")
+                    .uri("/register")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(r#"{"nick":"Located"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let id: i64 = sqlx::query_scalar(r#"SELECT id FROM visitor WHERE nick = 'Located'"#)
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        let location = response.headers()["Location"].to_str().unwrap().to_owned();
+        assert_eq!(location, format!("/visitors/{}", id));
+
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri(&location)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            &body[..],
+            format!(r#"{{"id":{},"nick":"Located","group":null}}"#, id).as_bytes()
+        );
+    }
+
+    #[tokio::test]
+    async fn should_normalize_group() {
         let time = ConstantTimeService::new();
         let db = testing::database().await;
-        let mut api = api(time.clone(), db.clone());
+        let mut api = api(time.clone(), db.clone(), Config::default());
 
-        async fn register(api: &mut Router, nick: &str) -> impl IntoResponse {
-            ServiceExt::<Request<Body>>::ready(&mut api.clone().into_service())
+        for (nick, group) in [("Spacey", "  Fairlight \\n\\t Crew "), ("Blank", "   ")] {
+            let response = ServiceExt::<Request<Body>>::ready(&mut api)
                 .await
                 .unwrap()
                 .call(
@@ -318,45 +2026,56 @@ mod test {
                         .method("POST")
                         .uri("/register")
                         .header("Content-Type", "application/json")
-                        .body(Body::from(format!(r#"{{"nick":"{}"}}"#, nick)))
+                        .body(Body::from(format!(
+                            r#"{{"nick":"{}","group":"{}"}}"#,
+                            nick, group
+                        )))
                         .unwrap(),
                 )
                 .await
-                .unwrap()
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::CREATED);
         }
 
-        let response = register(&mut api, "One").await.into_response();
-        assert_eq!(response.status(), StatusCode::CREATED);
-        let response = register(&mut api, "Two").await.into_response();
-        assert_eq!(response.status(), StatusCode::CREATED);
-        let response = register(&mut api, "Three").await.into_response();
-        assert_eq!(response.status(), StatusCode::CREATED);
-        let response = register(&mut api, "Four should fail").await.into_response();
-        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let groups: Vec<Option<String>> =
+            sqlx::query_scalar(r#"SELECT "group" FROM visitor ORDER BY id"#)
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert_eq!(groups, vec![Some("Fairlight Crew".to_owned()), None]);
     }
 
     #[tokio::test]
-    async fn can_list_visitors() {
+    async fn should_reject_overlong_group() {
         let time = ConstantTimeService::new();
         let db = testing::database().await;
-        let api = api(time.clone(), db.clone());
-
-        testing::insert_visitor(&db, "Groupless", None).await;
-
-        testing::insert_visitor(&db, "With Group", Some("Awesome".into())).await;
+        let api = api(
+            time.clone(),
+            db.clone(),
+            Config {
+                group_max_length: 4,
+                ..Config::default()
+            },
+        );
 
         let response = api
             .oneshot(
                 Request::builder()
-                    .method("GET")
-                    .uri("/visitors")
-                    .body(Body::empty())
+                    .extension(ConnectInfo(SocketAddr::new(
+                        IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                        8080,
+                    )))
+                    .method("POST")
+                    .uri("/register")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(r#"{"nick":"Test","group":"Too long"}"#))
                     .unwrap(),
             )
             .await
             .unwrap();
 
-        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
 
         let body = String::from_utf8(
             response
@@ -370,7 +2089,2400 @@ mod test {
         .unwrap();
         assert_eq!(
             body,
-            r#"[{"id":1,"nick":"Groupless","group":null},{"id":2,"nick":"With Group","group":"Awesome"}]"#
+            r#"{"error":"validation failed","fields":{"group":"must be at most 4 characters"}}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn should_enforce_field_lengths() {
+        let db = testing::database().await;
+        let api = api(ConstantTimeService::new(), db.clone(), Config::default());
+
+        let mut client = 0;
+        for (field, max_length) in [
+            ("nick", validate::NICK_MAX_LENGTH),
+            ("email", validate::EMAIL_MAX_LENGTH),
+            ("extra", validate::EXTRA_MAX_LENGTH),
+        ] {
+            for (length, status) in [
+                (max_length, StatusCode::CREATED),
+                (max_length + 1, StatusCode::UNPROCESSABLE_ENTITY),
+            ] {
+                client += 1;
+                let mut body =
+                    serde_json::json!({ "nick": format!("Visitor {}", client), "consent": true });
+                body[field] = "x".repeat(length).into();
+                match field {
+                    "nick" => body[field] = format!("{}{}", client, "x".repeat(length - 1)).into(),
+                    "email" => {
+                        body[field] = format!("{}@example.com", "x".repeat(length - 12)).into()
+                    }
+                    _ => {}
+                }
+
+                let response = api
+                    .clone()
+                    .oneshot(timed_request(
+                        "POST",
+                        "/register",
+                        client,
+                        Some(body.to_string()),
+                    ))
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), status, "{} {}", field, length);
+
+                if status == StatusCode::UNPROCESSABLE_ENTITY {
+                    let body: serde_json::Value = serde_json::from_slice(
+                        &response.into_body().collect().await.unwrap().to_bytes(),
+                    )
+                    .unwrap();
+                    assert_eq!(
+                        body["fields"][field],
+                        format!("must be at most {} characters", max_length)
+                    );
+                }
+            }
+        }
+
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(id) FROM visitor")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(count, 3);
+    }
+
+    #[tokio::test]
+    async fn should_validate_email() {
+        let db = testing::database().await;
+        let api = api(ConstantTimeService::new(), db.clone(), Config::default());
+
+        for (client, email, status) in [
+            (1, "visitor@example.com", StatusCode::CREATED),
+            (2, "not an address", StatusCode::UNPROCESSABLE_ENTITY),
+            (3, "visitor@localhost", StatusCode::UNPROCESSABLE_ENTITY),
+            (4, "", StatusCode::CREATED),
+        ] {
+            let body = serde_json::json!({ "nick": format!("Visitor {}", client), "email": email, "consent": true });
+            let response = api
+                .clone()
+                .oneshot(timed_request(
+                    "POST",
+                    "/register",
+                    client,
+                    Some(body.to_string()),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status, "{}", email);
+
+            if status == StatusCode::UNPROCESSABLE_ENTITY {
+                let body: serde_json::Value = serde_json::from_slice(
+                    &response.into_body().collect().await.unwrap().to_bytes(),
+                )
+                .unwrap();
+                assert!(body["fields"]["email"].is_string());
+            }
+        }
+
+        let emails: Vec<Option<String>> =
+            sqlx::query_scalar("SELECT email FROM visitor ORDER BY id")
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert_eq!(emails, vec![Some("visitor@example.com".to_owned()), None]);
+    }
+
+    #[tokio::test]
+    async fn should_require_consent_for_email() {
+        let time = ConstantTimeService::new();
+        let now = time.clone().now();
+        let db = testing::database().await;
+        let api = api(time.clone(), db.clone(), Config::default());
+
+        for (client, body, status) in [
+            (
+                1,
+                r#"{"nick":"Silent","email":"silent@example.com"}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                2,
+                r#"{"nick":"Declined","email":"declined@example.com","consent":false}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                3,
+                r#"{"nick":"Agreed","email":"agreed@example.com","consent":true}"#,
+                StatusCode::CREATED,
+            ),
+            (4, r#"{"nick":"Anonymous"}"#, StatusCode::CREATED),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(timed_request(
+                    "POST",
+                    "/register",
+                    client,
+                    Some(body.into()),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status, "{}", body);
+            if status == StatusCode::UNPROCESSABLE_ENTITY {
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                assert_eq!(
+                    body,
+                    r#"{"error":"validation failed","fields":{"consent":"is required to store an email"}}"#
+                );
+            }
+        }
+
+        let mut request = timed_request(
+            "POST",
+            "/register",
+            5,
+            Some("nick=Form&email=form%40example.com&consent=on".into()),
+        );
+        request.headers_mut().insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+        let response = api.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let stored: Vec<(String, Option<DateTime<Utc>>)> =
+            sqlx::query_as("SELECT nick, consent_at FROM visitor ORDER BY id")
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert_eq!(
+            stored,
+            [
+                ("Agreed".into(), Some(now)),
+                ("Anonymous".into(), None),
+                ("Form".into(), Some(now)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_accept_form_registrations() {
+        let db = testing::database().await;
+        let api = api(ConstantTimeService::new(), db.clone(), Config::default());
+
+        let mut request = timed_request(
+            "POST",
+            "/register",
+            1,
+            Some("nick=Razor+1911&group=Fairlight&email=&extra=&schema_version=1".into()),
+        );
+        request.headers_mut().insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+        let response = api.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], json::CONTENT_TYPE);
+
+        for (client, body, status) in [
+            (2, "nick=Stale&schema_version=0", StatusCode::CONFLICT),
+            (
+                3,
+                "nick=Typo&schema_version=one",
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+        ] {
+            let mut request = timed_request("POST", "/register", client, Some(body.into()));
+            request.headers_mut().insert(
+                header::CONTENT_TYPE,
+                header::HeaderValue::from_static("application/x-www-form-urlencoded"),
+            );
+            let response = api.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), status, "{}", body);
+        }
+
+        let stored: (String, Option<String>, Option<String>, Option<String>) =
+            sqlx::query_as(r#"SELECT nick, "group", email, extra FROM visitor"#)
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(
+            stored,
+            ("Razor 1911".into(), Some("Fairlight".into()), None, None)
+        );
+    }
+
+    #[tokio::test]
+    async fn should_report_every_invalid_field() {
+        let db = testing::database().await;
+        let api = api(ConstantTimeService::new(), db.clone(), Config::default());
+
+        let response = api
+            .oneshot(timed_request(
+                "POST",
+                "/register",
+                1,
+                Some(r#"{"nick":" ","email":"not an address","extra":42}"#.into()),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let body: serde_json::Value =
+            serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes())
+                .unwrap();
+        assert_eq!(body["error"], "validation failed");
+        let fields = body["fields"].as_object().unwrap();
+        assert_eq!(
+            fields.keys().collect::<Vec<_>>(),
+            ["consent", "email", "extra", "nick"]
+        );
+        assert_eq!(fields["email"], "must look like name@example.com");
+        let count: i64 = sqlx::query_scalar("SELECT count(*) FROM visitor")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[tokio::test]
+    async fn should_only_store_known_referrals() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let mut api = api(
+            time.clone(),
+            db.clone(),
+            Config {
+                referral_codes: vec!["Flyer".into()],
+                ..Config::default()
+            },
+        );
+
+        for (uri, body) in [
+            ("/register", r#"{"nick":"Body","ref":"flyer"}"#),
+            ("/register?ref=FLYER", r#"{"nick":"Query"}"#),
+            ("/register?ref=forum", r#"{"nick":"Unknown"}"#),
+        ] {
+            let response = ServiceExt::<Request<Body>>::ready(&mut api)
+                .await
+                .unwrap()
+                .call(
+                    Request::builder()
+                        .extension(ConnectInfo(SocketAddr::new(
+                            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                            8080,
+                        )))
+                        .method("POST")
+                        .uri(uri)
+                        .header("Content-Type", "application/json")
+                        .body(Body::from(body))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::CREATED);
+        }
+
+        let referrals: Vec<Option<String>> =
+            sqlx::query_scalar(r#"SELECT referral FROM visitor ORDER BY id"#)
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert_eq!(
+            referrals,
+            vec![Some("Flyer".to_owned()), Some("Flyer".to_owned()), None]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_return_payment_reference() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let api = api(
+            time.clone(),
+            db.clone(),
+            Config {
+                payment_reference: Some(payment::ReferenceScheme::Finnish),
+                ..Config::default()
+            },
+        );
+
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .extension(ConnectInfo(SocketAddr::new(
+                        IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                        8080,
+                    )))
+                    .method("POST")
+                    .uri("/register")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(r#"{"nick":"Payer"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            testing::without_edit_token(&body),
+            r#"{"id":1,"nick":"Payer","group":null,"payment_reference":"10016"}"#
+        );
+
+        let stored: (String, String) =
+            sqlx::query_as("SELECT payment_reference, payment_status FROM visitor")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(stored, ("10016".to_owned(), "unpaid".to_owned()));
+    }
+
+    #[tokio::test]
+    async fn should_hold_reserved_nick_for_returning_visitor() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let mut api = api(
+            time.clone(),
+            db.clone(),
+            Config {
+                admin_keys: admin::AdminKeys::new(vec!["key".into()]),
+                ..Config::default()
+            },
+        );
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut api)
+            .await
+            .unwrap()
+            .call(
+                Request::builder()
+                    .header("Authorization", "Bearer key")
+                    .header("Content-Type", "application/json")
+                    .method("POST")
+                    .uri("/admin/reservations/import")
+                    .body(Body::from(
+                        r#"[{"nick":"Regular","email":"regular@example.com"},{"nick":"Anonymous","email":null}]"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        for (body, status) in [
+            (
+                r#"{"nick":"regular","email":"squatter@example.com","consent":true}"#,
+                StatusCode::CONFLICT,
+            ),
+            (
+                r#"{"nick":"Regular","email":"Regular@Example.com","consent":true}"#,
+                StatusCode::CREATED,
+            ),
+            (r#"{"nick":"Anonymous"}"#, StatusCode::CREATED),
+        ] {
+            let response = ServiceExt::<Request<Body>>::ready(&mut api)
+                .await
+                .unwrap()
+                .call(
+                    Request::builder()
+                        .extension(ConnectInfo(SocketAddr::new(
+                            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                            8080,
+                        )))
+                        .method("POST")
+                        .uri("/register")
+                        .header("Content-Type", "application/json")
+                        .body(Body::from(body))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), status, "{}", body);
+            if status == StatusCode::CONFLICT {
+                let body = String::from_utf8(
+                    response
+                        .into_body()
+                        .collect()
+                        .await
+                        .unwrap()
+                        .to_bytes()
+                        .to_vec(),
+                )
+                .unwrap();
+                assert!(body.contains(r#""code":"nick_reserved_for_returning_visitor""#));
+            }
+        }
+
+        let reservations: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM reservation")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(reservations, 0);
+    }
+
+    #[tokio::test]
+    async fn should_keep_drafts_until_registration() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let mut api = api(
+            time.clone(),
+            db.clone(),
+            Config {
+                draft_max_bytes: 64,
+                ..Config::default()
+            },
+        );
+        let id = "0f8fad5b-d9cb-469f-a165-70867728950e";
+
+        for (body, status) in [
+            (
+                format!(r#"{{"id":"{}","data":{{"nick":"Halfway"}}}}"#, id),
+                StatusCode::NO_CONTENT,
+            ),
+            (
+                format!(r#"{{"id":"{}","data":"{}"}}"#, id, "x".repeat(64)),
+                StatusCode::PAYLOAD_TOO_LARGE,
+            ),
+            (
+                r#"{"id":"draft","data":{}}"#.to_owned(),
+                StatusCode::BAD_REQUEST,
+            ),
+        ] {
+            let response = ServiceExt::<Request<Body>>::ready(&mut api)
+                .await
+                .unwrap()
+                .call(
+                    Request::builder()
+                        .extension(ConnectInfo(SocketAddr::new(
+                            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                            8080,
+                        )))
+                        .method("PUT")
+                        .uri("/register/draft")
+                        .header("Content-Type", "application/json")
+                        .body(Body::from(body))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), status);
+        }
+
+        let load = || {
+            Request::builder()
+                .method("GET")
+                .uri(format!("/register/draft/{}", id))
+                .extension(ConnectInfo(SocketAddr::new(
+                    IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                    8080,
+                )))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut api)
+            .await
+            .unwrap()
+            .call(load())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], br#"{"nick":"Halfway"}"#);
+
+        let expired = super::api(
+            ConstantTimeService::at(time.clone().now() + chrono::Duration::hours(25)),
+            db.clone(),
+            Config::default(),
+        );
+        let response = expired.oneshot(load()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut api)
+            .await
+            .unwrap()
+            .call(
+                Request::builder()
+                    .extension(ConnectInfo(SocketAddr::new(
+                        IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                        8080,
+                    )))
+                    .method("POST")
+                    .uri("/register")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(format!(
+                        r#"{{"nick":"Halfway","draft_id":"{}"}}"#,
+                        id.to_uppercase()
+                    )))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = ServiceExt::<Request<Body>>::ready(&mut api)
+            .await
+            .unwrap()
+            .call(load())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn should_hide_disabled_routes() {
+        let db = testing::database().await;
+        testing::insert_visitor(&db, "Private", None).await;
+
+        for (routes, uri, method, expected) in [
+            (
+                config::Routes {
+                    public_list: false,
+                    ..Config::default().routes
+                },
+                "/visitors",
+                "GET",
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                config::Routes {
+                    public_list: false,
+                    ..Config::default().routes
+                },
+                "/visitors/buckets",
+                "GET",
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                config::Routes {
+                    public_list: false,
+                    ..Config::default().routes
+                },
+                "/groups",
+                "GET",
+                StatusCode::OK,
+            ),
+            (
+                config::Routes {
+                    groups: false,
+                    status: false,
+                    ..Config::default().routes
+                },
+                "/groups",
+                "GET",
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                config::Routes {
+                    groups: false,
+                    status: false,
+                    ..Config::default().routes
+                },
+                "/status",
+                "GET",
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                config::Routes {
+                    admin_delete: false,
+                    ..Config::default().routes
+                },
+                "/admin/visitors/1",
+                "DELETE",
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                Config::default().routes,
+                "/admin/visitors/1",
+                "DELETE",
+                StatusCode::NO_CONTENT,
+            ),
+        ] {
+            let api = api(
+                ConstantTimeService::new(),
+                db.clone(),
+                Config {
+                    admin_keys: admin::AdminKeys::new(vec!["key".into()]),
+                    routes,
+                    ..Config::default()
+                },
+            );
+
+            let response = api
+                .oneshot(
+                    Request::builder()
+                        .header("Authorization", "Bearer key")
+                        .method(method)
+                        .uri(uri)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), expected, "{} {}", method, uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_capture_rejected_registrations_when_enabled() {
+        for enabled in [false, true] {
+            let db = testing::database().await;
+            let mut api = api(
+                ConstantTimeService::new(),
+                db.clone(),
+                Config {
+                    group_max_length: 8,
+                    rejected_capture: enabled,
+                    ..Config::default()
+                },
+            );
+
+            for (body, status) in [
+                (
+                    r#"{"nick":"Long","group":"Much too long"}"#.to_owned(),
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                ),
+                (
+                    format!(r#"{{"nick":"{}"#, "x".repeat(2 * rejections::MAX_BYTES)),
+                    StatusCode::BAD_REQUEST,
+                ),
+                (r#"{"nick":"Fine"}"#.to_owned(), StatusCode::CREATED),
+            ] {
+                let response = ServiceExt::<Request<Body>>::ready(&mut api)
+                    .await
+                    .unwrap()
+                    .call(
+                        Request::builder()
+                            .extension(ConnectInfo(SocketAddr::new(
+                                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                                8080,
+                            )))
+                            .method("POST")
+                            .uri("/register")
+                            .header("Content-Type", "application/json")
+                            .body(Body::from(body))
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+
+                assert_eq!(response.status(), status);
+            }
+
+            let captured: Vec<(String, i64, Vec<u8>)> =
+                sqlx::query_as("SELECT ip, status, body FROM rejected_submission ORDER BY id")
+                    .fetch_all(&db)
+                    .await
+                    .unwrap();
+
+            if !enabled {
+                assert!(captured.is_empty());
+                continue;
+            }
+            assert_eq!(captured.len(), 2);
+            assert_eq!(captured[0].0, "127.0.0.1");
+            assert_eq!(captured[0].1, 422);
+            assert_eq!(captured[0].2, br#"{"nick":"Long","group":"Much too long"}"#);
+            assert_eq!(captured[1].2.len(), rejections::MAX_BYTES);
+        }
+    }
+
+    fn timed_request(method: &str, uri: &str, client: u32, body: Option<String>) -> Request<Body> {
+        Request::builder()
+            .extension(ConnectInfo(SocketAddr::new(
+                IpAddr::V4(Ipv4Addr::from(0x0a000000 + client)),
+                4711,
+            )))
+            .header("Content-Type", "application/json")
+            .method(method)
+            .uri(uri)
+            .body(body.map(Body::from).unwrap_or_default())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_expose_phase_timings_when_enabled() {
+        for enabled in [false, true] {
+            let db = testing::database().await;
+            let api = api(
+                ConstantTimeService::new(),
+                db,
+                Config {
+                    timing_header: enabled,
+                    ..Config::default()
+                },
+            );
+
+            let response = api
+                .oneshot(timed_request(
+                    "POST",
+                    "/register",
+                    1,
+                    Some(r#"{"nick":"Timed"}"#.into()),
+                ))
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::CREATED);
+            let phases: Vec<String> = response
+                .headers()
+                .get(timing::HEADER)
+                .map(|value| value.to_str().unwrap())
+                .into_iter()
+                .flat_map(|value| value.split(", "))
+                .map(|phase| phase.split(';').next().unwrap().to_owned())
+                .collect();
+            match enabled {
+                true => assert_eq!(phases, vec!["validation", "db"]),
+                false => assert!(phases.is_empty()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_stay_within_latency_budget() {
+        let budget = Duration::from_millis(
+            env::var("LATENCY_BUDGET_MS").map_or(100, |value| value.parse().unwrap()),
+        );
+        let db = testing::database().await;
+        let mut api = api(ConstantTimeService::new(), db, Config::default());
+
+        let mut register = Vec::new();
+        let mut list = Vec::new();
+        for client in 0..25 {
+            let started = Instant::now();
+            let response = ServiceExt::<Request<Body>>::ready(&mut api)
+                .await
+                .unwrap()
+                .call(timed_request(
+                    "POST",
+                    "/register",
+                    client,
+                    Some(format!(r#"{{"nick":"Visitor {}"}}"#, client)),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED);
+            register.push(started.elapsed());
+
+            let started = Instant::now();
+            let response = ServiceExt::<Request<Body>>::ready(&mut api)
+                .await
+                .unwrap()
+                .call(timed_request("GET", "/visitors", client, None))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            list.push(started.elapsed());
+        }
+
+        for (name, mut samples) in [("register", register), ("list", list)] {
+            samples.sort();
+            let median = samples[samples.len() / 2];
+            assert!(median < budget, "{} median {:?} over budget", name, median);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_compose_changes_into_full_listing() {
+        let db = testing::database().await;
+        let api = api(
+            ConstantTimeService::new(),
+            db,
+            Config {
+                admin_keys: admin::AdminKeys::new(vec!["key".into()]),
+                change_journal_length: 4,
+                ..Config::default()
+            },
+        );
+
+        let client = std::sync::atomic::AtomicU8::new(1);
+        let send = |method: &str, uri: String, body: Option<String>| {
+            let api = api.clone();
+            let client = client.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            let request = Request::builder()
+                .extension(ConnectInfo(SocketAddr::new(
+                    IpAddr::V4(Ipv4Addr::new(10, 0, 0, client)),
+                    8080,
+                )))
+                .header("Authorization", "Bearer key")
+                .header("Content-Type", "application/json")
+                .method(method)
+                .uri(uri)
+                .body(body.map(Body::from).unwrap_or_default())
+                .unwrap();
+            async move {
+                let response = api.oneshot(request).await.unwrap();
+                let generation = response
+                    .headers()
+                    .get("X-Generation")
+                    .map(|value| value.to_str().unwrap().parse::<i64>().unwrap());
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                (
+                    generation,
+                    serde_json::from_slice(&body).unwrap_or_default(),
+                )
+            }
+        };
+        let register = |nick: &str| {
+            send(
+                "POST",
+                "/register".into(),
+                Some(format!(r#"{{"nick":"{}"}}"#, nick)),
+            )
+        };
+
+        register("Before").await;
+        let (generation, listing): (_, serde_json::Value) =
+            send("GET", "/visitors".into(), None).await;
+        let generation = generation.unwrap();
+        let mut state: BTreeMap<i64, serde_json::Value> = listing
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|visitor| (visitor["id"].as_i64().unwrap(), visitor.clone()))
+            .collect();
+
+        register("Alpha").await;
+        register("Beta").await;
+        send("DELETE", "/admin/visitors/2".into(), None).await;
+        send("DELETE", "/admin/visitors/1".into(), None).await;
+
+        let (_, changes) = send(
+            "GET",
+            format!("/visitors/changes?since={}", generation),
+            None,
+        )
+        .await;
+        assert_eq!(changes["full_refetch"], false);
+        for visitor in changes["added"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .chain(changes["updated"].as_array().unwrap())
+        {
+            state.insert(visitor["id"].as_i64().unwrap(), visitor.clone());
+        }
+        for id in changes["removed"].as_array().unwrap() {
+            state.remove(&id.as_i64().unwrap());
+        }
+
+        let (_, listing) = send("GET", "/visitors".into(), None).await;
+        assert_eq!(
+            serde_json::Value::Array(state.into_values().collect()),
+            listing
+        );
+
+        register("Gamma").await;
+        let (_, changes) = send(
+            "GET",
+            format!("/visitors/changes?since={}", generation),
+            None,
+        )
+        .await;
+        assert_eq!(changes["full_refetch"], true);
+    }
+
+    #[tokio::test]
+    async fn can_register_with_byte_order_mark() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let api = api(time.clone(), db.clone(), Config::default());
+
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .extension(ConnectInfo(SocketAddr::new(
+                        IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                        8080,
+                    )))
+                    .method("POST")
+                    .uri("/register")
+                    .header("Content-Type", "application/json; charset=utf-8")
+                    .body(Body::from("\u{feff}{\"nick\":\"Test\"}"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let nick: String = sqlx::query_scalar("SELECT nick FROM visitor")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(nick, "Test");
+    }
+
+    #[tokio::test]
+    async fn should_reject_unsupported_charset() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let api = api(time.clone(), db.clone(), Config::default());
+
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .extension(ConnectInfo(SocketAddr::new(
+                        IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                        8080,
+                    )))
+                    .method("POST")
+                    .uri("/register")
+                    .header("Content-Type", "application/json; charset=iso-8859-1")
+                    .body(Body::from(r#"{"nick":"Test"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(
+            response.headers().get("Content-Type").unwrap(),
+            json::CONTENT_TYPE
+        );
+    }
+
+    #[tokio::test]
+    async fn should_answer_unreadable_bodies_with_json_errors() {
+        let db = testing::database().await;
+        let api = api(ConstantTimeService::new(), db.clone(), Config::default());
+
+        for (client, content_type, body, status) in [
+            (
+                1,
+                None,
+                r#"{"nick":"Test"}"#,
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ),
+            (
+                2,
+                Some("text/plain"),
+                r#"{"nick":"Test"}"#,
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ),
+            (
+                3,
+                Some("application/json"),
+                r#"{"nick":"Te"#,
+                StatusCode::BAD_REQUEST,
+            ),
+        ] {
+            let mut request = timed_request("POST", "/register", client, Some(body.into()));
+            match content_type {
+                Some(content_type) => request.headers_mut().insert(
+                    header::CONTENT_TYPE,
+                    header::HeaderValue::from_static(content_type),
+                ),
+                None => request.headers_mut().remove(header::CONTENT_TYPE),
+            };
+            let response = api.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), status, "{:?}", content_type);
+            assert_eq!(response.headers()[header::CONTENT_TYPE], json::CONTENT_TYPE);
+            let body: serde_json::Value =
+                serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes())
+                    .unwrap();
+            assert!(body["error"].is_string(), "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_rate_limit_register() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let mut api = api(time.clone(), db.clone(), Config::default());
+
+        async fn register(api: &mut Router, nick: &str) -> impl IntoResponse {
+            ServiceExt::<Request<Body>>::ready(&mut api.clone().into_service())
+                .await
+                .unwrap()
+                .call(
+                    Request::builder()
+                        .extension(ConnectInfo(SocketAddr::new(
+                            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                            8080,
+                        )))
+                        .method("POST")
+                        .uri("/register")
+                        .header("Content-Type", "application/json")
+                        .body(Body::from(format!(r#"{{"nick":"{}"}}"#, nick)))
+                        .unwrap(),
+                )
+                .await
+                .unwrap()
+        }
+
+        let response = register(&mut api, "One").await.into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = register(&mut api, "Two").await.into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = register(&mut api, "Three").await.into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = register(&mut api, "Four should fail").await.into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn should_apply_configured_register_rate_limit() {
+        let db = testing::database().await;
+        let api = api(
+            ConstantTimeService::new(),
+            db,
+            Config {
+                register_rate_limit: config::RateLimit {
+                    period_seconds: 3600,
+                    burst: 1,
+                    ..Config::default().register_rate_limit
+                },
+                ..Config::default()
+            },
+        );
+
+        let mut statuses = vec![];
+        for nick in ["One", "Two"] {
+            let body = format!(r#"{{"nick":"{}"}}"#, nick);
+            let response = api
+                .clone()
+                .oneshot(timed_request("POST", "/register", 1, Some(body)))
+                .await
+                .unwrap();
+            statuses.push(response.status());
+        }
+        assert_eq!(
+            statuses,
+            [StatusCode::CREATED, StatusCode::TOO_MANY_REQUESTS]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_delay_register_past_burst() {
+        let db = testing::database().await;
+        let limited = |max_wait_seconds| {
+            api(
+                ConstantTimeService::new(),
+                db.clone(),
+                Config {
+                    register_rate_limit: config::RateLimit {
+                        period_seconds: 1,
+                        burst: 3,
+                        mode: throttle::Mode::Delay,
+                        max_wait_seconds,
+                    },
+                    ..Config::default()
+                },
+            )
+        };
+
+        let api = limited(5);
+        let started = std::time::Instant::now();
+        for nick in ["One", "Two", "Three", "Four"] {
+            let body = format!(r#"{{"nick":"{}"}}"#, nick);
+            let response = api
+                .clone()
+                .oneshot(timed_request("POST", "/register", 1, Some(body)))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED, "{}", nick);
+        }
+        assert!(started.elapsed() >= std::time::Duration::from_millis(500));
+
+        // A wait longer than allowed is still refused
+        let api = limited(0);
+        for (nick, expected) in [
+            ("Five", StatusCode::CREATED),
+            ("Six", StatusCode::CREATED),
+            ("Seven", StatusCode::CREATED),
+            ("Eight", StatusCode::TOO_MANY_REQUESTS),
+        ] {
+            let body = format!(r#"{{"nick":"{}"}}"#, nick);
+            let response = api
+                .clone()
+                .oneshot(timed_request("POST", "/register", 1, Some(body)))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected, "{}", nick);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_cap_registrations_per_ip() {
+        let db = testing::database().await;
+        let api = api(
+            ConstantTimeService::new(),
+            db,
+            Config {
+                max_registrations_per_ip: Some(2),
+                ..Config::default()
+            },
+        );
+
+        for (client, nick, expected) in [
+            (1, "One", StatusCode::CREATED),
+            (1, "Two", StatusCode::CREATED),
+            (1, "Three", StatusCode::FORBIDDEN),
+            (2, "Four", StatusCode::CREATED),
+        ] {
+            let body = format!(r#"{{"nick":"{}"}}"#, nick);
+            let response = api
+                .clone()
+                .oneshot(timed_request("POST", "/register", client, Some(body)))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected, "{}", nick);
+            if expected == StatusCode::FORBIDDEN {
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                assert_eq!(
+                    body,
+                    r#"{"error":"too many registrations from this address","code":"ip_limit_reached"}"#
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_only_accept_allowed_groups() {
+        let db = testing::database().await;
+        let api = api(
+            ConstantTimeService::new(),
+            db.clone(),
+            Config {
+                allowed_groups: Some(vec!["Fairlight".into(), "Razor 1911".into()]),
+                ..Config::default()
+            },
+        );
+
+        for (client, body, expected) in [
+            (
+                1,
+                r#"{"nick":"Razor","group":"fairlight"}"#,
+                StatusCode::CREATED,
+            ),
+            (2, r#"{"nick":"Loner"}"#, StatusCode::CREATED),
+            (
+                3,
+                r#"{"nick":"Gate","group":"Crashers"}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(timed_request(
+                    "POST",
+                    "/register",
+                    client,
+                    Some(body.into()),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected, "{}", body);
+            if expected == StatusCode::UNPROCESSABLE_ENTITY {
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                assert_eq!(
+                    body,
+                    r#"{"error":"validation failed","fields":{"group":"is not one of the allowed groups"}}"#
+                );
+            }
+        }
+
+        let groups: Vec<Option<String>> =
+            sqlx::query_scalar(r#"SELECT "group" FROM visitor ORDER BY id"#)
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert_eq!(groups, [Some("Fairlight".into()), None]);
+    }
+
+    #[tokio::test]
+    async fn should_enforce_unique_email_when_enabled() {
+        let db = testing::database().await;
+        let api = api(
+            ConstantTimeService::new(),
+            db,
+            Config {
+                enforce_unique_email: true,
+                ..Config::default()
+            },
+        );
+
+        for (client, body, expected) in [
+            (
+                1,
+                r#"{"nick":"One","email":"one@example.com","consent":true}"#,
+                StatusCode::CREATED,
+            ),
+            (
+                2,
+                r#"{"nick":"Two","email":"one@example.com","consent":true}"#,
+                StatusCode::CONFLICT,
+            ),
+            (
+                3,
+                r#"{"nick":"Three","email":" ONE@Example.com ","consent":true}"#,
+                StatusCode::CONFLICT,
+            ),
+            (4, r#"{"nick":"Four"}"#, StatusCode::CREATED),
+            (5, r#"{"nick":"Five","email":null}"#, StatusCode::CREATED),
+            (
+                6,
+                r#"{"nick":"Six","email":"six@example.com","consent":true}"#,
+                StatusCode::CREATED,
+            ),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(timed_request(
+                    "POST",
+                    "/register",
+                    client,
+                    Some(body.into()),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected, "{}", body);
+            if expected == StatusCode::CONFLICT {
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                assert_eq!(
+                    body,
+                    r#"{"error":"email is already registered","code":"email_taken","field":"email"}"#
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_replay_registration_with_same_idempotency_key() {
+        let db = testing::database().await;
+        let api = api(ConstantTimeService::new(), db.clone(), Config::default());
+
+        let register = |key: &str, nick: &str| {
+            let mut request = timed_request(
+                "POST",
+                "/register",
+                1,
+                Some(format!(r#"{{"nick":"{}"}}"#, nick)),
+            );
+            request
+                .headers_mut()
+                .insert("Idempotency-Key", key.parse().unwrap());
+            api.clone().oneshot(request)
+        };
+
+        let first = register("retry-1", "Razor").await.unwrap();
+        let replayed = register("retry-1", "Razor").await.unwrap();
+        assert_eq!(first.status(), StatusCode::CREATED);
+        assert_eq!(replayed.status(), StatusCode::CREATED);
+        assert_eq!(replayed.headers()["Location"], "/visitors/1");
+        let first = first.into_body().collect().await.unwrap().to_bytes();
+        let replayed = replayed.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(first, replayed);
+
+        let other = register("retry-2", "Razor").await.unwrap();
+        assert_eq!(other.status(), StatusCode::CONFLICT);
+        let body = other.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            body,
+            r#"{"error":"nick is already registered","code":"nick_taken"}"#
+        );
+
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM visitor")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[tokio::test]
+    async fn should_answer_resent_registration_with_existing_visitor() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let register = |api: &Router, client: u32, nick: &str| {
+            api.clone().oneshot(timed_request(
+                "POST",
+                "/register",
+                client,
+                Some(format!(r#"{{"nick":"{}","group":"Fairlight"}}"#, nick)),
+            ))
+        };
+
+        let api = api(time.clone(), db.clone(), Config::default());
+        let first = register(&api, 1, "Razor").await.unwrap();
+        assert_eq!(first.status(), StatusCode::CREATED);
+        let resent = register(&api, 1, "razor").await.unwrap();
+        assert_eq!(resent.status(), StatusCode::OK);
+        assert_eq!(resent.headers()["Location"], "/visitors/1");
+        let body = resent.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, r#"{"id":1,"nick":"Razor","group":"Fairlight"}"#);
+
+        let other = register(&api, 2, "Razor").await.unwrap();
+        assert_eq!(other.status(), StatusCode::CONFLICT);
+
+        let later = crate::api(
+            ConstantTimeService::at(time.now() + chrono::Duration::minutes(11)),
+            db.clone(),
+            Config::default(),
+        );
+        let late = register(&later, 1, "Razor").await.unwrap();
+        assert_eq!(late.status(), StatusCode::CONFLICT);
+
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM visitor")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[tokio::test]
+    async fn can_list_visitors() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let api = api(time.clone(), db.clone(), Config::default());
+
+        testing::insert_visitor(&db, "Groupless", None).await;
+
+        testing::insert_visitor(&db, "With Group", Some("Awesome")).await;
+
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/visitors")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get("Content-Type").unwrap(),
+            json::CONTENT_TYPE
+        );
+
+        let body = String::from_utf8(
+            response
+                .into_body()
+                .collect()
+                .await
+                .unwrap()
+                .to_bytes()
+                .to_vec(),
+        )
+        .unwrap();
+        assert_eq!(
+            body,
+            r#"[{"id":1,"nick":"Groupless","group":null},{"id":2,"nick":"With Group","group":"Awesome"}]"#
+        );
+    }
+
+    #[tokio::test]
+    async fn should_return_json_for_unknown_routes() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let api = api(time.clone(), db.clone(), Config::default());
+
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/nonexistent")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers().get("Content-Type").unwrap(),
+            json::CONTENT_TYPE
+        );
+
+        let body = String::from_utf8(
+            response
+                .into_body()
+                .collect()
+                .await
+                .unwrap()
+                .to_bytes()
+                .to_vec(),
+        )
+        .unwrap();
+        assert_eq!(body, r#"{"error":"not found"}"#);
+    }
+
+    #[tokio::test]
+    async fn should_link_absolutely_behind_trusted_proxy() {
+        let db = testing::database().await;
+        let api = api(
+            ConstantTimeService::new(),
+            db.clone(),
+            Config {
+                trusted_proxies: "10.0.0.0/8".parse().unwrap(),
+                ..Config::default()
+            },
+        );
+        for nick in ["One", "Two", "Three"] {
+            testing::insert_visitor(&db, nick, None).await;
+        }
+
+        for (peer, expected) in [
+            (
+                Ipv4Addr::new(10, 0, 0, 1),
+                r#"<https://party.example.com/visitors?limit=2&offset=0>; rel="first", <https://party.example.com/visitors?limit=2&offset=2>; rel="next", <https://party.example.com/visitors?limit=2&offset=2>; rel="last""#,
+            ),
+            (
+                Ipv4Addr::new(198, 51, 100, 4),
+                r#"</visitors?limit=2&offset=0>; rel="first", </visitors?limit=2&offset=2>; rel="next", </visitors?limit=2&offset=2>; rel="last""#,
+            ),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .extension(ConnectInfo(SocketAddr::new(IpAddr::V4(peer), 4711)))
+                        .header("Host", "party.example.com")
+                        .header("X-Forwarded-Proto", "https")
+                        .uri("/visitors?limit=2")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()["Link"], expected, "{}", peer);
+        }
+    }
+
+    #[tokio::test]
+    async fn can_paginate_visitors() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let api = api(time.clone(), db.clone(), Config::default());
+
+        for nick in ["One", "Two", "Three", "Four", "Five"] {
+            testing::insert_visitor(&db, nick, None).await;
+        }
+
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/visitors?limit=2&offset=2")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("X-Total-Count").unwrap(), "5");
+        assert_eq!(
+            response.headers().get("Link").unwrap(),
+            r#"</visitors?limit=2&offset=0>; rel="first", </visitors?limit=2&offset=0>; rel="prev", </visitors?limit=2&offset=4>; rel="next", </visitors?limit=2&offset=4>; rel="last""#
+        );
+
+        let body = String::from_utf8(
+            response
+                .into_body()
+                .collect()
+                .await
+                .unwrap()
+                .to_bytes()
+                .to_vec(),
+        )
+        .unwrap();
+        assert_eq!(
+            body,
+            r#"[{"id":3,"nick":"Three","group":null},{"id":4,"nick":"Four","group":null}]"#
+        );
+    }
+
+    #[tokio::test]
+    async fn should_walk_every_page_once() {
+        let db = testing::database().await;
+        let api = api(ConstantTimeService::new(), db.clone(), Config::default());
+        for i in 0..37 {
+            testing::insert_visitor(&db, &format!("Visitor {}", i), None).await;
+        }
+
+        let page = |offset: u32| {
+            let api = api.clone();
+            async move {
+                let response = api
+                    .oneshot(timed_request(
+                        "GET",
+                        &format!("/visitors?limit=10&offset={}", offset),
+                        1,
+                        None,
+                    ))
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                assert_eq!(response.headers().get("X-Total-Count").unwrap(), "37");
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                serde_json::from_slice::<Vec<serde_json::Value>>(&body).unwrap()
+            }
+        };
+
+        let mut ids = Vec::new();
+        for offset in [0, 10, 20, 30] {
+            ids.extend(page(offset).await.iter().map(|x| x["id"].as_i64().unwrap()));
+        }
+        assert_eq!(ids, (1..=37).collect::<Vec<_>>());
+        assert!(page(100).await.is_empty());
+
+        let response = api
+            .oneshot(timed_request("GET", "/visitors?limit=0&offset=x", 1, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn should_sort_visitors() {
+        let db = testing::database().await;
+        let api = api(ConstantTimeService::new(), db.clone(), Config::default());
+        for nick in ["razor", "Blitter", "Copper", "Amiga"] {
+            testing::insert_visitor(&db, nick, None).await;
+        }
+
+        for (sort, expected) in [
+            ("nick", ["Amiga", "Blitter", "Copper", "razor"]),
+            ("-nick", ["razor", "Copper", "Blitter", "Amiga"]),
+            ("id", ["razor", "Blitter", "Copper", "Amiga"]),
+            ("-id", ["Amiga", "Copper", "Blitter", "razor"]),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(timed_request(
+                    "GET",
+                    &format!("/visitors?sort={}", sort),
+                    1,
+                    None,
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{}", sort);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let nicks: Vec<String> = serde_json::from_slice::<Vec<serde_json::Value>>(&body)
+                .unwrap()
+                .iter()
+                .map(|x| x["nick"].as_str().unwrap().to_owned())
+                .collect();
+            assert_eq!(nicks, expected, "{}", sort);
+        }
+
+        for sort in ["created_at", "email", "nick%20DESC"] {
+            let response = api
+                .clone()
+                .oneshot(timed_request(
+                    "GET",
+                    &format!("/visitors?sort={}", sort),
+                    1,
+                    None,
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["fields"][0]["code"], "unknown_sort", "{}", sort);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_count_listed_visitors() {
+        let db = testing::database().await;
+        for (nick, group) in [
+            ("Razor", Some("Razor 1911")),
+            ("Quartex", Some("razor  1911")),
+            ("Fairlight", Some("Fairlight")),
+            ("Raze", None),
+            ("Late", Some("Orange")),
+        ] {
+            testing::insert_visitor(&db, nick, group).await;
+        }
+        sqlx::query("UPDATE visitor SET status = $1 WHERE nick = 'Late'")
+            .bind(role::WAITLISTED)
+            .execute(&db)
+            .await
+            .unwrap();
+        let api = api(ConstantTimeService::new(), db, Config::default());
+
+        for (uri, expected) in [
+            ("/visitors/count", r#"{"count":4,"groups":2}"#),
+            (
+                "/visitors/count?group=Fairlight",
+                r#"{"count":1,"groups":1}"#,
+            ),
+            ("/visitors/count?grouped=false", r#"{"count":1,"groups":0}"#),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(timed_request("GET", uri, 1, None))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(body, expected, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_not_paginate_by_default() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let api = api(time.clone(), db.clone(), Config::default());
+
+        testing::insert_visitor(&db, "One", None).await;
+
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/visitors")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get("Link").is_none());
+        assert!(response.headers().get("X-Total-Count").is_none());
+    }
+
+    #[test]
+    fn should_bump_schema_version_with_required_fields() {
+        // Append a new entry (and bump SCHEMA_VERSION) whenever the required fields change
+        const HISTORY: &[(u32, u64)] = &[(1, 0x4760d2fe60facdfb)];
+
+        let declaration = include_str!("main.rs")
+            .split("struct RegisterRequest {")
+            .nth(1)
+            .and_then(|x| x.split('}').next())
+            .unwrap();
+        let required = declaration
+            .lines()
+            .map(str::trim)
+            .filter(|line| line.contains(':') && !line.starts_with('#'))
+            .filter(|line| !line.contains("Option<"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let fingerprint = required.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+        });
+
+        assert_eq!(
+            HISTORY.last(),
+            Some(&(SCHEMA_VERSION, fingerprint)),
+            "required fields of RegisterRequest changed to:\n{}",
+            required
+        );
+    }
+
+    #[tokio::test]
+    async fn should_check_registration_schema_version() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let mut api = api(time.clone(), db.clone(), Config::default());
+
+        async fn register(
+            api: &mut Router,
+            version: Option<&str>,
+            body: &'static str,
+        ) -> axum::response::Response {
+            let mut request = Request::builder()
+                .extension(ConnectInfo(SocketAddr::new(
+                    IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                    8080,
+                )))
+                .method("POST")
+                .uri("/register")
+                .header("Content-Type", "application/json");
+            if let Some(version) = version {
+                request = request.header("X-Schema-Version", version);
+            }
+
+            ServiceExt::<Request<Body>>::ready(api)
+                .await
+                .unwrap()
+                .call(request.body(Body::from(body)).unwrap())
+                .await
+                .unwrap()
+        }
+
+        let response = register(&mut api, Some("1"), r#"{"nick":"Current"}"#).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = register(&mut api, None, r#"{"nick":"Absent"}"#).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let response = register(&mut api, None, r#"{"nick":"Stale","schema_version":0}"#).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let body = String::from_utf8(
+            response
+                .into_body()
+                .collect()
+                .await
+                .unwrap()
+                .to_bytes()
+                .to_vec(),
+        )
+        .unwrap();
+        assert_eq!(
+            body,
+            r#"{"error":"registration form is outdated","code":"form_outdated","schema_version":1}"#
+        );
+
+        let count: i32 = sqlx::query_scalar("SELECT COUNT(id) FROM visitor")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[tokio::test]
+    async fn should_report_schema_version_in_status() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let api = api(time.clone(), db.clone(), Config::default());
+
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/status")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = String::from_utf8(
+            response
+                .into_body()
+                .collect()
+                .await
+                .unwrap()
+                .to_bytes()
+                .to_vec(),
+        )
+        .unwrap();
+        assert_eq!(body, r#"{"schema_version":1,"capabilities":[]}"#);
+    }
+
+    #[tokio::test]
+    async fn should_keep_registration_order_private() {
+        let db = testing::database().await;
+        for nick in ["Zed", "alpha", "Mike"] {
+            testing::insert_visitor(&db, nick, None).await;
+        }
+        let mut conn = db.acquire().await.unwrap();
+        analytics::record(
+            &mut conn,
+            analytics::Event::RegistrationCreated,
+            chrono::Utc::now(),
+        )
+        .await
+        .unwrap();
+
+        for (enabled, public, capabilities) in [
+            (false, ["Zed", "alpha", "Mike"], "[]"),
+            (
+                true,
+                ["alpha", "Mike", "Zed"],
+                r#"["public_stats_privacy"]"#,
+            ),
+        ] {
+            let api = api(
+                ConstantTimeService::new(),
+                db.clone(),
+                Config {
+                    admin_keys: admin::AdminKeys::new(vec!["key".into()]),
+                    public_stats: analytics::Privacy { enabled },
+                    ..Config::default()
+                },
+            );
+            let get = |uri: &'static str, admin: bool| {
+                let mut request = Request::builder().uri(uri);
+                if admin {
+                    request = request.header("Authorization", "Bearer key");
+                }
+                let api = api.clone();
+                async move {
+                    let response = api
+                        .oneshot(request.body(Body::empty()).unwrap())
+                        .await
+                        .unwrap();
+                    let body = response.into_body().collect().await.unwrap().to_bytes();
+                    serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+                }
+            };
+            let nicks = |visitors: serde_json::Value| {
+                visitors
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|visitor| visitor["nick"].as_str().unwrap().to_owned())
+                    .collect::<Vec<_>>()
+            };
+
+            assert_eq!(nicks(get("/visitors", false).await), public);
+            assert_eq!(
+                nicks(get("/admin/visitors", true).await),
+                ["Zed", "alpha", "Mike"]
+            );
+            assert_eq!(
+                get("/status", false).await["capabilities"].to_string(),
+                capabilities
+            );
+            let created = match enabled {
+                true => serde_json::json!("<5"),
+                false => serde_json::json!(1),
+            };
+            assert_eq!(
+                get("/visitors/timeline", false).await[0]["created"],
+                created
+            );
+            assert_eq!(
+                get("/admin/analytics/timeline", true).await[0]["created"],
+                1
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn can_list_merged_groups() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let api = api(time.clone(), db.clone(), Config::default());
+
+        testing::insert_visitor(&db, "One", Some("Fairlight ")).await;
+        testing::insert_visitor(&db, "Two", Some("fairlight")).await;
+        testing::insert_visitor(&db, "Three", Some("Fairlight")).await;
+        testing::insert_visitor(&db, "Four", Some("Razor 1911")).await;
+        testing::insert_visitor(&db, "Five", None).await;
+
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/groups")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = String::from_utf8(
+            response
+                .into_body()
+                .collect()
+                .await
+                .unwrap()
+                .to_bytes()
+                .to_vec(),
+        )
+        .unwrap();
+        assert_eq!(
+            body,
+            r#"[{"name":"Fairlight","count":3},{"name":"Razor 1911","count":1}]"#
+        );
+    }
+
+    fn captcha_config(fail_open: bool) -> captcha::CaptchaConfig {
+        captcha::CaptchaConfig {
+            verifier: Arc::new(captcha::FakeVerifier),
+            fail_open,
+        }
+    }
+
+    async fn register_with_captcha(
+        api: &mut Router,
+        nick: &str,
+        token: Option<&str>,
+    ) -> StatusCode {
+        let body = match token {
+            Some(token) => format!(r#"{{"nick":"{}","captcha_token":"{}"}}"#, nick, token),
+            None => format!(r#"{{"nick":"{}"}}"#, nick),
+        };
+
+        ServiceExt::<Request<Body>>::ready(api)
+            .await
+            .unwrap()
+            .call(
+                Request::builder()
+                    .extension(ConnectInfo(SocketAddr::new(
+                        IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                        8080,
+                    )))
+                    .method("POST")
+                    .uri("/register")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn should_verify_captcha_when_configured() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let mut api = api(
+            time.clone(),
+            db.clone(),
+            Config {
+                captcha: Some(captcha_config(false)),
+                ..Config::default()
+            },
+        );
+
+        assert_eq!(
+            register_with_captcha(&mut api, "Human", Some("good")).await,
+            StatusCode::CREATED
+        );
+        assert_eq!(
+            register_with_captcha(&mut api, "Bot", Some("bad")).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            register_with_captcha(&mut api, "Lazy Bot", None).await,
+            StatusCode::BAD_REQUEST
+        );
+
+        let nicks: Vec<String> = sqlx::query_scalar("SELECT nick FROM visitor")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+        assert_eq!(nicks, vec!["Human"]);
+    }
+
+    #[tokio::test]
+    async fn should_ignore_captcha_token_when_not_configured() {
+        let db = testing::database().await;
+        let mut api = api(ConstantTimeService::new(), db, Config::default());
+
+        assert_eq!(
+            register_with_captcha(&mut api, "Human", Some("bad")).await,
+            StatusCode::CREATED
+        );
+    }
+
+    #[tokio::test]
+    async fn should_apply_captcha_outage_policy() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+
+        let mut fail_open = api(
+            time.clone(),
+            db.clone(),
+            Config {
+                captcha: Some(captcha_config(true)),
+                ..Config::default()
+            },
+        );
+        assert_eq!(
+            register_with_captcha(&mut fail_open, "Lucky", Some("slow")).await,
+            StatusCode::CREATED
+        );
+
+        let mut fail_closed = api(
+            time.clone(),
+            db.clone(),
+            Config {
+                captcha: Some(captcha_config(false)),
+                ..Config::default()
+            },
+        );
+        assert_eq!(
+            register_with_captcha(&mut fail_closed, "Unlucky", Some("slow")).await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    #[tokio::test]
+    async fn can_list_visitor_buckets() {
+        let time = ConstantTimeService::new();
+        let db = testing::database().await;
+        let api = api(time.clone(), db.clone(), Config::default());
+
+        for nick in ["zeta", "1337", "Alpha", "🦀", "apple"] {
+            testing::insert_visitor(&db, nick, None).await;
+        }
+
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/visitors/buckets")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = String::from_utf8(
+            response
+                .into_body()
+                .collect()
+                .await
+                .unwrap()
+                .to_bytes()
+                .to_vec(),
+        )
+        .unwrap();
+        assert_eq!(
+            body,
+            concat!(
+                r##"{"total":5,"counts":{"#":2,"A":2,"Z":1},"buckets":{"#":[{"id":2,"nick":"1337","group":null},{"id":4,"nick":"🦀","group":null}],"##,
+                r#""A":[{"id":3,"nick":"Alpha","group":null},{"id":5,"nick":"apple","group":null}],"#,
+                r#""Z":[{"id":1,"nick":"zeta","group":null}]}}"#
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn should_open_registration_at_opening_time() {
+        let opens_at = chrono::DateTime::parse_from_rfc3339("2024-03-01T12:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+
+        for (client, now, opens_at, expected) in [
+            (
+                1,
+                opens_at - chrono::Duration::seconds(1),
+                Some(opens_at),
+                StatusCode::FORBIDDEN,
+            ),
+            (2, opens_at, Some(opens_at), StatusCode::CREATED),
+            (
+                3,
+                opens_at - chrono::Duration::days(1),
+                None,
+                StatusCode::CREATED,
+            ),
+        ] {
+            let db = testing::database().await;
+            let api = api(
+                ConstantTimeService::at(now),
+                db,
+                Config {
+                    registration_opens_at: opens_at,
+                    ..Config::default()
+                },
+            );
+
+            let response = api
+                .oneshot(timed_request(
+                    "POST",
+                    "/register",
+                    client,
+                    Some(r#"{"nick":"Early"}"#.into()),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected, "{}", now);
+            if expected == StatusCode::FORBIDDEN {
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                assert_eq!(
+                    body,
+                    r#"{"error":"registration is not open yet","code":"registration_not_open","opens_at":"2024-03-01T12:00:00Z"}"#
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_drop_honeypot_registrations() {
+        let db = testing::database().await;
+        let api = api(
+            ConstantTimeService::new(),
+            db.clone(),
+            Config {
+                honeypot_field: Some("homepage".into()),
+                ..Config::default()
+            },
+        );
+        let nicks = || async {
+            sqlx::query_scalar::<_, String>("SELECT nick FROM visitor ORDER BY id")
+                .fetch_all(&db)
+                .await
+                .unwrap()
+        };
+
+        let response = api
+            .clone()
+            .oneshot(timed_request(
+                "POST",
+                "/register",
+                1,
+                Some(r#"{"nick":"Spammer","email":"spam@example.com","consent":true,"homepage":"https://example.com"}"#.into()),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let location = response.headers()[header::LOCATION].clone();
+        let dropped_headers: Vec<_> = response.headers().keys().cloned().collect();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let dropped: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(dropped["nick"], "Spammer");
+        assert!(dropped["id"].as_i64().unwrap() > 0, "{}", dropped);
+        assert_eq!(location, format!("/visitors/{}", dropped["id"]).as_str());
+        assert!(nicks().await.is_empty());
+
+        for (client, body) in [
+            (2, r#"{"nick":"Razor"}"#),
+            (3, r#"{"nick":"Fairlight","homepage":""}"#),
+            (4, r#"{"nick":"Triad","website":"https://example.com"}"#),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(timed_request(
+                    "POST",
+                    "/register",
+                    client,
+                    Some(body.into()),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::CREATED, "{}", body);
+            let headers: Vec<_> = response.headers().keys().cloned().collect();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            let registered: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(headers, dropped_headers, "{}", registered);
+            assert_eq!(
+                registered.as_object().unwrap().keys().collect::<Vec<_>>(),
+                dropped.as_object().unwrap().keys().collect::<Vec<_>>()
+            );
+        }
+        assert_eq!(nicks().await, ["Razor", "Fairlight", "Triad"]);
+        let ids: Vec<i64> = sqlx::query_scalar("SELECT id FROM visitor")
+            .fetch_all(&db)
+            .await
+            .unwrap();
+        assert!(
+            !ids.contains(&dropped["id"].as_i64().unwrap()),
+            "{}",
+            dropped
+        );
+    }
+
+    #[tokio::test]
+    async fn should_refuse_registrations_once_full() {
+        let db = testing::database().await;
+        let api = api(
+            ConstantTimeService::new(),
+            db.clone(),
+            Config {
+                visitor_limit: Some(3),
+                ..Config::default()
+            },
+        );
+        for nick in ["One", "Two"] {
+            testing::insert_visitor(&db, nick, None).await;
+        }
+
+        for (client, nick, expected) in [
+            (1, "Three", StatusCode::CREATED),
+            (2, "Four", StatusCode::CONFLICT),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(timed_request(
+                    "POST",
+                    "/register",
+                    client,
+                    Some(format!(r#"{{"nick":"{}"}}"#, nick)),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected, "{}", nick);
+            if expected == StatusCode::CONFLICT {
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                assert_eq!(body, r#"{"error":"party is full","code":"party_full"}"#);
+            }
+        }
+
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM visitor")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(count, 3);
+    }
+
+    #[tokio::test]
+    async fn should_admit_only_one_for_the_last_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = testing::file_database(dir.path()).await;
+        let api = api(
+            ConstantTimeService::new(),
+            db.clone(),
+            Config {
+                visitor_limit: Some(2),
+                ..Config::default()
+            },
+        );
+        testing::insert_visitor(&db, "One", None).await;
+
+        let register = |client, nick: &str| {
+            api.clone().oneshot(timed_request(
+                "POST",
+                "/register",
+                client,
+                Some(format!(r#"{{"nick":"{}"}}"#, nick)),
+            ))
+        };
+        let (first, second) = tokio::join!(register(1, "Two"), register(2, "Three"));
+        let mut statuses = [first.unwrap().status(), second.unwrap().status()];
+        statuses.sort();
+        assert_eq!(statuses[0], StatusCode::CREATED);
+        assert_ne!(statuses[1], StatusCode::CREATED);
+
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM visitor")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[tokio::test]
+    async fn should_waitlist_once_full() {
+        let db = testing::database().await;
+        let api = api(
+            ConstantTimeService::new(),
+            db.clone(),
+            Config {
+                admin_keys: admin::AdminKeys::new(vec!["key".into()]),
+                visitor_limit: Some(2),
+                waitlist: true,
+                ..Config::default()
+            },
+        );
+        testing::insert_visitor(&db, "One", None).await;
+
+        let send = |method: &str, uri: &str, admin: bool| {
+            let mut request = Request::builder().method(method).uri(uri);
+            if admin {
+                request = request.header("Authorization", "Bearer key");
+            }
+            api.clone().oneshot(request.body(Body::empty()).unwrap())
+        };
+        let body = |response: axum::response::Response| async move {
+            let bytes = response.into_body().collect().await.unwrap().to_bytes();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        };
+
+        for (client, nick, status, expected) in [
+            (
+                1,
+                "Two",
+                StatusCode::CREATED,
+                r#"{"id":2,"nick":"Two","group":null}"#,
+            ),
+            (
+                2,
+                "Three",
+                StatusCode::ACCEPTED,
+                r#"{"id":3,"nick":"Three","group":null,"status":"waitlisted","waitlist_position":1}"#,
+            ),
+            (
+                3,
+                "Four",
+                StatusCode::ACCEPTED,
+                r#"{"id":4,"nick":"Four","group":null,"status":"waitlisted","waitlist_position":2}"#,
+            ),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(timed_request(
+                    "POST",
+                    "/register",
+                    client,
+                    Some(format!(r#"{{"nick":"{}"}}"#, nick)),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status, "{}", nick);
+            let bytes = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(testing::without_edit_token(&bytes), expected);
+        }
+
+        let public = body(send("GET", "/visitors", false).await.unwrap()).await;
+        assert_eq!(public.as_array().unwrap().len(), 2);
+        let all = body(send("GET", "/admin/visitors", true).await.unwrap()).await;
+        let statuses: Vec<&str> = all
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|visitor| visitor["status"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            statuses,
+            ["confirmed", "confirmed", "waitlisted", "waitlisted"]
+        );
+        assert_eq!(
+            send("GET", "/visitors/3", false).await.unwrap().status(),
+            StatusCode::NOT_FOUND
+        );
+
+        // A freed place stays free until an organizer promotes someone
+        let response = send("DELETE", "/admin/visitors/1", true).await.unwrap();
+        assert!(response.status().is_success());
+        let status: String = sqlx::query_scalar("SELECT status FROM visitor WHERE id = 3")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(status, "waitlisted");
+
+        for already in [false, true] {
+            let response = send("POST", "/admin/visitors/3/promote", true)
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(
+                body(response).await,
+                serde_json::json!({"id": 3, "status": "confirmed", "already": already})
+            );
+        }
+        assert_eq!(
+            send("GET", "/visitors/3", false).await.unwrap().status(),
+            StatusCode::OK
+        );
+
+        // The party is full again, so going past the limit has to be asked for
+        let response = send("POST", "/admin/visitors/4/promote", true)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(
+            body(response).await,
+            serde_json::json!({"error": "party is full", "code": "party_full", "visitor_limit": 2})
+        );
+        let response = send("POST", "/admin/visitors/4/promote?force=true", true)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            body(response).await,
+            serde_json::json!({"id": 4, "status": "confirmed", "already": false})
         );
     }
 }
@@ -0,0 +1,338 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+
+use crate::{debug, error::ApiError, time::TimeService, ApiState};
+
+const WINDOW: Duration = Duration::minutes(1);
+const MAX_CLIENTS: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Kind {
+    Visitor,
+    Draft,
+}
+
+#[derive(Clone, Copy)]
+pub struct Settings {
+    // Zero turns either one off
+    pub cache_size: usize,
+    pub cache_ttl: Duration,
+    pub budget: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            cache_size: 1024,
+            cache_ttl: Duration::seconds(30),
+            budget: 60,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Counts {
+    pub hits: u64,
+    pub misses: u64,
+    pub dampened: u64,
+}
+
+struct Cached {
+    expires_at: DateTime<Utc>,
+    used: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    cache: HashMap<(Kind, String), Cached>,
+    clock: u64,
+    // Misses per client within the window that started at its first one
+    clients: HashMap<String, (DateTime<Utc>, u32)>,
+    counts: Counts,
+}
+
+// Remembers lookups that found nothing, so a scanner repeating them does not reach the database, and
+// answers clients that keep missing with 429
+#[derive(Clone)]
+pub struct Misses {
+    settings: Settings,
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Misses {
+    pub fn new(settings: Settings) -> Self {
+        Self {
+            settings,
+            inner: Arc::default(),
+        }
+    }
+
+    pub fn is_cached(&self, kind: Kind, id: &str, now: DateTime<Utc>) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clock += 1;
+        let clock = inner.clock;
+        let key = (kind, id.to_owned());
+        match inner.cache.get_mut(&key) {
+            Some(cached) if cached.expires_at > now => {
+                cached.used = clock;
+                inner.counts.hits += 1;
+                true
+            }
+            Some(_) => {
+                inner.cache.remove(&key);
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn remember(&self, kind: Kind, id: &str, now: DateTime<Utc>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.counts.misses += 1;
+        if self.settings.cache_size == 0 {
+            return;
+        }
+
+        if inner.cache.len() >= self.settings.cache_size {
+            let oldest = inner
+                .cache
+                .iter()
+                .min_by_key(|(_, cached)| cached.used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                inner.cache.remove(&oldest);
+            }
+        }
+        inner.clock += 1;
+        let used = inner.clock;
+        inner.cache.insert(
+            (kind, id.to_owned()),
+            Cached {
+                expires_at: now + self.settings.cache_ttl,
+                used,
+            },
+        );
+    }
+
+    pub fn forget(&self, kind: Kind, id: &str) {
+        self.inner
+            .lock()
+            .unwrap()
+            .cache
+            .remove(&(kind, id.to_owned()));
+    }
+
+    pub fn forget_all(&self, kind: Kind) {
+        self.inner
+            .lock()
+            .unwrap()
+            .cache
+            .retain(|(cached, _), _| *cached != kind);
+    }
+
+    pub fn counts(&self) -> Counts {
+        self.inner.lock().unwrap().counts
+    }
+
+    fn is_dampened(&self, ip: &str, now: DateTime<Utc>) -> bool {
+        if self.settings.budget == 0 {
+            return false;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        let dampened = inner
+            .clients
+            .get(ip)
+            .is_some_and(|&(since, misses)| now < since + WINDOW && misses >= self.settings.budget);
+        if dampened {
+            inner.counts.dampened += 1;
+        }
+        dampened
+    }
+
+    fn record_miss(&self, ip: &str, now: DateTime<Utc>) {
+        if self.settings.budget == 0 {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        if inner.clients.len() >= MAX_CLIENTS {
+            inner.clients.retain(|_, (since, _)| now < *since + WINDOW);
+        }
+        let (since, misses) = inner.clients.entry(ip.to_owned()).or_insert((now, 0));
+        if now >= *since + WINDOW {
+            (*since, *misses) = (now, 0);
+        }
+        *misses += 1;
+    }
+}
+
+pub async fn dampen<T: TimeService>(
+    State(state): State<ApiState<T>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let ip = debug::client_ip(&request, &state.config.trusted_proxies);
+    if state.misses.is_dampened(&ip, state.time.clone().now()) {
+        return ApiError::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "too many lookups of missing items",
+        )
+        .with_code("miss_budget")
+        .into_response();
+    }
+
+    let response = next.run(request).await;
+    if response.status() == StatusCode::NOT_FOUND {
+        state.misses.record_miss(&ip, state.time.clone().now());
+    }
+    response
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use axum::{body::Body, extract::ConnectInfo};
+    use http_body_util::BodyExt;
+    use hyper::Request;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{admin::AdminKeys, config::Config, testing, time::ConstantTimeService};
+
+    fn settings(cache_size: usize, budget: u32) -> Settings {
+        Settings {
+            cache_size,
+            budget,
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn should_evict_least_recently_used() {
+        let misses = Misses::new(settings(2, 0));
+        let now = Utc::now();
+
+        misses.remember(Kind::Visitor, "1", now);
+        misses.remember(Kind::Visitor, "2", now);
+        assert!(misses.is_cached(Kind::Visitor, "1", now));
+        misses.remember(Kind::Draft, "1", now);
+
+        assert!(misses.is_cached(Kind::Visitor, "1", now));
+        assert!(!misses.is_cached(Kind::Visitor, "2", now));
+        assert!(misses.is_cached(Kind::Draft, "1", now));
+        assert!(!misses.is_cached(Kind::Draft, "1", now + Duration::minutes(1)));
+    }
+
+    fn lookup(uri: &str, client: u8) -> Request<Body> {
+        Request::builder()
+            .extension(ConnectInfo(SocketAddr::from(([10, 0, 0, client], 4711))))
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_cache_misses_until_created() {
+        let db = testing::database().await;
+        let config = Config {
+            admin_keys: AdminKeys::new(vec!["key".into()]),
+            ..Config::default()
+        };
+        let api = crate::api(ConstantTimeService::new(), db.clone(), config);
+        let status = |uri: &'static str| {
+            let api = api.clone();
+            async move { api.oneshot(lookup(uri, 1)).await.unwrap().status() }
+        };
+
+        assert_eq!(status("/visitors/1").await, StatusCode::NOT_FOUND);
+        // Written behind the API's back, so only the cache still says it is missing
+        testing::insert_visitor(&db, "Razor", None).await;
+        assert_eq!(status("/visitors/1").await, StatusCode::NOT_FOUND);
+        assert_eq!(status("/visitors/2").await, StatusCode::NOT_FOUND);
+
+        let response = api
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .extension(axum::extract::ConnectInfo(std::net::SocketAddr::from((
+                        [127, 0, 0, 1],
+                        8080,
+                    ))))
+                    .header("Content-Type", "application/json")
+                    .method("POST")
+                    .uri("/register")
+                    .body(Body::from(r#"{"nick":"Fairlight"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(status("/visitors/2").await, StatusCode::OK);
+
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .header("Authorization", "Bearer key")
+                    .uri("/admin/stats")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let stats: serde_json::Value =
+            serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes())
+                .unwrap();
+        assert_eq!(
+            stats["negative_cache"],
+            serde_json::json!({"hits": 1, "misses": 2, "dampened": 0})
+        );
+    }
+
+    #[tokio::test]
+    async fn should_dampen_scanning_clients() {
+        let db = testing::database().await;
+        testing::insert_visitor(&db, "Razor", None).await;
+        let api = crate::api(
+            ConstantTimeService::new(),
+            db,
+            Config {
+                misses: settings(0, 3),
+                ..Config::default()
+            },
+        );
+
+        for id in 100..103 {
+            let response = api
+                .clone()
+                .oneshot(lookup(&format!("/visitors/{}", id), 1))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        }
+        for uri in ["/visitors/103", "/visitors/1"] {
+            let response = api.clone().oneshot(lookup(uri, 1)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS, "{}", uri);
+        }
+
+        for (uri, expected) in [
+            ("/visitors/1", StatusCode::OK),
+            ("/visitors/100", StatusCode::NOT_FOUND),
+        ] {
+            let response = api.clone().oneshot(lookup(uri, 2)).await.unwrap();
+            assert_eq!(response.status(), expected, "{}", uri);
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Uri};
+
+use crate::{filter::Audience, params::Problem};
+
+pub const DEFAULT_LIMIT: u32 = 50;
+pub const MAX_LIMIT: u32 = 500;
+
+const PUBLIC_SORTS: &[&str] = &["nick", "-nick", "id", "-id"];
+const ADMIN_SORTS: &[&str] = &["nick", "-nick", "id", "-id", "created_at", "-created_at"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Page {
+    pub limit: u32,
+    pub offset: u32,
+}
+
+impl Page {
+    // Links are relative unless the origin the client used is known, see TrustedProxies::origin
+    pub fn headers(&self, uri: &Uri, origin: Option<&str>, total: u32) -> HeaderMap {
+        let last = total.saturating_sub(1) / self.limit * self.limit;
+
+        let mut links = vec![(0, "first")];
+        if self.offset > 0 {
+            links.push((self.offset.saturating_sub(self.limit).min(last), "prev"));
+        }
+        if self.offset.saturating_add(self.limit) < total {
+            links.push((self.offset + self.limit, "next"));
+        }
+        links.push((last, "last"));
+
+        let link = links
+            .into_iter()
+            .map(|(offset, rel)| {
+                format!(
+                    r#"<{}{}>; rel="{}""#,
+                    origin.unwrap_or_default(),
+                    self.link(uri, offset),
+                    rel
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static("x-total-count"),
+            HeaderValue::from(total),
+        );
+        headers.insert(
+            HeaderName::from_static("link"),
+            HeaderValue::from_str(&link).expect("link header is always valid"),
+        );
+        headers
+    }
+
+    fn link(&self, uri: &Uri, offset: u32) -> String {
+        let mut query = form_urlencoded::Serializer::new(String::new());
+        for (key, value) in form_urlencoded::parse(uri.query().unwrap_or_default().as_bytes()) {
+            if key != "limit" && key != "offset" {
+                query.append_pair(&key, &value);
+            }
+        }
+        query.append_pair("limit", &self.limit.to_string());
+        query.append_pair("offset", &offset.to_string());
+
+        format!("{}?{}", uri.path(), query.finish())
+    }
+}
+
+// Only whole ORDER BY clauses from this list reach the query, each ending on id so pages never overlap
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sort(&'static str);
+
+impl Sort {
+    pub fn parse(value: &str, audience: Audience) -> Result<Self, Problem> {
+        let allowed = match audience {
+            Audience::Public => PUBLIC_SORTS,
+            Audience::Admin => ADMIN_SORTS,
+        };
+        if !allowed.contains(&value) {
+            return Err(Problem::new("sort", "unknown_sort")
+                .with_value(value)
+                .with("allowed", allowed));
+        }
+        Ok(Self(match value {
+            "nick" => " ORDER BY nick COLLATE NOCASE, id",
+            "-nick" => " ORDER BY nick COLLATE NOCASE DESC, id DESC",
+            "id" => " ORDER BY id",
+            "-id" => " ORDER BY id DESC",
+            "created_at" => " ORDER BY created_at, id",
+            _ => " ORDER BY created_at DESC, id DESC",
+        }))
+    }
+
+    pub fn order_by(&self) -> &'static str {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::Uri;
+
+    use super::*;
+
+    fn link(page: Page, uri: &str, total: u32) -> String {
+        page.headers(&uri.parse::<Uri>().unwrap(), None, total)
+            .get("Link")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    #[test]
+    fn should_link_from_first_page() {
+        assert_eq!(
+            link(
+                Page {
+                    limit: 2,
+                    offset: 0
+                },
+                "/visitors?limit=2",
+                5
+            ),
+            r#"</visitors?limit=2&offset=0>; rel="first", </visitors?limit=2&offset=2>; rel="next", </visitors?limit=2&offset=4>; rel="last""#
+        );
+    }
+
+    #[test]
+    fn should_link_from_middle_page() {
+        assert_eq!(
+            link(
+                Page {
+                    limit: 2,
+                    offset: 2
+                },
+                "/visitors?screen=main+hall&offset=2&limit=2",
+                5
+            ),
+            r#"</visitors?screen=main+hall&limit=2&offset=0>; rel="first", </visitors?screen=main+hall&limit=2&offset=0>; rel="prev", </visitors?screen=main+hall&limit=2&offset=4>; rel="next", </visitors?screen=main+hall&limit=2&offset=4>; rel="last""#
+        );
+    }
+
+    #[test]
+    fn should_link_from_last_page() {
+        assert_eq!(
+            link(
+                Page {
+                    limit: 2,
+                    offset: 4
+                },
+                "/visitors?offset=4&limit=2",
+                5
+            ),
+            r#"</visitors?limit=2&offset=0>; rel="first", </visitors?limit=2&offset=2>; rel="prev", </visitors?limit=2&offset=4>; rel="last""#
+        );
+    }
+
+    #[test]
+    fn should_link_empty_collection() {
+        assert_eq!(
+            link(
+                Page {
+                    limit: 2,
+                    offset: 0
+                },
+                "/visitors?limit=2",
+                0
+            ),
+            r#"</visitors?limit=2&offset=0>; rel="first", </visitors?limit=2&offset=0>; rel="last""#
+        );
+    }
+
+    #[test]
+    fn should_link_absolutely_with_origin() {
+        let headers = Page {
+            limit: 2,
+            offset: 0,
+        }
+        .headers(
+            &"/visitors?limit=2".parse::<Uri>().unwrap(),
+            Some("https://party.example.com"),
+            3,
+        );
+        assert_eq!(
+            headers["Link"],
+            r#"<https://party.example.com/visitors?limit=2&offset=0>; rel="first", <https://party.example.com/visitors?limit=2&offset=2>; rel="next", <https://party.example.com/visitors?limit=2&offset=2>; rel="last""#
+        );
+    }
+
+    #[test]
+    fn should_only_sort_by_listed_keys() {
+        assert_eq!(
+            Sort::parse("-nick", Audience::Public).unwrap().order_by(),
+            " ORDER BY nick COLLATE NOCASE DESC, id DESC"
+        );
+        assert!(Sort::parse("-created_at", Audience::Admin).is_ok());
+        for value in [
+            "created_at",
+            "email",
+            "nick; DROP TABLE visitor",
+            "NICK",
+            "",
+        ] {
+            assert!(Sort::parse(value, Audience::Public).is_err(), "{}", value);
+        }
+    }
+}
@@ -0,0 +1,348 @@
+use std::str::FromStr;
+
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts, http::StatusCode};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::{
+    error::ApiError,
+    filter::{Audience, VisitorFilter},
+    pagination::{self, Page, Sort},
+    role::Role,
+    time::TimeService,
+    ApiState,
+};
+
+// Raw values are echoed back inside the JSON error only, and cut short so a long query is not repeated in full
+const MAX_VALUE_LENGTH: usize = 64;
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Problem {
+    field: String,
+    code: &'static str,
+    #[serde(flatten)]
+    details: Map<String, Value>,
+}
+
+impl Problem {
+    pub fn new(field: &str, code: &'static str) -> Self {
+        Self {
+            field: field.to_owned(),
+            code,
+            details: Map::new(),
+        }
+    }
+
+    pub fn with(mut self, key: &str, value: impl Serialize) -> Self {
+        self.details.insert(
+            key.to_owned(),
+            serde_json::to_value(value).unwrap_or_default(),
+        );
+        self
+    }
+
+    pub fn with_value(self, value: &str) -> Self {
+        let value: String = value.chars().take(MAX_VALUE_LENGTH).collect();
+        self.with("value", value)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Unknown {
+    #[default]
+    Reject,
+    Warn,
+}
+
+impl FromStr for Unknown {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "reject" => Ok(Unknown::Reject),
+            "warn" => Ok(Unknown::Warn),
+            _ => Err(format!("expected reject or warn, got {}", value)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Params {
+    pub filter: VisitorFilter,
+    pub page: Option<Page>,
+    pub sort: Option<Sort>,
+}
+
+impl Params {
+    pub fn parse(
+        query: &str,
+        audience: Audience,
+        paged: bool,
+        unknown: Unknown,
+    ) -> Result<Self, ApiError> {
+        let mut params = Self {
+            filter: VisitorFilter::new(audience),
+            page: None,
+            sort: None,
+        };
+        let mut problems = Vec::new();
+        let (mut limit, mut offset) = (None, None);
+
+        for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+            let result = match &*key {
+                "limit" if paged => number(&key, &value).and_then(|value| {
+                    match (1..=pagination::MAX_LIMIT).contains(&value) {
+                        true => {
+                            limit = Some(value);
+                            Ok(())
+                        }
+                        false => Err(Problem::new(&key, "out_of_range")
+                            .with("min", 1)
+                            .with("max", pagination::MAX_LIMIT)),
+                    }
+                }),
+                "offset" if paged => number(&key, &value).map(|value| offset = Some(value)),
+                "sort" if paged => {
+                    Sort::parse(&value, audience).map(|sort| params.sort = Some(sort))
+                }
+                key if VisitorFilter::allows(key, audience) => params.filter.set(key, &value),
+                key if VisitorFilter::accepts(key) => Err(Problem::new(key, "not_allowed")),
+                key if unknown == Unknown::Warn => {
+                    eprintln!("ignoring unknown query parameter {:?}", key);
+                    Ok(())
+                }
+                key => Err(Problem::new(key, "unknown")),
+            };
+            problems.extend(result.err());
+        }
+        problems.extend(params.filter.check().err());
+
+        if !problems.is_empty() {
+            return Err(ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "invalid query parameters",
+            )
+            .with_code("invalid_query")
+            .with_detail("fields", problems));
+        }
+
+        if limit.is_some() || offset.is_some() {
+            params.page = Some(Page {
+                limit: limit.unwrap_or(pagination::DEFAULT_LIMIT),
+                offset: offset.unwrap_or(0),
+            });
+        }
+        Ok(params)
+    }
+
+    fn from_parts<T: TimeService>(
+        parts: &Parts,
+        state: &ApiState<T>,
+        paged: bool,
+    ) -> Result<Self, ApiError> {
+        let audience = parts
+            .extensions
+            .get::<Role>()
+            .map_or(Audience::Public, |role| role.audience());
+
+        Self::parse(
+            parts.uri.query().unwrap_or_default(),
+            audience,
+            paged,
+            state.config.unknown_params,
+        )
+    }
+}
+
+// Filters and pagination, for list endpoints
+pub(crate) struct Listed(pub Params);
+
+// Filters only, for endpoints that aggregate
+pub(crate) struct Filtered(pub VisitorFilter);
+
+#[async_trait]
+impl<T: TimeService> FromRequestParts<ApiState<T>> for Listed {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ApiState<T>,
+    ) -> Result<Self, Self::Rejection> {
+        Params::from_parts(parts, state, true).map(Listed)
+    }
+}
+
+#[async_trait]
+impl<T: TimeService> FromRequestParts<ApiState<T>> for Filtered {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ApiState<T>,
+    ) -> Result<Self, Self::Rejection> {
+        Params::from_parts(parts, state, false).map(|params| Filtered(params.filter))
+    }
+}
+
+fn number(key: &str, value: &str) -> Result<u32, Problem> {
+    value
+        .parse()
+        .map_err(|_| Problem::new(key, "invalid_number").with_value(value))
+}
+
+#[cfg(test)]
+mod test {
+    use axum::body::Body;
+    use http_body_util::BodyExt;
+    use hyper::Request;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{admin::AdminKeys, config::Config, testing, time::ConstantTimeService};
+
+    fn fields(result: Result<Params, ApiError>) -> Value {
+        serde_json::to_value(result.unwrap_err()).unwrap()["fields"].clone()
+    }
+
+    #[test]
+    fn should_report_every_problem() {
+        assert_eq!(
+            fields(Params::parse(
+                "limit=9000&offset=-1&referral=flyer&created_after=yesterday&deleted=true",
+                Audience::Public,
+                true,
+                Unknown::Reject
+            )),
+            serde_json::json!([
+                {"field": "limit", "code": "out_of_range", "min": 1, "max": 500},
+                {"field": "offset", "code": "invalid_number", "value": "-1"},
+                {"field": "referral", "code": "not_allowed"},
+                {"field": "created_after", "code": "not_allowed"},
+                {"field": "deleted", "code": "unknown"},
+            ])
+        );
+        assert_eq!(
+            fields(Params::parse(
+                "limit=5",
+                Audience::Admin,
+                false,
+                Unknown::Reject
+            )),
+            serde_json::json!([{"field": "limit", "code": "unknown"}])
+        );
+    }
+
+    #[test]
+    fn should_parse_pages_and_filters() {
+        let params = Params::parse(
+            "group=Fairlight&limit=5&offset=10",
+            Audience::Public,
+            true,
+            Unknown::Reject,
+        )
+        .unwrap();
+        assert_eq!(
+            params.page,
+            Some(Page {
+                limit: 5,
+                offset: 10
+            })
+        );
+        assert!(params.filter != VisitorFilter::new(Audience::Public));
+
+        let params = Params::parse("", Audience::Public, true, Unknown::Reject).unwrap();
+        assert_eq!(params.page, None);
+
+        let params = Params::parse("role=orga", Audience::Public, true, Unknown::Warn).unwrap();
+        assert_eq!(params.filter, VisitorFilter::new(Audience::Public));
+    }
+
+    #[test]
+    fn should_validate_time_range() {
+        assert_eq!(
+            fields(Params::parse(
+                "created_after=2024-03-02T00:00:00Z&created_before=2024-03-01T00:00:00Z",
+                Audience::Admin,
+                false,
+                Unknown::Reject
+            )),
+            serde_json::json!([
+                {"field": "created_after", "code": "invalid_range", "before": "created_before"}
+            ])
+        );
+
+        let mut expected = VisitorFilter::new(Audience::Admin);
+        expected
+            .set("created_after", "2024-03-01T00:00:00Z")
+            .unwrap();
+        assert_eq!(
+            Params::parse(
+                "created_after=2024-03-01T02:00:00%2B02:00",
+                Audience::Admin,
+                false,
+                Unknown::Reject
+            )
+            .unwrap()
+            .filter,
+            expected
+        );
+    }
+
+    #[test]
+    fn should_cut_echoed_values() {
+        let value = "<script>".repeat(100);
+        let problem = Problem::new("offset", "invalid_number").with_value(&value);
+        assert_eq!(
+            problem.details["value"].as_str().unwrap().chars().count(),
+            MAX_VALUE_LENGTH
+        );
+    }
+
+    #[tokio::test]
+    async fn should_report_identically_on_every_endpoint() {
+        let db = testing::database().await;
+        let api = crate::api(
+            ConstantTimeService::new(),
+            db,
+            Config {
+                admin_keys: AdminKeys::new(vec!["key".into()]),
+                ..Config::default()
+            },
+        );
+
+        let query = "created_after=soon&group=x&order=nick%3Cb%3E";
+        let mut bodies = Vec::new();
+        for uri in [
+            format!("/visitors?{}", query),
+            format!("/visitors/buckets?{}", query),
+            format!("/admin/visitors?{}", query),
+            format!("/admin/stats?{}", query),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .header("Authorization", "Bearer key")
+                        .method("GET")
+                        .uri(&uri)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(
+                response.status(),
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "{}",
+                uri
+            );
+            bodies.push(response.into_body().collect().await.unwrap().to_bytes());
+        }
+
+        assert_eq!(
+            bodies[0],
+            r#"{"error":"invalid query parameters","code":"invalid_query","fields":[{"code":"invalid_timestamp","field":"created_after","value":"soon"},{"code":"unknown","field":"order"}]}"#
+        );
+        assert!(bodies.iter().all(|body| *body == bodies[0]));
+    }
+}
@@ -0,0 +1,129 @@
+use std::str::FromStr;
+
+// Keeps generated Finnish references at the required minimum length
+const BASE_OFFSET: i64 = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReferenceScheme {
+    Finnish,
+    Creditor,
+}
+
+impl FromStr for ReferenceScheme {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "fi" => Ok(Self::Finnish),
+            "rf" => Ok(Self::Creditor),
+            _ => Err(format!("unknown reference scheme: {}", value)),
+        }
+    }
+}
+
+impl ReferenceScheme {
+    pub fn generate(self, id: i64) -> String {
+        let base = (id + BASE_OFFSET).to_string();
+        let finnish = format!("{}{}", base, finnish_check_digit(&base));
+
+        match self {
+            Self::Finnish => finnish,
+            Self::Creditor => creditor_reference(&finnish),
+        }
+    }
+}
+
+pub fn normalize(reference: &str) -> String {
+    reference
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase()
+}
+
+pub fn is_valid(reference: &str) -> bool {
+    let reference = normalize(reference);
+
+    match reference.strip_prefix("RF") {
+        Some(_) => {
+            // RF, two check digits and at least one character of payload
+            (5..=25).contains(&reference.len())
+                && reference.chars().all(|c| c.is_ascii_alphanumeric())
+                && mod97(&format!("{}{}", &reference[4..], &reference[..4])) == Some(1)
+        }
+        None => {
+            (4..=20).contains(&reference.len())
+                && reference.chars().all(|c| c.is_ascii_digit())
+                && {
+                    let (base, check) = reference.split_at(reference.len() - 1);
+                    check == finnish_check_digit(base).to_string()
+                }
+        }
+    }
+}
+
+fn finnish_check_digit(base: &str) -> u32 {
+    let sum: u32 = base
+        .chars()
+        .rev()
+        .filter_map(|c| c.to_digit(10))
+        .zip([7, 3, 1].into_iter().cycle())
+        .map(|(digit, weight)| digit * weight)
+        .sum();
+
+    (10 - sum % 10) % 10
+}
+
+fn creditor_reference(payload: &str) -> String {
+    let remainder = mod97(&format!("{}RF00", payload)).expect("payload is alphanumeric");
+    format!("RF{:02}{}", 98 - remainder, payload)
+}
+
+fn mod97(value: &str) -> Option<u32> {
+    value.chars().try_fold(0, |remainder, c| {
+        let digits = c.to_digit(36)?;
+        Some(match digits {
+            0..=9 => (remainder * 10 + digits) % 97,
+            _ => (remainder * 100 + digits) % 97,
+        })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_compute_finnish_check_digit() {
+        assert_eq!(finnish_check_digit("123"), 2);
+        assert_eq!(finnish_check_digit("123456"), 1);
+        assert!(is_valid("1232"));
+        assert!(is_valid("12345 61"));
+        assert!(!is_valid("1233"));
+    }
+
+    #[test]
+    fn should_compute_creditor_reference() {
+        assert_eq!(creditor_reference("539007547034"), "RF18539007547034");
+        assert!(is_valid("RF18 5390 0754 7034"));
+        assert!(is_valid("RF712348231"));
+        assert!(!is_valid("RF19539007547034"));
+        assert!(!is_valid("RF"));
+        assert!(!is_valid("RF1"));
+        assert!(!is_valid("RF18"));
+    }
+
+    #[test]
+    fn should_generate_valid_references() {
+        for id in [1, 2, 42, 999, 123456] {
+            assert!(is_valid(&ReferenceScheme::Finnish.generate(id)));
+            assert!(is_valid(&ReferenceScheme::Creditor.generate(id)));
+        }
+
+        assert_eq!(ReferenceScheme::Finnish.generate(1), "10016");
+        assert_ne!(
+            ReferenceScheme::Creditor.generate(1),
+            ReferenceScheme::Creditor.generate(2)
+        );
+    }
+}
@@ -0,0 +1,387 @@
+use std::{
+    env,
+    panic::{self, AssertUnwindSafe},
+    sync::Arc,
+};
+
+use axum::http::StatusCode;
+use chrono::{DateTime, Utc};
+
+use crate::error::ApiError;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValidatedRegistration {
+    pub nick: String,
+    pub group: Option<String>,
+    pub email: Option<String>,
+    pub extra: Option<String>,
+    pub referral: Option<String>,
+    pub payment_exempt: bool,
+}
+
+pub struct PolicyContext {
+    pub now: DateTime<Utc>,
+    pub ip: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PolicyDecision {
+    Allow,
+    Deny { code: &'static str, message: String },
+    Modify(ValidatedRegistration),
+}
+
+pub trait RegistrationPolicy: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn evaluate(
+        &self,
+        registration: &ValidatedRegistration,
+        context: &PolicyContext,
+    ) -> PolicyDecision;
+}
+
+#[derive(Clone, Default)]
+pub struct Policies {
+    policies: Arc<[Box<dyn RegistrationPolicy>]>,
+    fail_open: bool,
+}
+
+impl Policies {
+    pub fn new(policies: Vec<Box<dyn RegistrationPolicy>>, fail_open: bool) -> Self {
+        Self {
+            policies: policies.into(),
+            fail_open,
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let policies = list("REGISTRATION_POLICIES")
+            .into_iter()
+            .map(|name| -> Box<dyn RegistrationPolicy> {
+                match name.as_str() {
+                    "blocked_words" => Box::new(BlockedWords::new(list("POLICY_BLOCKED_WORDS"))),
+                    "free_group" => Box::new(FreeGroup {
+                        group: env::var("POLICY_FREE_GROUP")
+                            .unwrap_or_else(|_| panic!("POLICY_FREE_GROUP not set")),
+                    }),
+                    _ => panic!("bad REGISTRATION_POLICIES: unknown policy {}", name),
+                }
+            })
+            .collect();
+
+        Self::new(
+            policies,
+            env::var("POLICY_FAIL_OPEN").is_ok_and(|value| value == "true"),
+        )
+    }
+
+    pub fn evaluate(
+        &self,
+        mut registration: ValidatedRegistration,
+        context: &PolicyContext,
+    ) -> Result<ValidatedRegistration, ApiError> {
+        for policy in self.policies.iter() {
+            let decision =
+                panic::catch_unwind(AssertUnwindSafe(|| policy.evaluate(&registration, context)));
+
+            let audit = |outcome: &str| {
+                eprintln!(
+                    "[policy {}] {} registration from {} at {}",
+                    policy.name(),
+                    outcome,
+                    context.ip.as_deref().unwrap_or("unknown"),
+                    context.now.to_rfc3339()
+                )
+            };
+
+            match decision {
+                Ok(PolicyDecision::Allow) => {}
+                Ok(PolicyDecision::Deny { code, message }) => {
+                    audit(&format!("denied ({})", code));
+                    return Err(ApiError::new(StatusCode::FORBIDDEN, message).with_code(code));
+                }
+                Ok(PolicyDecision::Modify(modified)) => {
+                    audit("modified");
+                    registration = modified;
+                }
+                Err(_) if self.fail_open => audit("failed, allowing"),
+                Err(_) => {
+                    audit("failed, denying");
+                    return Err(ApiError::new(
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        "registration policy failed",
+                    )
+                    .with_code("policy_unavailable"));
+                }
+            }
+        }
+
+        Ok(registration)
+    }
+}
+
+pub struct BlockedWords {
+    words: Vec<String>,
+}
+
+impl BlockedWords {
+    pub fn new(words: Vec<String>) -> Self {
+        Self {
+            words: words.iter().map(|word| word.to_lowercase()).collect(),
+        }
+    }
+}
+
+impl RegistrationPolicy for BlockedWords {
+    fn name(&self) -> &'static str {
+        "blocked_words"
+    }
+
+    fn evaluate(&self, registration: &ValidatedRegistration, _: &PolicyContext) -> PolicyDecision {
+        let nick = registration.nick.to_lowercase();
+        match self.words.iter().any(|word| nick.contains(word.as_str())) {
+            true => PolicyDecision::Deny {
+                code: "nick_not_allowed",
+                message: "nick is not allowed".into(),
+            },
+            false => PolicyDecision::Allow,
+        }
+    }
+}
+
+pub struct FreeGroup {
+    pub group: String,
+}
+
+impl RegistrationPolicy for FreeGroup {
+    fn name(&self) -> &'static str {
+        "free_group"
+    }
+
+    fn evaluate(&self, registration: &ValidatedRegistration, _: &PolicyContext) -> PolicyDecision {
+        match &registration.group {
+            Some(group) if group.eq_ignore_ascii_case(&self.group) => {
+                PolicyDecision::Modify(ValidatedRegistration {
+                    payment_exempt: true,
+                    ..registration.clone()
+                })
+            }
+            _ => PolicyDecision::Allow,
+        }
+    }
+}
+
+fn list(name: &str) -> Vec<String> {
+    env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use axum::body::Body;
+    use http_body_util::BodyExt;
+    use hyper::Request;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{config::Config, payment::ReferenceScheme, testing, time::ConstantTimeService};
+
+    fn registration(nick: &str, group: Option<&str>) -> ValidatedRegistration {
+        ValidatedRegistration {
+            nick: nick.into(),
+            group: group.map(str::to_owned),
+            email: None,
+            extra: None,
+            referral: None,
+            payment_exempt: false,
+        }
+    }
+
+    fn context() -> PolicyContext {
+        PolicyContext {
+            now: Utc::now(),
+            ip: None,
+        }
+    }
+
+    struct Panicking;
+
+    impl RegistrationPolicy for Panicking {
+        fn name(&self) -> &'static str {
+            "panicking"
+        }
+
+        fn evaluate(&self, _: &ValidatedRegistration, _: &PolicyContext) -> PolicyDecision {
+            panic!("broken rule")
+        }
+    }
+
+    // Denies whatever the previous policies let through as payment exempt
+    struct NoFreeLunch;
+
+    impl RegistrationPolicy for NoFreeLunch {
+        fn name(&self) -> &'static str {
+            "no_free_lunch"
+        }
+
+        fn evaluate(
+            &self,
+            registration: &ValidatedRegistration,
+            _: &PolicyContext,
+        ) -> PolicyDecision {
+            match registration.payment_exempt {
+                true => PolicyDecision::Deny {
+                    code: "no_free_lunch",
+                    message: "no free lunch".into(),
+                },
+                false => PolicyDecision::Allow,
+            }
+        }
+    }
+
+    fn code(result: Result<ValidatedRegistration, ApiError>) -> serde_json::Value {
+        serde_json::to_value(result.unwrap_err()).unwrap()["code"].clone()
+    }
+
+    #[test]
+    fn should_deny_blocked_words() {
+        let policies = Policies::new(
+            vec![Box::new(BlockedWords::new(vec!["Competitor".into()]))],
+            false,
+        );
+
+        assert!(policies
+            .evaluate(registration("Sponsored", None), &context())
+            .is_ok());
+        assert_eq!(
+            code(policies.evaluate(registration("xXcompetitorXx", None), &context())),
+            "nick_not_allowed"
+        );
+    }
+
+    #[test]
+    fn should_modify_free_group() {
+        let policies = Policies::new(
+            vec![Box::new(FreeGroup {
+                group: "Orgas".into(),
+            })],
+            false,
+        );
+
+        let free = policies
+            .evaluate(registration("Helper", Some("orgas")), &context())
+            .unwrap();
+        assert!(free.payment_exempt);
+
+        let paying = policies
+            .evaluate(registration("Visitor", Some("Others")), &context())
+            .unwrap();
+        assert_eq!(paying, registration("Visitor", Some("Others")));
+    }
+
+    #[test]
+    fn should_run_policies_in_order() {
+        let free_group = || -> Box<dyn RegistrationPolicy> {
+            Box::new(FreeGroup {
+                group: "Orgas".into(),
+            })
+        };
+
+        let policies = Policies::new(vec![free_group(), Box::new(NoFreeLunch)], false);
+        assert_eq!(
+            code(policies.evaluate(registration("Helper", Some("Orgas")), &context())),
+            "no_free_lunch"
+        );
+
+        let policies = Policies::new(vec![Box::new(NoFreeLunch), free_group()], false);
+        assert!(
+            policies
+                .evaluate(registration("Helper", Some("Orgas")), &context())
+                .unwrap()
+                .payment_exempt
+        );
+    }
+
+    #[test]
+    fn should_fall_back_when_policy_panics() {
+        let closed = Policies::new(vec![Box::new(Panicking)], false);
+        assert_eq!(
+            code(closed.evaluate(registration("Anyone", None), &context())),
+            "policy_unavailable"
+        );
+
+        let open = Policies::new(vec![Box::new(Panicking)], true);
+        assert!(open
+            .evaluate(registration("Anyone", None), &context())
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn should_apply_policies_to_registration() {
+        let db = testing::database().await;
+        let api = crate::api(
+            ConstantTimeService::new(),
+            db,
+            Config {
+                payment_reference: Some(ReferenceScheme::Finnish),
+                policies: Policies::new(
+                    vec![
+                        Box::new(BlockedWords::new(vec!["competitor".into()])),
+                        Box::new(FreeGroup {
+                            group: "Orgas".into(),
+                        }),
+                    ],
+                    false,
+                ),
+                ..Config::default()
+            },
+        );
+
+        for (client, body, expected) in [
+            (
+                1,
+                r#"{"nick":"Competitor Fan"}"#,
+                r#"{"error":"nick is not allowed","code":"nick_not_allowed"}"#,
+            ),
+            (
+                2,
+                r#"{"nick":"Helper","group":"Orgas"}"#,
+                r#"{"id":1,"nick":"Helper","group":"Orgas"}"#,
+            ),
+            (
+                3,
+                r#"{"nick":"Visitor"}"#,
+                r#"{"id":2,"nick":"Visitor","group":null,"payment_reference":"10029"}"#,
+            ),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .extension(axum::extract::ConnectInfo(std::net::SocketAddr::from((
+                            [10, 0, 0, client],
+                            4711,
+                        ))))
+                        .header("Content-Type", "application/json")
+                        .method("POST")
+                        .uri("/register")
+                        .body(Body::from(body))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            match client {
+                1 => assert_eq!(&body[..], expected.as_bytes()),
+                _ => assert_eq!(testing::without_edit_token(&body), expected),
+            }
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use axum::{
+    extract::ConnectInfo,
+    http::{uri::Authority, HeaderMap, Request},
+};
+use ipnet::IpNet;
+use tower_governor::{key_extractor::KeyExtractor, GovernorError};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrustedProxies(Vec<IpNet>);
+
+impl FromStr for TrustedProxies {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| {
+                item.parse::<IpNet>()
+                    .or_else(|_| item.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| format!("not an IP address or CIDR: {}", item))
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl TrustedProxies {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|net| net.contains(&ip))
+    }
+
+    // X-Forwarded-For wins over X-Real-IP, and a header that does not hold a valid address is skipped. A peer outside
+    // the set is the client itself, whatever it claims in its headers.
+    pub fn resolve(&self, headers: &HeaderMap, peer: IpAddr) -> IpAddr {
+        if !self.contains(peer) {
+            return peer;
+        }
+        self.forwarded_for(headers)
+            .or_else(|| parse_ip(header(headers, "X-Real-IP")?.trim()))
+            .unwrap_or(peer)
+    }
+
+    // Walking from the right, the first hop not added by one of our own proxies is the client
+    fn forwarded_for(&self, headers: &HeaderMap) -> Option<IpAddr> {
+        let mut client = None;
+        for hop in header(headers, "X-Forwarded-For")?.rsplit(',') {
+            let ip = parse_ip(hop.trim())?;
+            client = Some(ip);
+            if !self.contains(ip) {
+                break;
+            }
+        }
+        client
+    }
+
+    // Where the client sent the request, for links that have to work from its side of the proxy. The first value of
+    // each header is the one the client-facing proxy set.
+    pub fn origin(&self, headers: &HeaderMap, peer: IpAddr) -> Option<String> {
+        if !self.contains(peer) {
+            return None;
+        }
+        let first = |name| Some(header(headers, name)?.split(',').next()?.trim());
+        let proto = first("X-Forwarded-Proto").filter(|proto| ["http", "https"].contains(proto))?;
+        let host = first("X-Forwarded-Host")
+            .or_else(|| first("Host"))?
+            .parse::<Authority>()
+            .ok()
+            .filter(|host| !host.as_str().contains('@'))?;
+        Some(format!("{}://{}", proto, host))
+    }
+
+    pub fn client_ip<B>(&self, request: &Request<B>) -> Option<IpAddr> {
+        request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| self.resolve(request.headers(), addr.ip()))
+    }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn parse_ip(hop: &str) -> Option<IpAddr> {
+    hop.parse()
+        .or_else(|_| hop.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .ok()
+}
+
+#[derive(Clone)]
+pub struct ClientIpKeyExtractor(pub TrustedProxies);
+
+impl KeyExtractor for ClientIpKeyExtractor {
+    type Key = IpAddr;
+
+    fn extract<T>(&self, request: &Request<T>) -> Result<Self::Key, GovernorError> {
+        self.0
+            .client_ip(request)
+            .ok_or(GovernorError::UnableToExtractKey)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn forwarded(value: &str) -> HeaderMap {
+        headers(&[("X-Forwarded-For", value)])
+    }
+
+    fn headers(values: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in values {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn should_only_trust_configured_proxies() {
+        let proxies: TrustedProxies = "127.0.0.1, 10.0.0.0/8,::1".parse().unwrap();
+        let proxy = IpAddr::from([10, 0, 0, 1]);
+        let stranger = IpAddr::from([198, 51, 100, 4]);
+
+        for (peer, header, expected) in [
+            (proxy, "203.0.113.7", "203.0.113.7"),
+            (proxy, "192.0.2.1, 203.0.113.7, 10.0.0.2", "203.0.113.7"),
+            (proxy, "[2001:db8::7]:4711", "2001:db8::7"),
+            (proxy, "10.0.0.3, 10.0.0.2", "10.0.0.3"),
+            (proxy, "203.0.113.7, <script>", "10.0.0.1"),
+            (stranger, "203.0.113.7", "198.51.100.4"),
+        ] {
+            assert_eq!(
+                proxies.resolve(&forwarded(header), peer).to_string(),
+                expected,
+                "{} via {}",
+                header,
+                peer
+            );
+        }
+        assert_eq!(proxies.resolve(&HeaderMap::new(), proxy), proxy);
+        assert_eq!(
+            TrustedProxies::default().resolve(&forwarded("203.0.113.7"), proxy),
+            proxy
+        );
+        assert!("10.0.0.0/33".parse::<TrustedProxies>().is_err());
+        assert_eq!("".parse(), Ok(TrustedProxies::default()));
+    }
+
+    #[test]
+    fn should_prefer_forwarded_for_over_real_ip() {
+        let proxies: TrustedProxies = "10.0.0.0/8".parse().unwrap();
+        let proxy = IpAddr::from([10, 0, 0, 1]);
+
+        for (values, expected) in [
+            (
+                &[
+                    ("X-Forwarded-For", "203.0.113.7"),
+                    ("X-Real-IP", "192.0.2.1"),
+                ][..],
+                "203.0.113.7",
+            ),
+            (&[("X-Real-IP", " 192.0.2.1 ")], "192.0.2.1"),
+            (
+                &[("X-Forwarded-For", "<script>"), ("X-Real-IP", "192.0.2.1")],
+                "192.0.2.1",
+            ),
+            (
+                &[("X-Forwarded-For", "<script>"), ("X-Real-IP", "unknown")],
+                "10.0.0.1",
+            ),
+            (&[], "10.0.0.1"),
+        ] {
+            assert_eq!(
+                proxies.resolve(&headers(values), proxy).to_string(),
+                expected,
+                "{:?}",
+                values
+            );
+        }
+        assert_eq!(
+            proxies.resolve(
+                &headers(&[("X-Real-IP", "192.0.2.1")]),
+                IpAddr::from([198, 51, 100, 4])
+            ),
+            IpAddr::from([198, 51, 100, 4])
+        );
+    }
+
+    #[test]
+    fn should_take_origin_from_trusted_proxies_only() {
+        let proxies: TrustedProxies = "10.0.0.0/8".parse().unwrap();
+        let proxy = IpAddr::from([10, 0, 0, 1]);
+
+        for (values, expected) in [
+            (
+                &[
+                    ("X-Forwarded-Proto", "https"),
+                    ("Host", "party.example.com"),
+                ][..],
+                Some("https://party.example.com"),
+            ),
+            (
+                &[
+                    ("X-Forwarded-Proto", "https, http"),
+                    ("X-Forwarded-Host", "party.example.com:8443, internal"),
+                    ("Host", "127.0.0.1:3000"),
+                ],
+                Some("https://party.example.com:8443"),
+            ),
+            (&[("Host", "party.example.com")], None),
+            (
+                &[("X-Forwarded-Proto", "ftp"), ("Host", "party.example.com")],
+                None,
+            ),
+            (
+                &[
+                    ("X-Forwarded-Proto", "https"),
+                    ("Host", "evil.example.com/path"),
+                ],
+                None,
+            ),
+            (
+                &[
+                    ("X-Forwarded-Proto", "https"),
+                    ("Host", "user@evil.example.com"),
+                ],
+                None,
+            ),
+        ] {
+            assert_eq!(
+                proxies.origin(&headers(values), proxy).as_deref(),
+                expected,
+                "{:?}",
+                values
+            );
+        }
+        assert_eq!(
+            proxies.origin(
+                &headers(&[
+                    ("X-Forwarded-Proto", "https"),
+                    ("Host", "party.example.com")
+                ]),
+                IpAddr::from([198, 51, 100, 4])
+            ),
+            None
+        );
+    }
+}
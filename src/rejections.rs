@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::{
+    debug, error::ErrorCode, json::RawBody, supervisor::Stop, time::TimeService, ApiState,
+};
+
+pub const MAX_BYTES: usize = 4 * 1024;
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(sqlx::FromRow)]
+pub struct Row {
+    id: i64,
+    created_at: DateTime<Utc>,
+    ip: String,
+    status: i64,
+    code: Option<String>,
+    body: Option<Vec<u8>>,
+}
+
+#[derive(Serialize)]
+pub struct Rejection {
+    id: i64,
+    created_at: DateTime<Utc>,
+    ip: String,
+    status: i64,
+    code: Option<String>,
+    body: Option<String>,
+}
+
+impl From<Row> for Rejection {
+    fn from(row: Row) -> Self {
+        Self {
+            id: row.id,
+            created_at: row.created_at,
+            ip: row.ip,
+            status: row.status,
+            code: row.code,
+            body: row
+                .body
+                .map(|body| String::from_utf8_lossy(&body).into_owned()),
+        }
+    }
+}
+
+pub async fn capture<T: TimeService>(
+    State(state): State<ApiState<T>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if !state.config.rejected_capture {
+        return next.run(request).await;
+    }
+
+    let raw_body = RawBody::default();
+    request.extensions_mut().insert(raw_body.clone());
+    let ip = debug::client_ip(&request, &state.config.trusted_proxies);
+
+    let response = next.run(request).await;
+
+    let status = response.status();
+    if !status.is_client_error() || status == StatusCode::TOO_MANY_REQUESTS {
+        return response;
+    }
+
+    let body = raw_body
+        .get()
+        .map(|body| &body[..body.len().min(MAX_BYTES)]);
+    let result = sqlx::query(
+        r#"INSERT INTO rejected_submission (created_at, ip, status, code, body) VALUES ($1, $2, $3, $4, $5)"#,
+    )
+    .bind(state.time.now())
+    .bind(ip)
+    .bind(status.as_u16())
+    .bind(response.extensions().get::<ErrorCode>().map(|code| code.0))
+    .bind(body)
+    .execute(&state.db)
+    .await;
+    if let Err(error) = result {
+        eprintln!("failed to capture rejected submission: {}", error);
+    }
+
+    response
+}
+
+pub async fn sweep(
+    db: &SqlitePool,
+    now: DateTime<Utc>,
+    retention: chrono::Duration,
+) -> Result<u64, sqlx::Error> {
+    Ok(
+        sqlx::query(r#"DELETE FROM rejected_submission WHERE created_at <= $1"#)
+            .bind(now - retention)
+            .execute(db)
+            .await?
+            .rows_affected(),
+    )
+}
+
+pub async fn run_sweep(
+    time: impl TimeService,
+    db: SqlitePool,
+    retention: chrono::Duration,
+    mut stop: Stop,
+) {
+    while stop.sleep(SWEEP_INTERVAL).await {
+        if let Err(error) = sweep(&db, time.clone().now(), retention).await {
+            eprintln!("failed to sweep rejected submissions: {}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+
+    use crate::testing;
+
+    #[tokio::test]
+    async fn should_sweep_old_rejections() {
+        let db = testing::database().await;
+        let now = Utc::now();
+
+        for created_at in [now - Duration::days(15), now - Duration::days(1)] {
+            sqlx::query(
+                "INSERT INTO rejected_submission (created_at, ip, status) VALUES ($1, '127.0.0.1', 400)",
+            )
+            .bind(created_at)
+            .execute(&db)
+            .await
+            .unwrap();
+        }
+
+        let swept = super::sweep(&db, now, Duration::days(14)).await.unwrap();
+        assert_eq!(swept, 1);
+    }
+}
@@ -0,0 +1,568 @@
+use std::{
+    collections::BTreeSet,
+    env,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{
+    extract::{Request, State},
+    http::{Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use crate::{
+    admin::AdminKeys, changes, db, error::ApiError, fields, json::Json, misses, retry,
+    supervisor::Stop, time::TimeService, ApiState,
+};
+
+pub const APPLY_PATH: &str = "/admin/replica/apply";
+const PUSH_INTERVAL: Duration = Duration::from_secs(1);
+const RETRY: retry::Policy = retry::Policy {
+    max_attempts: u32::MAX,
+    base: Duration::from_secs(1),
+    max: Duration::from_secs(60),
+};
+
+#[derive(Clone)]
+pub struct Target {
+    pub url: String,
+    pub key: String,
+}
+
+impl Target {
+    pub fn from_env() -> Option<Self> {
+        let url = env::var("REPLICA_PUSH_URL")
+            .ok()
+            .filter(|x| !x.is_empty())?;
+        let key =
+            env::var("REPLICA_PUSH_KEY").unwrap_or_else(|_| panic!("REPLICA_PUSH_KEY not set"));
+        Some(Self { url, key })
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Batch {
+    pub from: i64,
+    pub to: i64,
+    pub full: bool,
+    pub visitors: Vec<db::Visitor>,
+    pub removed: Vec<i64>,
+}
+
+// Sequence numbers are generations of the visitor change journal, -1 until the standby has a full copy
+#[derive(Clone)]
+pub struct Progress {
+    pushed: Arc<AtomicI64>,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self {
+            pushed: Arc::new(AtomicI64::new(-1)),
+        }
+    }
+}
+
+impl Progress {
+    pub fn pushed(&self) -> i64 {
+        self.pushed.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PushError {
+    Gap(i64),
+    Failed(String),
+}
+
+#[derive(Serialize)]
+#[serde(tag = "role", rename_all = "snake_case")]
+pub enum Status {
+    Primary {
+        sequence: i64,
+        pending: i64,
+    },
+    Standby {
+        sequence: Option<i64>,
+        lag_seconds: Option<i64>,
+    },
+}
+
+pub async fn push(
+    db: &SqlitePool,
+    http: &reqwest::Client,
+    target: &Target,
+    progress: &Progress,
+) -> Result<i64, PushError> {
+    let failed = |error: sqlx::Error| PushError::Failed(error.to_string());
+    let pushed = progress.pushed();
+
+    let journal = match pushed {
+        -1 => changes::Journal {
+            generation: changes::generation(db).await.map_err(failed)?,
+            full_refetch: true,
+            ..changes::Journal::default()
+        },
+        _ => changes::since(db, pushed).await.map_err(failed)?,
+    };
+
+    let mut visitors = match journal.full_refetch {
+        true => sqlx::query_as::<_, db::Visitor>(r#"SELECT * FROM visitor ORDER BY id"#)
+            .fetch_all(db)
+            .await
+            .map_err(failed)?,
+        false => {
+            let ids = serde_json::to_string(&[&journal.added[..], &journal.updated[..]].concat())
+                .map_err(|error| PushError::Failed(error.to_string()))?;
+            sqlx::query_as::<_, db::Visitor>(
+                r#"SELECT * FROM visitor WHERE id IN (SELECT value FROM json_each($1)) ORDER BY id"#,
+            )
+            .bind(ids)
+            .fetch_all(db)
+            .await
+            .map_err(failed)?
+        }
+    };
+    fields::attach(db, &mut visitors).await.map_err(failed)?;
+
+    let batch = Batch {
+        from: pushed,
+        to: journal.generation,
+        full: journal.full_refetch,
+        visitors,
+        removed: journal.removed,
+    };
+    let response = http
+        .post(format!(
+            "{}{}",
+            target.url.trim_end_matches('/'),
+            APPLY_PATH
+        ))
+        .bearer_auth(&target.key)
+        .json(&batch)
+        .send()
+        .await
+        .map_err(|error| PushError::Failed(error.to_string()))?;
+
+    match response.status() {
+        StatusCode::NO_CONTENT => {
+            progress.pushed.store(batch.to, Ordering::Relaxed);
+            Ok(batch.to)
+        }
+        StatusCode::CONFLICT => {
+            progress.pushed.store(-1, Ordering::Relaxed);
+            Err(PushError::Gap(batch.from))
+        }
+        status => Err(PushError::Failed(format!("standby answered {}", status))),
+    }
+}
+
+// Batches are never dead-lettered, skipping one would leave the standby silently diverged
+pub async fn run_push(
+    db: SqlitePool,
+    http: reqwest::Client,
+    target: Target,
+    progress: Progress,
+    metrics: retry::Metrics,
+    mut stop: Stop,
+) {
+    let mut failures = 0;
+    while stop
+        .sleep(match failures {
+            0 => PUSH_INTERVAL,
+            _ => RETRY.delay(failures),
+        })
+        .await
+    {
+        match push(&db, &http, &target, &progress).await {
+            Ok(_) => failures = 0,
+            Err(PushError::Gap(from)) => {
+                eprintln!("standby is missing changes after {}, resyncing", from)
+            }
+            Err(PushError::Failed(error)) => {
+                failures += 1;
+                metrics.count("replica", |counts| counts.retries += 1);
+                eprintln!("failed to push to standby: {}", error)
+            }
+        }
+    }
+}
+
+pub async fn authorize(State(keys): State<AdminKeys>, request: Request, next: Next) -> Response {
+    match keys.authorizes(request.headers()) {
+        true => next.run(request).await,
+        false => ApiError::new(StatusCode::UNAUTHORIZED, "invalid replica key")
+            .with_code("unauthorized")
+            .into_response(),
+    }
+}
+
+pub async fn read_only(State(standby): State<bool>, request: Request, next: Next) -> Response {
+    let writes = !matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    match standby && writes && request.uri().path() != APPLY_PATH {
+        true => ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "this instance is a read-only standby",
+        )
+        .with_code("read_only")
+        .into_response(),
+        false => next.run(request).await,
+    }
+}
+
+pub async fn apply<T: TimeService>(
+    State(state): State<ApiState<T>>,
+    Json(batch): Json<Batch>,
+) -> Result<StatusCode, ApiError> {
+    let mut tx = state.db.begin().await?;
+    let sequence: Option<i64> =
+        sqlx::query_scalar(r#"SELECT sequence FROM replica_state WHERE id = 1"#)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+    if !batch.full && sequence != Some(batch.from) {
+        return Err(
+            ApiError::new(StatusCode::CONFLICT, "replica is missing changes")
+                .with_code("replica_gap")
+                .with_detail("sequence", sequence),
+        );
+    }
+
+    // Journaled here too, so the standby's lists carry the same X-Generation and Last-Modified semantics
+    let now = state.time.clone().now();
+    let keep = state.config.change_journal_length;
+    let mut gone: BTreeSet<i64> = BTreeSet::new();
+    if batch.full {
+        gone = sqlx::query_scalar(r#"DELETE FROM visitor RETURNING id"#)
+            .fetch_all(&mut *tx)
+            .await?
+            .into_iter()
+            .collect();
+    }
+    for visitor in batch.visitors {
+        let id = i64::from(visitor.id);
+        let existed = gone.remove(&id)
+            || sqlx::query_scalar(r#"SELECT EXISTS (SELECT 1 FROM visitor WHERE id = $1)"#)
+                .bind(id)
+                .fetch_one(&mut *tx)
+                .await?;
+        fields::replace(&mut tx, id, &visitor.fields).await?;
+        sqlx::query(
+            r#"INSERT OR REPLACE INTO visitor (id, created_at, ip, user_agent, nick, "group", email, confirmed_at, consent_at, extra, referral, admin_note, payment_reference, payment_status, status, source) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)"#,
+        )
+        .bind(visitor.id)
+        .bind(visitor.created_at)
+        .bind(visitor.ip)
+        .bind(visitor.user_agent)
+        .bind(visitor.nick)
+        .bind(visitor.group)
+        .bind(visitor.email)
+        .bind(visitor.confirmed_at)
+        .bind(visitor.consent_at)
+        .bind(visitor.extra)
+        .bind(visitor.referral)
+        .bind(visitor.admin_note)
+        .bind(visitor.payment_reference)
+        .bind(visitor.payment_status)
+        .bind(visitor.status)
+        .bind(visitor.source)
+        .execute(&mut *tx)
+        .await?;
+        let change = match existed {
+            true => changes::Change::Updated,
+            false => changes::Change::Created,
+        };
+        changes::record(&mut tx, id, change, keep, now).await?;
+    }
+    let removed: Vec<i64> = sqlx::query_scalar(
+        r#"DELETE FROM visitor WHERE id IN (SELECT value FROM json_each($1)) RETURNING id"#,
+    )
+    .bind(serde_json::to_string(&batch.removed)?)
+    .fetch_all(&mut *tx)
+    .await?;
+    for id in gone.into_iter().chain(removed) {
+        changes::record(&mut tx, id, changes::Change::Deleted, keep, now).await?;
+    }
+
+    sqlx::query(
+        r#"INSERT OR REPLACE INTO replica_state (id, sequence, applied_at) VALUES (1, $1, $2)"#,
+    )
+    .bind(batch.to)
+    .bind(now)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    state.misses.forget_all(misses::Kind::Visitor);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn status<T: TimeService>(state: &ApiState<T>) -> Result<Option<Status>, sqlx::Error> {
+    if !state.config.replica_keys.is_empty() {
+        let applied = sqlx::query_as::<_, (i64, DateTime<Utc>)>(
+            r#"SELECT sequence, applied_at FROM replica_state WHERE id = 1"#,
+        )
+        .fetch_optional(&state.db)
+        .await?;
+        let now = state.time.clone().now();
+
+        return Ok(Some(Status::Standby {
+            sequence: applied.map(|(sequence, _)| sequence),
+            lag_seconds: applied.map(|(_, applied_at)| (now - applied_at).num_seconds()),
+        }));
+    }
+
+    if state.config.replica_push.is_some() {
+        let pushed = state.replica.pushed();
+        return Ok(Some(Status::Primary {
+            sequence: pushed,
+            pending: changes::generation(&state.db).await? - pushed.max(0),
+        }));
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use axum::{body::Body, extract::ConnectInfo};
+    use http_body_util::BodyExt;
+    use hyper::Request;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{config::Config, testing, time::ConstantTimeService};
+
+    async fn register(api: &axum::Router, nick: &str) {
+        let response = api
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .extension(ConnectInfo(SocketAddr::from((
+                        [10, 0, 0, nick.len() as u8],
+                        4711,
+                    ))))
+                    .header("Content-Type", "application/json")
+                    .method("POST")
+                    .uri("/register")
+                    .body(Body::from(format!(r#"{{"nick":"{}"}}"#, nick)))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED, "{}", nick);
+    }
+
+    async fn nicks(db: &SqlitePool) -> Vec<String> {
+        sqlx::query_scalar(r#"SELECT nick FROM visitor ORDER BY id"#)
+            .fetch_all(db)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_replicate_and_resync_after_gap() {
+        let primary_db = testing::database().await;
+        let standby_db = testing::database().await;
+        let primary = crate::api(
+            ConstantTimeService::new(),
+            primary_db.clone(),
+            Config::default(),
+        );
+        let standby = crate::api(
+            ConstantTimeService::new(),
+            standby_db.clone(),
+            Config {
+                replica_keys: AdminKeys::new(vec!["replica".into()]),
+                ..Config::default()
+            },
+        );
+        let target = Target {
+            url: testing::serve(standby.clone()).await,
+            key: "replica".into(),
+        };
+        let http = reqwest::Client::new();
+        let progress = Progress::default();
+
+        register(&primary, "Primary").await;
+        assert!(nicks(&standby_db).await.is_empty());
+        assert_eq!(push(&primary_db, &http, &target, &progress).await, Ok(1));
+        assert_eq!(nicks(&standby_db).await, vec!["Primary"]);
+
+        // A batch that never reached the standby
+        register(&primary, "Lost").await;
+        progress.pushed.store(2, Ordering::Relaxed);
+        register(&primary, "After").await;
+        assert_eq!(
+            push(&primary_db, &http, &target, &progress).await,
+            Err(PushError::Gap(2))
+        );
+        assert_eq!(nicks(&standby_db).await, vec!["Primary"]);
+
+        assert_eq!(push(&primary_db, &http, &target, &progress).await, Ok(3));
+        assert_eq!(nicks(&standby_db).await, vec!["Primary", "Lost", "After"]);
+
+        let response = standby
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/status")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            &body[..],
+            br#"{"schema_version":1,"capabilities":[],"replica":{"role":"standby","sequence":3,"lag_seconds":0}}"#
+        );
+
+        let response = standby
+            .oneshot(
+                Request::builder()
+                    .extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 8080))))
+                    .header("Content-Type", "application/json")
+                    .method("POST")
+                    .uri("/register")
+                    .body(Body::from(r#"{"nick":"Standby"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn should_journal_applied_changes() {
+        let db = testing::database().await;
+        let applied_at = Utc::now() - chrono::Duration::hours(1);
+        let visitor = |id: i32, nick: &str| {
+            format!(
+                r#"{{"id":{},"created_at":"2024-01-01T00:00:00Z","ip":"","nick":"{}","extra":null,"status":"confirmed"}}"#,
+                id, nick
+            )
+        };
+
+        for (now, batch) in [
+            (
+                applied_at,
+                format!(
+                    r#"{{"from":-1,"to":1,"full":true,"visitors":[{}],"removed":[]}}"#,
+                    visitor(1, "Razor")
+                ),
+            ),
+            (
+                applied_at + chrono::Duration::minutes(30),
+                format!(
+                    r#"{{"from":1,"to":3,"full":false,"visitors":[{},{}],"removed":[1]}}"#,
+                    visitor(2, "Fairlight"),
+                    visitor(2, "Fairlight 1987")
+                ),
+            ),
+        ] {
+            let api = crate::api(
+                ConstantTimeService::at(now),
+                db.clone(),
+                Config {
+                    replica_keys: AdminKeys::new(vec!["replica".into()]),
+                    ..Config::default()
+                },
+            );
+            let response = api
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .header("Authorization", "Bearer replica")
+                        .header("Content-Type", "application/json")
+                        .method("POST")
+                        .uri(APPLY_PATH)
+                        .body(Body::from(batch))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+            let response = api
+                .oneshot(
+                    Request::builder()
+                        .extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 8080))))
+                        .uri("/visitors")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(
+                response.headers()[axum::http::header::LAST_MODIFIED],
+                crate::cache::http_date(now)
+            );
+        }
+
+        let journal: Vec<(i64, String)> =
+            sqlx::query_as(r#"SELECT visitor_id, kind FROM visitor_change ORDER BY generation"#)
+                .fetch_all(&db)
+                .await
+                .unwrap();
+        assert_eq!(
+            journal,
+            [
+                (1, "created".into()),
+                (2, "created".into()),
+                (2, "updated".into()),
+                (1, "deleted".into()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_require_replica_key() {
+        let db = testing::database().await;
+        let api = crate::api(
+            ConstantTimeService::new(),
+            db,
+            Config {
+                admin_keys: AdminKeys::new(vec!["admin".into()]),
+                replica_keys: AdminKeys::new(vec!["replica".into()]),
+                ..Config::default()
+            },
+        );
+
+        for (key, status) in [
+            ("admin", StatusCode::UNAUTHORIZED),
+            ("replica", StatusCode::NO_CONTENT),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .header("Authorization", format!("Bearer {}", key))
+                        .header("Content-Type", "application/json")
+                        .method("POST")
+                        .uri(APPLY_PATH)
+                        .body(Body::from(
+                            r#"{"from":-1,"to":0,"full":true,"visitors":[],"removed":[]}"#,
+                        ))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status, "{}", key);
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use axum::http::StatusCode;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{SqliteConnection, SqlitePool};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::{error::ApiError, validate};
+
+#[derive(Deserialize)]
+pub struct ImportEntry {
+    pub nick: String,
+    pub email: Option<String>,
+}
+
+#[derive(sqlx::FromRow, Serialize)]
+pub struct Reservation {
+    pub nick: String,
+    pub email: String,
+}
+
+pub fn key(nick: &str) -> String {
+    validate::normalize(nick)
+        .unwrap_or_default()
+        .nfc()
+        .collect::<String>()
+        .to_lowercase()
+}
+
+pub async fn import(db: &SqlitePool, entries: Vec<ImportEntry>) -> Result<u64, sqlx::Error> {
+    let mut tx = db.begin().await?;
+    let mut imported = 0;
+    for entry in entries {
+        let Some(email) = entry.email.filter(|email| !email.trim().is_empty()) else {
+            continue;
+        };
+
+        imported += sqlx::query(
+            r#"INSERT INTO reservation (nick_key, nick, email) VALUES ($1, $2, $3)
+               ON CONFLICT (nick_key) DO UPDATE SET nick = excluded.nick, email = excluded.email"#,
+        )
+        .bind(key(&entry.nick))
+        .bind(entry.nick)
+        .bind(email.trim())
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    }
+    tx.commit().await?;
+
+    Ok(imported)
+}
+
+pub async fn claim(
+    conn: &mut SqliteConnection,
+    nick: &str,
+    email: Option<&str>,
+    now: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<(), ApiError> {
+    if expires_at.is_some_and(|expires_at| now >= expires_at) {
+        return Ok(());
+    }
+
+    let key = key(nick);
+    let Some(reserved_for) =
+        sqlx::query_scalar::<_, String>(r#"SELECT email FROM reservation WHERE nick_key = $1"#)
+            .bind(&key)
+            .fetch_optional(&mut *conn)
+            .await?
+    else {
+        return Ok(());
+    };
+
+    if !email.is_some_and(|email| email.trim().eq_ignore_ascii_case(&reserved_for)) {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "nick is reserved for a returning visitor",
+        )
+        .with_code("nick_reserved_for_returning_visitor"));
+    }
+
+    sqlx::query(r#"DELETE FROM reservation WHERE nick_key = $1"#)
+        .bind(&key)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+
+    use super::*;
+    use crate::testing;
+
+    async fn reserve(db: &SqlitePool) {
+        import(
+            db,
+            vec![ImportEntry {
+                nick: "Slummy".into(),
+                email: Some("Slummy@Example.com".into()),
+            }],
+        )
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn should_key_by_normalized_nick() {
+        assert_eq!(key(" Slummy "), "slummy");
+        assert_eq!(key("A\u{308}rsyttävä"), key("ärsyttävä"));
+    }
+
+    #[tokio::test]
+    async fn should_accept_matching_email_and_consume() {
+        let db = testing::database().await;
+        reserve(&db).await;
+
+        let mut conn = db.acquire().await.unwrap();
+        claim(
+            &mut conn,
+            "slummy",
+            Some("slummy@example.com"),
+            Utc::now(),
+            None,
+        )
+        .await
+        .unwrap();
+
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM reservation")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(remaining, 0);
+        claim(&mut conn, "Slummy", None, Utc::now(), None)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_reject_mismatched_email() {
+        let db = testing::database().await;
+        reserve(&db).await;
+
+        let mut conn = db.acquire().await.unwrap();
+        for email in [None, Some("squatter@example.com")] {
+            let result = claim(&mut conn, "Slummy", email, Utc::now(), None).await;
+            assert!(result.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn should_release_after_expiry() {
+        let db = testing::database().await;
+        reserve(&db).await;
+
+        let now = Utc::now();
+        let mut conn = db.acquire().await.unwrap();
+        let before = claim(
+            &mut conn,
+            "Slummy",
+            None,
+            now,
+            Some(now + Duration::hours(1)),
+        )
+        .await;
+        assert!(before.is_err());
+
+        let after = claim(&mut conn, "Slummy", None, now, Some(now)).await;
+        assert!(after.is_ok());
+    }
+}
@@ -0,0 +1,252 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{Sqlite, Transaction};
+
+use crate::{error::ApiError, json::Json, reservation, time::TimeService, validate, ApiState};
+
+#[derive(Deserialize)]
+pub struct ReserveRequest {
+    nick: String,
+}
+
+#[derive(Serialize)]
+pub struct Reserved {
+    nick: String,
+    claim_token: String,
+}
+
+#[derive(sqlx::FromRow, Serialize)]
+pub struct ReservedNick {
+    nick: String,
+    created_at: DateTime<Utc>,
+    claimed: bool,
+    claimed_at: Option<DateTime<Utc>>,
+}
+
+fn hash(token: &str) -> String {
+    format!(
+        "{:x}",
+        Sha256::digest(token.trim().to_lowercase().as_bytes())
+    )
+}
+
+fn reserved() -> ApiError {
+    ApiError::new(StatusCode::BAD_REQUEST, "nick is reserved")
+        .with_code("nick_reserved")
+        .with_detail("field", "nick")
+}
+
+// Organizer nicks are kept from the public for good, unlike the returning visitors' reservations that lapse. Only
+// the token handed out when reserving gets one registered.
+pub async fn claim(
+    tx: &mut Transaction<'static, Sqlite>,
+    nick: &str,
+    token: Option<&str>,
+    now: DateTime<Utc>,
+) -> Result<(), ApiError> {
+    let key = reservation::key(nick);
+    let Some(token_hash) =
+        sqlx::query_scalar::<_, String>("SELECT token_hash FROM reserved_nick WHERE nick_key = $1")
+            .bind(&key)
+            .fetch_optional(&mut **tx)
+            .await?
+    else {
+        return Ok(());
+    };
+    if token.map(hash) != Some(token_hash) {
+        return Err(reserved());
+    }
+
+    sqlx::query(
+        "UPDATE reserved_nick SET claimed_at = $1 WHERE nick_key = $2 AND claimed_at IS NULL",
+    )
+    .bind(now)
+    .bind(&key)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+pub async fn reserve<T: TimeService>(
+    State(state): State<ApiState<T>>,
+    Json(request): Json<ReserveRequest>,
+) -> Result<(StatusCode, Json<Reserved>), ApiError> {
+    let nick = validate::nick(&request.nick)?;
+
+    let token: String = sqlx::query_scalar("SELECT lower(hex(randomblob(16)))")
+        .fetch_one(&state.db)
+        .await?;
+    let inserted = sqlx::query(
+        r#"INSERT INTO reserved_nick (nick_key, nick, token_hash, created_at) VALUES ($1, $2, $3, $4)
+           ON CONFLICT (nick_key) DO NOTHING"#,
+    )
+    .bind(reservation::key(&nick))
+    .bind(&nick)
+    .bind(hash(&token))
+    .bind(state.time.clone().now())
+    .execute(&state.db)
+    .await?
+    .rows_affected();
+    if inserted == 0 {
+        return Err(
+            ApiError::new(StatusCode::CONFLICT, "nick is already reserved")
+                .with_code("already_reserved"),
+        );
+    }
+    eprintln!("[reserved] reserved nick {}", nick);
+
+    Ok((
+        StatusCode::CREATED,
+        Json(Reserved {
+            nick,
+            claim_token: token,
+        }),
+    ))
+}
+
+pub async fn list<T: TimeService>(
+    State(state): State<ApiState<T>>,
+) -> Result<(StatusCode, Json<Vec<ReservedNick>>), ApiError> {
+    let reserved = sqlx::query_as::<_, ReservedNick>(
+        "SELECT nick, created_at, claimed_at IS NOT NULL AS claimed, claimed_at FROM reserved_nick ORDER BY nick_key",
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok((StatusCode::OK, Json(reserved)))
+}
+
+pub async fn release<T: TimeService>(
+    Path(nick): Path<String>,
+    State(state): State<ApiState<T>>,
+) -> Result<StatusCode, ApiError> {
+    let rows = sqlx::query("DELETE FROM reserved_nick WHERE nick_key = $1")
+        .bind(reservation::key(&nick))
+        .execute(&state.db)
+        .await?
+        .rows_affected();
+
+    match rows {
+        0 => Ok(StatusCode::NOT_FOUND),
+        _ => Ok(StatusCode::NO_CONTENT),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use axum::{body::Body, extract::ConnectInfo, Router};
+    use http_body_util::BodyExt;
+    use hyper::Request;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{admin::AdminKeys, config::Config, testing, time::ConstantTimeService};
+
+    async fn send(
+        api: &Router,
+        method: &str,
+        uri: &str,
+        admin: bool,
+        body: Value,
+    ) -> (StatusCode, Value) {
+        let mut request = Request::builder()
+            .extension(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 4711))))
+            .header("Content-Type", "application/json")
+            .method(method)
+            .uri(uri);
+        if admin {
+            request = request.header("Authorization", "Bearer key");
+        }
+        let response = api
+            .clone()
+            .oneshot(request.body(Body::from(body.to_string())).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn should_keep_reserved_nicks_for_token_holders() {
+        let db = testing::database().await;
+        let api = crate::api(
+            ConstantTimeService::new(),
+            db,
+            Config {
+                admin_keys: AdminKeys::new(vec!["key".into()]),
+                ..Config::default()
+            },
+        );
+
+        let (status, body) = send(
+            &api,
+            "POST",
+            "/admin/reserved-nicks",
+            true,
+            json!({"nick": "Info Desk"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let token = body["claim_token"].as_str().unwrap().to_owned();
+        let (status, _) = send(
+            &api,
+            "POST",
+            "/admin/reserved-nicks",
+            true,
+            json!({"nick": "info desk"}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        for claim_token in [None, Some("0".repeat(32))] {
+            let (status, body) = send(
+                &api,
+                "POST",
+                "/register",
+                false,
+                json!({"nick": "INFO  DESK", "claim_token": claim_token}),
+            )
+            .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(body["code"], "nick_reserved");
+        }
+
+        let (_, listed) = send(&api, "GET", "/admin/reserved-nicks", true, Value::Null).await;
+        assert_eq!(listed[0]["nick"], "Info Desk");
+        assert_eq!(listed[0]["claimed"], false);
+
+        let (status, _) = send(
+            &api,
+            "POST",
+            "/register",
+            false,
+            json!({"nick": "Info Desk", "claim_token": token}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (_, listed) = send(&api, "GET", "/admin/reserved-nicks", true, Value::Null).await;
+        assert_eq!(listed[0]["claimed"], true);
+        assert!(listed[0]["claimed_at"].is_string());
+
+        for expected in [StatusCode::NO_CONTENT, StatusCode::NOT_FOUND] {
+            let (status, _) = send(
+                &api,
+                "DELETE",
+                "/admin/reserved-nicks/info%20desk",
+                true,
+                Value::Null,
+            )
+            .await;
+            assert_eq!(status, expected);
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    collections::{hash_map::RandomState, BTreeMap, HashMap},
+    hash::{BuildHasher, Hasher},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::{error::ApiError, json::Json, time::TimeService, ApiState};
+
+#[derive(Clone, Copy)]
+pub struct Policy {
+    pub max_attempts: u32,
+    pub base: Duration,
+    pub max: Duration,
+}
+
+impl Policy {
+    // Equal jitter: anywhere between half and all of the exponential delay, so retries spread out but never come
+    // sooner than half the backoff
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .base
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max);
+        let random = RandomState::new().build_hasher().finish();
+        let half = exponential / 2;
+        half + half.mul_f64((random % 1000) as f64 / 1000.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+pub struct Counts {
+    pub retries: u64,
+    pub dead_letters: u64,
+}
+
+#[derive(Clone, Default)]
+pub struct Metrics(Arc<Mutex<BTreeMap<&'static str, Counts>>>);
+
+impl Metrics {
+    pub fn snapshot(&self) -> BTreeMap<&'static str, Counts> {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub fn count(&self, task: &'static str, update: impl FnOnce(&mut Counts)) {
+        update(self.0.lock().unwrap().entry(task).or_default());
+    }
+}
+
+struct Failing {
+    attempts: u32,
+    first_failed_at: DateTime<Utc>,
+    retry_at: DateTime<Utc>,
+}
+
+// Remembers failing items of one worker between runs, until they succeed or are dead-lettered
+pub struct Tracker {
+    task: &'static str,
+    policy: Policy,
+    metrics: Metrics,
+    failing: HashMap<String, Failing>,
+}
+
+impl Tracker {
+    pub fn new(task: &'static str, policy: Policy, metrics: Metrics) -> Self {
+        Self {
+            task,
+            policy,
+            metrics,
+            failing: HashMap::new(),
+        }
+    }
+
+    pub async fn ready(
+        &self,
+        db: &SqlitePool,
+        item: &str,
+        now: DateTime<Utc>,
+    ) -> Result<bool, sqlx::Error> {
+        if self
+            .failing
+            .get(item)
+            .is_some_and(|failing| now < failing.retry_at)
+        {
+            return Ok(false);
+        }
+
+        let dead: bool = sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM dead_letter WHERE task = $1 AND item = $2)",
+        )
+        .bind(self.task)
+        .bind(item)
+        .fetch_one(db)
+        .await?;
+        Ok(!dead)
+    }
+
+    pub fn succeeded(&mut self, item: &str) {
+        self.failing.remove(item);
+    }
+
+    pub async fn failed(
+        &mut self,
+        db: &SqlitePool,
+        item: &str,
+        error: &str,
+        now: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        let failing = self.failing.entry(item.to_owned()).or_insert(Failing {
+            attempts: 0,
+            first_failed_at: now,
+            retry_at: now,
+        });
+        failing.attempts += 1;
+
+        if failing.attempts < self.policy.max_attempts {
+            let delay = self.policy.delay(failing.attempts);
+            failing.retry_at = now + chrono::Duration::from_std(delay).unwrap_or_default();
+            self.metrics.count(self.task, |counts| counts.retries += 1);
+            eprintln!(
+                "[{}] {} failed (attempt {}), retrying in {:?}: {}",
+                self.task, item, failing.attempts, delay, error
+            );
+            return Ok(());
+        }
+
+        sqlx::query(
+            "INSERT INTO dead_letter (task, item, error, attempts, first_failed_at, dead_at) VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(self.task)
+        .bind(item)
+        .bind(error)
+        .bind(failing.attempts)
+        .bind(failing.first_failed_at)
+        .bind(now)
+        .execute(db)
+        .await?;
+        eprintln!(
+            "[{}] {} dead-lettered after {} attempts: {}",
+            self.task, item, failing.attempts, error
+        );
+        self.failing.remove(item);
+        self.metrics
+            .count(self.task, |counts| counts.dead_letters += 1);
+        Ok(())
+    }
+}
+
+#[derive(sqlx::FromRow, Serialize)]
+pub struct DeadLetter {
+    id: i64,
+    task: String,
+    item: String,
+    error: String,
+    attempts: i64,
+    first_failed_at: DateTime<Utc>,
+    dead_at: DateTime<Utc>,
+}
+
+pub async fn list<T: TimeService>(
+    State(state): State<ApiState<T>>,
+) -> Result<(StatusCode, Json<Vec<DeadLetter>>), ApiError> {
+    let dead_letters = sqlx::query_as::<_, DeadLetter>("SELECT * FROM dead_letter ORDER BY id")
+        .fetch_all(&state.db)
+        .await?;
+
+    Ok((StatusCode::OK, Json(dead_letters)))
+}
+
+// The worker picks the item up again with a fresh attempt count once its dead letter is gone
+pub async fn retry<T: TimeService>(
+    Path(id): Path<i64>,
+    State(state): State<ApiState<T>>,
+) -> Result<StatusCode, ApiError> {
+    let rows = sqlx::query("DELETE FROM dead_letter WHERE id = $1")
+        .bind(id)
+        .execute(&state.db)
+        .await?
+        .rows_affected();
+
+    match rows {
+        0 => Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            "dead letter not found",
+        )),
+        _ => Ok(StatusCode::ACCEPTED),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testing;
+
+    const POLICY: Policy = Policy {
+        max_attempts: 3,
+        base: Duration::from_secs(10),
+        max: Duration::from_secs(30),
+    };
+
+    #[test]
+    fn should_back_off_exponentially_with_jitter() {
+        for (attempt, full) in [(1, 10), (2, 20), (3, 30), (10, 30)] {
+            let full = Duration::from_secs(full);
+            for _ in 0..20 {
+                let delay = POLICY.delay(attempt);
+                assert!(delay >= full / 2 && delay <= full, "{:?}", delay);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_dead_letter_after_max_attempts() {
+        let db = testing::database().await;
+        let metrics = Metrics::default();
+        let mut tracker = Tracker::new("test", POLICY, metrics.clone());
+        let mut now = Utc::now();
+
+        for _ in 0..POLICY.max_attempts {
+            assert!(tracker.ready(&db, "poison", now).await.unwrap());
+            tracker.failed(&db, "poison", "boom", now).await.unwrap();
+            assert!(!tracker.ready(&db, "poison", now).await.unwrap());
+            now += chrono::Duration::minutes(1);
+        }
+        assert!(!tracker.ready(&db, "poison", now).await.unwrap());
+        assert!(tracker.ready(&db, "healthy", now).await.unwrap());
+        assert_eq!(
+            metrics.snapshot()["test"],
+            Counts {
+                retries: 2,
+                dead_letters: 1
+            }
+        );
+
+        let (task, item, attempts): (String, String, i64) =
+            sqlx::query_as("SELECT task, item, attempts FROM dead_letter")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!((&*task, &*item, attempts), ("test", "poison", 3));
+
+        sqlx::query("DELETE FROM dead_letter")
+            .execute(&db)
+            .await
+            .unwrap();
+        assert!(tracker.ready(&db, "poison", now).await.unwrap());
+    }
+}
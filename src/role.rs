@@ -0,0 +1,239 @@
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{admin::AdminKeys, db, filter::Audience};
+
+pub const CONFIRMED: &str = "confirmed";
+pub const WAITLISTED: &str = "waitlisted";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Role {
+    Public,
+    Readonly,
+    Admin,
+}
+
+impl Role {
+    pub fn audience(self) -> Audience {
+        match self {
+            Role::Public | Role::Readonly => Audience::Public,
+            Role::Admin => Audience::Admin,
+        }
+    }
+
+    // Waitlisted visitors are only shown to organizers
+    pub fn sees(self, visitor: &db::Visitor) -> bool {
+        self == Role::Admin || visitor.status == CONFIRMED
+    }
+
+    pub fn project(self, visitor: db::Visitor) -> Projection {
+        match self {
+            Role::Public => Projection::Public(PublicVisitor {
+                id: visitor.id,
+                nick: visitor.nick,
+                group: visitor.group,
+            }),
+            Role::Readonly => Projection::Extended(ExtendedVisitor {
+                id: visitor.id,
+                nick: visitor.nick,
+                group: visitor.group,
+                created_at: visitor.created_at,
+            }),
+            Role::Admin => Projection::Full(Box::new(visitor)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Roles {
+    pub admin: AdminKeys,
+    pub readonly: AdminKeys,
+}
+
+impl Roles {
+    // An unknown key is treated like no key at all, the admin routes still reject it
+    pub fn resolve(&self, request: &Request) -> Role {
+        if self.admin.authorizes(request.headers()) {
+            Role::Admin
+        } else if self.readonly.authorizes(request.headers()) {
+            Role::Readonly
+        } else {
+            Role::Public
+        }
+    }
+}
+
+pub async fn attach(State(roles): State<Roles>, mut request: Request, next: Next) -> Response {
+    let role = roles.resolve(&request);
+    request.extensions_mut().insert(role);
+    next.run(request).await
+}
+
+#[derive(Serialize)]
+pub struct PublicVisitor {
+    id: i32,
+    nick: String,
+    group: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ExtendedVisitor {
+    id: i32,
+    nick: String,
+    group: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum Projection {
+    Public(PublicVisitor),
+    Extended(ExtendedVisitor),
+    Full(Box<db::Visitor>),
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use axum::{body::Body, http::StatusCode};
+    use http_body_util::BodyExt;
+    use hyper::Request;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{config::Config, testing, time::ConstantTimeService};
+
+    fn visitor() -> db::Visitor {
+        db::Visitor {
+            id: 1,
+            created_at: Utc::now(),
+            ip: "10.0.0.1".into(),
+            user_agent: Some("Mozilla/5.0".into()),
+            nick: "Razor".into(),
+            group: Some("Razor 1911".into()),
+            email: Some("razor@example.com".into()),
+            confirmed_at: None,
+            consent_at: None,
+            extra: Some("Vegetarian".into()),
+            referral: Some("flyer".into()),
+            admin_note: Some("Bringing the big screen".into()),
+            payment_reference: Some("10016".into()),
+            payment_status: Some("paid".into()),
+            status: CONFIRMED.into(),
+            source: db::WEB.into(),
+            fields: [("shirt".to_owned(), "L".into())].into(),
+        }
+    }
+
+    fn fields(value: &serde_json::Value) -> BTreeSet<&str> {
+        value
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect()
+    }
+
+    const PUBLIC: &[&str] = &["group", "id", "nick"];
+    const EXTENDED: &[&str] = &["created_at", "group", "id", "nick"];
+    const FULL: &[&str] = &[
+        "admin_note",
+        "confirmed_at",
+        "consent_at",
+        "created_at",
+        "email",
+        "extra",
+        "fields",
+        "group",
+        "id",
+        "ip",
+        "nick",
+        "payment_reference",
+        "payment_status",
+        "referral",
+        "source",
+        "status",
+        "user_agent",
+    ];
+
+    #[test]
+    fn should_lock_down_fields_per_role() {
+        for (role, expected) in [
+            (Role::Public, PUBLIC),
+            (Role::Readonly, EXTENDED),
+            (Role::Admin, FULL),
+        ] {
+            let projected = serde_json::to_value(role.project(visitor())).unwrap();
+            assert_eq!(
+                fields(&projected),
+                expected.iter().copied().collect(),
+                "{:?}",
+                role
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn should_project_visitors_by_key() {
+        let db = testing::database().await;
+        testing::insert_visitor(&db, "Razor", Some("Razor 1911")).await;
+        sqlx::query(
+            r#"INSERT INTO visitor_field (visitor_id, name, value) VALUES (1, 'shirt', '"L"')"#,
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        let api = crate::api(
+            ConstantTimeService::new(),
+            db,
+            Config {
+                admin_keys: AdminKeys::new(vec!["admin".into()]),
+                readonly_keys: AdminKeys::new(vec!["readonly".into()]),
+                ..Config::default()
+            },
+        );
+
+        for (uri, key, expected) in [
+            ("/visitors", None, PUBLIC),
+            ("/visitors", Some("unknown"), PUBLIC),
+            ("/visitors", Some("readonly"), EXTENDED),
+            ("/visitors", Some("admin"), FULL),
+            ("/visitors/1", None, PUBLIC),
+            ("/visitors/1", Some("readonly"), EXTENDED),
+            ("/visitors/1", Some("admin"), FULL),
+            ("/admin/visitors", Some("admin"), FULL),
+        ] {
+            let mut request = Request::builder().method("GET").uri(uri);
+            if let Some(key) = key {
+                request = request.header("Authorization", format!("Bearer {}", key));
+            }
+            let response = api
+                .clone()
+                .oneshot(request.body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{} {:?}", uri, key);
+
+            let body: serde_json::Value =
+                serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes())
+                    .unwrap();
+            let visitor = match &body {
+                serde_json::Value::Array(visitors) => &visitors[0],
+                _ => &body,
+            };
+            assert_eq!(
+                fields(visitor),
+                expected.iter().copied().collect(),
+                "{} {:?}",
+                uri,
+                key
+            );
+        }
+    }
+}
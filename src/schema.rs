@@ -0,0 +1,316 @@
+use axum::{
+    extract::Path,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{error::ApiError, json::Json};
+
+// Written by hand and kept honest by the tests below, which hold live responses against them. Changing one
+// means bumping its version and adding a line to schemas/CHANGELOG.md.
+const SCHEMAS: &[(&str, &str)] = &[
+    ("error", include_str!("../schemas/error.json")),
+    (
+        "register-request",
+        include_str!("../schemas/register-request.json"),
+    ),
+    ("registration", include_str!("../schemas/registration.json")),
+    (
+        "registration-edit",
+        include_str!("../schemas/registration-edit.json"),
+    ),
+    ("stats", include_str!("../schemas/stats.json")),
+    ("status", include_str!("../schemas/status.json")),
+    ("visitor", include_str!("../schemas/visitor.json")),
+    (
+        "visitor-extended",
+        include_str!("../schemas/visitor-extended.json"),
+    ),
+    ("visitor-full", include_str!("../schemas/visitor-full.json")),
+];
+
+#[derive(Serialize)]
+pub struct Entry {
+    name: &'static str,
+    version: u64,
+    url: String,
+}
+
+fn version(document: &str) -> u64 {
+    serde_json::from_str::<Value>(document)
+        .ok()
+        .and_then(|schema| schema["version"].as_u64())
+        .unwrap_or_default()
+}
+
+pub async fn list() -> Json<Vec<Entry>> {
+    Json(
+        SCHEMAS
+            .iter()
+            .map(|&(name, document)| Entry {
+                name,
+                version: version(document),
+                url: format!("/schemas/{}.json", name),
+            })
+            .collect(),
+    )
+}
+
+pub async fn get(Path(file): Path<String>) -> Result<Response, ApiError> {
+    let document = file
+        .strip_suffix(".json")
+        .and_then(|name| SCHEMAS.iter().find(|(known, _)| *known == name))
+        .map(|&(_, document)| document)
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, "schema not found"))?;
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/schema+json")],
+        document,
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod test {
+    use axum::body::Body;
+    use http_body_util::BodyExt;
+    use hyper::Request;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{admin::AdminKeys, config::Config, testing, time::ConstantTimeService};
+
+    fn schema(name: &str) -> Value {
+        let (_, document) = SCHEMAS.iter().find(|(known, _)| *known == name).unwrap();
+        serde_json::from_str(document).unwrap()
+    }
+
+    // Just the keywords the schemas above use
+    fn check(schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+        if let Some(types) = schema.get("type") {
+            let types: Vec<&str> = match types {
+                Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+                _ => types.as_str().into_iter().collect(),
+            };
+            let matches = types.iter().any(|&kind| match kind {
+                "object" => value.is_object(),
+                "array" => value.is_array(),
+                "string" => value.is_string(),
+                "integer" => value.is_i64() || value.is_u64(),
+                "number" => value.is_number(),
+                "boolean" => value.is_boolean(),
+                "null" => value.is_null(),
+                _ => false,
+            });
+            if !matches {
+                return Err(format!("{}: expected {:?}, got {}", path, types, value));
+            }
+        }
+
+        if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+            if !allowed.contains(value) {
+                return Err(format!("{}: {} is not one of {:?}", path, value, allowed));
+            }
+        }
+
+        if let Some(variants) = schema.get("oneOf").and_then(Value::as_array) {
+            let matching = variants
+                .iter()
+                .filter(|variant| check(variant, value, path).is_ok())
+                .count();
+            if matching != 1 {
+                return Err(format!("{}: matches {} variants", path, matching));
+            }
+        }
+
+        if let Value::Object(object) = value {
+            for required in schema["required"].as_array().into_iter().flatten() {
+                let required = required.as_str().unwrap();
+                if !object.contains_key(required) {
+                    return Err(format!("{}: missing {}", path, required));
+                }
+            }
+            for (key, value) in object {
+                let path = format!("{}.{}", path, key);
+                match (
+                    schema["properties"].get(key),
+                    schema.get("additionalProperties"),
+                ) {
+                    (Some(property), _) => check(property, value, &path)?,
+                    (None, Some(Value::Bool(false))) => {
+                        return Err(format!("{}: not in the schema", path))
+                    }
+                    (None, Some(additional @ Value::Object(_))) => check(additional, value, &path)?,
+                    (None, _) => {}
+                }
+            }
+        }
+
+        if let (Value::Array(items), Some(schema)) = (value, schema.get("items")) {
+            for (index, item) in items.iter().enumerate() {
+                check(schema, item, &format!("{}[{}]", path, index))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn should_version_and_log_every_schema() {
+        let changelog = include_str!("../schemas/CHANGELOG.md");
+        for &(name, document) in SCHEMAS {
+            let schema: Value = serde_json::from_str(document).unwrap();
+            assert_eq!(schema["$id"], format!("/schemas/{}.json", name));
+            let version = version(document);
+            assert!(version > 0, "{} has no version", name);
+            assert!(
+                changelog.contains(&format!("## {} v{}\n", name, version)),
+                "{} v{} is missing from schemas/CHANGELOG.md",
+                name,
+                version
+            );
+        }
+    }
+
+    #[test]
+    fn should_accept_own_examples() {
+        for &(name, document) in SCHEMAS {
+            let schema: Value = serde_json::from_str(document).unwrap();
+            let examples = schema["examples"].as_array().unwrap();
+            assert!(!examples.is_empty(), "{} has no examples", name);
+            for example in examples {
+                check(&schema, example, name).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn should_reject_drift() {
+        let leaked = serde_json::json!({"id": 1, "nick": "Razor", "group": null, "email": "razor@example.com"});
+        assert!(check(&schema("visitor"), &leaked, "visitor").is_err());
+
+        let missing = serde_json::json!({"id": 1, "nick": "Razor"});
+        assert!(check(&schema("visitor"), &missing, "visitor").is_err());
+
+        let retyped = serde_json::json!({"schema_version": "1"});
+        assert!(check(&schema("status"), &retyped, "status").is_err());
+    }
+
+    #[tokio::test]
+    async fn should_describe_live_responses() {
+        let db = testing::database().await;
+        let api = crate::api(
+            ConstantTimeService::new(),
+            db,
+            Config {
+                admin_keys: AdminKeys::new(vec!["admin".into()]),
+                readonly_keys: AdminKeys::new(vec!["readonly".into()]),
+                ..Config::default()
+            },
+        );
+
+        let register = serde_json::json!({"nick": "Razor", "group": "Razor 1911", "ref": "flyer"});
+        check(&schema("register-request"), &register, "request").unwrap();
+
+        let mut requests = vec![(
+            "registration",
+            Request::builder()
+                .extension(axum::extract::ConnectInfo(std::net::SocketAddr::from((
+                    [127, 0, 0, 1],
+                    8080,
+                ))))
+                .header("Content-Type", "application/json")
+                .method("POST")
+                .uri("/register")
+                .body(Body::from(register.to_string()))
+                .unwrap(),
+        )];
+        requests.push((
+            "error",
+            Request::builder()
+                .extension(axum::extract::ConnectInfo(std::net::SocketAddr::from((
+                    [127, 0, 0, 1],
+                    8080,
+                ))))
+                .header("Content-Type", "application/json")
+                .method("POST")
+                .uri("/register")
+                .body(Body::from(r#"{"nick":"","email":"nope"}"#))
+                .unwrap(),
+        ));
+        for (name, uri, key) in [
+            ("visitor", "/visitors", None),
+            ("visitor-extended", "/visitors", Some("readonly")),
+            ("visitor-full", "/visitors", Some("admin")),
+            ("visitor", "/visitors/1", None),
+            ("visitor-full", "/visitors/1", Some("admin")),
+            ("stats", "/admin/stats", Some("admin")),
+            ("status", "/status", None),
+            ("error", "/visitors/9", None),
+            ("error", "/visitors?limit=0", None),
+            ("error", "/admin/stats", None),
+        ] {
+            let mut request = Request::builder().method("GET").uri(uri);
+            if let Some(key) = key {
+                request = request.header("Authorization", format!("Bearer {}", key));
+            }
+            requests.push((name, request.body(Body::empty()).unwrap()));
+        }
+
+        for (name, request) in requests {
+            let uri = request.uri().to_string();
+            let response = api.clone().oneshot(request).await.unwrap();
+            let body: Value =
+                serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes())
+                    .unwrap();
+            let values = match &body {
+                Value::Array(values) if !values.is_empty() => values.iter().collect(),
+                _ => vec![&body],
+            };
+            for value in values {
+                check(&schema(name), value, &uri).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn should_serve_schemas() {
+        let db = testing::database().await;
+        let api = crate::api(ConstantTimeService::new(), db, Config::default());
+        let get = |uri: &str| {
+            api.clone()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+        };
+
+        let response = get("/schemas").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value =
+            serde_json::from_slice(&response.into_body().collect().await.unwrap().to_bytes())
+                .unwrap();
+        assert_eq!(body.as_array().unwrap().len(), SCHEMAS.len());
+        assert_eq!(
+            body[0],
+            serde_json::json!({"name": "error", "version": 2, "url": "/schemas/error.json"})
+        );
+
+        let response = get("/schemas/visitor.json").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/schema+json"
+        );
+        assert_eq!(
+            serde_json::from_slice::<Value>(
+                &response.into_body().collect().await.unwrap().to_bytes()
+            )
+            .unwrap(),
+            schema("visitor")
+        );
+
+        for uri in ["/schemas/visitor", "/schemas/secrets.json"] {
+            assert_eq!(get(uri).await.unwrap().status(), StatusCode::NOT_FOUND);
+        }
+    }
+}
@@ -0,0 +1,450 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    SqlitePool,
+};
+
+use crate::{config, db, error::ApiError, time::TimeService, ApiState};
+
+// A snapshot is a plain SQLite file written by VACUUM INTO, so it opens with any SQLite tool. Next to the
+// regular tables it holds snapshot_manifest (format, schema_version, crate_version, created_at) and
+// snapshot_config (the effective environment, secrets replaced by REDACTED unless asked for). Restore
+// drops both tables again. Bump FORMAT if this layout ever changes.
+pub const FORMAT: &str = "1";
+pub const REDACTED: &str = "[redacted]";
+
+#[derive(Debug, PartialEq)]
+pub struct Manifest {
+    pub format: String,
+    pub schema_version: u32,
+    pub crate_version: String,
+    pub created_at: String,
+}
+
+#[derive(Debug)]
+pub struct Restored {
+    pub manifest: Manifest,
+    pub config: Vec<(String, String)>,
+    pub findings: Vec<db::Finding>,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Database(sqlx::Error),
+    Io(io::Error),
+    Invalid(String),
+}
+
+impl From<sqlx::Error> for SnapshotError {
+    fn from(error: sqlx::Error) -> Self {
+        Self::Database(error)
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+impl std::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Database(error) => write!(f, "{}", error),
+            Self::Io(error) => write!(f, "{}", error),
+            Self::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+pub async fn create(
+    db: &SqlitePool,
+    out: &Path,
+    config: Vec<(String, String)>,
+) -> Result<Manifest, SnapshotError> {
+    if out.exists() {
+        return Err(SnapshotError::Invalid(format!(
+            "{} already exists",
+            out.display()
+        )));
+    }
+    sqlx::query("VACUUM INTO $1")
+        .bind(out.to_string_lossy())
+        .execute(db)
+        .await?;
+
+    let manifest = Manifest {
+        format: FORMAT.into(),
+        schema_version: db::SCHEMA_VERSION,
+        crate_version: env!("CARGO_PKG_VERSION").into(),
+        created_at: Utc::now().to_rfc3339(),
+    };
+
+    let snapshot = open(out, false).await?;
+    sqlx::query(
+        "CREATE TABLE snapshot_manifest (key TEXT PRIMARY KEY, value TEXT NOT NULL) STRICT",
+    )
+    .execute(&snapshot)
+    .await?;
+    sqlx::query("CREATE TABLE snapshot_config (name TEXT PRIMARY KEY, value TEXT NOT NULL) STRICT")
+        .execute(&snapshot)
+        .await?;
+    for (key, value) in [
+        ("format", manifest.format.clone()),
+        ("schema_version", manifest.schema_version.to_string()),
+        ("crate_version", manifest.crate_version.clone()),
+        ("created_at", manifest.created_at.clone()),
+    ] {
+        sqlx::query("INSERT INTO snapshot_manifest (key, value) VALUES ($1, $2)")
+            .bind(key)
+            .bind(value)
+            .execute(&snapshot)
+            .await?;
+    }
+    for (name, value) in config {
+        sqlx::query("INSERT INTO snapshot_config (name, value) VALUES ($1, $2)")
+            .bind(name)
+            .bind(value)
+            .execute(&snapshot)
+            .await?;
+    }
+    snapshot.close().await;
+
+    Ok(manifest)
+}
+
+pub async fn restore(from: &Path, to: &Path, force: bool) -> Result<Restored, SnapshotError> {
+    let snapshot = open(from, true).await?;
+    let manifest = read_manifest(&snapshot).await?;
+    let config = sqlx::query_as::<_, (String, String)>(
+        "SELECT name, value FROM snapshot_config ORDER BY name",
+    )
+    .fetch_all(&snapshot)
+    .await?;
+    snapshot.close().await;
+
+    if manifest.format != FORMAT {
+        return Err(SnapshotError::Invalid(format!(
+            "unsupported snapshot format {}",
+            manifest.format
+        )));
+    }
+    if manifest.schema_version > db::SCHEMA_VERSION {
+        return Err(SnapshotError::Invalid(format!(
+            "snapshot schema {} from {} is newer than this binary's schema {}",
+            manifest.schema_version,
+            manifest.crate_version,
+            db::SCHEMA_VERSION
+        )));
+    }
+    if !force && fs::metadata(to).is_ok_and(|metadata| metadata.len() > 0) {
+        return Err(SnapshotError::Invalid(format!(
+            "{} already exists, restore with --force to replace it",
+            to.display()
+        )));
+    }
+
+    for suffix in ["-wal", "-shm"] {
+        let _ = fs::remove_file(sidecar(to, suffix));
+    }
+    fs::copy(from, to)?;
+
+    let db = open(to, false).await?;
+    sqlx::query("DROP TABLE snapshot_manifest")
+        .execute(&db)
+        .await?;
+    sqlx::query("DROP TABLE snapshot_config")
+        .execute(&db)
+        .await?;
+    db::init(&db).await?;
+    let findings = db::check_consistency(&db).await?;
+    db.close().await;
+
+    Ok(Restored {
+        manifest,
+        config,
+        findings,
+    })
+}
+
+pub async fn download<T: TimeService>(
+    State(state): State<ApiState<T>>,
+) -> Result<Response, ApiError> {
+    let path = std::env::temp_dir().join(format!(
+        "party-api-snapshot-{}-{}.db",
+        process::id(),
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+    ));
+
+    let result = create(&state.db, &path, config::effective(false)).await;
+    let bytes = result.and_then(|_| Ok(fs::read(&path)?));
+    let _ = fs::remove_file(&path);
+
+    match bytes {
+        Ok(bytes) => Ok((
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, "application/vnd.sqlite3"),
+                (
+                    header::CONTENT_DISPOSITION,
+                    r#"attachment; filename="party-api-snapshot.db""#,
+                ),
+            ],
+            bytes,
+        )
+            .into_response()),
+        Err(SnapshotError::Database(error)) => Err(error.into()),
+        Err(error) => Err(ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            error.to_string(),
+        )),
+    }
+}
+
+pub async fn run(args: &[String], db_path: &Path) -> Result<(), SnapshotError> {
+    let flag = |name: &str| args.iter().any(|arg| arg == name);
+    let value = |name: &str| {
+        args.iter()
+            .position(|arg| arg == name)
+            .and_then(|i| args.get(i + 1))
+            .map(PathBuf::from)
+            .ok_or_else(|| SnapshotError::Invalid(format!("{} <path> is required", name)))
+    };
+
+    match args.first().map(String::as_str) {
+        Some("create") => {
+            let out = value("--out")?;
+            let db = open(db_path, true).await?;
+            let manifest = create(&db, &out, config::effective(flag("--include-secrets"))).await?;
+            eprintln!(
+                "wrote snapshot of schema {} to {}",
+                manifest.schema_version,
+                out.display()
+            );
+        }
+        Some("restore") => {
+            let from = value("--from")?;
+            let restored = restore(&from, db_path, flag("--force")).await?;
+            eprintln!(
+                "restored snapshot from {} ({}) to {}",
+                restored.manifest.created_at,
+                restored.manifest.crate_version,
+                db_path.display()
+            );
+            for (name, value) in restored.config {
+                eprintln!("snapshot configuration: {}={}", name, value);
+            }
+            for finding in restored.findings {
+                eprintln!("database inconsistency: {:?}", finding);
+            }
+        }
+        _ => {
+            return Err(SnapshotError::Invalid(
+                "usage: party-api snapshot create --out <path> [--include-secrets] | restore --from <path> [--force]".into(),
+            ))
+        }
+    }
+
+    Ok(())
+}
+
+async fn open(path: &Path, read_only: bool) -> Result<SqlitePool, sqlx::Error> {
+    SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(
+            SqliteConnectOptions::new()
+                .filename(path)
+                .read_only(read_only),
+        )
+        .await
+}
+
+async fn read_manifest(snapshot: &SqlitePool) -> Result<Manifest, SnapshotError> {
+    let rows = sqlx::query_as::<_, (String, String)>("SELECT key, value FROM snapshot_manifest")
+        .fetch_all(snapshot)
+        .await
+        .map_err(|_| SnapshotError::Invalid("not a party-api snapshot".into()))?;
+    let get = |key: &str| {
+        rows.iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.clone())
+            .ok_or_else(|| SnapshotError::Invalid(format!("manifest is missing {}", key)))
+    };
+
+    Ok(Manifest {
+        format: get("format")?,
+        schema_version: get("schema_version")?
+            .parse()
+            .map_err(|_| SnapshotError::Invalid("bad schema_version in manifest".into()))?,
+        crate_version: get("crate_version")?,
+        created_at: get("created_at")?,
+    })
+}
+
+fn sidecar(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::Body, Router};
+    use http_body_util::BodyExt;
+    use hyper::Request;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{admin::AdminKeys, config::Config, testing, time::ConstantTimeService};
+
+    fn api(db: SqlitePool) -> Router {
+        crate::api(
+            ConstantTimeService::new(),
+            db,
+            Config {
+                admin_keys: AdminKeys::new(vec!["key".into()]),
+                ..Config::default()
+            },
+        )
+    }
+
+    async fn responses(api: Router) -> Vec<String> {
+        let mut bodies = Vec::new();
+        for uri in [
+            "/visitors",
+            "/groups",
+            "/admin/visitors",
+            "/admin/reservations",
+        ] {
+            let response = api
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .header("Authorization", "Bearer key")
+                        .uri(uri)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            bodies.push(String::from_utf8(body.to_vec()).unwrap());
+        }
+        bodies
+    }
+
+    #[tokio::test]
+    async fn should_restore_identical_instance() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = testing::file_database(dir.path()).await;
+        testing::insert_visitor(&db, "Fairlight", Some("Fairlight")).await;
+        testing::insert_visitor(&db, "Razor", None).await;
+        crate::reservation::import(
+            &db,
+            vec![crate::reservation::ImportEntry {
+                nick: "Returning".into(),
+                email: Some("returning@example.com".into()),
+            }],
+        )
+        .await
+        .unwrap();
+        let original = responses(api(db.clone())).await;
+
+        let snapshot = dir.path().join("state.db");
+        create(
+            &db,
+            &snapshot,
+            vec![
+                ("API_KEY".into(), REDACTED.into()),
+                ("GROUP_MAX_LENGTH".into(), "32".into()),
+            ],
+        )
+        .await
+        .unwrap();
+
+        let restored_path = dir.path().join("fresh").join("data.db");
+        fs::create_dir_all(restored_path.parent().unwrap()).unwrap();
+        let restored = restore(&snapshot, &restored_path, false).await.unwrap();
+        assert_eq!(restored.manifest.schema_version, db::SCHEMA_VERSION);
+        assert_eq!(
+            restored.config,
+            vec![
+                ("API_KEY".to_owned(), REDACTED.to_owned()),
+                ("GROUP_MAX_LENGTH".to_owned(), "32".to_owned()),
+            ]
+        );
+        assert!(restored.findings.is_empty());
+
+        let restored_db = open(&restored_path, false).await.unwrap();
+        let tables: Vec<String> =
+            sqlx::query_scalar("SELECT name FROM sqlite_master WHERE name LIKE 'snapshot_%'")
+                .fetch_all(&restored_db)
+                .await
+                .unwrap();
+        assert!(tables.is_empty());
+        assert_eq!(responses(api(restored_db)).await, original);
+
+        assert!(matches!(
+            restore(&snapshot, &restored_path, false).await,
+            Err(SnapshotError::Invalid(_))
+        ));
+        assert!(restore(&snapshot, &restored_path, true).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn should_refuse_newer_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = testing::file_database(dir.path()).await;
+        let snapshot = dir.path().join("state.db");
+        create(&db, &snapshot, Vec::new()).await.unwrap();
+
+        let writable = open(&snapshot, false).await.unwrap();
+        sqlx::query("UPDATE snapshot_manifest SET value = $1 WHERE key = 'schema_version'")
+            .bind((db::SCHEMA_VERSION + 1).to_string())
+            .execute(&writable)
+            .await
+            .unwrap();
+        writable.close().await;
+
+        let target = dir.path().join("data.db");
+        let error = restore(&snapshot, &target, false).await.unwrap_err();
+        assert!(error.to_string().contains("newer"), "{}", error);
+        assert!(!target.exists());
+    }
+
+    #[tokio::test]
+    async fn should_download_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = testing::file_database(dir.path()).await;
+        testing::insert_visitor(&db, "Downloaded", None).await;
+
+        let response = api(db)
+            .oneshot(
+                Request::builder()
+                    .header("Authorization", "Bearer key")
+                    .uri("/admin/snapshot")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(body.starts_with(b"SQLite format 3\0"));
+    }
+}
@@ -0,0 +1,386 @@
+use std::{collections::BTreeMap, env};
+
+use axum::{extract::State, http::StatusCode};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Sqlite, SqlitePool, Transaction};
+
+use crate::{error::ApiError, json::Json, time::TimeService, validate, ApiState};
+
+const MAX_BATCH: u32 = 1000;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Stage {
+    pub label: String,
+    pub opens_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub criteria: Criteria,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Criteria {
+    Referral(String),
+    Groups(Vec<String>),
+    InviteBatch(String),
+}
+
+// Early openings for some registrations ahead of REGISTRATION_OPENS_AT, tried in the order they are listed
+#[derive(Clone, Default)]
+pub struct Stages(Vec<Stage>);
+
+pub struct Candidate<'a> {
+    pub referral: Option<&'a str>,
+    pub group: Option<&'a str>,
+    pub invite_token: Option<&'a str>,
+}
+
+#[derive(Clone)]
+pub struct Admission {
+    label: String,
+    invite_token: Option<String>,
+}
+
+impl Stages {
+    pub fn new(stages: Vec<Stage>) -> Self {
+        Self(stages)
+    }
+
+    pub fn from_env() -> Self {
+        let Ok(stages) = env::var("REGISTRATION_STAGES") else {
+            return Self::default();
+        };
+        Self::new(
+            serde_json::from_str(&stages)
+                .unwrap_or_else(|error| panic!("bad REGISTRATION_STAGES: {}", error)),
+        )
+    }
+
+    // Before general opening, lets in whoever qualifies for a stage that has opened. Everyone else learns the
+    // earliest time they could get in.
+    pub async fn admit(
+        &self,
+        db: &SqlitePool,
+        candidate: &Candidate<'_>,
+        now: DateTime<Utc>,
+        opens_at: DateTime<Utc>,
+    ) -> Result<Admission, ApiError> {
+        let invite_batch: Option<String> = match candidate.invite_token {
+            Some(token) => {
+                sqlx::query_scalar("SELECT batch FROM invite WHERE token = $1 AND used_at IS NULL")
+                    .bind(token.trim().to_lowercase())
+                    .fetch_optional(db)
+                    .await?
+            }
+            None => None,
+        };
+
+        let mut earliest = opens_at;
+        for stage in &self.0 {
+            let matches = match &stage.criteria {
+                Criteria::Referral(code) => {
+                    validate::referral(candidate.referral, std::slice::from_ref(code)).is_some()
+                }
+                Criteria::Groups(groups) => candidate.group.is_some_and(|group| {
+                    groups.iter().any(|x| {
+                        validate::normalize(x).map(|x| x.to_lowercase())
+                            == Some(group.to_lowercase())
+                    })
+                }),
+                Criteria::InviteBatch(batch) => invite_batch.as_ref() == Some(batch),
+            };
+            if !matches {
+                continue;
+            }
+
+            if stage.opens_at <= now {
+                return Ok(Admission {
+                    label: stage.label.clone(),
+                    invite_token: match stage.criteria {
+                        Criteria::InviteBatch(_) => {
+                            candidate.invite_token.map(|x| x.trim().to_lowercase())
+                        }
+                        _ => None,
+                    },
+                });
+            }
+            earliest = earliest.min(stage.opens_at);
+        }
+
+        Err(not_open(earliest))
+    }
+}
+
+fn not_open(opens_at: DateTime<Utc>) -> ApiError {
+    ApiError::new(StatusCode::FORBIDDEN, "registration is not open yet")
+        .with_code("registration_not_open")
+        .with_detail("opens_at", opens_at)
+}
+
+impl Admission {
+    pub async fn record(
+        self,
+        tx: &mut Transaction<'static, Sqlite>,
+        visitor_id: i64,
+        now: DateTime<Utc>,
+    ) -> Result<(), ApiError> {
+        if let Some(token) = &self.invite_token {
+            let used = sqlx::query(
+                "UPDATE invite SET used_at = $1, visitor_id = $2 WHERE token = $3 AND used_at IS NULL",
+            )
+            .bind(now)
+            .bind(visitor_id)
+            .bind(token)
+            .execute(&mut **tx)
+            .await?
+            .rows_affected();
+            if used == 0 {
+                return Err(
+                    ApiError::new(StatusCode::FORBIDDEN, "invite token is already used")
+                        .with_code("invite_used"),
+                );
+            }
+        }
+
+        sqlx::query("INSERT INTO registration_stage (visitor_id, label) VALUES ($1, $2)")
+            .bind(visitor_id)
+            .bind(&self.label)
+            .execute(&mut **tx)
+            .await?;
+        Ok(())
+    }
+}
+
+pub async fn usage(db: &SqlitePool) -> Result<BTreeMap<String, i64>, sqlx::Error> {
+    let usage: Vec<(String, i64)> =
+        sqlx::query_as("SELECT label, COUNT(*) FROM registration_stage GROUP BY label")
+            .fetch_all(db)
+            .await?;
+    Ok(usage.into_iter().collect())
+}
+
+#[derive(Deserialize)]
+pub struct InviteRequest {
+    batch: String,
+    count: u32,
+}
+
+#[derive(Serialize)]
+pub struct Invites {
+    batch: String,
+    tokens: Vec<String>,
+}
+
+pub async fn create_invites<T: TimeService>(
+    State(state): State<ApiState<T>>,
+    Json(request): Json<InviteRequest>,
+) -> Result<(StatusCode, Json<Invites>), ApiError> {
+    let batch = request.batch.trim().to_owned();
+    if batch.is_empty() {
+        return Err(ApiError::new(StatusCode::BAD_REQUEST, "batch is required"));
+    }
+    if !(1..=MAX_BATCH).contains(&request.count) {
+        return Err(
+            ApiError::new(StatusCode::BAD_REQUEST, "count is out of range")
+                .with_detail("max", MAX_BATCH),
+        );
+    }
+
+    // SQLite's randomblob() is backed by a proper CSPRNG, unlike anything else at hand
+    let tokens: Vec<String> = sqlx::query_scalar(
+        r#"WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < $3)
+INSERT INTO invite (token, batch, created_at)
+SELECT lower(hex(randomblob(16))), $1, $2 FROM n
+RETURNING token"#,
+    )
+    .bind(&batch)
+    .bind(state.time.clone().now())
+    .bind(request.count)
+    .fetch_all(&state.db)
+    .await?;
+    eprintln!("[invites] created {} in batch {}", tokens.len(), batch);
+
+    Ok((StatusCode::CREATED, Json(Invites { batch, tokens })))
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use axum::{body::Body, extract::ConnectInfo, Router};
+    use chrono::Duration;
+    use http_body_util::BodyExt;
+    use hyper::Request;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{admin::AdminKeys, config::Config, testing, time::ConstantTimeService};
+
+    fn stages(opens_at: DateTime<Utc>) -> Stages {
+        serde_json::from_value(serde_json::json!([
+            {"label": "nordic", "opens_at": opens_at, "referral": "nordic"},
+            {"label": "crews", "opens_at": opens_at + Duration::hours(1), "groups": ["Fairlight", "Razor 1911"]},
+            {"label": "invited", "opens_at": opens_at + Duration::hours(2), "invite_batch": "vip"},
+        ]))
+        .map(Stages::new)
+        .unwrap()
+    }
+
+    async fn send(api: Router, uri: &str, client: u8, body: Value) -> (StatusCode, Value) {
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .extension(ConnectInfo(SocketAddr::from(([10, 0, 0, client], 4711))))
+                    .header("Authorization", "Bearer key")
+                    .header("Content-Type", "application/json")
+                    .method(if body.is_null() { "GET" } else { "POST" })
+                    .uri(uri)
+                    .body(match body.is_null() {
+                        true => Body::empty(),
+                        false => Body::from(body.to_string()),
+                    })
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn should_open_stage_by_stage() {
+        let db = testing::database().await;
+        let first = DateTime::parse_from_rfc3339("2024-03-01T10:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let api = |now: DateTime<Utc>| {
+            crate::api(
+                ConstantTimeService::at(now),
+                db.clone(),
+                Config {
+                    admin_keys: AdminKeys::new(vec!["key".into()]),
+                    registration_opens_at: Some(first + Duration::hours(3)),
+                    stages: stages(first),
+                    ..Config::default()
+                },
+            )
+        };
+
+        let (status, invites) = send(
+            api(first),
+            "/admin/invites",
+            1,
+            serde_json::json!({"batch": "vip", "count": 2}),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let tokens: Vec<&str> = invites["tokens"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(Value::as_str)
+            .collect();
+        assert_eq!(tokens.len(), 2);
+        assert_ne!(tokens[0], tokens[1]);
+        assert!(tokens.iter().all(|token| token.len() == 32));
+
+        let steps = [
+            // Nobody is in yet, each learns when they will be
+            (
+                -1,
+                serde_json::json!({"nick": "A", "ref": "NORDIC"}),
+                Err(0),
+            ),
+            (
+                -1,
+                serde_json::json!({"nick": "B", "group": "fairlight"}),
+                Err(1),
+            ),
+            (
+                -1,
+                serde_json::json!({"nick": "C", "invite_token": tokens[0]}),
+                Err(2),
+            ),
+            (
+                -1,
+                serde_json::json!({"nick": "D", "invite_token": "guess"}),
+                Err(3),
+            ),
+            (
+                0,
+                serde_json::json!({"nick": "A", "ref": "nordic"}),
+                Ok("nordic"),
+            ),
+            (
+                0,
+                serde_json::json!({"nick": "B", "group": "Fairlight"}),
+                Err(1),
+            ),
+            (
+                60,
+                serde_json::json!({"nick": "B", "group": " Razor  1911 "}),
+                Ok("crews"),
+            ),
+            (
+                120,
+                serde_json::json!({"nick": "C", "invite_token": tokens[0]}),
+                Ok("invited"),
+            ),
+            // Single use
+            (
+                120,
+                serde_json::json!({"nick": "E", "invite_token": tokens[0]}),
+                Err(3),
+            ),
+            (
+                120,
+                serde_json::json!({"nick": "F", "invite_token": "guess"}),
+                Err(3),
+            ),
+            (180, serde_json::json!({"nick": "G"}), Ok("general")),
+        ];
+        for (client, (minutes, body, expected)) in steps.into_iter().enumerate() {
+            let now = first + Duration::minutes(minutes);
+            let (status, response) = send(api(now), "/register", client as u8 + 2, body).await;
+            match expected {
+                Ok(_) => assert_eq!(status, StatusCode::CREATED, "{}: {}", now, response),
+                Err(hours) => {
+                    assert_eq!(status, StatusCode::FORBIDDEN, "{}: {}", now, response);
+                    assert_eq!(response["code"], "registration_not_open");
+                    assert_eq!(
+                        response["opens_at"],
+                        serde_json::json!(first + Duration::hours(hours))
+                    );
+                }
+            }
+        }
+
+        let (_, stats) = send(api(first), "/admin/stats", 1, Value::Null).await;
+        assert_eq!(
+            stats["stages"],
+            serde_json::json!({"crews": 1, "invited": 1, "nordic": 1})
+        );
+        let unused: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM invite WHERE used_at IS NULL")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(unused, 1);
+    }
+
+    #[test]
+    fn should_parse_stages() {
+        let parsed: Vec<Stage> = serde_json::from_str(
+            r#"[{"label":"crews","opens_at":"2024-03-01T10:00:00+01:00","groups":["Fairlight"]}]"#,
+        )
+        .unwrap();
+        assert!(
+            matches!(&parsed[0].criteria, Criteria::Groups(groups) if groups == &["Fairlight"])
+        );
+        assert_eq!(parsed[0].opens_at.to_rfc3339(), "2024-03-01T09:00:00+00:00");
+
+        let unknown = r#"[{"label":"x","opens_at":"2024-03-01T10:00:00Z","country":"SE"}]"#;
+        assert!(serde_json::from_str::<Vec<Stage>>(unknown).is_err());
+    }
+}
@@ -0,0 +1,194 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use sqlx::SqlitePool;
+
+use crate::{
+    error::{ApiError, ErrorCode},
+    supervisor::Stop,
+};
+
+pub const UNAVAILABLE: &str = "storage_unavailable";
+pub const PROBE_INTERVAL: Duration = Duration::from_secs(10);
+
+const SQLITE_IOERR: i32 = 10;
+const SQLITE_READONLY: i32 = 8;
+const SQLITE_FULL: i32 = 13;
+
+#[derive(Clone, Default)]
+pub struct Storage {
+    degraded: Arc<AtomicBool>,
+}
+
+impl Storage {
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::SeqCst)
+    }
+
+    fn degrade(&self) {
+        if !self.degraded.swap(true, Ordering::SeqCst) {
+            eprintln!("!!! database is not writable, switching to read-only mode");
+        }
+    }
+
+    pub async fn probe(&self, db: &SqlitePool) {
+        let result = sqlx::query(
+            r#"INSERT OR REPLACE INTO storage_probe (id, probed_at) VALUES (1, CURRENT_TIMESTAMP)"#,
+        )
+        .execute(db)
+        .await;
+
+        match result {
+            Ok(_) => {
+                if self.degraded.swap(false, Ordering::SeqCst) {
+                    eprintln!("database is writable again, leaving read-only mode");
+                }
+            }
+            Err(error) if is_unavailable(&error) => self.degrade(),
+            Err(error) => eprintln!("storage probe failed: {}", error),
+        }
+    }
+
+    pub async fn run_probe(self, db: SqlitePool, mut stop: Stop) {
+        while stop.sleep(PROBE_INTERVAL).await {
+            if self.is_degraded() {
+                self.probe(&db).await;
+            }
+        }
+    }
+}
+
+pub fn is_unavailable(error: &sqlx::Error) -> bool {
+    let sqlx::Error::Database(db_error) = error else {
+        return false;
+    };
+
+    db_error
+        .code()
+        .and_then(|code| code.parse::<i32>().ok())
+        .is_some_and(|code| matches!(code & 0xff, SQLITE_IOERR | SQLITE_READONLY | SQLITE_FULL))
+}
+
+pub fn error() -> ApiError {
+    ApiError::new(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "storage is unavailable, the API is read-only for now",
+    )
+    .with_code(UNAVAILABLE)
+}
+
+pub async fn guard(State(storage): State<Storage>, request: Request, next: Next) -> Response {
+    let writes = !matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+
+    let mut response = match writes && storage.is_degraded() {
+        true => error().into_response(),
+        false => next.run(request).await,
+    };
+
+    if response
+        .extensions()
+        .get::<ErrorCode>()
+        .is_some_and(|code| code.0 == UNAVAILABLE)
+    {
+        storage.degrade();
+        response.headers_mut().insert(
+            header::RETRY_AFTER,
+            HeaderValue::from(PROBE_INTERVAL.as_secs()),
+        );
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod test {
+    use axum::body::Body;
+    use hyper::{Request, StatusCode};
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+    use tower::ServiceExt;
+
+    use super::Storage;
+    use crate::{config::Config, db, time::ConstantTimeService};
+
+    #[tokio::test]
+    async fn should_degrade_and_recover() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = SqliteConnectOptions::new()
+            .filename(dir.path().join("data.db"))
+            .create_if_missing(true);
+        let writable = SqlitePoolOptions::new()
+            .connect_with(options.clone())
+            .await
+            .unwrap();
+        db::init(&writable).await.unwrap();
+        let read_only = SqlitePoolOptions::new()
+            .connect_with(options.read_only(true))
+            .await
+            .unwrap();
+
+        let storage = Storage::default();
+        let api = crate::api_with_storage(
+            ConstantTimeService::new(),
+            read_only.clone(),
+            Config::default(),
+            storage.clone(),
+            crate::supervisor::Supervisor::new(
+                ConstantTimeService::new(),
+                crate::supervisor::RESTART,
+            ),
+        );
+
+        let register = || {
+            Request::builder()
+                .extension(axum::extract::ConnectInfo(std::net::SocketAddr::from((
+                    [127, 0, 0, 1],
+                    8080,
+                ))))
+                .method("POST")
+                .uri("/register")
+                .header("Content-Type", "application/json")
+                .body(Body::from(r#"{"nick":"Stuck"}"#))
+                .unwrap()
+        };
+
+        let response = api.clone().oneshot(register()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["Retry-After"], "10");
+        assert!(storage.is_degraded());
+
+        let response = api
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("GET")
+                    .uri("/visitors")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        storage.probe(&read_only).await;
+        assert!(storage.is_degraded());
+        let response = api.clone().oneshot(register()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        storage.probe(&writable).await;
+        assert!(!storage.is_degraded());
+    }
+}
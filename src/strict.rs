@@ -0,0 +1,113 @@
+use std::net::SocketAddr;
+
+use crate::config::Config;
+
+pub const MIN_KEY_LENGTH: usize = 16;
+
+#[derive(Debug, PartialEq)]
+pub struct Violation {
+    pub key: &'static str,
+    pub message: String,
+}
+
+pub fn check(config: &Config, listen_addr: SocketAddr) -> Vec<Violation> {
+    let mut violations = Vec::new();
+
+    for (key, keys) in [
+        ("API_KEY", &config.admin_keys),
+        ("VERIFY_KEYS", &config.verify_keys),
+        ("READONLY_KEYS", &config.readonly_keys),
+        ("REPLICA_KEYS", &config.replica_keys),
+    ] {
+        if keys
+            .shortest()
+            .is_some_and(|length| length < MIN_KEY_LENGTH)
+        {
+            violations.push(Violation {
+                key,
+                message: format!("keys must be at least {} characters", MIN_KEY_LENGTH),
+            });
+        }
+    }
+
+    if !listen_addr.ip().is_loopback() && !config.behind_proxy {
+        violations.push(Violation {
+            key: "BEHIND_PROXY",
+            message: format!(
+                "{} is not loopback and plain HTTP is served, set BEHIND_PROXY=true if TLS is terminated in front",
+                listen_addr
+            ),
+        });
+    }
+
+    violations
+}
+
+pub fn enforce(violations: &[Violation], strict: bool) -> Result<(), String> {
+    for violation in violations {
+        eprintln!(
+            "insecure configuration, {}: {}",
+            violation.key, violation.message
+        );
+    }
+
+    match strict && !violations.is_empty() {
+        true => Err(format!(
+            "refusing to start in STRICT_MODE, change {}",
+            violations
+                .iter()
+                .map(|violation| violation.key)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+        false => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::admin::AdminKeys;
+
+    fn keys(violations: &[Violation]) -> Vec<&'static str> {
+        violations.iter().map(|violation| violation.key).collect()
+    }
+
+    #[test]
+    fn should_accept_defaults_on_loopback() {
+        let violations = check(&Config::default(), SocketAddr::from(([127, 0, 0, 1], 3000)));
+        assert!(violations.is_empty());
+        assert!(enforce(&violations, true).is_ok());
+    }
+
+    #[test]
+    fn should_report_each_violation() {
+        let config = Config {
+            admin_keys: AdminKeys::new(vec!["short".into()]),
+            verify_keys: AdminKeys::new(vec!["also short".into()]),
+            ..Config::default()
+        };
+        let violations = check(&config, SocketAddr::from(([0, 0, 0, 0], 3000)));
+        assert_eq!(
+            keys(&violations),
+            vec!["API_KEY", "VERIFY_KEYS", "BEHIND_PROXY"]
+        );
+
+        assert!(enforce(&violations, false).is_ok());
+        let error = enforce(&violations, true).unwrap_err();
+        assert!(error.contains("API_KEY, VERIFY_KEYS, BEHIND_PROXY"));
+    }
+
+    #[test]
+    fn should_accept_public_bind_behind_proxy() {
+        let config = Config {
+            admin_keys: AdminKeys::new(vec!["a".repeat(MIN_KEY_LENGTH)]),
+            behind_proxy: true,
+            ..Config::default()
+        };
+        let violations = check(&config, SocketAddr::from(([0, 0, 0, 0], 3000)));
+        assert!(violations.is_empty());
+    }
+}
@@ -0,0 +1,314 @@
+use std::{
+    any::Any,
+    collections::BTreeMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::{sync::watch, task::JoinHandle};
+
+use crate::{retry, time::TimeService};
+
+pub const RESTART: retry::Policy = retry::Policy {
+    max_attempts: u32::MAX,
+    base: Duration::from_secs(1),
+    max: Duration::from_secs(60),
+};
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Health {
+    pub running: bool,
+    pub restarts: u32,
+    pub last_started_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_failed_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_failure: Option<String>,
+}
+
+type Clock = Arc<dyn Fn() -> DateTime<Utc> + Send + Sync>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Phase {
+    Running,
+    Stopping,
+    Aborting,
+}
+
+// Handed to every task, which waits on it between rounds of work so that shutdown never cuts one off halfway
+#[derive(Clone)]
+pub struct Stop(watch::Receiver<Phase>);
+
+impl Stop {
+    // Waits out the pause before the next round, answering false once the task should return instead
+    pub async fn sleep(&mut self, duration: Duration) -> bool {
+        let stopping = tokio::select! {
+            _ = tokio::time::sleep(duration) => false,
+            _ = self.0.wait_for(|phase| *phase != Phase::Running) => true,
+        };
+        !stopping && *self.0.borrow() == Phase::Running
+    }
+}
+
+// Background tasks loop until told to stop, so one that panics or returns before that is restarted, backing off while
+// it keeps failing. A run that lasted longer than the longest backoff starts the backoff over.
+#[derive(Clone)]
+pub struct Supervisor {
+    clock: Clock,
+    policy: retry::Policy,
+    roster: Arc<Mutex<BTreeMap<&'static str, Health>>>,
+    handles: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    phase: watch::Sender<Phase>,
+}
+
+impl Supervisor {
+    pub fn new(time: impl TimeService, policy: retry::Policy) -> Self {
+        Self {
+            clock: Arc::new(move || time.clone().now()),
+            policy,
+            roster: Arc::default(),
+            handles: Arc::default(),
+            phase: watch::channel(Phase::Running).0,
+        }
+    }
+
+    pub fn spawn<F, Fut>(&self, name: &'static str, task: F)
+    where
+        F: Fn(Stop) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let now = (self.clock)();
+        self.update(name, |health| {
+            health.running = true;
+            health.last_started_at = Some(now);
+        });
+        let supervisor = self.clone();
+        let mut phase = self.phase.subscribe();
+        let handle = tokio::spawn(async move {
+            let mut failures = 0;
+            loop {
+                let started = tokio::time::Instant::now();
+                let now = (supervisor.clock)();
+                supervisor.update(name, |health| {
+                    health.running = true;
+                    health.last_started_at = Some(now);
+                });
+
+                let mut run = tokio::spawn(task(Stop(phase.clone())));
+                let failure = tokio::select! {
+                    outcome = &mut run => match outcome {
+                        Ok(()) => "returned".to_owned(),
+                        Err(error) => match error.try_into_panic() {
+                            Ok(payload) => format!("panicked: {}", message(payload)),
+                            Err(error) => error.to_string(),
+                        },
+                    },
+                    _ = phase.wait_for(|phase| *phase == Phase::Aborting) => {
+                        run.abort();
+                        return;
+                    }
+                };
+                // Done as asked rather than failed
+                if *phase.borrow() != Phase::Running {
+                    return;
+                }
+
+                failures = match started.elapsed() > supervisor.policy.max {
+                    true => 1,
+                    false => failures + 1,
+                };
+                let delay = supervisor.policy.delay(failures);
+                eprintln!(
+                    "[supervisor] {} {}, restarting in {:?}",
+                    name, failure, delay
+                );
+                let now = (supervisor.clock)();
+                supervisor.update(name, |health| {
+                    health.running = false;
+                    health.last_failed_at = Some(now);
+                    health.last_failure = Some(failure);
+                });
+
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = phase.wait_for(|phase| *phase != Phase::Running) => return,
+                }
+                supervisor.update(name, |health| health.restarts += 1);
+            }
+        });
+        self.handles.lock().unwrap().push(handle);
+    }
+
+    pub fn roster(&self) -> BTreeMap<&'static str, Health> {
+        self.roster.lock().unwrap().clone()
+    }
+
+    pub fn stopped(&self) -> Vec<&'static str> {
+        self.roster
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, health)| !health.running)
+            .map(|(name, _)| *name)
+            .collect()
+    }
+
+    // Tells every task to stop once it is done with the round it is in and waits for them until the deadline. Those
+    // still busy then are aborted. Answers whether they all stopped in time.
+    pub async fn shutdown(&self, deadline: Duration) -> bool {
+        self.phase.send_replace(Phase::Stopping);
+        let mut handles = std::mem::take(&mut *self.handles.lock().unwrap());
+        let all = async {
+            for handle in &mut handles {
+                let _ = handle.await;
+            }
+        };
+        let stopped = tokio::time::timeout(deadline, all).await.is_ok();
+        if !stopped {
+            self.phase.send_replace(Phase::Aborting);
+            for handle in handles.into_iter().filter(|handle| !handle.is_finished()) {
+                let _ = handle.await;
+            }
+        }
+        stopped
+    }
+
+    fn update(&self, name: &'static str, change: impl FnOnce(&mut Health)) {
+        change(self.roster.lock().unwrap().entry(name).or_default());
+    }
+}
+
+fn message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic".to_owned(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+    use crate::time::ConstantTimeService;
+
+    const FAST: retry::Policy = retry::Policy {
+        max_attempts: u32::MAX,
+        base: Duration::from_millis(10),
+        max: Duration::from_millis(50),
+    };
+
+    #[tokio::test]
+    async fn should_restart_panicking_task() {
+        let time = ConstantTimeService::new();
+        let supervisor = Supervisor::new(time.clone(), FAST);
+        let runs = Arc::new(AtomicU32::new(0));
+        let counted = runs.clone();
+        supervisor.spawn("flaky", move |mut stop| {
+            let run = counted.fetch_add(1, Ordering::SeqCst);
+            async move {
+                if run < 2 {
+                    panic!("run {} failed", run);
+                }
+                while stop.sleep(Duration::from_millis(10)).await {}
+            }
+        });
+
+        for _ in 0..100 {
+            if runs.load(Ordering::SeqCst) == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        let health = supervisor.roster()["flaky"].clone();
+        assert_eq!(
+            health,
+            Health {
+                running: true,
+                restarts: 2,
+                last_started_at: Some(time.clone().now()),
+                last_failed_at: Some(time.now()),
+                last_failure: Some("panicked: run 1 failed".into()),
+            }
+        );
+        assert!(supervisor.stopped().is_empty());
+
+        assert!(supervisor.shutdown(Duration::from_secs(1)).await);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn should_report_task_waiting_for_restart() {
+        let supervisor = Supervisor::new(ConstantTimeService::new(), RESTART);
+        supervisor.spawn("done", |_| async {});
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert_eq!(supervisor.stopped(), vec!["done"]);
+        assert_eq!(
+            supervisor.roster()["done"].last_failure.as_deref(),
+            Some("returned")
+        );
+        assert!(supervisor.shutdown(Duration::from_secs(1)).await);
+    }
+
+    #[tokio::test]
+    async fn should_let_task_finish_its_round_on_shutdown() {
+        let supervisor = Supervisor::new(ConstantTimeService::new(), FAST);
+        let rounds = Arc::new((AtomicU32::new(0), AtomicU32::new(0)));
+        let counted = rounds.clone();
+        supervisor.spawn("sweep", move |mut stop| {
+            let rounds = counted.clone();
+            async move {
+                while stop.sleep(Duration::from_millis(1)).await {
+                    rounds.0.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    rounds.1.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert!(supervisor.shutdown(Duration::from_secs(1)).await);
+        let (started, finished) = (
+            rounds.0.load(Ordering::SeqCst),
+            rounds.1.load(Ordering::SeqCst),
+        );
+        assert_eq!((started, finished), (1, 1));
+        assert_eq!(supervisor.roster()["sweep"].restarts, 0);
+    }
+
+    #[tokio::test]
+    async fn should_abort_task_still_busy_at_deadline() {
+        let supervisor = Supervisor::new(ConstantTimeService::new(), FAST);
+        let aborted = Arc::new(AtomicU32::new(0));
+        let counted = aborted.clone();
+        struct Aborted(Arc<AtomicU32>);
+        impl Drop for Aborted {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        supervisor.spawn("stuck", move |_| {
+            let aborted = Aborted(counted.clone());
+            async move {
+                let _aborted = aborted;
+                std::future::pending::<()>().await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        assert!(!supervisor.shutdown(Duration::from_millis(50)).await);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(aborted.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,4 +1,12 @@
-use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
+use std::path::Path;
+
+use axum::Router;
+use chrono::{DateTime, Utc};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    SqlitePool,
+};
+use tokio::net::TcpListener;
 
 use crate::db;
 
@@ -11,11 +19,51 @@ pub async fn database() -> SqlitePool {
     db
 }
 
+// VACUUM INTO from an in-memory database writes to memory as well
+pub async fn file_database(dir: &Path) -> SqlitePool {
+    let db = SqlitePoolOptions::new()
+        .connect_with(
+            SqliteConnectOptions::new()
+                .filename(dir.join("original.db"))
+                .create_if_missing(true),
+        )
+        .await
+        .unwrap();
+    db::init(&db).await.unwrap();
+    db
+}
+
 pub async fn insert_visitor(db: &SqlitePool, nick: &str, group: Option<&str>) {
-    sqlx::query(r#"INSERT INTO visitor (created_at, ip, nick, "group") VALUES (CURRENT_TIMESTAMP, '127.0.0.1:8080', $1, $2)"#)
+    sqlx::query(r#"INSERT INTO visitor (created_at, ip, nick, "group") VALUES (CURRENT_TIMESTAMP, '127.0.0.1', $1, $2)"#)
         .bind(nick)
         .bind(group)
         .execute(db)
         .await
         .unwrap();
 }
+
+pub async fn insert_visitor_at(db: &SqlitePool, nick: &str, created_at: DateTime<Utc>) {
+    sqlx::query(r#"INSERT INTO visitor (created_at, ip, nick) VALUES ($1, '127.0.0.1', $2)"#)
+        .bind(created_at)
+        .bind(nick)
+        .execute(db)
+        .await
+        .unwrap();
+}
+
+// Edit tokens are random, so this checks there is one and leaves the rest of a registration to compare
+pub fn without_edit_token(body: &[u8]) -> String {
+    let body = std::str::from_utf8(body).unwrap();
+    let field = r#","edit_token":""#;
+    let start = body.find(field).expect("no edit token");
+    let token = &body[start + field.len()..][..32];
+    assert!(token.bytes().all(|x| x.is_ascii_hexdigit()), "{}", body);
+    format!("{}{}", &body[..start], &body[start + field.len() + 33..])
+}
+
+pub async fn serve(router: Router) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+    format!("http://{}", addr)
+}
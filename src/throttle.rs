@@ -0,0 +1,86 @@
+use std::{
+    net::IpAddr,
+    num::NonZeroU32,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use governor::{
+    clock::{Clock, DefaultClock},
+    DefaultKeyedRateLimiter, Quota, RateLimiter,
+};
+use tower_governor::{key_extractor::KeyExtractor, GovernorError};
+
+use crate::{config::RateLimit, error::ApiError, proxy};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Mode {
+    #[default]
+    Reject,
+    Delay,
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "reject" => Ok(Mode::Reject),
+            "delay" => Ok(Mode::Delay),
+            _ => Err(format!("expected delay or reject, got {}", value)),
+        }
+    }
+}
+
+// The /register limit, which unlike the fixed limits on other routes can hold a request back until the client has
+// another one to spend instead of answering 429 right away
+#[derive(Clone)]
+pub struct Throttle {
+    limiter: Arc<DefaultKeyedRateLimiter<IpAddr>>,
+    clients: proxy::ClientIpKeyExtractor,
+    max_wait: Duration,
+}
+
+impl Throttle {
+    pub fn new(limit: &RateLimit, clients: proxy::ClientIpKeyExtractor) -> Self {
+        let quota = Quota::with_period(Duration::from_secs(limit.period_seconds))
+            .expect("rate limit period is at least a second")
+            .allow_burst(NonZeroU32::new(limit.burst).expect("rate limit burst is at least 1"));
+        Self {
+            limiter: Arc::new(RateLimiter::keyed(quota)),
+            clients,
+            max_wait: match limit.mode {
+                Mode::Reject => Duration::ZERO,
+                Mode::Delay => Duration::from_secs(limit.max_wait_seconds),
+            },
+        }
+    }
+}
+
+// Every waiting request checks again when its turn should have come, so one that keeps losing to others from the same
+// address still gives up at the deadline
+pub async fn limit(State(throttle): State<Throttle>, request: Request, next: Next) -> Response {
+    let client = match throttle.clients.extract(&request) {
+        Ok(client) => client,
+        Err(error) => return ApiError::from(error).into_response(),
+    };
+    let deadline = Instant::now() + throttle.max_wait;
+    while let Err(not_until) = throttle.limiter.check_key(&client) {
+        let wait = not_until.wait_time_from(DefaultClock::default().now());
+        if Instant::now() + wait > deadline {
+            return ApiError::from(GovernorError::TooManyRequests {
+                wait_time: wait.as_secs(),
+                headers: None,
+            })
+            .into_response();
+        }
+        tokio::time::sleep(wait).await;
+    }
+    next.run(request).await
+}
@@ -24,6 +24,10 @@ impl ConstantTimeService {
     pub fn new() -> Self {
         Self { value: Utc::now() }
     }
+
+    pub fn at(value: DateTime<Utc>) -> Self {
+        Self { value }
+    }
 }
 
 #[cfg(test)]
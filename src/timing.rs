@@ -0,0 +1,70 @@
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{Request, State},
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+};
+
+pub const HEADER: &str = "X-Timing";
+
+#[derive(Clone, Debug)]
+pub struct Timings {
+    last: Instant,
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    pub fn start() -> Self {
+        Self {
+            last: Instant::now(),
+            phases: Vec::new(),
+        }
+    }
+
+    pub fn phase(&mut self, name: &'static str) {
+        let now = Instant::now();
+        self.phases.push((name, now - self.last));
+        self.last = now;
+    }
+
+    pub fn header(&self) -> HeaderValue {
+        let value = self
+            .phases
+            .iter()
+            .map(|(name, duration)| format!("{};dur={:.3}", name, duration.as_secs_f64() * 1000.0))
+            .collect::<Vec<_>>()
+            .join(", ");
+        HeaderValue::try_from(value).expect("phase names are valid header values")
+    }
+}
+
+pub async fn expose(State(enabled): State<bool>, request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    if let Some(header) = enabled
+        .then(|| response.extensions().get::<Timings>().map(Timings::header))
+        .flatten()
+    {
+        response.headers_mut().insert(HEADER, header);
+    }
+    response
+}
+
+#[cfg(test)]
+mod test {
+    use super::Timings;
+
+    #[test]
+    fn should_format_phases_in_milliseconds() {
+        let mut timings = Timings::start();
+        timings.phase("validation");
+        timings.phase("db");
+
+        let header = timings.header();
+        let phases: Vec<&str> = header.to_str().unwrap().split(", ").collect();
+        assert_eq!(phases.len(), 2);
+        assert!(phases[0].starts_with("validation;dur=0."));
+        assert!(phases[1].starts_with("db;dur="));
+    }
+}
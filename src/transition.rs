@@ -0,0 +1,162 @@
+use axum::http::StatusCode;
+use sqlx::{Sqlite, SqlitePool, Transaction};
+
+use crate::error::ApiError;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    Effective,
+    Already,
+}
+
+impl Outcome {
+    pub fn of<T: PartialEq>(current: &T, target: &T) -> Self {
+        match current == target {
+            true => Outcome::Already,
+            false => Outcome::Effective,
+        }
+    }
+
+    pub fn is_already(self) -> bool {
+        self == Outcome::Already
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Outcome::Effective => "effective",
+            Outcome::Already => "no-op",
+        }
+    }
+}
+
+// Starts with a write, so identical transitions racing each other take turns: the later one reads the state
+// the earlier one left behind and sees it already reached, rather than failing on a stale read
+pub async fn begin(db: &SqlitePool, id: i32) -> Result<Transaction<'static, Sqlite>, ApiError> {
+    let mut tx = db.begin().await?;
+    let rows = sqlx::query("UPDATE visitor SET id = id WHERE id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+    match rows {
+        0 => Err(ApiError::new(StatusCode::NOT_FOUND, "visitor not found")),
+        _ => Ok(tx),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{body::Body, Router};
+    use http_body_util::BodyExt;
+    use hyper::Request;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{admin::AdminKeys, config::Config, testing, time::ConstantTimeService};
+
+    async fn send(api: Router, method: &str, uri: &str, body: &str) -> (StatusCode, Value) {
+        let response = api
+            .oneshot(
+                Request::builder()
+                    .header("Authorization", "Bearer key")
+                    .header("Content-Type", "application/json")
+                    .method(method)
+                    .uri(uri)
+                    .body(Body::from(body.to_owned()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    // The same request sent by several organizers at once
+    async fn race(
+        api: &Router,
+        method: &'static str,
+        uri: &'static str,
+        body: &'static str,
+    ) -> Vec<(StatusCode, Value)> {
+        let mut requests = tokio::task::JoinSet::new();
+        for _ in 0..8 {
+            requests.spawn(send(api.clone(), method, uri, body));
+        }
+        let mut responses = Vec::new();
+        while let Some(response) = requests.join_next().await {
+            responses.push(response.unwrap());
+        }
+        responses
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn should_apply_racing_transitions_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = testing::file_database(dir.path()).await;
+        testing::insert_visitor(&db, "Truck", None).await;
+        sqlx::query("UPDATE visitor SET payment_reference = '10016', payment_status = 'unpaid'")
+            .execute(&db)
+            .await
+            .unwrap();
+        let api = crate::api(
+            ConstantTimeService::new(),
+            db.clone(),
+            Config {
+                admin_keys: AdminKeys::new(vec!["key".into()]),
+                ..Config::default()
+            },
+        );
+
+        let renames = race(
+            &api,
+            "PUT",
+            "/admin/visitors/1/nick",
+            r#"{"nick":"Truck Driver"}"#,
+        )
+        .await;
+        assert!(renames.iter().all(|(status, _)| *status == StatusCode::OK));
+        assert_eq!(
+            renames
+                .iter()
+                .filter(|(_, body)| body["already"] == false)
+                .count(),
+            1
+        );
+
+        let notes = race(&api, "PUT", "/admin/visitors/1/note", r#"{"note":"VIP"}"#).await;
+        assert!(notes.iter().all(|(status, _)| *status == StatusCode::OK));
+        assert_eq!(
+            notes
+                .iter()
+                .filter(|(_, body)| body["already"] == false)
+                .count(),
+            1
+        );
+
+        let imports = race(&api, "POST", "/admin/payments/import", "10016").await;
+        assert!(imports.iter().all(|(status, _)| *status == StatusCode::OK));
+        assert_eq!(
+            imports
+                .iter()
+                .filter(|(_, body)| body["matched"] == serde_json::json!(["10016"]))
+                .count(),
+            1
+        );
+        assert!(imports
+            .iter()
+            .all(|(_, body)| body["unmatched"] == serde_json::json!([])));
+
+        let updates: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM visitor_change WHERE kind = 'updated'")
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(updates, 3, "one rename, one note and one payment");
+
+        let (status, _) = send(api, "PUT", "/admin/visitors/2/nick", r#"{"nick":"Nobody"}"#).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
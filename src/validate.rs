@@ -0,0 +1,353 @@
+use std::collections::BTreeMap;
+
+use axum::http::StatusCode;
+use serde_json::Value;
+use unicode_normalization::UnicodeNormalization;
+use unicode_properties::{GeneralCategory, UnicodeGeneralCategory};
+
+use crate::error::ApiError;
+
+const NOTE_MAX_LENGTH: usize = 1000;
+pub const NICK_MAX_LENGTH: usize = 64;
+pub const EMAIL_MAX_LENGTH: usize = 254;
+pub const EXTRA_MAX_LENGTH: usize = 1024;
+
+pub fn nick(value: &str) -> Result<String, ApiError> {
+    let normalized: String = value.nfc().collect();
+    if normalized.chars().any(|c| {
+        !c.is_whitespace()
+            && matches!(
+                c.general_category(),
+                GeneralCategory::Control | GeneralCategory::Format
+            )
+    }) {
+        return Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "nick must not contain invisible or control characters",
+        )
+        .with_detail("field", "nick"));
+    }
+
+    match normalized.split_whitespace().collect::<Vec<_>>().join(" ") {
+        nick if nick.is_empty() => Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "nick must not be empty",
+        )
+        .with_detail("field", "nick")),
+        nick => {
+            length("nick", &nick, NICK_MAX_LENGTH)?;
+            Ok(nick)
+        }
+    }
+}
+
+// Collects field failures to answer them together, anything else is passed on as it is
+#[derive(Default)]
+pub struct Failures(BTreeMap<String, String>);
+
+impl Failures {
+    pub fn check<T>(&mut self, result: Result<T, ApiError>) -> Result<Option<T>, ApiError> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(error) => match error.field_failure() {
+                Some((field, message)) => {
+                    self.0.entry(field).or_insert(message);
+                    Ok(None)
+                }
+                None => Err(error),
+            },
+        }
+    }
+
+    pub fn into_error(self) -> ApiError {
+        ApiError::validation(self.0)
+    }
+}
+
+pub fn length(field: &str, value: &str, max_length: usize) -> Result<(), ApiError> {
+    match value.chars().count() > max_length {
+        true => Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("{} must be at most {} characters", field, max_length),
+        )
+        .with_detail("field", field)),
+        false => Ok(()),
+    }
+}
+
+// A plain string is kept as sent, an object is stored as JSON text in the same column
+pub fn extra(value: Option<Value>) -> Result<Option<String>, ApiError> {
+    let extra = match value {
+        None | Some(Value::Null) => return Ok(None),
+        Some(Value::String(text)) => text,
+        Some(object @ Value::Object(_)) => object.to_string(),
+        Some(_) => {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "extra must be a string or an object",
+            )
+            .with_detail("field", "extra"))
+        }
+    };
+    length("extra", &extra, EXTRA_MAX_LENGTH)?;
+    Ok(Some(extra))
+}
+
+pub fn email(value: Option<String>) -> Result<Option<String>, ApiError> {
+    let Some(email) = value
+        .as_deref()
+        .map(str::trim)
+        .filter(|email| !email.is_empty())
+    else {
+        return Ok(None);
+    };
+
+    length("email", email, EMAIL_MAX_LENGTH)?;
+    let plausible = email.split_once('@').is_some_and(|(local, domain)| {
+        !local.is_empty()
+            && !domain.contains('@')
+            && domain.contains('.')
+            && !domain.starts_with('.')
+            && !domain.ends_with('.')
+    }) && !email.chars().any(char::is_whitespace);
+
+    match plausible {
+        true => Ok(Some(email.to_owned())),
+        false => Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "email must look like name@example.com",
+        )
+        .with_detail("field", "email")),
+    }
+}
+
+// Contact details are only kept with the visitor's explicit agreement
+pub fn consent(email: Option<&str>, consent: Option<bool>) -> Result<(), ApiError> {
+    let gives_email = email.is_some_and(|email| !email.trim().is_empty());
+    match gives_email && consent != Some(true) {
+        true => Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "consent is required to store an email",
+        )
+        .with_code("consent_required")
+        .with_detail("field", "consent")),
+        false => Ok(()),
+    }
+}
+
+pub fn group(value: Option<String>, max_length: usize) -> Result<Option<String>, ApiError> {
+    let Some(group) = value.as_deref().and_then(normalize) else {
+        return Ok(None);
+    };
+
+    length("group", &group, max_length)?;
+    Ok(Some(group))
+}
+
+// Compared ignoring case, and the group is stored as the organizers spelled it
+pub fn allowed_group(
+    group: Option<String>,
+    allowed: Option<&[String]>,
+) -> Result<Option<String>, ApiError> {
+    let (Some(group), Some(allowed)) = (group.as_deref(), allowed) else {
+        return Ok(group);
+    };
+    match allowed
+        .iter()
+        .find(|name| name.to_lowercase() == group.to_lowercase())
+    {
+        Some(name) => Ok(Some(name.clone())),
+        None => Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            "group is not one of the allowed groups",
+        )
+        .with_code("group_not_allowed")
+        .with_detail("field", "group")
+        .with_detail("allowed", allowed)),
+    }
+}
+
+pub fn note(value: Option<String>) -> Result<Option<String>, ApiError> {
+    let note = value
+        .as_deref()
+        .map(str::trim)
+        .filter(|note| !note.is_empty());
+
+    match note {
+        Some(note) if note.chars().count() > NOTE_MAX_LENGTH => Err(ApiError::new(
+            StatusCode::BAD_REQUEST,
+            format!("note must be at most {} characters", NOTE_MAX_LENGTH),
+        )),
+        _ => Ok(note.map(str::to_owned)),
+    }
+}
+
+pub fn referral<'a>(value: Option<&str>, codes: &'a [String]) -> Option<&'a String> {
+    let value = value?.trim();
+    codes.iter().find(|code| code.eq_ignore_ascii_case(value))
+}
+
+pub fn normalize(value: &str) -> Option<String> {
+    let cleaned: String = value
+        .chars()
+        .map(|c| if c.is_whitespace() { ' ' } else { c })
+        .filter(|c| !c.is_control() && !is_bidi_control(*c))
+        .collect();
+    let collapsed = cleaned.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    match collapsed.is_empty() {
+        true => None,
+        false => Some(collapsed),
+    }
+}
+
+fn is_bidi_control(c: char) -> bool {
+    matches!(
+        c,
+        '\u{061C}' | '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}'
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_collapse_whitespace() {
+        assert_eq!(
+            normalize("  Fairlight \t and\n  friends ").as_deref(),
+            Some("Fairlight and friends")
+        );
+    }
+
+    #[test]
+    fn should_strip_control_and_bidi_characters() {
+        assert_eq!(
+            normalize("Fair\u{0007}light\u{202E}").as_deref(),
+            Some("Fairlight")
+        );
+    }
+
+    #[test]
+    fn should_normalize_nick() {
+        assert_eq!(nick(" Truck\t").unwrap(), "Truck");
+        assert!(nick("").is_err());
+        assert!(nick(" \n ").is_err());
+        assert_eq!(nick("Truck\n\nDriver").unwrap(), "Truck Driver");
+        assert_eq!(nick("Cafe\u{301}").unwrap(), "Caf\u{e9}");
+        assert!(nick("Slu\u{200B}mmy").is_err());
+        assert!(nick("Slummy\u{7}").is_err());
+        assert!(nick("\u{202E}ymmulS").is_err());
+    }
+
+    #[test]
+    fn should_limit_field_lengths() {
+        assert!(nick(&"n".repeat(NICK_MAX_LENGTH)).is_ok());
+        assert!(nick(&"n".repeat(NICK_MAX_LENGTH + 1)).is_err());
+        assert!(nick(&format!(" {} ", "ä".repeat(NICK_MAX_LENGTH))).is_ok());
+
+        assert!(length("extra", &"x".repeat(EXTRA_MAX_LENGTH), EXTRA_MAX_LENGTH).is_ok());
+        let error = length("extra", &"x".repeat(EXTRA_MAX_LENGTH + 1), EXTRA_MAX_LENGTH);
+        assert_eq!(
+            serde_json::to_string(&error.unwrap_err()).unwrap(),
+            r#"{"error":"extra must be at most 1024 characters","field":"extra"}"#
+        );
+    }
+
+    #[test]
+    fn should_validate_email() {
+        for valid in [
+            "visitor@example.com",
+            " orga@party.example.org ",
+            "a.b+c@d.fi",
+        ] {
+            assert_eq!(
+                email(Some(valid.into())).unwrap().as_deref(),
+                Some(valid.trim()),
+                "{}",
+                valid
+            );
+        }
+        for invalid in [
+            "asdf",
+            "@example.com",
+            "visitor@",
+            "visitor@localhost",
+            "visitor@example.",
+            "visitor@.com",
+            "a@b@example.com",
+            "visitor name@example.com",
+        ] {
+            assert!(email(Some(invalid.into())).is_err(), "{}", invalid);
+        }
+        assert_eq!(email(Some("".into())).unwrap(), None);
+        assert_eq!(email(Some("  ".into())).unwrap(), None);
+        assert_eq!(email(None).unwrap(), None);
+    }
+
+    #[test]
+    fn should_treat_empty_group_as_none() {
+        assert_eq!(group(Some("   ".into()), 48).unwrap(), None);
+        assert_eq!(group(Some("\u{200E}".into()), 48).unwrap(), None);
+        assert_eq!(group(None, 48).unwrap(), None);
+    }
+
+    #[test]
+    fn should_normalize_note() {
+        assert_eq!(
+            note(Some("  paid cash ".into())).unwrap().as_deref(),
+            Some("paid cash")
+        );
+        assert_eq!(note(Some("  ".into())).unwrap(), None);
+        assert_eq!(
+            note(Some("x".repeat(NOTE_MAX_LENGTH))).unwrap(),
+            Some("x".repeat(NOTE_MAX_LENGTH))
+        );
+        assert!(note(Some("x".repeat(NOTE_MAX_LENGTH + 1))).is_err());
+    }
+
+    #[test]
+    fn should_match_whitelisted_referrals() {
+        let codes = vec!["Flyer".to_owned(), "forum".to_owned()];
+
+        assert_eq!(referral(Some(" flyer "), &codes), Some(&codes[0]));
+        assert_eq!(referral(Some("FORUM"), &codes), Some(&codes[1]));
+        assert_eq!(referral(Some("spam"), &codes), None);
+        assert_eq!(referral(None, &codes), None);
+    }
+
+    #[test]
+    fn should_enforce_max_length() {
+        assert_eq!(
+            group(Some("é".repeat(48)), 48).unwrap(),
+            Some("é".repeat(48))
+        );
+        assert!(group(Some("é".repeat(49)), 48).is_err());
+    }
+
+    #[test]
+    fn should_accept_extra_as_string_or_object() {
+        use serde_json::json;
+
+        assert_eq!(extra(None).unwrap(), None);
+        assert_eq!(extra(Some(Value::Null)).unwrap(), None);
+        assert_eq!(
+            extra(Some(json!("Vegan"))).unwrap().as_deref(),
+            Some("Vegan")
+        );
+        assert_eq!(
+            extra(Some(json!({"sleeping": "floor", "shirt": "L"})))
+                .unwrap()
+                .as_deref(),
+            Some(r#"{"shirt":"L","sleeping":"floor"}"#)
+        );
+        for value in [json!(["L"]), json!(42), json!(true)] {
+            assert_eq!(
+                serde_json::to_string(&extra(Some(value)).unwrap_err()).unwrap(),
+                r#"{"error":"extra must be a string or an object","field":"extra"}"#
+            );
+        }
+        let long = json!({ "notes": "x".repeat(EXTRA_MAX_LENGTH) });
+        assert!(extra(Some(long)).is_err());
+    }
+}
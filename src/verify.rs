@@ -0,0 +1,164 @@
+use std::sync::atomic::Ordering;
+
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::{
+    admin::AdminKeys, error::ApiError, json::Json, role, time::TimeService, validate, ApiState,
+};
+
+#[derive(Deserialize)]
+pub struct VerifyRequest {
+    nick: Option<String>,
+    votekey: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Verification {
+    registered: bool,
+}
+
+pub async fn authorize(State(keys): State<AdminKeys>, request: Request, next: Next) -> Response {
+    match keys.authorizes(request.headers()) {
+        true => next.run(request).await,
+        false => ApiError::new(StatusCode::UNAUTHORIZED, "invalid verification key")
+            .with_code("unauthorized")
+            .into_response(),
+    }
+}
+
+pub async fn verify<T: TimeService>(
+    State(state): State<ApiState<T>>,
+    Json(request): Json<VerifyRequest>,
+) -> Result<(StatusCode, Json<Verification>), ApiError> {
+    state.verify_lookups.fetch_add(1, Ordering::Relaxed);
+
+    let nick = match (request.nick, request.votekey) {
+        (Some(nick), None) => nick,
+        (None, Some(_)) => {
+            return Err(
+                ApiError::new(StatusCode::BAD_REQUEST, "vote keys are not supported")
+                    .with_code("unsupported"),
+            )
+        }
+        _ => {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "expected exactly one of nick or votekey",
+            ))
+        }
+    };
+
+    // Stored nicks are normalized the same way, and a waitlisted visitor is not in yet
+    let nick: String = validate::normalize(&nick)
+        .unwrap_or_default()
+        .nfc()
+        .collect();
+    let registered = sqlx::query_scalar(
+        r#"SELECT EXISTS (SELECT 1 FROM visitor WHERE nick = $1 COLLATE NOCASE AND status = $2)"#,
+    )
+    .bind(nick)
+    .bind(role::CONFIRMED)
+    .fetch_one(&state.db)
+    .await?;
+
+    Ok((StatusCode::OK, Json(Verification { registered })))
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use axum::{body::Body, extract::ConnectInfo};
+    use http_body_util::BodyExt;
+    use hyper::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    use crate::{admin::AdminKeys, config::Config, testing, time::ConstantTimeService};
+
+    #[tokio::test]
+    async fn should_only_answer_registered() {
+        let db = testing::database().await;
+        testing::insert_visitor(&db, "Fairlight", Some("Secret Group")).await;
+        testing::insert_visitor(&db, "Waiting", None).await;
+        sqlx::query("UPDATE visitor SET status = 'waitlisted' WHERE nick = 'Waiting'")
+            .execute(&db)
+            .await
+            .unwrap();
+        let api = crate::api(
+            ConstantTimeService::new(),
+            db,
+            Config {
+                admin_keys: AdminKeys::new(vec!["admin".into()]),
+                verify_keys: AdminKeys::new(vec!["verify".into()]),
+                ..Config::default()
+            },
+        );
+
+        for (key, body, status, expected) in [
+            (
+                "admin",
+                r#"{"nick":"Fairlight"}"#,
+                StatusCode::UNAUTHORIZED,
+                None,
+            ),
+            (
+                "verify",
+                r#"{"nick":" fairlight"}"#,
+                StatusCode::OK,
+                Some(r#"{"registered":true}"#),
+            ),
+            (
+                "verify",
+                r#"{"nick":"FAIRLIGHT "}"#,
+                StatusCode::OK,
+                Some(r#"{"registered":true}"#),
+            ),
+            (
+                "verify",
+                r#"{"nick":"Waiting"}"#,
+                StatusCode::OK,
+                Some(r#"{"registered":false}"#),
+            ),
+            (
+                "verify",
+                r#"{"nick":"Razor"}"#,
+                StatusCode::OK,
+                Some(r#"{"registered":false}"#),
+            ),
+            (
+                "verify",
+                r#"{"votekey":"ABCD"}"#,
+                StatusCode::BAD_REQUEST,
+                None,
+            ),
+        ] {
+            let response = api
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .extension(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 8080))))
+                        .header("Authorization", format!("Bearer {}", key))
+                        .header("Content-Type", "application/json")
+                        .method("POST")
+                        .uri("/verify")
+                        .body(Body::from(body))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), status, "{} {}", key, body);
+            if let Some(expected) = expected {
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                assert_eq!(&body[..], expected.as_bytes());
+            }
+        }
+    }
+}